@@ -0,0 +1,7 @@
+// Rewrites the golden references after an intended change to the flow or
+// shader; cargo test checks against them.
+use terrain_flow::golden::{self, GoldenMode};
+
+fn main() {
+    golden::run(GoldenMode::Bless, golden::GOLDEN_PATH);
+}
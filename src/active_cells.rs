@@ -0,0 +1,77 @@
+use crate::terrain::{Terrain, TerrainDelta};
+
+// Lets the flow skip working out what leaves cells that have gone quiet. A
+// cell is active while it has changed by more than `threshold` (its gains
+// and losses of height and depth together) in one of the last `linger`
+// steps, or neighbors a cell that has. Every `sweep_every` steps all cells
+// are active, which catches cells set moving by anything besides the flow,
+// such as brushes or uplift, and changes too slow to cross the threshold.
+#[derive(Clone, Copy, Debug)]
+pub struct ActiveCells {
+    pub threshold: f64,
+    pub linger: u32,
+    pub sweep_every: u32,
+}
+
+// the cells active this step, and per cell the steps since it last changed
+pub struct ActiveSet {
+    config: ActiveCells,
+    step: u64,
+    quiet: Vec<u32>,
+    active: Vec<bool>,
+    change: Vec<f64>,
+}
+
+impl ActiveCells {
+    pub fn new(threshold: f64, linger: u32, sweep_every: u32) -> ActiveCells {
+        assert!(threshold >= 0.0 && threshold.is_finite());
+        assert!(linger > 0);
+        assert!(sweep_every > 0);
+        ActiveCells { threshold, linger, sweep_every }
+    }
+}
+
+impl ActiveSet {
+    pub fn new(config: ActiveCells) -> ActiveSet {
+        ActiveSet { config, step: 0, quiet: Vec::new(), active: Vec::new(), change: Vec::new() }
+    }
+
+    // the cells to work out this step, all of them on a sweep or when the
+    // terrain's cells have changed
+    pub fn begin_step(&mut self, terrain: &Terrain) -> &[bool] {
+        let cells_len = terrain.cells_len();
+        let sweep = self.quiet.len() != cells_len || self.step.is_multiple_of(self.config.sweep_every as u64);
+        self.step += 1;
+        if sweep {
+            self.quiet = vec![0; cells_len];
+            self.active = vec![true; cells_len];
+            return &self.active;
+        }
+
+        let offsets = terrain.neighbor_offsets();
+        let neighbors = terrain.neighbors();
+        let linger = self.config.linger;
+        let quiet = &self.quiet;
+        for (cell_index, active) in self.active.iter_mut().enumerate() {
+            *active = quiet[cell_index] < linger
+                || neighbors[offsets[cell_index]..offsets[cell_index + 1]].iter().any(|nd| quiet[nd.index()] < linger);
+        }
+        &self.active
+    }
+
+    // notes which cells the step's deltas, already scaled, changed
+    pub fn after_flow(&mut self, deltas: &[TerrainDelta]) {
+        self.change.clear();
+        self.change.resize(self.quiet.len(), 0.0);
+        for delta in deltas.iter() {
+            self.change[delta.cell_index] += delta.height_delta.abs() + delta.depth_delta.abs();
+        }
+        for (quiet, &change) in self.quiet.iter_mut().zip(self.change.iter()) {
+            *quiet = if change > self.config.threshold { 0 } else { quiet.saturating_add(1) };
+        }
+    }
+
+    pub fn active_len(&self) -> usize {
+        self.active.iter().filter(|&&active| active).count()
+    }
+}
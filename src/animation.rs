@@ -0,0 +1,392 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::thread;
+
+const PNG_SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+// where acTL's frame count sits, after the signature, IHDR and acTL's own
+// length and type
+const ACTL_DATA_OFFSET: u64 = 8 + 25 + 8;
+const GIF_MAX_CODES: u16 = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnimationFormat {
+    Apng,
+    Gif,
+}
+
+// An animated PNG or GIF written a frame at a time, each shown for
+// `delay_ms` and looping forever, with the frames shrunk by `scale` by
+// averaging each scale by scale block of pixels. GIF frames get a palette
+// of their own of up to 256 colors. Dropping it finishes the file.
+pub struct Animation {
+    path: String,
+    format: AnimationFormat,
+    file: BufWriter<File>,
+    source_size: (usize, usize),
+    width: usize,
+    height: usize,
+    delay_ms: u32,
+    scale: usize,
+    frames: u32,
+    sequence: u32,
+}
+
+impl AnimationFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AnimationFormat::Apng => "apng",
+            AnimationFormat::Gif => "gif",
+        }
+    }
+}
+
+impl Animation {
+    // `width` and `height` are those of the frames before scaling
+    pub fn start(path: &str, format: AnimationFormat, width: usize, height: usize, delay_ms: u32, scale: u32)
+        -> Animation {
+        assert!(scale > 0);
+        let scale = scale as usize;
+        let source_size = (width, height);
+        let (width, height) = (width.div_ceil(scale), height.div_ceil(scale));
+        let file = File::create(path).unwrap_or_else(|e| panic!("couldn't create {}: {}", path, e));
+        let mut animation = Animation {
+            path: path.to_string(),
+            format,
+            file: BufWriter::new(file),
+            source_size,
+            width,
+            height,
+            delay_ms,
+            scale,
+            frames: 0,
+            sequence: 0,
+        };
+        match format {
+            AnimationFormat::Apng => {
+                // the delay goes in as a fraction of a second over 1000
+                assert!(delay_ms <= u16::MAX as u32);
+                assert!(width <= i32::MAX as usize && height <= i32::MAX as usize);
+                animation.write(&PNG_SIGNATURE);
+                let mut ihdr = Vec::new();
+                ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+                ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+                // 8 bits per sample RGB, deflate, adaptive filtering, no interlace
+                ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+                animation.write_chunk(b"IHDR", &ihdr);
+                // the frame count is filled in on finishing; 0 plays loops forever
+                animation.write_chunk(b"acTL", &[0; 8]);
+            }
+            AnimationFormat::Gif => {
+                // the delay goes in as hundredths of a second
+                assert!(delay_ms / 10 <= u16::MAX as u32);
+                assert!(width <= u16::MAX as usize && height <= u16::MAX as usize);
+                animation.write(b"GIF89a");
+                animation.write(&(width as u16).to_le_bytes());
+                animation.write(&(height as u16).to_le_bytes());
+                // no global color table, background color 0, square pixels
+                animation.write(&[0, 0, 0]);
+                // the NETSCAPE2.0 extension, looping forever
+                animation.write(&[0x21, 0xff, 11]);
+                animation.write(b"NETSCAPE2.0");
+                animation.write(&[3, 1, 0, 0, 0]);
+            }
+        }
+        animation
+    }
+
+    pub fn add_frame(&mut self, pixel_data: &[u8]) {
+        let pixel_data = downscale(pixel_data, self.source_size, self.scale);
+        match self.format {
+            AnimationFormat::Apng => self.add_apng_frame(&pixel_data),
+            AnimationFormat::Gif => self.add_gif_frame(&pixel_data),
+        }
+        self.frames += 1;
+    }
+
+    fn add_apng_frame(&mut self, pixel_data: &[u8]) {
+        let mut fctl = Vec::new();
+        fctl.extend_from_slice(&self.next_sequence().to_be_bytes());
+        fctl.extend_from_slice(&(self.width as u32).to_be_bytes());
+        fctl.extend_from_slice(&(self.height as u32).to_be_bytes());
+        // at the top left, with no disposal and replacing what was there
+        fctl.extend_from_slice(&[0; 8]);
+        fctl.extend_from_slice(&(self.delay_ms as u16).to_be_bytes());
+        fctl.extend_from_slice(&1000u16.to_be_bytes());
+        fctl.extend_from_slice(&[0, 0]);
+        self.write_chunk(b"fcTL", &fctl);
+
+        let image_data = miniz_oxide::deflate::compress_to_vec_zlib(&filter_rows(pixel_data, self.width), 6);
+        // the first frame doubles as the image for viewers that don't animate
+        if self.frames == 0 {
+            self.write_chunk(b"IDAT", &image_data);
+        } else {
+            let mut fdat = Vec::with_capacity(image_data.len() + 4);
+            fdat.extend_from_slice(&self.next_sequence().to_be_bytes());
+            fdat.extend_from_slice(&image_data);
+            self.write_chunk(b"fdAT", &fdat);
+        }
+    }
+
+    fn add_gif_frame(&mut self, pixel_data: &[u8]) {
+        let (palette, indices) = quantize(pixel_data);
+
+        // the graphic control extension, giving the delay
+        let delay = ((self.delay_ms + 5) / 10).min(u16::MAX as u32) as u16;
+        self.write(&[0x21, 0xf9, 4, 0]);
+        self.write(&delay.to_le_bytes());
+        self.write(&[0, 0]);
+
+        // the image descriptor, covering the whole screen, with a local color
+        // table of 256 colors
+        self.write(&[0x2c, 0, 0, 0, 0]);
+        self.write(&(self.width as u16).to_le_bytes());
+        self.write(&(self.height as u16).to_le_bytes());
+        self.write(&[0x80 | 7]);
+        let mut color_table = vec![0; 256 * 3];
+        for (entry, color) in color_table.chunks_mut(3).zip(palette.iter()) {
+            entry.copy_from_slice(color);
+        }
+        self.write(&color_table);
+
+        self.write(&[8]);
+        for block in lzw_encode(&indices).chunks(255) {
+            self.write(&[block.len() as u8]);
+            self.write(block);
+        }
+        self.write(&[0]);
+    }
+
+    fn next_sequence(&mut self) -> u32 {
+        self.sequence += 1;
+        self.sequence - 1
+    }
+
+    fn write_chunk(&mut self, kind: &[u8; 4], data: &[u8]) {
+        self.write(&chunk(kind, data));
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        if let Err(e) = self.file.write_all(data) {
+            panic!("couldn't write {}: {}", self.path, e);
+        }
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        match self.format {
+            AnimationFormat::Apng => {
+                self.file.write_all(&chunk(b"IEND", &[]))?;
+                let mut actl = Vec::new();
+                actl.extend_from_slice(&self.frames.to_be_bytes());
+                actl.extend_from_slice(&0u32.to_be_bytes());
+                self.file.seek(SeekFrom::Start(ACTL_DATA_OFFSET))?;
+                self.file.write_all(&chunk(b"acTL", &actl)[8..])?;
+            }
+            AnimationFormat::Gif => self.file.write_all(&[0x3b])?,
+        }
+        self.file.flush()
+    }
+}
+
+impl Drop for Animation {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            if !thread::panicking() {
+                panic!("couldn't finish {}: {}", self.path, e);
+            }
+        }
+    }
+}
+
+// a PNG chunk's length, type, data and CRC
+fn chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = Vec::with_capacity(data.len() + 12);
+    chunk.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    chunk.extend_from_slice(kind);
+    chunk.extend_from_slice(data);
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&chunk[4..]);
+    chunk.extend_from_slice(&hasher.finalize().to_be_bytes());
+    chunk
+}
+
+// averages each scale by scale block of RGB pixels into one, the blocks on
+// the right and bottom edges over the pixels they have
+fn downscale(pixel_data: &[u8], source_size: (usize, usize), scale: usize) -> Vec<u8> {
+    if scale == 1 {
+        return pixel_data.to_vec();
+    }
+    let (source_width, source_height) = source_size;
+    let (width, height) = (source_width.div_ceil(scale), source_height.div_ceil(scale));
+    let mut data = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0u32; 3];
+            let mut count = 0;
+            for sy in y * scale..((y + 1) * scale).min(source_height) {
+                for sx in x * scale..((x + 1) * scale).min(source_width) {
+                    let offset = (sy * source_width + sx) * 3;
+                    for (total, &value) in sum.iter_mut().zip(&pixel_data[offset..offset + 3]) {
+                        *total += value as u32;
+                    }
+                    count += 1;
+                }
+            }
+            data.extend(sum.iter().map(|&total| ((total + count / 2) / count.max(1)) as u8));
+        }
+    }
+    data
+}
+
+// puts the Sub filter before each row, which suits the smooth shading of
+// terrain better than none
+fn filter_rows(pixel_data: &[u8], width: usize) -> Vec<u8> {
+    let row_len = width * 3;
+    let mut data = Vec::with_capacity(pixel_data.len() + pixel_data.len() / row_len.max(1));
+    for row in pixel_data.chunks(row_len.max(1)) {
+        data.push(1);
+        data.extend_from_slice(&row[..3.min(row.len())]);
+        data.extend(row.iter().zip(row.iter().skip(3)).map(|(&left, &value)| value.wrapping_sub(left)));
+    }
+    data
+}
+
+// Picks up to 256 colors for a frame by median cut, splitting the box of
+// colors with the widest spread on one channel at the median pixel until
+// there are 256 boxes, and gives each pixel its box's average color.
+fn quantize(pixel_data: &[u8]) -> (Vec<[u8; 3]>, Vec<u8>) {
+    let mut counts: HashMap<[u8; 3], u32> = HashMap::new();
+    for pixel in pixel_data.chunks(3) {
+        *counts.entry([pixel[0], pixel[1], pixel[2]]).or_insert(0) += 1;
+    }
+    let mut boxes: Vec<Vec<([u8; 3], u32)>> = vec![counts.into_iter().collect()];
+    while boxes.len() < 256 {
+        let widest = boxes.iter()
+            .enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .map(|(box_index, colors)| {
+                let (channel, spread) = (0..3)
+                    .map(|channel| {
+                        let min = colors.iter().map(|(color, _)| color[channel]).min().unwrap();
+                        let max = colors.iter().map(|(color, _)| color[channel]).max().unwrap();
+                        (channel, max - min)
+                    })
+                    .max_by_key(|&(_, spread)| spread)
+                    .unwrap();
+                (box_index, channel, spread)
+            })
+            .max_by_key(|&(_, _, spread)| spread);
+        let (box_index, channel, _) = match widest {
+            Some(widest) => widest,
+            None => break,
+        };
+        let colors = &mut boxes[box_index];
+        colors.sort_unstable_by_key(|(color, _)| color[channel]);
+        let total: u32 = colors.iter().map(|(_, count)| count).sum();
+        let mut seen = 0;
+        let split = colors.iter()
+            .position(|(_, count)| {
+                seen += count;
+                seen * 2 >= total
+            })
+            .unwrap()
+            .min(colors.len() - 2);
+        let upper = colors.split_off(split + 1);
+        boxes.push(upper);
+    }
+
+    let mut palette = Vec::with_capacity(boxes.len());
+    let mut lookup = HashMap::new();
+    for (box_index, colors) in boxes.iter().enumerate() {
+        let mut sum = [0u64; 3];
+        let mut total = 0u64;
+        for (color, count) in colors.iter() {
+            for (channel_sum, &value) in sum.iter_mut().zip(color.iter()) {
+                *channel_sum += value as u64 * *count as u64;
+            }
+            total += *count as u64;
+            lookup.insert(*color, box_index as u8);
+        }
+        let total = total.max(1);
+        palette.push([(sum[0] / total) as u8, (sum[1] / total) as u8, (sum[2] / total) as u8]);
+    }
+    let indices = pixel_data.chunks(3).map(|pixel| lookup[&[pixel[0], pixel[1], pixel[2]]]).collect();
+    (palette, indices)
+}
+
+// GIF's variable width LZW with 8 bit symbols, starting over with a clear
+// code whenever the code table fills
+fn lzw_encode(indices: &[u8]) -> Vec<u8> {
+    let clear_code: u16 = 256;
+    let end_code: u16 = 257;
+    let mut bits = BitWriter::new();
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut code_size = 9;
+    let mut next_code = end_code + 1;
+    bits.write(clear_code, code_size);
+
+    let mut symbols = indices.iter();
+    let mut prefix = match symbols.next() {
+        Some(&symbol) => symbol as u16,
+        None => {
+            bits.write(end_code, code_size);
+            return bits.finish();
+        }
+    };
+    for &symbol in symbols {
+        if let Some(&code) = table.get(&(prefix, symbol)) {
+            prefix = code;
+            continue;
+        }
+        bits.write(prefix, code_size);
+        if next_code == 1 << code_size && code_size < 12 {
+            code_size += 1;
+        }
+        if next_code < GIF_MAX_CODES {
+            table.insert((prefix, symbol), next_code);
+            next_code += 1;
+        } else {
+            bits.write(clear_code, code_size);
+            table.clear();
+            code_size = 9;
+            next_code = end_code + 1;
+        }
+        prefix = symbol as u16;
+    }
+    bits.write(prefix, code_size);
+    if next_code == 1 << code_size && code_size < 12 {
+        code_size += 1;
+    }
+    bits.write(end_code, code_size);
+    bits.finish()
+}
+
+struct BitWriter {
+    data: Vec<u8>,
+    buffer: u32,
+    buffer_len: u32,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter { data: Vec::new(), buffer: 0, buffer_len: 0 }
+    }
+
+    // codes go in least significant bit first
+    fn write(&mut self, code: u16, size: u32) {
+        self.buffer |= (code as u32) << self.buffer_len;
+        self.buffer_len += size;
+        while self.buffer_len >= 8 {
+            self.data.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.buffer_len -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.buffer_len > 0 {
+            self.data.push(self.buffer as u8);
+        }
+        self.data
+    }
+}
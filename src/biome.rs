@@ -0,0 +1,163 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::climate::TEMPERATURE_LAYER;
+use crate::history::WETNESS_LAYER;
+use crate::render::{FrameTime, RGB, Shade};
+use crate::terrain::{Cell, Terrain};
+
+pub const MOISTURE_LAYER: &str = "moisture";
+
+const WATER_DEPTH: f64 = 0.1;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Biome {
+    Water,
+    Ice,
+    Tundra,
+    Taiga,
+    ColdDesert,
+    Grassland,
+    Shrubland,
+    TemperateForest,
+    Desert,
+    Savanna,
+    Rainforest,
+}
+
+// Classifies cells on a simplified Whittaker diagram from the temperature
+// layer and the moisture layer, the time-averaged water depth of the cell,
+// mapped onto [0, 1) by `moisture_scale`. When the wetness layer is tracked,
+// cells that are often wet count as at least that moist, so dry stream beds
+// keep their riparian vegetation.
+#[derive(Clone)]
+pub struct BiomeClassifier {
+    moisture_scale: f64,
+}
+
+// keeps an exponential moving average of each cell's water depth in the
+// moisture layer, forgetting old water over roughly `timescale`
+pub struct MoistureTracker {
+    timescale: f64,
+}
+
+pub struct BiomeShader {
+    classifier: BiomeClassifier,
+}
+
+impl Biome {
+    pub fn classify(temperature: f64, moisture: f64) -> Biome {
+        if temperature < -5.0 {
+            if moisture > 0.5 { Biome::Ice } else { Biome::Tundra }
+        } else if temperature < 3.0 {
+            Biome::Tundra
+        } else if temperature < 10.0 {
+            if moisture > 0.4 { Biome::Taiga } else if moisture > 0.15 { Biome::Grassland } else { Biome::ColdDesert }
+        } else if temperature < 20.0 {
+            if moisture > 0.5 {
+                Biome::TemperateForest
+            } else if moisture > 0.25 {
+                Biome::Grassland
+            } else if moisture > 0.1 {
+                Biome::Shrubland
+            } else {
+                Biome::Desert
+            }
+        } else if moisture > 0.6 {
+            Biome::Rainforest
+        } else if moisture > 0.2 {
+            Biome::Savanna
+        } else {
+            Biome::Desert
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Biome::Water => "water",
+            Biome::Ice => "ice",
+            Biome::Tundra => "tundra",
+            Biome::Taiga => "taiga",
+            Biome::ColdDesert => "cold_desert",
+            Biome::Grassland => "grassland",
+            Biome::Shrubland => "shrubland",
+            Biome::TemperateForest => "temperate_forest",
+            Biome::Desert => "desert",
+            Biome::Savanna => "savanna",
+            Biome::Rainforest => "rainforest",
+        }
+    }
+
+    pub fn color(&self) -> RGB {
+        let (r, g, b) = match self {
+            Biome::Water => (0.2, 0.4, 1.0),
+            Biome::Ice => (0.95, 0.95, 1.0),
+            Biome::Tundra => (0.6, 0.65, 0.6),
+            Biome::Taiga => (0.2, 0.4, 0.3),
+            Biome::ColdDesert => (0.7, 0.7, 0.6),
+            Biome::Grassland => (0.55, 0.75, 0.35),
+            Biome::Shrubland => (0.6, 0.6, 0.35),
+            Biome::TemperateForest => (0.15, 0.5, 0.2),
+            Biome::Desert => (0.9, 0.8, 0.5),
+            Biome::Savanna => (0.75, 0.7, 0.3),
+            Biome::Rainforest => (0.05, 0.4, 0.1),
+        };
+        RGB { r, g, b }
+    }
+}
+
+impl BiomeClassifier {
+    pub fn new(moisture_scale: f64) -> BiomeClassifier {
+        assert!(moisture_scale > 0.0);
+        BiomeClassifier { moisture_scale }
+    }
+
+    pub fn classify_cell(&self, cell: &Cell, terrain: &Terrain) -> Biome {
+        if cell.depth() > WATER_DEPTH {
+            return Biome::Water;
+        }
+        let temperature = terrain.layer(TEMPERATURE_LAYER).unwrap()[cell.index()];
+        let water = terrain.layer(MOISTURE_LAYER).map_or(0.0, |layer| layer[cell.index()]);
+        let mut moisture = 1.0 - (-water / self.moisture_scale).exp();
+        if let Some(wetness) = terrain.layer(WETNESS_LAYER) {
+            moisture = moisture.max(wetness[cell.index()]);
+        }
+        Biome::classify(temperature, moisture)
+    }
+
+    // writes one "index,x,y,biome" line per cell
+    pub fn write_labels(&self, terrain: &Terrain, path: &str) {
+        let mut w = BufWriter::new(File::create(path).unwrap());
+        writeln!(w, "index,x,y,biome").unwrap();
+        for cell in terrain.cells_iter() {
+            let biome = self.classify_cell(&cell, terrain);
+            writeln!(w, "{},{},{},{}", cell.index(), cell.x(), cell.y(), biome.name()).unwrap();
+        }
+    }
+}
+
+impl MoistureTracker {
+    pub fn new(timescale: f64) -> MoistureTracker {
+        MoistureTracker { timescale }
+    }
+
+    pub fn update(&self, terrain: &mut Terrain, time_step: f64) {
+        let depths: Vec<f64> = terrain.cells_iter().map(|cell| cell.depth()).collect();
+        let weight = time_step / (time_step + self.timescale);
+        for (moisture, depth) in terrain.layer_mut(MOISTURE_LAYER).iter_mut().zip(depths) {
+            *moisture += (depth - *moisture) * weight;
+        }
+    }
+}
+
+impl BiomeShader {
+    pub fn new(classifier: BiomeClassifier) -> BiomeShader {
+        BiomeShader { classifier }
+    }
+}
+
+impl Shade for BiomeShader {
+    fn shade_cell(&self, cell: &Cell, terrain: &Terrain, _time: FrameTime) -> RGB {
+        self.classifier.classify_cell(cell, terrain).color()
+    }
+}
@@ -0,0 +1,137 @@
+use crate::terrain::{Terrain, TerrainDelta};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BrushOp {
+    Raise,
+    Lower,
+    // pulls heights towards the mean of their neighbors, by up to
+    // `strength` of the difference (capped at all of it)
+    Smooth,
+    AddWater,
+}
+
+// how the brush weakens from its center (1) to its rim
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Falloff {
+    Constant,
+    Linear,
+    Smooth,
+}
+
+// A sculpting stroke over the cells within `radius` of (x, y). Raise, lower
+// and add water change height or depth by `strength` at the center.
+#[derive(Clone, Copy, Debug)]
+pub struct Brush {
+    pub op: BrushOp,
+    pub x: f64,
+    pub y: f64,
+    pub radius: f64,
+    pub strength: f64,
+    pub falloff: Falloff,
+}
+
+impl BrushOp {
+    pub fn parse(name: &str) -> Option<BrushOp> {
+        match name {
+            "raise" => Some(BrushOp::Raise),
+            "lower" => Some(BrushOp::Lower),
+            "smooth" => Some(BrushOp::Smooth),
+            "add_water" => Some(BrushOp::AddWater),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BrushOp::Raise => "raise",
+            BrushOp::Lower => "lower",
+            BrushOp::Smooth => "smooth",
+            BrushOp::AddWater => "add_water",
+        }
+    }
+}
+
+impl Falloff {
+    pub fn parse(name: &str) -> Option<Falloff> {
+        match name {
+            "constant" => Some(Falloff::Constant),
+            "linear" => Some(Falloff::Linear),
+            "smooth" => Some(Falloff::Smooth),
+            _ => None,
+        }
+    }
+
+    // weight at a distance from the center as a fraction of the radius
+    fn weight(&self, t: f64) -> f64 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Falloff::Constant => 1.0,
+            Falloff::Linear => 1.0 - t,
+            Falloff::Smooth => 1.0 - t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+impl Brush {
+    pub fn new(op: BrushOp, x: f64, y: f64, radius: f64, strength: f64, falloff: Falloff) -> Brush {
+        assert!(radius > 0.0);
+        assert!(strength >= 0.0);
+        Brush { op, x, y, radius, strength, falloff }
+    }
+
+    // returns the number of cells touched
+    pub fn apply(&self, terrain: &mut Terrain) -> usize {
+        let weights: Vec<(usize, f64)> = terrain.cells_iter()
+            .filter_map(|cell| {
+                let distance = (cell.x() - self.x).hypot(cell.y() - self.y);
+                if distance <= self.radius {
+                    Some((cell.index(), self.falloff.weight(distance / self.radius)))
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        let deltas: Vec<TerrainDelta> = weights.iter()
+            .map(|&(cell_index, weight)| {
+                let cell = terrain.get_cell(cell_index);
+                let amount = self.strength * weight;
+                let (height_delta, depth_delta) = match self.op {
+                    BrushOp::Raise => (amount, 0.0),
+                    BrushOp::Lower => (-amount, 0.0),
+                    BrushOp::Smooth if cell.neighbor_data_iter().count() == 0 => (0.0, 0.0),
+                    BrushOp::Smooth => {
+                        let count = cell.neighbor_data_iter().count();
+                        let mean = cell.neighbor_data_iter()
+                            .map(|nd| terrain.get_cell(nd.index()).height())
+                            .sum::<f64>() / count as f64;
+                        ((mean - cell.height()) * amount.min(1.0), 0.0)
+                    }
+                    BrushOp::AddWater => (0.0, amount),
+                };
+                TerrainDelta { cell_index, height_delta, depth_delta }
+            })
+            .collect();
+        terrain.apply_deltas(&deltas);
+        deltas.len()
+    }
+
+    // the stroke's settings and the number of cells it changed, as recorded
+    // in a run manifest
+    pub fn fields(&self, cells: usize) -> [(&'static str, f64); 5] {
+        [("x", self.x), ("y", self.y), ("radius", self.radius), ("strength", self.strength), ("cells", cells as f64)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lower_lowers_below_zero() {
+        let mut terrain = Terrain::square_grid(5.0, 5.0, 1.0, |_| -2.0, |_| 0.0);
+        let center = terrain.nearest_cell_index(2.5, 2.5).unwrap();
+        Brush::new(BrushOp::Lower, 2.5, 2.5, 0.5, 1.0, Falloff::Constant).apply(&mut terrain);
+        assert_eq!(terrain.get_cell(center).height(), -3.0);
+    }
+}
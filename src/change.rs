@@ -0,0 +1,143 @@
+use crate::raster;
+use crate::render::{shade_pixels, FrameTime, Image, Projection, RGB, Shade};
+use crate::terrain::{Cell, Terrain};
+
+pub const HEIGHT_CHANGE_LAYER: &str = "height_change";
+pub const DEPTH_CHANGE_LAYER: &str = "depth_change";
+pub const EROSION_LAYER: &str = "erosion";
+pub const DEPOSITION_LAYER: &str = "deposition";
+pub const NET_CHANGE_LAYER: &str = "net_change";
+pub const HEIGHT_RATE_LAYER: &str = "height_rate";
+
+// Records how far each cell's height and water depth moved since the
+// previous rendered frame, in the height change and depth change layers.
+pub struct FrameDifference {
+    previous: Vec<(f64, f64)>,
+}
+
+// Totals the height each cell has lost and gained over the whole run in the
+// erosion and deposition layers, and the difference in the net change
+// layer. Updated every step, so ground that is cut and later refilled
+// counts towards both totals; the height rate layer keeps the last step's
+// change per unit time.
+pub struct CumulativeChange {
+    previous_heights: Vec<f64>,
+}
+
+// A diverging heatmap of a change layer: black where nothing changed,
+// brightening towards `loss_color` or `gain_color` as the change approaches
+// `scale` in either direction.
+pub struct ChangeShader {
+    name: &'static str,
+    scale: f64,
+    loss_color: RGB,
+    gain_color: RGB,
+}
+
+impl FrameDifference {
+    pub fn new(terrain: &Terrain) -> FrameDifference {
+        FrameDifference { previous: surface(terrain) }
+    }
+
+    pub fn update(&mut self, terrain: &mut Terrain) {
+        let current = surface(terrain);
+        let height_changes = terrain.layer_mut(HEIGHT_CHANGE_LAYER);
+        for ((change, &(height, _)), &(previous, _)) in height_changes.iter_mut().zip(current.iter()).zip(self.previous.iter()) {
+            *change = height - previous;
+        }
+        let depth_changes = terrain.layer_mut(DEPTH_CHANGE_LAYER);
+        for ((change, &(_, depth)), &(_, previous)) in depth_changes.iter_mut().zip(current.iter()).zip(self.previous.iter()) {
+            *change = depth - previous;
+        }
+        self.previous = current;
+    }
+}
+
+impl CumulativeChange {
+    pub fn new(terrain: &Terrain) -> CumulativeChange {
+        CumulativeChange { previous_heights: terrain.cells_iter().map(|cell| cell.height()).collect() }
+    }
+
+    pub fn update(&mut self, terrain: &mut Terrain, time_step: f64) {
+        let heights: Vec<f64> = terrain.cells_iter().map(|cell| cell.height()).collect();
+        let erosion = terrain.layer_mut(EROSION_LAYER);
+        for ((total, height), previous) in erosion.iter_mut().zip(heights.iter()).zip(self.previous_heights.iter()) {
+            *total += (previous - height).max(0.0);
+        }
+        let deposition = terrain.layer_mut(DEPOSITION_LAYER);
+        for ((total, height), previous) in deposition.iter_mut().zip(heights.iter()).zip(self.previous_heights.iter()) {
+            *total += (height - previous).max(0.0);
+        }
+        let net_change = terrain.layer_mut(NET_CHANGE_LAYER);
+        for ((total, height), previous) in net_change.iter_mut().zip(heights.iter()).zip(self.previous_heights.iter()) {
+            *total += height - previous;
+        }
+        let rates = terrain.layer_mut(HEIGHT_RATE_LAYER);
+        for ((rate, height), previous) in rates.iter_mut().zip(heights.iter()).zip(self.previous_heights.iter()) {
+            *rate = (height - previous) / time_step;
+        }
+        self.previous_heights = heights;
+    }
+
+    // writes erosion_total and deposition_total as .png heatmaps saturating at
+    // the largest total and as .asc rasters of unit pixels
+    pub fn export(&self, terrain: &Terrain, time: FrameTime, world_size: (usize, usize), projection: Projection, path: &str) {
+        let (width, height) = world_size;
+        let maps = [
+            (EROSION_LAYER, RGB { r: 0.2, g: 0.4, b: 1.0 }),
+            (DEPOSITION_LAYER, RGB { r: 1.0, g: 0.2, b: 0.1 }),
+        ];
+        for (name, color) in maps.iter() {
+            let zeros = vec![0.0; terrain.cells_len()];
+            let totals = terrain.layer(name).unwrap_or(&zeros);
+            let max_total = totals.iter().cloned().fold(0.0, f64::max);
+            let shader = ChangeShader::new(name, max_total.max(f64::MIN_POSITIVE), RGB { r: 0.0, g: 0.0, b: 0.0 }, color.clone());
+            let pixels = shade_pixels(&shader, terrain, time, projection, world_size, world_size);
+            Image { width, height, pixels }.save(&format!("{}/{}_total.png", path, name));
+
+            let grid = raster::sample_grid(terrain, totals, width, height);
+            raster::write_ascii_grid(&format!("{}/{}_total.asc", path, name), width, height, &grid);
+        }
+    }
+}
+
+impl ChangeShader {
+    // erosion in blue and deposition in red
+    pub fn height(scale: f64) -> ChangeShader {
+        ChangeShader::new(HEIGHT_CHANGE_LAYER, scale, RGB { r: 0.2, g: 0.4, b: 1.0 }, RGB { r: 1.0, g: 0.2, b: 0.1 })
+    }
+
+    // draining in amber and filling in cyan
+    pub fn depth(scale: f64) -> ChangeShader {
+        ChangeShader::new(DEPTH_CHANGE_LAYER, scale, RGB { r: 1.0, g: 0.7, b: 0.1 }, RGB { r: 0.1, g: 0.9, b: 1.0 })
+    }
+
+    // the last step's erosion in blue and deposition in red, per unit time
+    pub fn height_rate(scale: f64) -> ChangeShader {
+        ChangeShader::new(HEIGHT_RATE_LAYER, scale, RGB { r: 0.2, g: 0.4, b: 1.0 }, RGB { r: 1.0, g: 0.2, b: 0.1 })
+    }
+
+    // the run's erosion in blue and deposition in red, net of each other
+    pub fn net_change(scale: f64) -> ChangeShader {
+        ChangeShader::new(NET_CHANGE_LAYER, scale, RGB { r: 0.2, g: 0.4, b: 1.0 }, RGB { r: 1.0, g: 0.2, b: 0.1 })
+    }
+
+    pub fn new(name: &'static str, scale: f64, loss_color: RGB, gain_color: RGB) -> ChangeShader {
+        assert!(scale > 0.0);
+        ChangeShader { name, scale, loss_color, gain_color }
+    }
+}
+
+impl Shade for ChangeShader {
+    fn shade_cell(&self, cell: &Cell, terrain: &Terrain, _time: FrameTime) -> RGB {
+        let change = terrain.layer(self.name).map_or(0.0, |layer| layer[cell.index()]);
+        let v = (change / self.scale).clamp(-1.0, 1.0);
+        let color = if v < 0.0 { &self.loss_color } else { &self.gain_color };
+        let v = v.abs().sqrt();
+        RGB { r: color.r * v, g: color.g * v, b: color.b * v }
+    }
+}
+
+fn surface(terrain: &Terrain) -> Vec<(f64, f64)> {
+    terrain.cells_iter().map(|cell| (cell.height(), cell.depth())).collect()
+}
@@ -0,0 +1,235 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use crate::biome::MOISTURE_LAYER;
+use crate::change::{DEPOSITION_LAYER, DEPTH_CHANGE_LAYER, EROSION_LAYER, HEIGHT_CHANGE_LAYER, HEIGHT_RATE_LAYER, NET_CHANGE_LAYER};
+use crate::climate::TEMPERATURE_LAYER;
+use crate::default_flow::{FLUX_X_LAYER, FLUX_Y_LAYER, SUSPENDED_LOAD_LAYER};
+use crate::flow::DISCHARGE_LAYER;
+use crate::groundwater::{CONDUCTIVITY_LAYER, GROUNDWATER_LAYER, SPRING_LAYER};
+use crate::history::{EXPOSURE_AGE_LAYER, WETNESS_LAYER};
+use crate::humidity::{HUMIDITY_LAYER, RAINFALL_LAYER};
+use crate::karst::SOLUBILITY_LAYER;
+use crate::pipe_model::PIPE_SEDIMENT_LAYER;
+use crate::point::Point;
+use crate::precipitation::PRECIPITATION_MAP_LAYER;
+use crate::salinity::SALINITY_LAYER;
+use crate::sediment::{SUBSTRATE_LAYERS, SUSPENDED_LAYERS};
+use crate::shallow_water::{VELOCITY_X_LAYER, VELOCITY_Y_LAYER};
+use crate::terrain::{NeighborData, Terrain, Topology};
+use crate::tracer::TRACER_LAYER;
+
+const MAGIC: &[u8; 8] = b"TFCKPT02";
+
+// every layer a run keeps, so a checkpoint's layers are named by the same
+// constants rather than names read from the file
+const LAYER_NAMES: &[&str] = &[
+    CONDUCTIVITY_LAYER, DEPOSITION_LAYER, DEPTH_CHANGE_LAYER, DISCHARGE_LAYER, EROSION_LAYER,
+    EXPOSURE_AGE_LAYER, FLUX_X_LAYER, FLUX_Y_LAYER, GROUNDWATER_LAYER, HEIGHT_CHANGE_LAYER,
+    HEIGHT_RATE_LAYER, HUMIDITY_LAYER, MOISTURE_LAYER, NET_CHANGE_LAYER, PIPE_SEDIMENT_LAYER,
+    PRECIPITATION_MAP_LAYER, RAINFALL_LAYER, SALINITY_LAYER, SOLUBILITY_LAYER, SPRING_LAYER,
+    SUBSTRATE_LAYERS[0], SUBSTRATE_LAYERS[1], SUBSTRATE_LAYERS[2], SUSPENDED_LAYERS[0],
+    SUSPENDED_LAYERS[1], SUSPENDED_LAYERS[2], SUSPENDED_LOAD_LAYER, TEMPERATURE_LAYER,
+    TRACER_LAYER, VELOCITY_X_LAYER, VELOCITY_Y_LAYER, WETNESS_LAYER,
+];
+
+// longer than any of the layer names, so a corrupt length is caught before
+// anything is allocated for it
+const MAX_LAYER_NAME_LEN: usize = 64;
+
+// A run's state between frames: the whole terrain, with every cell's
+// neighbors and the attribute layers, the flow's random state and how far
+// the run had got, along with the settings the terrain and random numbers
+// came from, which a resumed run must share. Processes keeping state of
+// their own outside the terrain and the flow, such as tracer particles,
+// start afresh from a checkpoint.
+//
+// Stored little endian as the magic bytes, the world's width and height
+// (u32), whether it's spherical (u8), its topology (u8: 0 for delaunay, 1
+// for square and 2 for hex), whether the run is seeded (u8) and the seed
+// (u64), the frame (u32), step count (u64) and time (f64), the length of
+// the flow's random state (u64) and the state, then the cell count (u64)
+// and per cell its x, y, height and depth (f64) and neighbor count (u32)
+// followed by each neighbor's index (u64) and distance (f64), then the
+// layer count (u32) and per layer its name's length (u32), the name and a
+// value (f64) per cell.
+pub struct Checkpoint {
+    pub origin: Origin,
+    pub frame: u32,
+    pub steps: u64,
+    pub time: f64,
+    pub flow_state: Vec<u8>,
+    pub terrain: Terrain,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct Origin {
+    pub world_size: (usize, usize),
+    pub spherical: bool,
+    pub topology: Topology,
+    pub seed: Option<u64>,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a {}x{}", self.world_size.0, self.world_size.1)?;
+        if self.spherical {
+            write!(f, " spherical")?;
+        }
+        write!(f, " {} world", self.topology.name())?;
+        match self.seed {
+            Some(seed) => write!(f, " with seed {}", seed),
+            None => write!(f, " without a seed"),
+        }
+    }
+}
+
+// writes a checkpoint aside and moves it into place, so an interrupted write
+// never replaces a good checkpoint with a partial one
+pub fn write(path: &str, origin: &Origin, frame: u32, steps: u64, time: f64, flow_state: &[u8], terrain: &Terrain) {
+    let partial_path = format!("{}.partial", path);
+    let mut writer = BufWriter::new(File::create(&partial_path).unwrap());
+    let mut put = |bytes: &[u8]| writer.write_all(bytes).unwrap();
+    put(MAGIC);
+    put(&(origin.world_size.0 as u32).to_le_bytes());
+    put(&(origin.world_size.1 as u32).to_le_bytes());
+    put(&[origin.spherical as u8]);
+    put(&[match origin.topology {
+        Topology::Delaunay => 0,
+        Topology::SquareGrid => 1,
+        Topology::HexGrid => 2,
+    }]);
+    put(&[origin.seed.is_some() as u8]);
+    put(&origin.seed.unwrap_or(0).to_le_bytes());
+    put(&frame.to_le_bytes());
+    put(&steps.to_le_bytes());
+    put(&time.to_le_bytes());
+    put(&(flow_state.len() as u64).to_le_bytes());
+    put(flow_state);
+    put(&(terrain.cells_len() as u64).to_le_bytes());
+    for cell in terrain.cells_iter() {
+        for value in [cell.x(), cell.y(), cell.height(), cell.depth()].iter() {
+            put(&value.to_le_bytes());
+        }
+        put(&(cell.neighbor_data_iter().count() as u32).to_le_bytes());
+        for nd in cell.neighbor_data_iter() {
+            put(&(nd.index() as u64).to_le_bytes());
+            put(&nd.distance().to_le_bytes());
+        }
+    }
+    let layers: Vec<(&str, &[f64])> = terrain.layers_iter().collect();
+    put(&(layers.len() as u32).to_le_bytes());
+    for (name, values) in layers {
+        put(&(name.len() as u32).to_le_bytes());
+        put(name.as_bytes());
+        for value in values {
+            put(&value.to_le_bytes());
+        }
+    }
+    writer.into_inner().map_err(|e| e.into_error()).unwrap().sync_all().unwrap();
+    fs::rename(&partial_path, path).unwrap();
+}
+
+pub fn read(path: &str) -> Result<Checkpoint, String> {
+    let file = File::open(path).map_err(|e| format!("checkpoint {}: {}", path, e))?;
+    read_from(&mut BufReader::new(file)).map_err(|e| format!("checkpoint {}: {}", path, e))
+}
+
+fn read_from(reader: &mut impl Read) -> Result<Checkpoint, String> {
+    let mut magic = [0; 8];
+    read_bytes(reader, &mut magic)?;
+    if &magic != MAGIC {
+        return Err(String::from("not a checkpoint file"));
+    }
+    let world_size = (read_u32(reader)? as usize, read_u32(reader)? as usize);
+    let spherical = read_u8(reader)? != 0;
+    let topology = match read_u8(reader)? {
+        0 => Topology::Delaunay,
+        1 => Topology::SquareGrid,
+        2 => Topology::HexGrid,
+        other => return Err(format!("unknown topology {}", other)),
+    };
+    let seeded = read_u8(reader)? != 0;
+    let seed = read_u64(reader)?;
+    let origin = Origin { world_size, spherical, topology, seed: if seeded { Some(seed) } else { None } };
+    let frame = read_u32(reader)?;
+    let steps = read_u64(reader)?;
+    let time = read_f64(reader)?;
+    let flow_state_len = read_u64(reader)?;
+    let mut flow_state = Vec::new();
+    reader.by_ref().take(flow_state_len).read_to_end(&mut flow_state).map_err(|e| e.to_string())?;
+    if flow_state.len() as u64 != flow_state_len {
+        return Err(String::from("truncated: the flow's random state"));
+    }
+
+    let cell_count = read_u64(reader)? as usize;
+    let mut cells = Vec::new();
+    for index in 0..cell_count {
+        let location = Point { x: read_f64(reader)?, y: read_f64(reader)? };
+        let height = read_f64(reader)?;
+        let depth = read_f64(reader)?;
+        let neighbor_count = read_u32(reader)?;
+        let mut neighbor_data = Vec::new();
+        for _ in 0..neighbor_count {
+            let neighbor = read_u64(reader)? as usize;
+            if neighbor >= cell_count {
+                return Err(format!("cell {} has neighbor {} of {} cells", index, neighbor, cell_count));
+            }
+            neighbor_data.push(NeighborData::new(neighbor, read_f64(reader)?));
+        }
+        cells.push((location, height, depth, neighbor_data));
+    }
+
+    let layer_count = read_u32(reader)?;
+    let mut layers = Vec::new();
+    for _ in 0..layer_count {
+        let name_len = read_u32(reader)? as usize;
+        if name_len > MAX_LAYER_NAME_LEN {
+            return Err(format!("layer name of {} bytes", name_len));
+        }
+        let mut name = vec![0; name_len];
+        read_bytes(reader, &mut name)?;
+        let name = LAYER_NAMES.iter()
+            .find(|known| known.as_bytes() == &name[..])
+            .ok_or_else(|| format!("unknown layer {}", String::from_utf8_lossy(&name)))?;
+        let values = (0..cell_count).map(|_| read_f64(reader)).collect::<Result<Vec<f64>, String>>()?;
+        layers.push((*name, values));
+    }
+
+    if reader.read(&mut [0]).map_err(|e| e.to_string())? != 0 {
+        return Err(String::from("unexpected data after the last layer"));
+    }
+    Ok(Checkpoint { origin, frame, steps, time, flow_state, terrain: Terrain::from_parts(cells, layers) })
+}
+
+// readers of the little endian values checkpoints and the states they
+// carry are made of
+
+pub fn read_bytes(reader: &mut impl Read, buffer: &mut [u8]) -> Result<(), String> {
+    reader.read_exact(buffer).map_err(|e| format!("truncated: {}", e))
+}
+
+fn read_u8(reader: &mut impl Read) -> Result<u8, String> {
+    let mut bytes = [0; 1];
+    read_bytes(reader, &mut bytes)?;
+    Ok(bytes[0])
+}
+
+pub fn read_u32(reader: &mut impl Read) -> Result<u32, String> {
+    let mut bytes = [0; 4];
+    read_bytes(reader, &mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub fn read_u64(reader: &mut impl Read) -> Result<u64, String> {
+    let mut bytes = [0; 8];
+    read_bytes(reader, &mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub fn read_f64(reader: &mut impl Read) -> Result<f64, String> {
+    let mut bytes = [0; 8];
+    read_bytes(reader, &mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
@@ -0,0 +1,1623 @@
+use std::path::Path;
+use std::process;
+
+use terrain_flow::active_cells::ActiveCells;
+use terrain_flow::animation::AnimationFormat;
+use terrain_flow::biome::BiomeClassifier;
+use terrain_flow::climate::Climate;
+use terrain_flow::default_flow::{DrainPolicy, ErodedRouting, SuspendedTransport};
+use terrain_flow::export::{self, MeshFormat};
+use terrain_flow::flood::FloodEvent;
+use terrain_flow::flow::AdaptiveStep;
+use terrain_flow::flow_shader::STREAM_FLUX;
+use terrain_flow::goal::{Goal, Metric};
+use terrain_flow::groundwater::Groundwater;
+use terrain_flow::humidity::HumidityModel;
+use terrain_flow::karst::{Karst, Stratum};
+use terrain_flow::precipitation::{Precipitation, PrecipitationMap};
+use terrain_flow::render::{Blend, ColorRamp, FrameFormat, MeshOverlay, Output, Raster, Viewport};
+use terrain_flow::run::FrameShader;
+use terrain_flow::salinity::Salinity;
+use terrain_flow::schedule::{Interpolation, ParameterSchedule};
+use terrain_flow::sediment::{SedimentModel, DEFAULT_GRAIN_CLASSES};
+use terrain_flow::snapshot;
+use terrain_flow::stream_power::{ErosionMode, StreamPower};
+use terrain_flow::height_source::DemScale;
+use terrain_flow::terrain_gen::NoiseParams;
+use terrain_flow::terrain_preset::InitialTerrain;
+use terrain_flow::tracer::TracerRegion;
+use terrain_flow::water_shader::CLEAR_WATER_ABSORPTION;
+use terrain_flow::view3d::{OrbitCamera, Stereo};
+use terrain_flow::{Projection, RunnerBuilder, Topology, RGB};
+
+type Apply = for<'a> fn(&mut RunnerBuilder<'a>, &'a str) -> Result<(), String>;
+
+// One command line option, given as --name VALUE or --name=VALUE, or as
+// --name alone when it takes no value. Options without a default are off
+// unless given; repeatable ones add to each other, and otherwise the last
+// one given wins.
+struct CliOption {
+    name: &'static str,
+    value: &'static str,
+    default: Option<&'static str>,
+    repeatable: bool,
+    help: &'static str,
+    apply: Apply,
+}
+
+const OPTIONS: &[CliOption] = &[
+    CliOption {
+        name: "width",
+        value: "N",
+        default: Some("1280"),
+        repeatable: false,
+        help: "world and image width in pixels, at least 1",
+        apply: |builder, value| {
+            builder.width(at_least_one(count(value)?, "the width")?);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "height",
+        value: "N",
+        default: Some("720"),
+        repeatable: false,
+        help: "world and image height in pixels, at least 1",
+        apply: |builder, value| {
+            builder.height(at_least_one(count(value)?, "the height")?);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "density",
+        value: "N",
+        default: Some("2"),
+        repeatable: false,
+        help: "cells per pixel along each side, at least 1",
+        apply: |builder, value| {
+            builder.density(at_least_one(count(value)?, "the density")?);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "max-z",
+        value: "Z",
+        default: Some("36"),
+        repeatable: false,
+        help: "height of the initial terrain's peak",
+        apply: |builder, value| {
+            builder.max_z(number(value)?);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "initial-terrain",
+        value: "dome|random:SEED|heightmap:PNG|noise:SEED[,OCTAVES,LACUNARITY,PERSISTENCE,SCALE]|geotiff:TIF[,Z_SCALE,Z_OFFSET]",
+        default: Some("dome"),
+        repeatable: false,
+        help: "the starting landform; a heightmap is a grayscale png stretched over the world, white at max-z, \
+            noise is fractal simplex noise, by default 6 octaves, lacunarity 2, persistence 0.5 \
+            and features 200 across, and a geotiff elevation model is stretched over the world \
+            likewise, scaled to fit under max-z or to elevation * Z_SCALE + Z_OFFSET",
+        apply: |builder, value| {
+            let initial_terrain = match split_kind(value) {
+                ("dome", "") => InitialTerrain::Dome,
+                ("random", seed) => InitialTerrain::Random(count(seed)?),
+                ("heightmap", path) if !path.is_empty() => InitialTerrain::Heightmap(path),
+                ("noise", args) => {
+                    let (seed, rest) = args.split_once(',').unwrap_or((args, ""));
+                    let mut params = NoiseParams::new(count(seed)?);
+                    if !rest.is_empty() {
+                        let n = numbers(rest, 4)?;
+                        if !(1.0..=16.0).contains(&n[0]) || n[1] <= 1.0 || !(n[2] > 0.0 && n[2] < 1.0) || n[3] <= 0.0 {
+                            return Err(String::from("noise needs 1 to 16 octaves, lacunarity above 1, \
+                                persistence between 0 and 1 and scale above 0"));
+                        }
+                        params.octaves = whole(n[0])?;
+                        params.lacunarity = n[1];
+                        params.persistence = n[2];
+                        params.scale = n[3];
+                    }
+                    InitialTerrain::Noise(params)
+                }
+                ("geotiff", args) => {
+                    // the path may itself hold commas, so the scale is only
+                    // taken from two trailing numbers
+                    let mut parts = args.rsplitn(3, ',');
+                    let (offset, scale, path) = (parts.next().unwrap(), parts.next(), parts.next());
+                    match (path, scale.map(number), number(offset)) {
+                        (Some(path), Some(Ok(scale)), Ok(offset)) => InitialTerrain::GeoTiff(path, DemScale::Linear { scale, offset }),
+                        _ if !args.is_empty() => InitialTerrain::GeoTiff(args, DemScale::Fit),
+                        _ => return Err(String::from("geotiff needs a file")),
+                    }
+                }
+                _ => return Err(format!("unknown initial terrain {}", value)),
+            };
+            builder.initial_terrain(initial_terrain);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "spherical",
+        value: "",
+        default: None,
+        repeatable: false,
+        help: "wrap the world onto a sphere whose equator is the width long",
+        apply: |builder, _| {
+            builder.spherical(true);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "topology",
+        value: "delaunay|square|hex",
+        default: Some("delaunay"),
+        repeatable: false,
+        help: "how cells are laid out over a flat world",
+        apply: |builder, value| {
+            let topology = match value {
+                "delaunay" => Topology::Delaunay,
+                "square" => Topology::SquareGrid,
+                "hex" => Topology::HexGrid,
+                _ => return Err(format!("unknown topology {}", value)),
+            };
+            builder.topology(topology);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "projection",
+        value: "equirectangular|orthographic:LON,LAT",
+        default: Some("equirectangular"),
+        repeatable: false,
+        help: "map projection; orthographic suits spherical worlds, LAT in -90..=90",
+        apply: |builder, value| {
+            let projection = match split_kind(value) {
+                ("equirectangular", "") => Projection::Equirectangular,
+                ("orthographic", args) => {
+                    let n = numbers(args, 2)?;
+                    if !(-90.0..=90.0).contains(&n[1]) {
+                        return Err("the latitude must be from -90 to 90".to_string());
+                    }
+                    Projection::Orthographic { center_lon: n[0], center_lat: n[1] }
+                }
+                _ => return Err(format!("unknown projection {}", value)),
+            };
+            builder.projection(projection);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "raster",
+        value: "splat|triangles|voronoi",
+        default: Some("splat"),
+        repeatable: false,
+        help: "draw each cell as a blurred point, fill each triangle of neighboring cells with their colors \
+            blended, which leaves no gaps when cells are sparser than pixels, or color each pixel as its nearest cell",
+        apply: |builder, value| {
+            builder.raster(match value {
+                "splat" => Raster::Splat,
+                "triangles" => Raster::Triangles,
+                "voronoi" => Raster::Voronoi,
+                _ => return Err(format!("unknown raster {}", value)),
+            });
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "shader",
+        value: "default|hypsometric[:ELEVATION=RRGGBB,...]|water[:R,G,B]|flow[:SCALE]|relief|erosion-rate[:SCALE]|net-change[:SCALE]",
+        default: Some("default"),
+        repeatable: false,
+        help: "draw frames with hillshading, or tint them by elevation above the datum through a ramp of colors \
+            given at elevations in increasing order, by default from blue below the datum to white at max-z, \
+            or hillshading under water absorbing R, G and B light by this much per unit depth, by default 6,2.5,1.2, \
+            or hillshading under the flow's direction as hue and strength as brightness, about two thirds full \
+            at a flux of SCALE, by default 0.001, or the hillshading's lighting alone in gray, 1 on flat ground, \
+            or heatmaps of erosion in blue and deposition in red, per unit time over the last step saturating at SCALE, \
+            by default 0.05, or over the whole run saturating at SCALE, by default 1",
+        apply: |builder, value| {
+            builder.frame_shader(frame_shader(value)?);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "layer",
+        value: "alpha|multiply|overlay,OPACITY,SHADER",
+        default: None,
+        repeatable: true,
+        help: "layer a shader, as for --shader, over the frames and any layers before it, covering, multiplying or \
+            overlaying their colors and mixed over them by OPACITY from 0 to 1; multiply,1,relief hillshades \
+            a hypsometric tint",
+        apply: |builder, value| {
+            let mut parts = value.splitn(3, ',');
+            let blend = match parts.next().unwrap() {
+                "alpha" => Blend::Alpha,
+                "multiply" => Blend::Multiply,
+                "overlay" => Blend::Overlay,
+                blend => return Err(format!("unknown blend {}", blend)),
+            };
+            let opacity = number(parts.next().ok_or("expected BLEND,OPACITY,SHADER")?)?;
+            if !(0.0..=1.0).contains(&opacity) {
+                return Err("layer opacity must be from 0 to 1".to_string());
+            }
+            let shader = frame_shader(parts.next().ok_or("expected BLEND,OPACITY,SHADER")?)?;
+            builder.shader_layer(shader, blend, opacity);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "contours",
+        value: "INTERVAL",
+        default: None,
+        repeatable: false,
+        help: "draw contour lines every INTERVAL of elevation above the datum over the frames, above 0",
+        apply: |builder, value| {
+            let interval = number(value)?;
+            if interval <= 0.0 {
+                return Err("the contour interval must be above 0".to_string());
+            }
+            builder.contours(interval);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "viewport",
+        value: "X,Y,WIDTH,HEIGHT",
+        default: None,
+        repeatable: false,
+        help: "render only the part of the world from X,Y up and right, in world units, stretched over the frame; \
+            equirectangular projection only",
+        apply: |builder, value| {
+            let n = numbers(value, 4)?;
+            if n[2] <= 0.0 || n[3] <= 0.0 {
+                return Err("the viewport must have a positive width and height".to_string());
+            }
+            builder.viewport(Viewport { x: n[0], y: n[1], width: n[2], height: n[3] });
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "mesh-overlay",
+        value: "edges|distances",
+        default: None,
+        repeatable: false,
+        help: "draw the edges between neighboring cells and the cells' points over the frames, to check the mesh, \
+            the edges colored from blue through white at the mean distance between neighbors to red at twice it \
+            for distances",
+        apply: |builder, value| {
+            let distances = match value {
+                "edges" => false,
+                "distances" => true,
+                _ => return Err(format!("unknown mesh overlay {}", value)),
+            };
+            builder.mesh_overlay(MeshOverlay { distances });
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "vertical-scale",
+        value: "DATUM,EXAGGERATION",
+        default: Some("0,1"),
+        repeatable: false,
+        help: "sea level for shaders and zero for heightmap exports, and relief stretch, above 0",
+        apply: |builder, value| {
+            let n = numbers(value, 2)?;
+            builder.vertical_scale(n[0], above_zero(n[1], "the exaggeration")?);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "flow-rate",
+        value: "RATE",
+        default: Some("0.9"),
+        repeatable: false,
+        help: "fraction of the water surface difference that flows per unit time",
+        apply: |builder, value| {
+            builder.flow_rate(number(value)?);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "flow-erosion-rate",
+        value: "RATE",
+        default: Some("1"),
+        repeatable: false,
+        help: "ground carried off per unit of flowing water",
+        apply: |builder, value| {
+            builder.flow_erosion_rate(number(value)?);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "erosion-threshold",
+        value: "SLOPE",
+        default: Some("0.2"),
+        repeatable: false,
+        help: "slope above which ground slumps downhill",
+        apply: |builder, value| {
+            builder.erosion_threshold(number(value)?);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "erosion-rate",
+        value: "RATE",
+        default: Some("0.5"),
+        repeatable: false,
+        help: "rate at which ground above the threshold slumps",
+        apply: |builder, value| {
+            builder.erosion_rate(number(value)?);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "precipitation-rate",
+        value: "P",
+        default: Some("0.001"),
+        repeatable: false,
+        help: "chance of rain on each cell each step, in (0, 1)",
+        apply: |builder, value| {
+            let rate = number(value)?;
+            if !(rate > 0.0 && rate < 1.0) {
+                return Err("the precipitation rate must be between 0 and 1".to_string());
+            }
+            builder.precipitation_rate(rate);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "precipitation-amount",
+        value: "DEPTH",
+        default: Some("0.01"),
+        repeatable: false,
+        help: "depth of each rainfall",
+        apply: |builder, value| {
+            builder.precipitation_amount(number(value)?);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "precipitation",
+        value: "DISTRIBUTION",
+        default: None,
+        repeatable: false,
+        help: "rain drawn from bernoulli:RATE,AMOUNT, drizzle:AMOUNT, \
+            bursts:MEAN_INTERVAL,DURATION,AMOUNT or gamma:RATE,SHAPE,SCALE, \
+            in place of the rate and amount",
+        apply: |builder, value| {
+            let precipitation = match split_kind(value) {
+                ("bernoulli", args) => {
+                    let n = numbers(args, 2)?;
+                    Precipitation::Bernoulli { rate: n[0], amount: n[1] }
+                }
+                ("drizzle", args) => Precipitation::Drizzle { amount: number(args)? },
+                ("bursts", args) => {
+                    let n = numbers(args, 3)?;
+                    Precipitation::Bursts { mean_interval: n[0], duration: whole(n[1])?, amount: n[2] }
+                }
+                ("gamma", args) => {
+                    let n = numbers(args, 3)?;
+                    Precipitation::Gamma { rate: n[0], shape: n[1], scale: n[2] }
+                }
+                _ => return Err(format!("unknown precipitation {}", value)),
+            };
+            if !precipitation.is_valid() {
+                return Err(format!("{} precipitation out of range", precipitation.name()));
+            }
+            builder.precipitation(precipitation);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "precipitation-map",
+        value: "PNG[,MAX_INTENSITY]",
+        default: None,
+        repeatable: false,
+        help: "scale the rain by a grayscale png stretched over the world, none at black \
+            and MAX_INTENSITY, by default 1, at white",
+        apply: |builder, value| {
+            // the path may itself hold commas, so the intensity is only taken
+            // from a trailing number
+            let map = match value.rsplit_once(',').map(|(path, intensity)| (path, number(intensity))) {
+                Some((path, Ok(max_intensity))) if !path.is_empty() => {
+                    PrecipitationMap::Raster(path, not_negative(max_intensity, "the max intensity")?)
+                }
+                _ if !value.is_empty() => PrecipitationMap::Raster(value, 1.0),
+                _ => return Err(String::from("precipitation map needs a file")),
+            };
+            builder.precipitation_map(map);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "drain-policy",
+        value: "FLOOR_HEIGHT,FLOOR_RATE,MAX_DEPTH,DRAIN_RATE|off",
+        default: Some("0,0.5,1,0.5"),
+        repeatable: false,
+        help: "ground topped up below the floor height and water drained above the max depth, rates in [0, 1]",
+        apply: |builder, value| {
+            let drain_policy = if value == "off" {
+                DrainPolicy::disabled()
+            } else {
+                let n = numbers(value, 4)?;
+                DrainPolicy::new(n[0], not_negative(n[1], "the floor rate")?, n[2], not_negative(n[3], "the drain rate")?)
+            };
+            builder.drain_policy(drain_policy);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "eroded-routing",
+        value: "DELIVERED,SUSPENDED",
+        default: Some("1,0"),
+        repeatable: false,
+        help: "fractions of eroded ground deposited downstream and held in suspension, summing to at most 1",
+        apply: |builder, value| {
+            let n = numbers(value, 2)?;
+            if n[0] < 0.0 || n[1] < 0.0 || n[0] + n[1] > 1.0 {
+                return Err("eroded routing fractions can't be negative or sum to more than 1".to_string());
+            }
+            builder.eroded_routing(ErodedRouting::new(n[0], n[1]));
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "suspended-transport",
+        value: "CAPACITY,SETTLING_RATE",
+        default: None,
+        repeatable: false,
+        help: "carry suspended ground downstream, settling the excess over capacity * outflow * slope; \
+            needs a suspended share of eroded routing",
+        apply: |builder, value| {
+            let n = numbers(value, 2)?;
+            if !(n[1] > 0.0 && n[1] <= 1.0) {
+                return Err("the settling rate must be above 0 and at most 1".to_string());
+            }
+            builder.suspended_transport(SuspendedTransport::new(not_negative(n[0], "the capacity")?, n[1]));
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "render-step",
+        value: "DT",
+        default: Some("1"),
+        repeatable: false,
+        help: "simulated time per step, above 0",
+        apply: |builder, value| {
+            builder.render_step(above_zero(number(value)?, "the render step")?);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "frame-skip",
+        value: "N",
+        default: Some("30"),
+        repeatable: false,
+        help: "steps per rendered frame, at least 1",
+        apply: |builder, value| {
+            builder.frame_skip(at_least_one(count(value)?, "the frame skip")?);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "frame-count",
+        value: "N",
+        default: Some("20000"),
+        repeatable: false,
+        help: "frames to render, at least 1",
+        apply: |builder, value| {
+            builder.frame_count(at_least_one(count(value)?, "the frame count")?);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "interpolated-frames",
+        value: "N",
+        default: Some("0"),
+        repeatable: false,
+        help: "blended images between rendered frames",
+        apply: |builder, value| {
+            builder.interpolated_frames(count(value)?);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "data-path",
+        value: "DIR",
+        default: Some("./point_data"),
+        repeatable: false,
+        help: "existing directory caching generated points",
+        apply: |builder, value| {
+            builder.data_path(directory(value)?);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "render-path",
+        value: "DIR",
+        default: Some("./render"),
+        repeatable: false,
+        help: "existing directory for frames and other output",
+        apply: |builder, value| {
+            builder.render_path(directory(value)?);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "schedule",
+        value: "PARAM:step|linear|smooth:T,V[,T,V...]",
+        default: None,
+        repeatable: true,
+        help: "drive a flow or humidity parameter or render_step through keyframes in increasing time",
+        apply: |builder, value| {
+            let (name, rest) = split_kind(value);
+            let interpolation = match split_kind(rest) {
+                ("step", _) => Interpolation::Step,
+                ("linear", _) => Interpolation::Linear,
+                ("smooth", _) => Interpolation::Smooth,
+                _ => return Err(format!("unknown interpolation in schedule {}", value)),
+            };
+            let values = number_list(split_kind(rest).1)?;
+            if values.is_empty() || values.len() % 2 != 0 {
+                return Err(format!("schedule {} needs time and value pairs", value));
+            }
+            let keyframes: Vec<(f64, f64)> = values.chunks(2).map(|pair| (pair[0], pair[1])).collect();
+            if keyframes.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+                return Err("schedule times must be in increasing order".to_string());
+            }
+            if name == "render_step" && keyframes.iter().any(|&(_, value)| value <= 0.0) {
+                return Err("scheduled render steps must be above 0".to_string());
+            }
+            builder.schedule(name, ParameterSchedule::new(interpolation, keyframes));
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "climate",
+        value: "BASE,LAPSE_RATE,LATITUDE_GRADIENT",
+        default: None,
+        repeatable: false,
+        help: "keep a temperature layer, from a sea level equator temperature",
+        apply: |builder, value| {
+            let n = numbers(value, 3)?;
+            builder.climate(Climate::new(n[0], n[1], n[2]));
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "biomes",
+        value: "MOISTURE_SCALE,TIMESCALE",
+        default: None,
+        repeatable: false,
+        help: "write the final biome map, both above 0; needs --climate",
+        apply: |builder, value| {
+            let n = numbers(value, 2)?;
+            builder.biomes(BiomeClassifier::new(above_zero(n[0], "the moisture scale")?), above_zero(n[1], "the timescale")?);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "humidity",
+        value: "WIND_DIRECTION,INFLOW,CONDENSATION,OROGRAPHIC,RAIN",
+        default: None,
+        repeatable: false,
+        help: "rain carried by a prevailing wind heading in degrees, the rest at least 0",
+        apply: |builder, value| {
+            let n = numbers(value, 5)?;
+            if n[1..].iter().any(|&n| n < 0.0) {
+                return Err("humidity rates can't be negative".to_string());
+            }
+            builder.humidity(HumidityModel::new(n[0], n[1], n[2], n[3], n[4]));
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "probe",
+        value: "NAME,X,Y",
+        default: None,
+        repeatable: true,
+        help: "record a time series at a point to gauges.csv",
+        apply: |builder, value| {
+            let (name, position) = value.split_once(',').ok_or_else(|| format!("probe {} needs a name", value))?;
+            let n = numbers(position, 2)?;
+            builder.probe(name, n[0], n[1]);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "tracers",
+        value: "MIXING_DEPTH:X,Y,RADIUS,COUNT[:...]",
+        default: None,
+        repeatable: false,
+        help: "follow particles seeded in regions with eroded material",
+        apply: |builder, value| {
+            let mut groups = value.split(':');
+            let mixing_depth = above_zero(number(groups.next().unwrap())?, "the mixing depth")?;
+            let mut regions = Vec::new();
+            for group in groups {
+                let n = numbers(group, 4)?;
+                let count = at_least_one(whole(n[3])?, "the tracer count")?;
+                regions.push(TracerRegion { x: n[0], y: n[1], radius: above_zero(n[2], "the radius")?, count: count as usize });
+            }
+            if regions.is_empty() {
+                return Err("tracers need at least one region".to_string());
+            }
+            builder.tracers(&regions, mixing_depth);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "exposure-age",
+        value: "THRESHOLD",
+        default: None,
+        repeatable: false,
+        help: "track how long surfaces go without moving more than the threshold, above 0",
+        apply: |builder, value| {
+            builder.exposure_age(above_zero(number(value)?, "the threshold")?);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "wetness",
+        value: "WET_DEPTH",
+        default: None,
+        repeatable: false,
+        help: "track how often cells are deeper than the wet depth, at least 0",
+        apply: |builder, value| {
+            builder.wetness(not_negative(number(value)?, "the wet depth")?);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "flood",
+        value: "FRAME,X,Y,RADIUS,VOLUME",
+        default: None,
+        repeatable: true,
+        help: "add a volume of water within the radius at the start of a frame",
+        apply: |builder, value| {
+            let n = numbers(value, 5)?;
+            if !(n[1].is_finite() && n[2].is_finite() && n[3] > 0.0 && n[4] > 0.0 && n[4].is_finite()) {
+                return Err(String::from("the flood needs a finite position and a positive radius and volume"));
+            }
+            builder.flood(FloodEvent { frame: whole(n[0])?, x: n[1], y: n[2], radius: n[3], volume: n[4] });
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "groundwater",
+        value: "THICKNESS,POROSITY,INFILTRATION,CONDUCTIVITY",
+        default: None,
+        repeatable: false,
+        help: "simulate an aquifer; thickness above 0, porosity in (0, 1], the rest at least 0",
+        apply: |builder, value| {
+            let n = numbers(value, 4)?;
+            if !(n[1] > 0.0 && n[1] <= 1.0) {
+                return Err("the porosity must be above 0 and at most 1".to_string());
+            }
+            builder.groundwater(Groundwater::new(
+                above_zero(n[0], "the thickness")?,
+                n[1],
+                not_negative(n[2], "the infiltration")?,
+                not_negative(n[3], "the conductivity")?,
+            ));
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "sediment",
+        value: "PICKUP,ACTIVE_LAYER,ENTRY_DEPOSITION",
+        default: None,
+        repeatable: false,
+        help: "carry sand, silt and clay; active layer above 0, entry deposition in [0, 1]",
+        apply: |builder, value| {
+            let n = numbers(value, 3)?;
+            builder.sediment(SedimentModel::new(
+                DEFAULT_GRAIN_CLASSES,
+                not_negative(n[0], "the pickup rate")?,
+                above_zero(n[1], "the active layer")?,
+                fraction(n[2], "the entry deposition")?,
+            ));
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "karst",
+        value: "DISSOLUTION,SINKHOLE_FACTOR,SWALLOW:BOTTOM,TOP,SOLUBILITY[:...]",
+        default: None,
+        repeatable: false,
+        help: "dissolve soluble strata; sinkhole factor at least 1",
+        apply: |builder, value| {
+            let mut groups = value.split(':');
+            let n = numbers(groups.next().unwrap(), 3)?;
+            let mut strata = Vec::new();
+            for group in groups {
+                let s = numbers(group, 3)?;
+                if s[0] >= s[1] {
+                    return Err(format!("stratum {} must have its bottom below its top", group));
+                }
+                strata.push(Stratum { bottom: s[0], top: s[1], solubility: not_negative(s[2], "the solubility")? });
+            }
+            if n[1] < 1.0 {
+                return Err("the sinkhole factor must be at least 1".to_string());
+            }
+            builder.karst(Karst::new(strata, not_negative(n[0], "the dissolution rate")?, n[1], not_negative(n[2], "the swallow rate")?));
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "hillslope-diffusion",
+        value: "DIFFUSIVITY",
+        default: None,
+        repeatable: false,
+        help: "stack soil creep on the flow, at least 0",
+        apply: |builder, value| {
+            builder.hillslope_diffusion(not_negative(number(value)?, "the diffusivity")?);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "thermal-erosion",
+        value: "REPOSE_ANGLE,RATE",
+        default: None,
+        repeatable: false,
+        help: "stack talus slides on the flow wherever slopes pass the angle of repose in degrees, \
+            RATE at least 0",
+        apply: |builder, value| {
+            let n = numbers(value, 2)?;
+            if !(n[0] > 0.0 && n[0] < 90.0) {
+                return Err("the angle of repose must be between 0 and 90".to_string());
+            }
+            builder.thermal_erosion(n[0], not_negative(n[1], "the rate")?);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "shallow-water",
+        value: "GRAVITY,FRICTION,EROSION_RATE",
+        default: None,
+        repeatable: false,
+        help: "move water with momentum, accelerated down the water surface by GRAVITY and \
+            slowed by FRICTION per unit time, wearing away EROSION_RATE times its speed \
+            times the water moved downhill, in place of the flow rate",
+        apply: |builder, value| {
+            let n = numbers(value, 3)?;
+            builder.shallow_water(
+                above_zero(n[0], "gravity")?,
+                not_negative(n[1], "the friction")?,
+                not_negative(n[2], "the erosion rate")?,
+            );
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "pipe-model",
+        value: "GRAVITY,CAPACITY,DISSOLVING_RATE,DEPOSITION_RATE",
+        default: None,
+        repeatable: false,
+        help: "move water through virtual pipes between neighbors in place of the flow rate, \
+            the water holding CAPACITY times its speed times the tilt in sediment and \
+            dissolving or depositing the rates (in [0, 1]) of the difference",
+        apply: |builder, value| {
+            let n = numbers(value, 4)?;
+            builder.pipe_model(
+                above_zero(n[0], "gravity")?,
+                not_negative(n[1], "the capacity")?,
+                fraction(n[2], "the dissolving rate")?,
+                fraction(n[3], "the deposition rate")?,
+            );
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "droplets",
+        value: "COUNT,CAPACITY,EROSION_RATE,DEPOSITION_RATE,EVAPORATION",
+        default: None,
+        repeatable: false,
+        help: "stack droplet erosion on the flow, COUNT droplets per unit time walking down the \
+            ground carrying up to CAPACITY times their slope, speed and water in sediment, \
+            the rates in [0, 1] and EVAPORATION below 1",
+        apply: |builder, value| {
+            let n = numbers(value, 5)?;
+            if !(0.0..1.0).contains(&n[4]) {
+                return Err("the evaporation must be from 0 to below 1".to_string());
+            }
+            builder.droplets(
+                at_least_one(whole(n[0])?, "the droplet count")?,
+                not_negative(n[1], "the capacity")?,
+                fraction(n[2], "the erosion rate")?,
+                fraction(n[3], "the deposition rate")?,
+                n[4],
+            );
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "evaporation",
+        value: "RATE[,DEPTH_COEFFICIENT]",
+        default: None,
+        repeatable: false,
+        help: "stack evaporation on the flow, RATE of depth per unit time plus DEPTH_COEFFICIENT \
+            (in [0, 1], default 0) of the depth",
+        apply: |builder, value| {
+            let n = number_list(value)?;
+            match n.len() {
+                1 => builder.evaporation(not_negative(n[0], "the rate")?, 0.0),
+                2 => builder.evaporation(not_negative(n[0], "the rate")?, fraction(n[1], "the depth coefficient")?),
+                _ => return Err(format!("expected 1 or 2 comma separated numbers but found {}", n.len())),
+            };
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "stream-power",
+        value: "detachment|transport:K,M,N,BLEND",
+        default: None,
+        repeatable: false,
+        help: "blend in stream power erosion; K at least 0, BLEND in [0, 1]",
+        apply: |builder, value| {
+            let (mode, args) = split_kind(value);
+            let mode = match mode {
+                "detachment" => ErosionMode::DetachmentLimited,
+                "transport" => ErosionMode::TransportLimited,
+                _ => return Err(format!("unknown stream power mode {}", mode)),
+            };
+            let n = numbers(args, 4)?;
+            builder.stream_power(StreamPower::new(mode, not_negative(n[0], "K")?, n[1], n[2]), fraction(n[3], "the blend")?);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "salinity",
+        value: "SEA_LEVEL,MIXING_RATE",
+        default: None,
+        repeatable: false,
+        help: "track salt from the sea, mixing rate at least 0",
+        apply: |builder, value| {
+            let n = numbers(value, 2)?;
+            builder.salinity(Salinity::new(n[0], not_negative(n[1], "the mixing rate")?));
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "view-3d",
+        value: "CX,CY,DISTANCE,ELEVATION,AZIMUTH,SPIN,FOV[:side-by-side|anaglyph:SEPARATION]",
+        default: None,
+        repeatable: false,
+        help: "also render an orbiting perspective view, angles in degrees, FOV in (0, 180)",
+        apply: |builder, value| {
+            let (camera, stereo) = split_kind(value);
+            let n = numbers(camera, 7)?;
+            let camera = OrbitCamera {
+                center_x: n[0],
+                center_y: n[1],
+                distance: above_zero(n[2], "the distance")?,
+                elevation: n[3],
+                azimuth: n[4],
+                spin: n[5],
+                fov: n[6],
+            };
+            if !(camera.fov > 0.0 && camera.fov < 180.0) {
+                return Err("the field of view must be between 0 and 180".to_string());
+            }
+            let stereo = match split_kind(stereo) {
+                ("", "") => Stereo::Mono,
+                ("side-by-side", separation) => Stereo::SideBySide { eye_separation: number(separation)? },
+                ("anaglyph", separation) => Stereo::Anaglyph { eye_separation: number(separation)? },
+                _ => return Err(format!("unknown stereo mode {}", stereo)),
+            };
+            builder.view_3d(camera, stereo);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "dashboard",
+        value: "",
+        default: None,
+        repeatable: false,
+        help: "also write dashboard images with a minimap, 3D thumbnail and plots",
+        apply: |builder, _| {
+            builder.dashboard(true);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "change-maps",
+        value: "HEIGHT_SCALE,DEPTH_SCALE",
+        default: None,
+        repeatable: false,
+        help: "also write per-frame change heatmaps saturating at the scales, above 0",
+        apply: |builder, value| {
+            let n = numbers(value, 2)?;
+            builder.change_maps(above_zero(n[0], "the height scale")?, above_zero(n[1], "the depth scale")?);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "cumulative-change",
+        value: "",
+        default: None,
+        repeatable: false,
+        help: "write total erosion and deposition at the end",
+        apply: |builder, _| {
+            builder.cumulative_change(true);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "export-heightmap",
+        value: "",
+        default: None,
+        repeatable: false,
+        help: "write the final terrain as a 16-bit heightmap png with its range in a json sidecar",
+        apply: |builder, _| {
+            builder.export_heightmap(true);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "export-svg",
+        value: "edges|shaded",
+        default: None,
+        repeatable: false,
+        help: "write the final mesh as an svg, its edges only or its triangles filled with the frame shader's colors",
+        apply: |builder, value| {
+            let shaded = match value {
+                "edges" => false,
+                "shaded" => true,
+                _ => return Err(format!("unknown svg style {}", value)),
+            };
+            builder.export_svg(shaded);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "export-mesh",
+        value: "obj|ply[:water]",
+        default: None,
+        repeatable: false,
+        help: "write the final ground as a 3D mesh, and the water's surface as a second one with water",
+        apply: |builder, value| {
+            let (format, water) = split_kind(value);
+            let format = match format {
+                "obj" => MeshFormat::Obj,
+                "ply" => MeshFormat::Ply,
+                _ => return Err(format!("unknown mesh format {}", format)),
+            };
+            let water = match water {
+                "" => false,
+                "water" => true,
+                _ => return Err(format!("unknown mesh option {}", water)),
+            };
+            builder.export_mesh(format, water);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "export-stl",
+        value: "SIZE,BASE",
+        default: None,
+        repeatable: false,
+        help: "write the final ground as an stl solid for 3D printing, SIZE mm along its longer side on a base BASE mm thick, both above 0",
+        apply: |builder, value| {
+            let n = numbers(value, 2)?;
+            if n[0] <= 0.0 || n[1] <= 0.0 {
+                return Err("the stl size and base must be above 0".to_string());
+            }
+            builder.export_stl(n[0], n[1]);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "export-raw",
+        value: "SIZE",
+        default: None,
+        repeatable: false,
+        help: "write the final heights as a SIZE by SIZE 16-bit raw heightmap for Unity or Unreal, \
+            SIZE a power of two plus one like 513, with a json sidecar",
+        apply: |builder, value| {
+            let size: usize = count(value)?;
+            if !export::raw_size_valid(size) {
+                return Err(format!("{} is not a power of two plus one up to {}", size, export::MAX_RAW_SIZE));
+            }
+            builder.export_raw(size);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "export-gltf",
+        value: "end|frames",
+        default: None,
+        repeatable: false,
+        help: "write the ground and water as a glb colored by the frame shader, at the end or every frame",
+        apply: |builder, value| {
+            let every_frame = match value {
+                "end" => false,
+                "frames" => true,
+                _ => return Err(format!("unknown glTF export {}", value)),
+            };
+            builder.export_gltf(every_frame);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "goal",
+        value: "above|below:METRIC:TARGET",
+        default: None,
+        repeatable: false,
+        help: "end the run once relief, water_volume, hypsometric_integral or \
+            drainage_density/DISCHARGE_THRESHOLD passes the target",
+        apply: |builder, value| {
+            let (comparison, rest) = split_kind(value);
+            let (metric, target) = split_kind(rest);
+            let metric = match metric.split_once('/') {
+                None if metric == "relief" => Metric::Relief,
+                None if metric == "water_volume" => Metric::WaterVolume,
+                None if metric == "hypsometric_integral" => Metric::HypsometricIntegral,
+                Some(("drainage_density", threshold)) => Metric::DrainageDensity { discharge_threshold: number(threshold)? },
+                _ => return Err(format!("unknown goal metric {}", metric)),
+            };
+            let goal = match comparison {
+                "above" => Goal::above(metric, number(target)?),
+                "below" => Goal::below(metric, number(target)?),
+                _ => return Err(format!("goal {} must be above or below", value)),
+            };
+            builder.goal(goal);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "scenario",
+        value: "FILE",
+        default: None,
+        repeatable: false,
+        help: "run the timed actions in a scenario file",
+        apply: |builder, value| {
+            builder.scenario(file(value)?);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "ensemble",
+        value: "MEMBERS,PROCESSES",
+        default: None,
+        repeatable: false,
+        help: "run the configuration repeatedly and aggregate the results, both at least 1",
+        apply: |builder, value| {
+            let n = numbers(value, 2)?;
+            builder.ensemble(at_least_one(whole(n[0])?, "the members")?, at_least_one(whole(n[1])?, "the processes")?);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "distributed",
+        value: "ADDR,WORKERS[,local]",
+        default: None,
+        repeatable: false,
+        help: "split each step across worker processes joining at ADDR, at least 1, \
+            started here with local",
+        apply: |builder, value| {
+            let parts: Vec<&str> = value.split(',').collect();
+            let spawn_local = match parts.get(2) {
+                None => false,
+                Some(&"local") => true,
+                Some(other) => return Err(format!("unknown distributed option {}", other)),
+            };
+            let workers = parts.get(1).ok_or_else(|| format!("distributed {} needs a worker count", value))?;
+            builder.distributed(parts[0], at_least_one(count(workers)?, "the workers")?, spawn_local);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "adaptive-step",
+        value: "MAX_SHARE[,MAX_SUBSTEPS]",
+        default: None,
+        repeatable: false,
+        help: "split steps so no cell loses more than MAX_SHARE (in (0, 1]) of its water in one, \
+            into at most MAX_SUBSTEPS, by default 16",
+        apply: |builder, value| {
+            let n = number_list(value)?;
+            if !(n[0] > 0.0 && n[0] <= 1.0) {
+                return Err("the max share must be above 0 and at most 1".to_string());
+            }
+            let adaptive_step = match n.len() {
+                1 => AdaptiveStep::new(n[0], 16),
+                2 => AdaptiveStep::new(n[0], at_least_one(whole(n[1])?, "the max substeps")?),
+                _ => return Err(format!("expected 1 or 2 comma separated numbers but found {}", n.len())),
+            };
+            builder.adaptive_step(adaptive_step);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "active-cells",
+        value: "THRESHOLD[,LINGER[,SWEEP_EVERY]]",
+        default: None,
+        repeatable: false,
+        help: "only work out the flow around cells that changed by more than THRESHOLD in the last LINGER steps, \
+            by default 4, with every cell every SWEEP_EVERY steps, by default 32",
+        apply: |builder, value| {
+            let n = number_list(value)?;
+            let threshold = not_negative(n[0], "the threshold")?;
+            let linger = n.get(1).map(|&linger| at_least_one(whole(linger)?, "the linger")).transpose()?;
+            let sweep_every = n.get(2).map(|&sweep_every| at_least_one(whole(sweep_every)?, "the sweep")).transpose()?;
+            let active_cells = match n.len() {
+                1..=3 => ActiveCells::new(threshold, linger.unwrap_or(4), sweep_every.unwrap_or(32)),
+                _ => return Err(format!("expected 1 to 3 comma separated numbers but found {}", n.len())),
+            };
+            builder.active_cells(active_cells);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "deterministic",
+        value: "",
+        default: None,
+        repeatable: false,
+        help: "apply each step's deltas in a fixed order so reruns match",
+        apply: |builder, _| {
+            builder.deterministic(true);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "seed",
+        value: "N",
+        default: None,
+        repeatable: false,
+        help: "draw every random number from the seed so runs repeat exactly; implies --deterministic",
+        apply: |builder, value| {
+            builder.seed(count(value)?);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "nan-guard",
+        value: "HISTORY_STEPS[,clamp]",
+        default: None,
+        repeatable: false,
+        help: "stop at the first non-finite value with a dump of the last steps, at least 1, \
+            or with clamp drop bad deltas and restore bad cells and go on",
+        apply: |builder, value| {
+            let (history_steps, action) = value.split_once(',').unwrap_or((value, ""));
+            builder.nan_guard(at_least_one(count(history_steps)?, "the history")?);
+            match action {
+                "" => {}
+                "clamp" => {
+                    builder.nan_recovery(true);
+                }
+                _ => return Err(format!("unknown nan guard action {}", action)),
+            }
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "mass-audit",
+        value: "",
+        default: None,
+        repeatable: false,
+        help: "write each step's total water and rock, sources, sinks and drift to mass_audit.csv",
+        apply: |builder, _| {
+            builder.mass_audit(true);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "pipelined-render",
+        value: "",
+        default: None,
+        repeatable: false,
+        help: "draw and write each frame on another thread while the next frame's steps run",
+        apply: |builder, _| {
+            builder.pipelined_render(true);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "output",
+        value: "frames[:png|png16|exr]|video:FILE[,FPS]|apng:FILE[,DELAY_MS[,SCALE]]|gif:FILE[,DELAY_MS[,SCALE]]",
+        default: Some("frames"),
+        repeatable: false,
+        help: "write numbered frames, as 8-bit PNGs by default, 16-bit PNGs or 32-bit float OpenEXR files, \
+            pipe them through ffmpeg into a video FILE in the render path, its format from the extension, \
+            at FPS frames per second, by default 30, or gather them into an \
+            animated PNG or GIF FILE in the render path showing each for DELAY_MS, by default 100, shrunk \
+            SCALE times, by default 1",
+        apply: |builder, value| {
+            let output = match value.split_once(':') {
+                None if value == "frames" => Output::Frames(FrameFormat::Png),
+                Some(("frames", format)) => Output::Frames(match format {
+                    "png" => FrameFormat::Png,
+                    "png16" => FrameFormat::Png16,
+                    "exr" => FrameFormat::Exr,
+                    _ => return Err(format!("unknown frame format {}", format)),
+                }),
+                Some(("video", spec)) => {
+                    let (file, fps) = match spec.split_once(',') {
+                        Some((file, fps)) => (file, at_least_one(count(fps)?, "the fps")?),
+                        None => (spec, 30),
+                    };
+                    if file.is_empty() {
+                        return Err("expected a video file name".to_string());
+                    }
+                    Output::Video { file: file.to_string(), fps }
+                }
+                Some((kind @ ("apng" | "gif"), spec)) => {
+                    let mut parts = spec.split(',');
+                    let file = parts.next().unwrap();
+                    let delay_ms = parts.next().map(count).transpose()?.unwrap_or(100);
+                    let scale = parts.next().map(count).transpose()?.unwrap_or(1);
+                    if file.is_empty() || parts.next().is_some() {
+                        return Err(format!("expected {}:FILE[,DELAY_MS[,SCALE]]", kind));
+                    }
+                    if scale == 0 {
+                        return Err("the scale must be at least 1".to_string());
+                    }
+                    let format = if kind == "apng" { AnimationFormat::Apng } else { AnimationFormat::Gif };
+                    Output::Animation { file: file.to_string(), format, delay_ms, scale }
+                }
+                _ => return Err(format!("unknown output {}", value)),
+            };
+            builder.output(output);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "checkpoint-every",
+        value: "FRAMES",
+        default: None,
+        repeatable: false,
+        help: "write checkpoint_NNNNNN.dat to the render path every this many frames, at least 1",
+        apply: |builder, value| {
+            builder.checkpoint_every(at_least_one(count(value)?, "the checkpoint interval")?);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "resume",
+        value: "FILE",
+        default: None,
+        repeatable: false,
+        help: "continue from a checkpoint, given the settings of the run that wrote it",
+        apply: |builder, value| {
+            builder.resume(value);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "snapshot-path",
+        value: "DIR",
+        default: None,
+        repeatable: false,
+        help: "write each frame's heights and depths to the directory instead of drawing it, to draw later with render",
+        apply: |builder, value| {
+            builder.snapshot_path(value);
+            Ok(())
+        },
+    },
+    CliOption {
+        name: "map-tiles",
+        value: "FRAME,MAX_ZOOM",
+        default: None,
+        repeatable: false,
+        help: "export a frame as slippy map tiles, max zoom at most 10",
+        apply: |builder, value| {
+            let n = numbers(value, 2)?;
+            let max_zoom = whole(n[1])?;
+            if max_zoom > 10 {
+                return Err("the max zoom must be at most 10".to_string());
+            }
+            builder.map_tiles(whole(n[0])?, max_zoom);
+            Ok(())
+        },
+    },
+    #[cfg(feature = "tui")]
+    CliOption {
+        name: "tui",
+        value: "",
+        default: None,
+        repeatable: false,
+        help: "show the run in the terminal",
+        apply: |builder, _| {
+            builder.tui(true);
+            Ok(())
+        },
+    },
+    #[cfg(feature = "window")]
+    CliOption {
+        name: "window",
+        value: "",
+        default: None,
+        repeatable: false,
+        help: "show the run in a window",
+        apply: |builder, _| {
+            builder.window(true);
+            Ok(())
+        },
+    },
+    #[cfg(feature = "http")]
+    CliOption {
+        name: "http-addr",
+        value: "ADDR",
+        default: None,
+        repeatable: false,
+        help: "serve the control API on the address",
+        apply: |builder, value| {
+            builder.http_addr(value);
+            Ok(())
+        },
+    },
+    #[cfg(feature = "plugins")]
+    CliOption {
+        name: "flow-plugin",
+        value: "FILE",
+        default: None,
+        repeatable: false,
+        help: "load the flow from a shared library",
+        apply: |builder, value| {
+            builder.flow_plugin(file(value)?);
+            Ok(())
+        },
+    },
+    #[cfg(feature = "plugins")]
+    CliOption {
+        name: "shader-plugin",
+        value: "FILE",
+        default: None,
+        repeatable: false,
+        help: "load the shader from a shared library",
+        apply: |builder, value| {
+            builder.shader_plugin(file(value)?);
+            Ok(())
+        },
+    },
+    #[cfg(feature = "scripting")]
+    CliOption {
+        name: "script",
+        value: "FILE",
+        default: None,
+        repeatable: false,
+        help: "run a control script every step",
+        apply: |builder, value| {
+            builder.script(file(value)?);
+            Ok(())
+        },
+    },
+    #[cfg(feature = "gpu")]
+    CliOption {
+        name: "gpu",
+        value: "",
+        default: None,
+        repeatable: false,
+        help: "work out the flow's transfers on the GPU",
+        apply: |builder, _| {
+            builder.gpu(true);
+            Ok(())
+        },
+    },
+];
+
+// Sets up the builder from the command line arguments, after the program
+// name, on top of the defaults, or to render a run's snapshots when they
+// start with render and the snapshots' directory. Prints the usage and
+// exits for --help, and exits with a message for arguments that don't
+// parse or are out of range.
+pub fn configure<'a>(builder: &mut RunnerBuilder<'a>, args: &'a [String]) {
+    let (replay, args) = match args.split_first() {
+        Some((command, rest)) if command == "render" => match rest.split_first() {
+            Some((path, rest)) => (Some(path.as_str()), rest),
+            None => {
+                eprintln!("error: render needs the directory of the snapshots");
+                process::exit(2);
+            }
+        },
+        _ => (None, args),
+    };
+    let given = parse_args(args).unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        eprintln!("run with --help for the options");
+        process::exit(2);
+    });
+    // the defaults are checked too, as the paths among them may not exist
+    let defaults = OPTIONS.iter().filter_map(|option| {
+        option.default.filter(|_| given.iter().all(|(given, _)| given.name != option.name)).map(|default| (option, default))
+    });
+    for (option, value) in given.iter().copied().chain(defaults) {
+        if let Err(e) = (option.apply)(builder, value) {
+            eprintln!("error: --{} {}: {}", option.name, value, e);
+            process::exit(2);
+        }
+    }
+    // the world is the one the snapshots were taken of
+    if let Some(path) = replay {
+        let snapshot = snapshot::list(path).and_then(|paths| snapshot::read(&paths[0])).unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            process::exit(2);
+        });
+        builder.width(snapshot.world_size.0).height(snapshot.world_size.1).spherical(snapshot.spherical).replay(path);
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Vec<(&'static CliOption, &str)>, String> {
+    let mut given: Vec<(&'static CliOption, &str)> = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--help" || arg == "-h" {
+            print_usage();
+            process::exit(0);
+        }
+        let name = arg.strip_prefix("--").ok_or_else(|| format!("unexpected argument {}", arg))?;
+        let (name, inline_value) = match name.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (name, None),
+        };
+        let option = OPTIONS.iter().find(|option| option.name == name).ok_or_else(|| format!("unknown option --{}", name))?;
+        let value = match (option.value.is_empty(), inline_value) {
+            (true, None) => "",
+            (true, Some(_)) => return Err(format!("--{} takes no value", name)),
+            (false, Some(value)) => value,
+            (false, None) => args.next().ok_or_else(|| format!("--{} needs a value {}", name, option.value))?,
+        };
+        given.push((option, value));
+    }
+    Ok(given)
+}
+
+fn print_usage() {
+    println!("usage: terrain_flow [OPTIONS]");
+    println!("       terrain_flow render DIR [OPTIONS]");
+    println!();
+    println!("render draws the frames of a run from the snapshots it wrote to DIR with --snapshot-path, through");
+    println!("the shader and render options given, taking the world's size and shape from the snapshots.");
+    println!();
+    println!("  --config FILE");
+    println!("        read options from a TOML file keyed by option name; later options override it");
+    for option in OPTIONS.iter() {
+        if option.value.is_empty() {
+            println!("  --{}", option.name);
+        } else {
+            println!("  --{} {}", option.name, option.value);
+        }
+        let mut help = String::from(option.help);
+        if let Some(default) = option.default {
+            help += &format!(" [default: {}]", default);
+        }
+        if option.repeatable {
+            help += " [repeatable]";
+        }
+        println!("        {}", help);
+    }
+}
+
+// splits KIND:REST at the first colon, giving an empty rest without one
+fn split_kind(value: &str) -> (&str, &str) {
+    value.split_once(':').unwrap_or((value, ""))
+}
+
+fn number(value: &str) -> Result<f64, String> {
+    value.parse::<f64>().ok().filter(|value| value.is_finite()).ok_or_else(|| format!("invalid number {}", value))
+}
+
+fn count<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid count {}", value))
+}
+
+fn whole(value: f64) -> Result<u32, String> {
+    if value >= 0.0 && value.fract() == 0.0 && value <= u32::MAX as f64 {
+        Ok(value as u32)
+    } else {
+        Err(format!("{} is not a whole number", value))
+    }
+}
+
+// range checks matching the builder's, naming the value in the error
+fn above_zero(value: f64, name: &str) -> Result<f64, String> {
+    if value > 0.0 {
+        Ok(value)
+    } else {
+        Err(format!("{} must be above 0", name))
+    }
+}
+
+fn not_negative(value: f64, name: &str) -> Result<f64, String> {
+    if value >= 0.0 {
+        Ok(value)
+    } else {
+        Err(format!("{} can't be negative", name))
+    }
+}
+
+fn fraction(value: f64, name: &str) -> Result<f64, String> {
+    if (0.0..=1.0).contains(&value) {
+        Ok(value)
+    } else {
+        Err(format!("{} must be from 0 to 1", name))
+    }
+}
+
+fn at_least_one<T: Default + PartialEq>(value: T, name: &str) -> Result<T, String> {
+    if value != T::default() {
+        Ok(value)
+    } else {
+        Err(format!("{} must be at least 1", name))
+    }
+}
+
+fn directory(value: &str) -> Result<&str, String> {
+    if Path::new(value).is_dir() {
+        Ok(value)
+    } else {
+        Err(format!("{} is not a directory", value))
+    }
+}
+
+fn file(value: &str) -> Result<&str, String> {
+    if Path::new(value).is_file() {
+        Ok(value)
+    } else {
+        Err(format!("{} is not a file", value))
+    }
+}
+
+fn frame_shader(value: &str) -> Result<FrameShader, String> {
+    Ok(match split_kind(value) {
+        ("default", "") => FrameShader::Default,
+        ("hypsometric", "") => FrameShader::Hypsometric(None),
+        ("hypsometric", stops) => FrameShader::Hypsometric(Some(color_ramp(stops)?)),
+        ("water", "") => FrameShader::Water { absorption: CLEAR_WATER_ABSORPTION },
+        ("water", absorption) => {
+            let n = numbers(absorption, 3)?;
+            if n.iter().any(|&a| a < 0.0) {
+                return Err("water absorption can't be negative".to_string());
+            }
+            FrameShader::Water { absorption: RGB { r: n[0], g: n[1], b: n[2] } }
+        }
+        ("flow", "") => FrameShader::Flow { scale: STREAM_FLUX },
+        ("flow", scale) => match number(scale)? {
+            scale if scale > 0.0 => FrameShader::Flow { scale },
+            _ => return Err("flow scale must be above 0".to_string()),
+        },
+        ("relief", "") => FrameShader::Relief,
+        ("erosion-rate", "") => FrameShader::ErosionRate { scale: 0.05 },
+        ("erosion-rate", scale) => FrameShader::ErosionRate { scale: change_scale(scale)? },
+        ("net-change", "") => FrameShader::NetChange { scale: 1.0 },
+        ("net-change", scale) => FrameShader::NetChange { scale: change_scale(scale)? },
+        _ => return Err(format!("unknown shader {}", value)),
+    })
+}
+
+fn change_scale(value: &str) -> Result<f64, String> {
+    match number(value)? {
+        scale if scale > 0.0 => Ok(scale),
+        _ => Err("change scales must be above 0".to_string()),
+    }
+}
+
+// VALUE=RRGGBB stops in increasing order of value
+fn color_ramp(value: &str) -> Result<ColorRamp, String> {
+    let mut stops = Vec::new();
+    for stop in value.split(',') {
+        let (at, hex) = stop.split_once('=').ok_or_else(|| format!("expected VALUE=RRGGBB but found {}", stop))?;
+        let rgb = u32::from_str_radix(hex, 16).ok()
+            .filter(|_| hex.len() == 6)
+            .ok_or_else(|| format!("invalid color {}", hex))?;
+        let channel = |shift: u32| ((rgb >> shift) & 0xff) as f64 / 255.0;
+        stops.push((number(at)?, RGB { r: channel(16), g: channel(8), b: channel(0) }));
+    }
+    if stops.windows(2).any(|pair| pair[0].0 > pair[1].0) {
+        return Err("color ramp stops must be in increasing order".to_string());
+    }
+    Ok(ColorRamp::new(stops))
+}
+
+fn number_list(value: &str) -> Result<Vec<f64>, String> {
+    value.split(',').map(number).collect()
+}
+
+fn numbers(value: &str, expected: usize) -> Result<Vec<f64>, String> {
+    let numbers = number_list(value)?;
+    if numbers.len() != expected {
+        return Err(format!("expected {} comma separated numbers but found {}", expected, numbers.len()));
+    }
+    Ok(numbers)
+}
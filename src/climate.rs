@@ -0,0 +1,39 @@
+use crate::sphere;
+use crate::terrain::Terrain;
+
+pub const TEMPERATURE_LAYER: &str = "temperature";
+
+// Derives a per-cell temperature from a base (sea level, equator) value,
+// cooling by `lapse_rate` per unit of height above zero and by
+// `latitude_gradient` per degree of latitude.
+#[derive(Clone)]
+pub struct Climate {
+    base_temperature: f64,
+    lapse_rate: f64,
+    latitude_gradient: f64,
+}
+
+impl Climate {
+    pub fn new(base_temperature: f64, lapse_rate: f64, latitude_gradient: f64) -> Climate {
+        assert!(base_temperature.is_finite());
+        assert!(lapse_rate.is_finite());
+        assert!(latitude_gradient.is_finite());
+        Climate { base_temperature, lapse_rate, latitude_gradient }
+    }
+
+    pub fn temperature_at(&self, z: f64, latitude: f64) -> f64 {
+        self.base_temperature - self.lapse_rate * z.max(0.0) - self.latitude_gradient * latitude.abs()
+    }
+
+    // refreshes the temperature layer; latitude follows the world's y axis
+    // from -90 degrees at the bottom to 90 at the top, as on the sphere
+    pub fn update(&self, terrain: &mut Terrain, world_width: f64, world_height: f64) {
+        let temperatures: Vec<f64> = terrain.cells_iter()
+            .map(|cell| {
+                let (_, latitude) = sphere::lon_lat(cell.x(), cell.y(), world_width, world_height);
+                self.temperature_at(cell.height(), latitude.to_degrees())
+            })
+            .collect();
+        terrain.layer_mut(TEMPERATURE_LAYER).copy_from_slice(&temperatures);
+    }
+}
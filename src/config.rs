@@ -0,0 +1,89 @@
+use std::fs;
+
+use toml_edit::{Document, Item, Value};
+
+// A run configuration file is TOML whose keys are the command line options,
+// with dashes or underscores, so
+//
+//   width = 640
+//   max_z = 24.0
+//   precipitation = "gamma:0.01,0.5,0.02"
+//   vertical_scale = [2, 3]
+//   probe = ["outlet,320,20", "summit,320,180"]
+//   deterministic = true
+//
+//   [paths]
+//   render_path = "./render/experiment_1"
+//
+// sets the same things as the matching options. Tables only group keys.
+// Arrays of numbers stand for comma separated values and arrays of strings
+// for a repeated option; flags are booleans.
+//
+// Expands each --config FILE among the arguments into the options the file
+// sets, in its place, so options after it override the file and options
+// before it are overridden.
+pub fn expand_args(args: Vec<String>) -> Result<Vec<String>, String> {
+    let mut expanded = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let path = match arg.strip_prefix("--config") {
+            Some("") => args.next().ok_or("--config needs a value FILE")?,
+            Some(value) if value.starts_with('=') => value[1..].to_string(),
+            _ => {
+                expanded.push(arg);
+                continue;
+            }
+        };
+        expanded.extend(read(&path).map_err(|e| format!("config {}: {}", path, e))?);
+    }
+    Ok(expanded)
+}
+
+// the options set by a config file, as command line arguments
+pub fn read(path: &str) -> Result<Vec<String>, String> {
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let document = text.parse::<Document>().map_err(|e| e.to_string())?;
+    let mut args = Vec::new();
+    add_table_args(document.as_table(), &mut args)?;
+    Ok(args)
+}
+
+fn add_table_args(table: &toml_edit::Table, args: &mut Vec<String>) -> Result<(), String> {
+    for (key, item) in table.iter() {
+        let option = format!("--{}", key.replace('_', "-"));
+        match item {
+            Item::Table(table) => add_table_args(table, args)?,
+            Item::Value(Value::Boolean(flag)) => {
+                if *flag.value() {
+                    args.push(option);
+                }
+            }
+            Item::Value(Value::Array(array)) if array.iter().all(|value| value.is_str()) => {
+                for value in array.iter() {
+                    args.push(option.clone());
+                    args.push(value.as_str().unwrap().to_string());
+                }
+            }
+            Item::Value(Value::Array(array)) => {
+                let values: Result<Vec<String>, String> = array.iter().map(|value| scalar(key, value)).collect();
+                args.push(option);
+                args.push(values?.join(","));
+            }
+            Item::Value(value) => {
+                args.push(option);
+                args.push(scalar(key, value)?);
+            }
+            _ => return Err(format!("unsupported value for {}", key)),
+        }
+    }
+    Ok(())
+}
+
+fn scalar(key: &str, value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(s) => Ok(s.value().clone()),
+        Value::Integer(i) => Ok(i.value().to_string()),
+        Value::Float(f) => Ok(f.value().to_string()),
+        _ => Err(format!("unsupported value for {}", key)),
+    }
+}
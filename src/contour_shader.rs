@@ -0,0 +1,41 @@
+use crate::render::{FrameTime, RGB, Shade, VerticalScale};
+use crate::terrain::{Cell, Terrain};
+
+const LINE_COLOR: RGB = RGB { r: 0.25, g: 0.15, b: 0.08 };
+// how much of the line color covers the base shader's
+const LINE_OPACITY: f64 = 0.7;
+
+// Draws isolines of elevation above the datum every `interval` over another
+// shader's colors. A cell is on a line when one of its neighbors lies below
+// a level it's at or above, so each line is a cell wide, along its upper
+// side.
+pub struct ContourShader<S: Shade> {
+    base: S,
+    interval: f64,
+    vertical: VerticalScale,
+}
+
+impl<S: Shade> ContourShader<S> {
+    pub fn new(base: S, interval: f64, vertical: VerticalScale) -> ContourShader<S> {
+        assert!(interval > 0.0 && interval.is_finite());
+        ContourShader { base, interval, vertical }
+    }
+
+    fn level(&self, height: f64) -> f64 {
+        (self.vertical.elevation(height) / self.interval).floor()
+    }
+}
+
+impl<S: Shade> Shade for ContourShader<S> {
+    fn shade_cell(&self, cell: &Cell, terrain: &Terrain, time: FrameTime) -> RGB {
+        let color = self.base.shade_cell(cell, terrain, time);
+        let level = self.level(cell.height());
+        let on_line = cell.neighbor_data_iter()
+            .any(|nd| self.level(terrain.get_cell(nd.index()).height()) < level);
+        if on_line {
+            color.mix(&LINE_COLOR, LINE_OPACITY)
+        } else {
+            color
+        }
+    }
+}
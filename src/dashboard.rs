@@ -0,0 +1,100 @@
+use crate::diagnostic_shader::HeightShader;
+use crate::render::{shade_pixels, FrameTime, Image, Projection, RGB, Shade, VerticalScale};
+use crate::stats::TerrainStats;
+use crate::terrain::Terrain;
+use crate::view3d::{OrbitCamera, Stereo, View3d};
+
+const PANEL_ROWS: usize = 4;
+const PLOT_BACKGROUND: RGB = RGB { r: 0.1, g: 0.1, b: 0.12 };
+const WATER_LINE: RGB = RGB { r: 0.3, g: 0.6, b: 1.0 };
+const RELIEF_LINE: RGB = RGB { r: 0.9, g: 0.6, b: 0.3 };
+
+// Composites each frame into one image: the shaded view on the left and a
+// side panel a quarter as wide holding, top to bottom, a height minimap, a
+// slowly orbiting 3D thumbnail, and plots of water volume and relief over
+// the frames so far.
+pub struct Dashboard {
+    width: usize,
+    height: usize,
+    minimap_shader: HeightShader,
+    thumbnail: View3d,
+    water_volumes: Vec<f64>,
+    reliefs: Vec<f64>,
+}
+
+impl Dashboard {
+    pub fn new(width: usize, height: usize, max_z: f64, vertical: VerticalScale) -> Dashboard {
+        assert!(width >= PANEL_ROWS && height >= PANEL_ROWS);
+        let camera = OrbitCamera {
+            center_x: width as f64 / 2.0,
+            center_y: height as f64 / 2.0,
+            distance: width.max(height) as f64 * 1.2,
+            elevation: 35.0,
+            azimuth: -90.0,
+            spin: 2.0,
+            fov: 50.0,
+        };
+        let (panel_width, row_height) = (width / PANEL_ROWS, height / PANEL_ROWS);
+        Dashboard {
+            width,
+            height,
+            minimap_shader: HeightShader::new(max_z, vertical),
+            thumbnail: View3d::new(panel_width, row_height, camera, Stereo::Mono, vertical),
+            water_volumes: Vec::new(),
+            reliefs: Vec::new(),
+        }
+    }
+
+    pub fn render<S: Shade + ?Sized>(&mut self, shader: &S, terrain: &Terrain, projection: Projection, time: FrameTime) -> Image {
+        let stats = TerrainStats::of(terrain);
+        self.water_volumes.push(stats.water_volume);
+        self.reliefs.push(stats.relief());
+
+        let (panel_width, row_height) = (self.width / PANEL_ROWS, self.height / PANEL_ROWS);
+        let mut image = Image::new(self.width + panel_width, self.height, PLOT_BACKGROUND);
+        let world_size = (self.width, self.height);
+
+        let main = shade_pixels(shader, terrain, time, projection, world_size, world_size);
+        blit(&mut image, &main, self.width, 0, 0);
+        let minimap = shade_pixels(&self.minimap_shader, terrain, time, projection, world_size, (panel_width, row_height));
+        blit(&mut image, &minimap, panel_width, self.width, 0);
+        let thumbnail = self.thumbnail.render(shader, terrain, time);
+        blit(&mut image, &thumbnail.pixels, panel_width, self.width, row_height);
+        plot(&mut image, &self.water_volumes, &WATER_LINE, (self.width, 2 * row_height), (panel_width, row_height));
+        plot(&mut image, &self.reliefs, &RELIEF_LINE, (self.width, 3 * row_height), (panel_width, row_height));
+        image
+    }
+}
+
+// copies rows of source_width pixels into the image with their top left
+// corner at (x, y)
+fn blit(image: &mut Image, source: &[RGB], source_width: usize, x: usize, y: usize) {
+    for (row, pixels) in source.chunks(source_width).enumerate() {
+        let start = (y + row) * image.width + x;
+        image.pixels[start..start + source_width].clone_from_slice(pixels);
+    }
+}
+
+// draws the series as a line scaled to fill a box, leaving a one pixel margin
+fn plot(image: &mut Image, values: &[f64], color: &RGB, (x, y): (usize, usize), (width, height): (usize, usize)) {
+    if values.is_empty() || width < 3 || height < 3 {
+        return;
+    }
+    let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let range = if max > min { max - min } else { 1.0 };
+    let row_of = |column: usize| {
+        let index = column * (values.len() - 1) / (width - 3);
+        let v = (values[index] - min) / range;
+        y + 1 + ((1.0 - v) * (height - 3) as f64).round() as usize
+    };
+
+    let mut previous_row = row_of(0);
+    for column in 0..width - 2 {
+        let row = row_of(column);
+        for r in row.min(previous_row)..=row.max(previous_row) {
+            image.pixels[r * image.width + x + 1 + column] = color.clone();
+        }
+        previous_row = row;
+    }
+}
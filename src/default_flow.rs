@@ -1,19 +1,134 @@
-use std::collections::HashMap;
+use std::sync::Mutex;
 
 use crossbeam;
 use crossbeam::channel;
 use rand::Rng;
+use rand_chacha::ChaCha12Rng;
+use smallvec::SmallVec;
 
-use crate::flow::Flow;
+use crate::flow::{within_step, Flow};
+#[cfg(feature = "gpu")]
+use crate::gpu::{GpuTransfers, TransferParams};
+use crate::invariants::MassBudget;
+use crate::precipitation::{BurstState, Precipitation, PRECIPITATION_MAP_LAYER};
+use crate::rng::{self, SplitMix64};
 use crate::terrain::{Cell, NeighborData, Terrain, TerrainDelta};
 
+// ground carried off by flowing water and held in suspension where it went
+pub const SUSPENDED_LOAD_LAYER: &str = "suspended_load";
+
+// the net water crossing each cell per unit time during the last step, by
+// its x and y parts, kept only once DefaultFlow::record_flux is called
+pub const FLUX_X_LAYER: &str = "flux_x";
+pub const FLUX_Y_LAYER: &str = "flux_y";
+
+// cells handed to a thread at a time, enough that passing them around
+// costs little next to the work and few enough to keep the threads evenly
+// loaded
+const CHUNK_CELLS: usize = 1024;
+
+// a cell's neighbors or its deltas, held inline as cells rarely have more
+type PerNeighbor<T> = SmallVec<[T; 8]>;
+
 pub struct DefaultFlow {
     flow_rate: f64,
     flow_erosion_rate: f64,
     erosion_threshold: f64,
     erosion_rate: f64,
-    precipitation_rate: f64,
-    precipitation_amount: f64,
+    precipitation: Precipitation,
+    rain: RainState,
+    // each cell's index in the whole terrain, which its rain is drawn for,
+    // when flowing only part of it
+    global_indices: Option<Vec<usize>>,
+    drain_policy: DrainPolicy,
+    eroded_routing: ErodedRouting,
+    suspended_transport: Option<SuspendedTransport>,
+    // what the last flow leaves for update_layers and its budget, gathered
+    // from the threads handling the cells
+    pending: Mutex<Pending>,
+    // what each cell keeps and sends its neighbors, and then the changes
+    // gathered for it, each written only by the thread handling the cell
+    // and kept from step to step so they needn't be allocated again
+    outgoing: Vec<Outgoing>,
+    changes: Vec<[(f64, f64); 2]>,
+    // per cell, the slot each neighbor lists it in, found once for the
+    // terrain's cells
+    back_slots: Vec<PerNeighbor<u16>>,
+    // whether to keep the flux layers, and the width of the world if it
+    // wraps east to west
+    flux: Option<Option<f64>>,
+    #[cfg(feature = "gpu")]
+    gpu: Option<GpuTransfers>,
+}
+
+// What becomes of the ground flowing water wears away: `delivered` of it is
+// deposited in the cell the water flows into, `suspended` is added to that
+// cell's suspended load and the rest is removed. The default delivers all of
+// it, conserving mass. Without SuspendedTransport the suspended load stays
+// where it was put.
+#[derive(Clone, Copy, Debug)]
+pub struct ErodedRouting {
+    pub delivered: f64,
+    pub suspended: f64,
+}
+
+// Moves suspended load on with the water leaving each cell, in proportion to
+// the share of the cell's water that leaves. Water can hold `capacity` times
+// its outflow times the steepest slope of its surface; any load beyond that
+// settles onto the ground at `settling_rate` of the excess per unit time, so
+// material picked up in fast, steep channels is dropped where they open out
+// or reach still water.
+#[derive(Clone, Copy, Debug)]
+pub struct SuspendedTransport {
+    pub capacity: f64,
+    pub settling_rate: f64,
+}
+
+// Where water and ground leave or enter the system besides precipitation:
+// each unit of time, ground below floor_height is raised by floor_rate of
+// the shortfall and water deeper than max_depth drained by drain_rate of the
+// excess. A rate of 0 turns that part off.
+#[derive(Clone, Copy, Debug)]
+pub struct DrainPolicy {
+    pub floor_height: f64,
+    pub floor_rate: f64,
+    pub max_depth: f64,
+    pub drain_rate: f64,
+}
+
+// the random state of the rain between steps
+struct RainState {
+    rng: ChaCha12Rng,
+    bursts: BurstState,
+}
+
+// whether a step is in a burst of rain, the seed each cell's draws that
+// step come from, and how long it is
+#[derive(Clone, Copy)]
+struct Step {
+    raining: bool,
+    seed: u64,
+    dt: f64,
+}
+
+// per unit time
+#[derive(Default)]
+struct Pending {
+    // change in suspended load per cell
+    suspended: Vec<(usize, f64)>,
+    // what rain, drains, the floor and erosion that isn't delivered added
+    // and took away
+    budget: MassBudget,
+}
+
+// One cell's part of the last flow, per unit time, as (height, depth): the
+// gains and losses it keeps, apart so the engine still sees how much leaves
+// each cell, and what it sends each neighbor, by slot.
+#[derive(Clone, Default)]
+struct Outgoing {
+    gain: (f64, f64),
+    loss: (f64, f64),
+    sent: PerNeighbor<(f64, f64)>,
 }
 
 struct TransferWeight {
@@ -27,88 +142,322 @@ impl DefaultFlow {
         flow_erosion_rate: f64,
         erosion_threshold: f64,
         erosion_rate: f64,
-        precipitation_rate: f64,
-        precipitation_amount: f64,
+        precipitation: Precipitation,
+        drain_policy: DrainPolicy,
     ) -> DefaultFlow {
+        precipitation.validate();
         DefaultFlow {
             flow_rate,
             flow_erosion_rate,
             erosion_threshold,
             erosion_rate,
-            precipitation_rate,
-            precipitation_amount,
+            precipitation,
+            rain: RainState::new(&precipitation, None),
+            global_indices: None,
+            drain_policy,
+            eroded_routing: ErodedRouting::default(),
+            suspended_transport: None,
+            pending: Mutex::new(Pending::default()),
+            outgoing: Vec::new(),
+            changes: Vec::new(),
+            back_slots: Vec::new(),
+            flux: None,
+            #[cfg(feature = "gpu")]
+            gpu: None,
+        }
+    }
+
+    pub fn set_eroded_routing(&mut self, eroded_routing: ErodedRouting) {
+        self.eroded_routing = eroded_routing;
+    }
+
+    pub fn set_suspended_transport(&mut self, suspended_transport: SuspendedTransport) {
+        self.suspended_transport = Some(suspended_transport);
+    }
+
+    // keeps FLUX_X_LAYER and FLUX_Y_LAYER, taking neighbors across the seam
+    // of a world wrapping every `wrap_width` the short way round; the GPU
+    // doesn't say which neighbor each cell's water went to
+    pub fn record_flux(&mut self, wrap_width: Option<f64>) {
+        assert!(wrap_width.is_none_or(|width| width > 0.0));
+        #[cfg(feature = "gpu")]
+        assert!(self.gpu.is_none());
+        self.flux = Some(wrap_width);
+    }
+
+    // works out the transfers between cells on the GPU, which has no
+    // suspended load to carry; the rain and sinks are still added here
+    #[cfg(feature = "gpu")]
+    pub fn use_gpu(&mut self) {
+        assert!(self.eroded_routing.suspended == 0.0 && self.suspended_transport.is_none());
+        assert!(self.flux.is_none());
+        self.gpu = Some(GpuTransfers::new());
+    }
+
+    // draws the rain from the seed, so runs repeat whatever the thread each
+    // cell is handled on
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rain = RainState::new(&self.precipitation, Some(seed));
+    }
+}
+
+impl RainState {
+    fn new(precipitation: &Precipitation, seed: Option<u64>) -> RainState {
+        let mut rng = rng::chacha_rng(seed);
+        let bursts = precipitation.burst_state(&mut rng);
+        RainState { rng, bursts }
+    }
+}
+
+impl Outgoing {
+    fn keep(&mut self, delta: &TerrainDelta) {
+        if delta.height_delta > 0.0 {
+            self.gain.0 += delta.height_delta;
+        } else {
+            self.loss.0 += delta.height_delta;
         }
+        if delta.depth_delta > 0.0 {
+            self.gain.1 += delta.depth_delta;
+        } else {
+            self.loss.1 += delta.depth_delta;
+        }
+    }
+}
+
+impl ErodedRouting {
+    pub fn new(delivered: f64, suspended: f64) -> ErodedRouting {
+        assert!(delivered >= 0.0 && suspended >= 0.0);
+        assert!(delivered + suspended <= 1.0);
+        ErodedRouting { delivered, suspended }
+    }
+}
+
+impl SuspendedTransport {
+    pub fn new(capacity: f64, settling_rate: f64) -> SuspendedTransport {
+        assert!(capacity >= 0.0 && capacity.is_finite());
+        assert!(settling_rate > 0.0 && settling_rate <= 1.0);
+        SuspendedTransport { capacity, settling_rate }
+    }
+}
+
+impl Default for ErodedRouting {
+    fn default() -> ErodedRouting {
+        ErodedRouting::new(1.0, 0.0)
+    }
+}
+
+impl DrainPolicy {
+    pub fn new(floor_height: f64, floor_rate: f64, max_depth: f64, drain_rate: f64) -> DrainPolicy {
+        assert!(floor_height.is_finite() && max_depth.is_finite());
+        assert!(floor_rate >= 0.0 && drain_rate >= 0.0);
+        DrainPolicy { floor_height, floor_rate, max_depth, drain_rate }
+    }
+
+    pub fn disabled() -> DrainPolicy {
+        DrainPolicy::new(0.0, 0.0, 0.0, 0.0)
+    }
+}
+
+impl Default for DrainPolicy {
+    // halves negative ground and water deeper than 1
+    fn default() -> DrainPolicy {
+        DrainPolicy::new(0.0, 0.5, 1.0, 0.5)
     }
 }
 
 impl DefaultFlow {
-    fn do_flow(&self, terrain: &Terrain) -> Vec<TerrainDelta> {
-        let (tx_work, rx_work) = channel::bounded(1);
-        let (tx_result, rx_result) = channel::bounded(1);
-
-        crossbeam::scope(|s| {
-            // send all cell indices as "work" units
-            s.spawn(|_| {
-                for cell_index in 0..terrain.cells_len() {
-                    tx_work.send(cell_index).unwrap();
+    // Works out what every cell keeps and sends in one pass, then gathers
+    // each cell's change from its own part and what its neighbors sent it
+    // in another. In both the threads write only to the cells they were
+    // handed, so neither pass needs locks or merging, and each cell's sum
+    // comes out the same whatever thread handled it. Cells that aren't
+    // active send nothing, but still take their rain and sinks.
+    fn do_flow(&mut self, terrain: &Terrain, step: Step, active: Option<&[bool]>) -> Vec<TerrainDelta> {
+        #[cfg(feature = "gpu")]
+        if self.gpu.is_some() {
+            return self.do_gpu_flow(terrain, step);
+        }
+
+        let mut outgoing = std::mem::take(&mut self.outgoing);
+        let mut changes = std::mem::take(&mut self.changes);
+        outgoing.resize(terrain.cells_len(), Outgoing::default());
+        changes.resize(terrain.cells_len(), [(0.0, 0.0); 2]);
+        if self.back_slots.len() != terrain.cells_len() {
+            self.back_slots = terrain.cells_iter().map(|cell| back_slots(terrain, &cell)).collect();
+        }
+
+        let flow = &*self;
+        let loads = flow.suspended_transport.and(terrain.layer(SUSPENDED_LOAD_LAYER));
+        for_each_chunk(&mut outgoing, |start, chunk| {
+            let mut chunk_pending = Pending::default();
+            for (cell_index, out) in (start..).zip(chunk.iter_mut()) {
+                let cell = terrain.get_cell(cell_index);
+                if active.is_none_or(|active| active[cell_index]) {
+                    let load = loads.map_or(0.0, |loads| loads[cell_index]);
+                    *out = flow.calc_outgoing(cell_index, &cell, terrain, step, load, &mut chunk_pending);
+                } else {
+                    flow.calc_still(out, cell_index, terrain, step, &mut chunk_pending.budget);
                 }
-                drop(tx_work);
-            });
+                out.keep(&flow.calc_sink_delta(cell_index, &cell, step.dt, &mut chunk_pending.budget));
+            }
+            let mut pending = flow.pending.lock().unwrap();
+            pending.suspended.append(&mut chunk_pending.suspended);
+            pending.budget.add(&chunk_pending.budget, 1.0);
+        });
+
+        let sent = &outgoing;
+        for_each_chunk(&mut changes, |start, chunk| {
+            for (cell_index, change) in (start..).zip(chunk.iter_mut()) {
+                *change = gather(terrain, sent, &flow.back_slots[cell_index], cell_index);
+            }
+        });
 
-            // process each cell in parallel threads
-            for _ in 0..num_cpus::get() {
-                let (tx, rx) = (tx_result.clone(), rx_work.clone());
-                s.spawn(move |_| {
-                    for cell_index in rx.iter() {
-                        let cell = terrain.get_cell(cell_index);
-                        for delta in self.calc_flow_deltas(cell_index, cell, terrain) {
-                            tx.send(delta).unwrap();
-                        }
-                        for delta in self.calc_sink_deltas(cell_index, cell) {
-                            tx.send(delta).unwrap();
-                        }
-                    }
-                });
+        let deltas = change_deltas(&changes);
+        self.outgoing = outgoing;
+        self.changes = changes;
+        deltas
+    }
+
+    // The GPU hands back what each cell loses and what its neighbors sent
+    // it; the cell's rain and sinks are added to those much as do_flow adds
+    // them to what the cell keeps.
+    #[cfg(feature = "gpu")]
+    fn do_gpu_flow(&mut self, terrain: &Terrain, step: Step) -> Vec<TerrainDelta> {
+        let params = TransferParams {
+            flow_share: within_step(self.flow_rate, step.dt),
+            flow_erosion_rate: self.flow_erosion_rate,
+            erosion_threshold: self.erosion_threshold,
+            erosion_share: within_step(self.erosion_rate, step.dt),
+            delivered: self.eroded_routing.delivered,
+        };
+        let transfers = self.gpu.as_mut().unwrap().transfers(terrain, &params);
+        let mut changes = std::mem::take(&mut self.changes);
+        changes.resize(terrain.cells_len(), [(0.0, 0.0); 2]);
+
+        let flow = &*self;
+        let lost = 1.0 - flow.eroded_routing.delivered;
+        for_each_chunk(&mut changes, |start, chunk| {
+            let mut budget = MassBudget::default();
+            for (cell_index, change) in (start..).zip(chunk.iter_mut()) {
+                let cell = terrain.get_cell(cell_index);
+                let (height_delta, mut depth_delta) = transfers.own[cell_index];
+                if let Some(rain) = flow.calc_rain(cell_index, terrain, step, &mut budget) {
+                    depth_delta += rain;
+                }
+                let mut kept = Outgoing::default();
+                kept.keep(&TerrainDelta { cell_index, height_delta, depth_delta });
+                kept.keep(&flow.calc_sink_delta(cell_index, &cell, step.dt, &mut budget));
+                budget.carried_off += transfers.eroded[cell_index] * lost;
+                let received = transfers.received[cell_index];
+                *change = [kept.loss, (kept.gain.0 + received.0, kept.gain.1 + received.1)];
             }
+            flow.pending.lock().unwrap().budget.add(&budget, 1.0);
+        });
 
-            drop(tx_result);
+        let deltas = change_deltas(&changes);
+        self.changes = changes;
+        deltas
+    }
 
-            rx_result.iter().collect()
-        }).unwrap()
+    fn next_step(&mut self, dt: f64) -> Step {
+        *self.pending.get_mut().unwrap() = Pending::default();
+        let RainState { rng, bursts } = &mut self.rain;
+        Step { raining: self.precipitation.advance(bursts, rng), seed: rng.gen(), dt }
     }
 
-    fn calc_sink_deltas(&self, cell_index: usize, cell: &Cell) -> Vec<TerrainDelta> {
+    // Each cell's flux is half the net water it sent each neighbor, in the
+    // direction of that neighbor, so water running straight through a cell
+    // counts once whether coming in or going out.
+    fn update_flux(&self, terrain: &mut Terrain, wrap_width: Option<f64>) {
+        // nothing has flowed yet
+        if self.outgoing.len() != terrain.cells_len() {
+            return;
+        }
+        let mut flux = vec![(0.0, 0.0); terrain.cells_len()];
+        let sent = &self.outgoing;
+        let back_slots = &self.back_slots;
+        let cells: &Terrain = terrain;
+        for_each_chunk(&mut flux, |start, chunk| {
+            for (cell_index, flux) in (start..).zip(chunk.iter_mut()) {
+                let cell = cells.get_cell(cell_index);
+                for (slot, (nd, &back_slot)) in cell.neighbor_data_iter().zip(back_slots[cell_index].iter()).enumerate() {
+                    // inactive cells send nothing
+                    let out = sent[cell_index].sent.get(slot).map_or(0.0, |sent| sent.1);
+                    let back = sent[nd.index()].sent.get(back_slot as usize).map_or(0.0, |sent| sent.1);
+                    let (x, y) = direction(&cell, &cells.get_cell(nd.index()), wrap_width);
+                    flux.0 += 0.5 * (out - back) * x;
+                    flux.1 += 0.5 * (out - back) * y;
+                }
+            }
+        });
+        for (x, &(flux_x, _)) in terrain.layer_mut(FLUX_X_LAYER).iter_mut().zip(flux.iter()) {
+            *x = flux_x;
+        }
+        for (y, &(_, flux_y)) in terrain.layer_mut(FLUX_Y_LAYER).iter_mut().zip(flux.iter()) {
+            *y = flux_y;
+        }
+    }
+
+    fn calc_sink_delta(&self, cell_index: usize, cell: &Cell, dt: f64, budget: &mut MassBudget) -> TerrainDelta {
+        let policy = &self.drain_policy;
         let mut height_delta = 0.0;
         let mut depth_delta = 0.0;
-        if cell.height() < 0.0 {
-            height_delta = -0.5 * cell.height();
+        if cell.height() < policy.floor_height {
+            height_delta = within_step(policy.floor_rate, dt) * (policy.floor_height - cell.height());
         }
-        if cell.depth() > 1.0 {
-            depth_delta = -0.5 * (cell.depth() - 1.0);
+        if cell.depth() > policy.max_depth {
+            depth_delta = -within_step(policy.drain_rate, dt) * (cell.depth() - policy.max_depth);
         }
-        vec!(TerrainDelta { cell_index, height_delta, depth_delta })
+        budget.floored += height_delta;
+        budget.drained -= depth_delta;
+        TerrainDelta { cell_index, height_delta, depth_delta }
     }
 
-    fn calc_flow_deltas(&self, cell_index: usize, cell: &Cell, terrain: &Terrain) -> Vec<TerrainDelta> {
+    fn calc_outgoing(
+        &self,
+        cell_index: usize,
+        cell: &Cell,
+        terrain: &Terrain,
+        step: Step,
+        load: f64,
+        pending: &mut Pending,
+    ) -> Outgoing {
         let flow_weights = self.calc_flow_weights(terrain, cell);
-        let flow_agg = aggregate_transfer_weights(flow_weights.values());
+        let flow_agg = aggregate_transfer_weights(flow_weights.iter().map(|(_, weight)| weight));
 
         let erosion_weights = self.calc_erosion_weights(terrain, cell);
-        let erosion_agg = aggregate_transfer_weights(erosion_weights.values());
+        let erosion_agg = aggregate_transfer_weights(erosion_weights.iter().map(|(_, weight)| weight));
 
         let mut self_delta: Option<TerrainDelta> = None;
-        let mut neighbor_deltas: HashMap<usize, TerrainDelta> = HashMap::new();
+        // by neighbor slot
+        let mut neighbor_deltas: PerNeighbor<TerrainDelta> = cell.neighbor_data_iter()
+            .map(|nd| TerrainDelta::new(nd.index()))
+            .collect();
+        let mut carried = 0.0;
+        let mut outflow = 0.0;
+        let mut steepest = 0.0f64;
+        let lost = 1.0 - self.eroded_routing.delivered - self.eroded_routing.suspended;
 
-        for (&neighbor_index, flow_weight) in flow_weights.iter() {
-            let depth_delta = (flow_weight.weight / flow_agg.weight) * flow_agg.available * self.flow_rate;
+        for &(slot, ref flow_weight) in flow_weights.iter() {
+            let depth_delta = (flow_weight.weight / flow_agg.weight) * flow_agg.available * within_step(self.flow_rate, step.dt);
             let height_delta = depth_delta * self.flow_erosion_rate;
 
             if depth_delta > 0.0 {
-                let neighbor_delta = neighbor_deltas
-                    .entry(neighbor_index)
-                    .or_insert(TerrainDelta::new(neighbor_index));
+                let neighbor_delta = &mut neighbor_deltas[slot];
+                let neighbor_index = neighbor_delta.cell_index;
                 neighbor_delta.depth_delta += depth_delta;
-                //neighbor_delta.height_delta += height_delta;
+                neighbor_delta.height_delta += height_delta * self.eroded_routing.delivered;
+                if self.eroded_routing.suspended > 0.0 {
+                    pending.suspended.push((neighbor_index, height_delta * self.eroded_routing.suspended));
+                }
+                if load > 0.0 {
+                    let share = load * depth_delta / cell.depth();
+                    pending.suspended.push((neighbor_index, share));
+                    carried += share;
+                }
+                pending.budget.carried_off += height_delta * lost;
+                outflow += depth_delta;
+                steepest = steepest.max(flow_weight.weight.cbrt());
 
                 let self_delta = self_delta
                     .get_or_insert(TerrainDelta::new(cell_index));
@@ -117,14 +466,11 @@ impl DefaultFlow {
             }
         }
 
-        for (&neighbor_index, erosion_weight) in erosion_weights.iter() {
-            let height_delta = (erosion_weight.weight / erosion_agg.weight) * erosion_agg.available * self.erosion_rate;
+        for &(slot, ref erosion_weight) in erosion_weights.iter() {
+            let height_delta = (erosion_weight.weight / erosion_agg.weight) * erosion_agg.available * within_step(self.erosion_rate, step.dt);
 
             if height_delta > 0.0 {
-                let neighbor_delta = neighbor_deltas
-                    .entry(neighbor_index)
-                    .or_insert(TerrainDelta::new(neighbor_index));
-                neighbor_delta.height_delta += height_delta;
+                neighbor_deltas[slot].height_delta += height_delta;
 
                 let self_delta = self_delta
                     .get_or_insert(TerrainDelta::new(cell_index));
@@ -132,30 +478,59 @@ impl DefaultFlow {
             }
         }
 
-        if let Some(precipitation_amount) = self.calc_precipitation() {
+        if let Some(transport) = self.suspended_transport.filter(|_| load > 0.0) {
+            let excess = load - transport.capacity * outflow * steepest;
+            let settled = (transport.settling_rate * excess).min(load - carried);
+            if settled > 0.0 {
+                let self_delta = self_delta
+                    .get_or_insert(TerrainDelta::new(cell_index));
+                self_delta.height_delta += settled;
+            }
+            pending.suspended.push((cell_index, -carried - settled.max(0.0)));
+        }
+
+        if let Some(rain) = self.calc_rain(cell_index, terrain, step, &mut pending.budget) {
             let self_delta = self_delta
                 .get_or_insert(TerrainDelta::new(cell_index));
-            self_delta.depth_delta += precipitation_amount;
+            self_delta.depth_delta += rain;
         }
 
-        let mut deltas: Vec<_> = neighbor_deltas
-            .drain()
-            .map(|(_, delta)| { delta })
-            .collect();
+        let mut outgoing = Outgoing {
+            sent: neighbor_deltas.iter().map(|delta| (delta.height_delta, delta.depth_delta)).collect(),
+            ..Outgoing::default()
+        };
         if let Some(self_delta) = self_delta {
-            deltas.push(self_delta);
+            outgoing.keep(&self_delta);
         }
+        outgoing
+    }
 
-        deltas
+    // what an inactive cell keeps, which is only its rain
+    fn calc_still(&self, out: &mut Outgoing, cell_index: usize, terrain: &Terrain, step: Step, budget: &mut MassBudget) {
+        out.gain = (0.0, 0.0);
+        out.loss = (0.0, 0.0);
+        out.sent.clear();
+        if let Some(rain) = self.calc_rain(cell_index, terrain, step, budget) {
+            out.keep(&TerrainDelta { cell_index, height_delta: 0.0, depth_delta: rain });
+        }
+    }
+
+    fn calc_rain(&self, cell_index: usize, terrain: &Terrain, step: Step, budget: &mut MassBudget) -> Option<f64> {
+        let global_index = self.global_indices.as_ref().map_or(cell_index, |indices| indices[cell_index]);
+        let mut cell_rng = SplitMix64::stream(step.seed, global_index as u64);
+        let precipitation_amount = self.precipitation.sample(step.raining, &mut cell_rng)?;
+        let intensity = terrain.layer(PRECIPITATION_MAP_LAYER).map_or(1.0, |map| map[cell_index]);
+        budget.precipitation += precipitation_amount * intensity;
+        Some(precipitation_amount * intensity)
     }
 
-    fn calc_flow_weights(&self, terrain: &Terrain, cell: &Cell) -> HashMap<usize, TransferWeight> {
+    fn calc_flow_weights(&self, terrain: &Terrain, cell: &Cell) -> PerNeighbor<(usize, TransferWeight)> {
         calc_transfer_weights_with(terrain, cell, |cell, neighbor, n_data| {
             self.calc_flow_weight(cell, neighbor, n_data.distance())
         })
     }
 
-    fn calc_erosion_weights(&self, terrain: &Terrain, cell: &Cell) -> HashMap<usize, TransferWeight> {
+    fn calc_erosion_weights(&self, terrain: &Terrain, cell: &Cell) -> PerNeighbor<(usize, TransferWeight)> {
         calc_transfer_weights_with(terrain, cell, |cell, neighbor, n_data| {
             self.calc_erosion_weight(cell, neighbor, n_data.distance())
         })
@@ -183,33 +558,189 @@ impl DefaultFlow {
         }
     }
 
-    fn calc_precipitation(&self) -> Option<f64> {
-        if rand::thread_rng().gen::<f64>() < self.precipitation_rate {
-            Some(self.precipitation_amount)
-        } else {
-            None
+}
+
+impl Flow for DefaultFlow {
+    fn flow(&mut self, terrain: &Terrain, dt: f64) -> Vec<TerrainDelta> {
+        let step = self.next_step(dt);
+        self.do_flow(terrain, step, None)
+    }
+
+    fn flow_active(&mut self, terrain: &Terrain, dt: f64, active: &[bool]) -> Vec<TerrainDelta> {
+        let step = self.next_step(dt);
+        self.do_flow(terrain, step, Some(active))
+    }
+
+    fn update_layers(&mut self, terrain: &mut Terrain, time_delta: f64) {
+        if let Some(wrap_width) = self.flux {
+            self.update_flux(terrain, wrap_width);
+        }
+        let pending = &self.pending.get_mut().unwrap().suspended;
+        if pending.is_empty() {
+            return;
+        }
+        let load = terrain.layer_mut(SUSPENDED_LOAD_LAYER);
+        for &(cell_index, amount) in pending.iter() {
+            load[cell_index] += amount * time_delta;
         }
+        // what leaves a cell never exceeds its load, but the sums may round
+        // just below zero
+        load.iter_mut().for_each(|load| *load = load.max(0.0));
+    }
+
+    fn budget(&self) -> MassBudget {
+        self.pending.lock().unwrap().budget
+    }
+
+    // the rain's generator followed by where any bursts are
+    fn random_state(&self) -> Vec<u8> {
+        let mut state = Vec::new();
+        rng::write_state(&self.rain.rng, &mut state);
+        self.rain.bursts.write(&mut state);
+        state
+    }
+
+    fn restore_random_state(&mut self, mut state: &[u8]) -> Result<(), String> {
+        let rng = rng::read_state(&mut state)?;
+        let bursts = BurstState::read(&mut state)?;
+        if !state.is_empty() {
+            return Err(String::from("unexpected data after the rain's random state"));
+        }
+        self.rain = RainState { rng, bursts };
+        Ok(())
+    }
+
+    fn set_global_indices(&mut self, indices: &[usize]) {
+        self.global_indices = Some(indices.to_vec());
+    }
+
+    fn accepts_param(&self, name: &str, value: f64) -> bool {
+        if !value.is_finite() {
+            return false;
+        }
+        if name.starts_with("precipitation_") {
+            return self.precipitation.accepts_param(name, value);
+        }
+        match name {
+            "flow_rate" | "flow_erosion_rate" | "erosion_threshold" | "erosion_rate" => true,
+            "floor_height" | "max_depth" => true,
+            "floor_rate" | "drain_rate" => value >= 0.0,
+            "suspended_capacity" => value >= 0.0 && self.suspended_transport.is_some(),
+            "settling_rate" => value > 0.0 && value <= 1.0 && self.suspended_transport.is_some(),
+            _ => false,
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f64) -> bool {
+        if !self.accepts_param(name, value) {
+            return false;
+        }
+        if name.starts_with("precipitation_") {
+            return self.precipitation.set_param(name, value);
+        }
+        let param = match (name, self.suspended_transport.as_mut()) {
+            ("flow_rate", _) => &mut self.flow_rate,
+            ("flow_erosion_rate", _) => &mut self.flow_erosion_rate,
+            ("erosion_threshold", _) => &mut self.erosion_threshold,
+            ("erosion_rate", _) => &mut self.erosion_rate,
+            ("floor_height", _) => &mut self.drain_policy.floor_height,
+            ("floor_rate", _) => &mut self.drain_policy.floor_rate,
+            ("max_depth", _) => &mut self.drain_policy.max_depth,
+            ("drain_rate", _) => &mut self.drain_policy.drain_rate,
+            ("suspended_capacity", Some(transport)) => &mut transport.capacity,
+            (_, Some(transport)) => &mut transport.settling_rate,
+            _ => return false,
+        };
+        *param = value;
+        true
     }
 }
 
-impl Flow for DefaultFlow {
-    fn flow(&self, terrain: &Terrain) -> Vec<TerrainDelta> {
-        self.do_flow(terrain)
+// Runs work over the items in ranges of CHUNK_CELLS on every thread, each
+// range handed whole to the one thread that takes it along with the index
+// of its first item.
+fn for_each_chunk<T: Send>(items: &mut [T], work: impl Fn(usize, &mut [T]) + Sync) {
+    let (tx_work, rx_work) = channel::unbounded();
+    for (chunk_index, chunk) in items.chunks_mut(CHUNK_CELLS).enumerate() {
+        tx_work.send((chunk_index * CHUNK_CELLS, chunk)).unwrap();
     }
+    drop(tx_work);
+
+    let work = &work;
+    crossbeam::scope(|s| {
+        for _ in 0..num_cpus::get() {
+            let rx = rx_work.clone();
+            s.spawn(move |_| {
+                for (start, chunk) in rx.iter() {
+                    work(start, chunk);
+                }
+            });
+        }
+    }).unwrap();
 }
 
-fn calc_transfer_weights_with<F>(terrain: &Terrain, cell: &Cell, calc: F) -> HashMap<usize, TransferWeight>
+// the nonzero changes, losses first for each cell
+fn change_deltas(changes: &[[(f64, f64); 2]]) -> Vec<TerrainDelta> {
+    let mut deltas = Vec::new();
+    for (cell_index, change) in changes.iter().enumerate() {
+        for &(height_delta, depth_delta) in change.iter() {
+            if height_delta != 0.0 || depth_delta != 0.0 {
+                deltas.push(TerrainDelta { cell_index, height_delta, depth_delta });
+            }
+        }
+    }
+    deltas
+}
+
+// a cell's losses, then its gains along with what its neighbors sent it
+fn gather(terrain: &Terrain, outgoing: &[Outgoing], back_slots: &[u16], cell_index: usize) -> [(f64, f64); 2] {
+    let own = &outgoing[cell_index];
+    let mut gain = own.gain;
+    for (nd, &slot) in terrain.get_cell(cell_index).neighbor_data_iter().zip(back_slots.iter()) {
+        // inactive cells send nothing
+        let sent = outgoing[nd.index()].sent.get(slot as usize).copied().unwrap_or_default();
+        gain.0 += sent.0;
+        gain.1 += sent.1;
+    }
+    [own.loss, gain]
+}
+
+// the unit vector from a cell toward its neighbor
+fn direction(cell: &Cell, neighbor: &Cell, wrap_width: Option<f64>) -> (f64, f64) {
+    let mut x = neighbor.x() - cell.x();
+    let y = neighbor.y() - cell.y();
+    if let Some(width) = wrap_width.filter(|&width| x.abs() > width / 2.0) {
+        x -= width.copysign(x);
+    }
+    let length = x.hypot(y);
+    if length > 0.0 {
+        (x / length, y / length)
+    } else {
+        (0.0, 0.0)
+    }
+}
+
+// neighbors always list each other, as Terrain::validate checks
+fn back_slots(terrain: &Terrain, cell: &Cell) -> PerNeighbor<u16> {
+    cell.neighbor_data_iter()
+        .map(|nd| {
+            let slot = terrain.get_cell(nd.index()).neighbor_data_iter().position(|back| back.index() == cell.index());
+            slot.expect("neighbors list each other") as u16
+        })
+        .collect()
+}
+
+// keyed by neighbor slot and in neighbor order, so the weights always sum
+// the same way
+fn calc_transfer_weights_with<F>(terrain: &Terrain, cell: &Cell, calc: F) -> PerNeighbor<(usize, TransferWeight)>
     where
         F: Fn(&Cell, &Cell, &NeighborData) -> Option<TransferWeight>
 {
     cell.neighbor_data_iter()
-        .filter_map(|nd| {
+        .enumerate()
+        .filter_map(|(slot, nd)| {
             let neighbor = terrain.get_cell(nd.index());
-            if let Some(transfer_weight) = calc(cell, neighbor, nd) {
-                Some((nd.index(), transfer_weight))
-            } else {
-                None
-            }
+            calc(cell, &neighbor, nd).map(|transfer_weight| (slot, transfer_weight))
         })
         .collect()
 }
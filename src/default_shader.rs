@@ -1,11 +1,45 @@
-use crate::render::{RGB, Shade};
+use crate::render::{FrameTime, RGB, Shade, VerticalScale};
 use crate::terrain::{Cell, Terrain};
 
-pub struct DefaultShader;
+// water shows where it stands on ground less than this far above sea level
+const SHORE_ELEVATION: f64 = 1.0;
+
+pub struct DefaultShader {
+    vertical: VerticalScale,
+}
+
+// the default shader's ground lit from the northwest, without its water
+pub struct HillshadeShader {
+    vertical: VerticalScale,
+}
+
+// the same lighting alone in gray, 1 on flat ground, to multiply over the
+// colors of other shaders
+pub struct ReliefShader {
+    vertical: VerticalScale,
+}
+
+impl DefaultShader {
+    pub fn new(vertical: VerticalScale) -> DefaultShader {
+        DefaultShader { vertical }
+    }
+}
+
+impl HillshadeShader {
+    pub fn new(vertical: VerticalScale) -> HillshadeShader {
+        HillshadeShader { vertical }
+    }
+}
+
+impl ReliefShader {
+    pub fn new(vertical: VerticalScale) -> ReliefShader {
+        ReliefShader { vertical }
+    }
+}
 
 impl Shade for DefaultShader {
-    fn shade_cell(&self, cell: &Cell, terrain: &Terrain) -> RGB {
-        if cell.depth() > 0.1 && cell.height() < 1.0 {
+    fn shade_cell(&self, cell: &Cell, terrain: &Terrain, time: FrameTime) -> RGB {
+        if cell.depth() > 0.1 && self.vertical.elevation(cell.height()) < SHORE_ELEVATION {
             let factor = (cell.depth() - 0.1) * 2.0 + 0.5;
             RGB {
                 r: 0.2 / factor,
@@ -13,28 +47,48 @@ impl Shade for DefaultShader {
                 b: 1.0 / factor,
             }
         } else {
-            let p_cell = [cell.x(), cell.y(), cell.height()];
-            let mut v_light = [-1.0, 1.0, 1.0];
-            vec3::norm_mut(&mut v_light);
-            let mut lighting_sum = 0.0;
-            let mut lighting_count = 0;
-            for neighbor_dat in cell.neighbor_data_iter() {
-                let neighbor = terrain.get_cell(neighbor_dat.index());
-                let p_neighbor = [neighbor.x(), neighbor.y(), neighbor.height()];
-                let mut v_neighbor = p_neighbor.clone();
-                vec3::sub_mut(&mut v_neighbor, &p_cell);
-                let mut v_normal = [v_neighbor[1], -v_neighbor[0], 0.0];
-                vec3::cross_mut(&mut v_normal, &v_neighbor);
-                vec3::norm_mut(&mut v_normal);
-                lighting_sum += vec3::dot(&v_normal, &v_light);
-                lighting_count += 1;
-            }
-            let lighting = 2.0 * lighting_sum / lighting_count as f64;
-            RGB {
-                r: 1.0 * lighting * lighting * lighting,
-                g: 0.5 * lighting * lighting * lighting,
-                b: 0.1 * lighting * lighting * lighting,
-            }
+            HillshadeShader::new(self.vertical).shade_cell(cell, terrain, time)
+        }
+    }
+}
+
+impl Shade for HillshadeShader {
+    fn shade_cell(&self, cell: &Cell, terrain: &Terrain, _time: FrameTime) -> RGB {
+        let lighting = 2.0 * lighting(cell, terrain, self.vertical);
+        RGB {
+            r: 1.0 * lighting * lighting * lighting,
+            g: 0.5 * lighting * lighting * lighting,
+            b: 0.1 * lighting * lighting * lighting,
         }
     }
 }
+
+impl Shade for ReliefShader {
+    fn shade_cell(&self, cell: &Cell, terrain: &Terrain, _time: FrameTime) -> RGB {
+        // flat ground faces the light at 1 / sqrt(3)
+        let gray = (lighting(cell, terrain, self.vertical) * 3f64.sqrt()).max(0.0);
+        RGB { r: gray, g: gray, b: gray }
+    }
+}
+
+// how squarely the ground around a cell faces the light, on average over
+// the slopes up to each of its neighbors
+fn lighting(cell: &Cell, terrain: &Terrain, vertical: VerticalScale) -> f64 {
+    let p_cell = [cell.x(), cell.y(), vertical.relief(cell.height())];
+    let mut v_light = [-1.0, 1.0, 1.0];
+    vec3::norm_mut(&mut v_light);
+    let mut lighting_sum = 0.0;
+    let mut lighting_count = 0;
+    for neighbor_dat in cell.neighbor_data_iter() {
+        let neighbor = terrain.get_cell(neighbor_dat.index());
+        let p_neighbor = [neighbor.x(), neighbor.y(), vertical.relief(neighbor.height())];
+        let mut v_neighbor = p_neighbor;
+        vec3::sub_mut(&mut v_neighbor, &p_cell);
+        let mut v_normal = [v_neighbor[1], -v_neighbor[0], 0.0];
+        vec3::cross_mut(&mut v_normal, &v_neighbor);
+        vec3::norm_mut(&mut v_normal);
+        lighting_sum += vec3::dot(&v_normal, &v_light);
+        lighting_count += 1;
+    }
+    lighting_sum / lighting_count as f64
+}
@@ -0,0 +1,106 @@
+use crate::climate::TEMPERATURE_LAYER;
+use crate::history::WETNESS_LAYER;
+use crate::render::{FrameTime, RGB, Shade, VerticalScale};
+use crate::terrain::{Cell, Terrain};
+
+const MAX_SHADED_DEPTH: f64 = 1.0;
+
+// grayscale from black at the datum to white at max_z
+pub struct HeightShader {
+    max_z: f64,
+    vertical: VerticalScale,
+}
+
+pub struct DepthShader;
+
+// currently wet cells in blue, and dry ground tinted from tan towards
+// teal by how often it has been wet, picking out ephemeral stream beds
+pub struct WetnessShader {
+    wet_depth: f64,
+}
+
+// grayscale view of any attribute layer, from zero up to max_value
+pub struct LayerShader {
+    name: &'static str,
+    max_value: f64,
+}
+
+// blue at or below min_temperature through white to red at max_temperature
+pub struct TemperatureShader {
+    min_temperature: f64,
+    max_temperature: f64,
+}
+
+impl HeightShader {
+    pub fn new(max_z: f64, vertical: VerticalScale) -> HeightShader {
+        assert!(max_z > vertical.datum);
+        HeightShader { max_z, vertical }
+    }
+}
+
+impl Shade for HeightShader {
+    fn shade_cell(&self, cell: &Cell, _terrain: &Terrain, _time: FrameTime) -> RGB {
+        let v = self.vertical.elevation(cell.height()) / self.vertical.elevation(self.max_z);
+        RGB { r: v, g: v, b: v }
+    }
+}
+
+impl Shade for DepthShader {
+    fn shade_cell(&self, cell: &Cell, _terrain: &Terrain, _time: FrameTime) -> RGB {
+        let v = (cell.depth() / MAX_SHADED_DEPTH).min(1.0);
+        RGB { r: 0.1 * (1.0 - v), g: 0.1 + 0.3 * v, b: 0.1 + 0.9 * v }
+    }
+}
+
+impl TemperatureShader {
+    pub fn new(min_temperature: f64, max_temperature: f64) -> TemperatureShader {
+        assert!(min_temperature < max_temperature);
+        TemperatureShader { min_temperature, max_temperature }
+    }
+}
+
+impl Shade for TemperatureShader {
+    fn shade_cell(&self, cell: &Cell, terrain: &Terrain, _time: FrameTime) -> RGB {
+        let temperature = terrain.layer(TEMPERATURE_LAYER).map_or(0.0, |layer| layer[cell.index()]);
+        let v = ((temperature - self.min_temperature) / (self.max_temperature - self.min_temperature)).clamp(0.0, 1.0);
+        if v < 0.5 {
+            let w = v * 2.0;
+            RGB { r: w, g: w, b: 1.0 }
+        } else {
+            let w = (1.0 - v) * 2.0;
+            RGB { r: 1.0, g: w, b: w }
+        }
+    }
+}
+
+impl LayerShader {
+    pub fn new(name: &'static str, max_value: f64) -> LayerShader {
+        assert!(max_value > 0.0);
+        LayerShader { name, max_value }
+    }
+}
+
+impl Shade for LayerShader {
+    fn shade_cell(&self, cell: &Cell, terrain: &Terrain, _time: FrameTime) -> RGB {
+        let value = terrain.layer(self.name).map_or(0.0, |layer| layer[cell.index()]);
+        let v = (value / self.max_value).clamp(0.0, 1.0);
+        RGB { r: v, g: v, b: v }
+    }
+}
+
+impl WetnessShader {
+    pub fn new(wet_depth: f64) -> WetnessShader {
+        WetnessShader { wet_depth }
+    }
+}
+
+impl Shade for WetnessShader {
+    fn shade_cell(&self, cell: &Cell, terrain: &Terrain, _time: FrameTime) -> RGB {
+        if cell.depth() > self.wet_depth {
+            return RGB { r: 0.1, g: 0.3, b: 0.9 };
+        }
+        let wetness = terrain.layer(WETNESS_LAYER).map_or(0.0, |layer| layer[cell.index()]);
+        let v = wetness.sqrt().min(1.0);
+        RGB { r: 0.8 - 0.7 * v, g: 0.7 - 0.1 * v, b: 0.5 + 0.1 * v }
+    }
+}
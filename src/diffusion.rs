@@ -0,0 +1,90 @@
+use crate::flow::{within_step, Flow};
+use crate::terrain::{Terrain, TerrainDelta};
+
+// Soil creep: material moves from every cell to each lower neighbor at a
+// rate of diffusivity * height difference / distance^2, wet or dry, which
+// rounds off hilltops and ridgelines. Steps never move more than half of
+// what a cell stands above its lower neighbors, so a peak only ever sinks
+// towards them however long the step.
+pub struct HillslopeDiffusion {
+    diffusivity: f64,
+}
+
+impl HillslopeDiffusion {
+    pub fn new(diffusivity: f64) -> HillslopeDiffusion {
+        HillslopeDiffusion { diffusivity }
+    }
+}
+
+impl Flow for HillslopeDiffusion {
+    fn flow(&mut self, terrain: &Terrain, dt: f64) -> Vec<TerrainDelta> {
+        let mut deltas = Vec::new();
+        for cell in terrain.cells_iter() {
+            // the share of its drop the cell sends down per unit time, summed
+            // over its lower neighbors, held to half of it over the step
+            let rate: f64 = cell.neighbor_data_iter()
+                .filter(|nd| terrain.get_cell(nd.index()).height() < cell.height())
+                .map(|nd| self.diffusivity / (nd.distance() * nd.distance()))
+                .sum();
+            if rate <= 0.0 {
+                continue;
+            }
+            let scale = within_step(rate, 2.0 * dt) / rate;
+
+            let mut self_delta = TerrainDelta::new(cell.index());
+            for nd in cell.neighbor_data_iter() {
+                let diff = cell.height() - terrain.get_cell(nd.index()).height();
+                if diff > 0.0 {
+                    let creep = scale * self.diffusivity * diff / (nd.distance() * nd.distance());
+                    self_delta.height_delta -= creep;
+                    let mut neighbor_delta = TerrainDelta::new(nd.index());
+                    neighbor_delta.height_delta = creep;
+                    deltas.push(neighbor_delta);
+                }
+            }
+            if self_delta.height_delta != 0.0 {
+                deltas.push(self_delta);
+            }
+        }
+        deltas
+    }
+
+    fn accepts_param(&self, name: &str, value: f64) -> bool {
+        name == "diffusivity" && value.is_finite() && value >= 0.0
+    }
+
+    fn set_param(&mut self, name: &str, value: f64) -> bool {
+        if self.accepts_param(name, value) {
+            self.diffusivity = value;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flow::FlowEngine;
+    use crate::point::Point;
+
+    #[test]
+    fn spike_decays_monotonically_for_large_steps() {
+        let spike = |p: &Point| if (p.x - 4.5).abs() < 0.1 && (p.y - 4.5).abs() < 0.1 { 10.0 } else { 0.0 };
+        let terrain = Terrain::square_grid(9.0, 9.0, 1.0, spike, |_| 0.0);
+        let peak = (0..terrain.cells_len()).find(|&index| terrain.get_cell(index).height() > 0.0).unwrap();
+        let mut flow_engine = FlowEngine::new(terrain, HillslopeDiffusion::new(1.0));
+        let mut last = 10.0;
+        for _ in 0..20 {
+            flow_engine.step(100.0);
+            let terrain = flow_engine.terrain();
+            let cell = terrain.get_cell(peak);
+            let highest = cell.neighbor_data_iter().map(|nd| terrain.get_cell(nd.index()).height()).fold(f64::MIN, f64::max);
+            assert!(cell.height() <= last, "the peak rose from {} to {}", last, cell.height());
+            assert!(cell.height() >= highest, "the peak sank to {} below a neighbor at {}", cell.height(), highest);
+            last = cell.height();
+        }
+        assert!(last < 10.0);
+    }
+}
@@ -0,0 +1,367 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command};
+
+use crate::flow::{self, Flow};
+use crate::point::Point;
+use crate::terrain::Terrain;
+
+// set on worker processes to the coordinator address they should join
+pub const COORDINATOR_ENV: &str = "TERRAIN_FLOW_COORDINATOR";
+
+// each worker keeps two rings of cells around its own: the flow of a cell
+// depends on its neighbors, and reaches only its neighbors, so the cells
+// next to a worker's own need their full neighborhood for the deltas they
+// send inwards to be right
+const HALO_DEPTH: usize = 2;
+
+const STEP: u8 = 1;
+const GATHER: u8 = 2;
+const SET_PARAM: u8 = 3;
+const SCATTER: u8 = 4;
+const FINISH: u8 = 0;
+
+// Splits the terrain into strips across x, one per worker process, which
+// hold only their own cells and the halo around them. Every step the
+// workers flow their strips in parallel and swap the edge cells their
+// neighbors' halos need, routed through this coordinator. The coordinator
+// keeps the whole terrain for rendering, but it is only current after a
+// gather; changes made to it must be scattered back before stepping on.
+pub struct Cluster {
+    workers: Vec<WorkerLink>,
+    children: Vec<Child>,
+}
+
+struct WorkerLink {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+    // global indices of the worker's own cells followed by its halo, in the
+    // order the worker stores them
+    cells: Vec<usize>,
+    owned_count: usize,
+    // own cells other workers need each step, and halo cells to refresh,
+    // as positions in `cells`
+    exports: Vec<usize>,
+    imports: Vec<usize>,
+}
+
+pub fn coordinator_from_env() -> Option<String> {
+    env::var(COORDINATOR_ENV).ok()
+}
+
+impl Cluster {
+    // listens on addr for `workers` worker processes, first starting them as
+    // copies of this executable if spawn_local is set; otherwise they are
+    // started by hand (on any machine) with the same arguments and
+    // TERRAIN_FLOW_COORDINATOR set to the address
+    pub fn start(addr: &str, workers: u32, spawn_local: bool, terrain: &Terrain) -> Cluster {
+        assert!(workers > 0);
+        let listener = TcpListener::bind(addr).unwrap_or_else(|e| panic!("failed to listen on {}: {}", addr, e));
+        let mut children = Vec::new();
+        if spawn_local {
+            let exe = env::current_exe().unwrap();
+            for _ in 0..workers {
+                let child = Command::new(&exe)
+                    .args(env::args().skip(1))
+                    .env(COORDINATOR_ENV, addr)
+                    .spawn()
+                    .unwrap_or_else(|e| panic!("failed to start worker: {}", e));
+                children.push(child);
+            }
+        }
+        println!("waiting for {} workers on {}", workers, addr);
+
+        let mut links = Vec::new();
+        for owned in partition(terrain, workers as usize).into_iter() {
+            let (stream, peer) = listener.accept().unwrap();
+            println!("worker {} joined from {}", links.len(), peer);
+            stream.set_nodelay(true).unwrap();
+            let halo = halo(terrain, &owned);
+            let owned_count = owned.len();
+            let mut cells = owned;
+            cells.extend(halo);
+            links.push(WorkerLink {
+                reader: BufReader::new(stream.try_clone().unwrap()),
+                writer: BufWriter::new(stream),
+                cells,
+                owned_count,
+                exports: Vec::new(),
+                imports: Vec::new(),
+            });
+        }
+
+        // a worker exports its own cells that appear in any other halo
+        let mut exported = vec![false; terrain.cells_len()];
+        for link in links.iter_mut() {
+            link.imports = (link.owned_count..link.cells.len()).collect();
+            for &index in link.cells[link.owned_count..].iter() {
+                exported[index] = true;
+            }
+        }
+        for link in links.iter_mut() {
+            link.exports = (0..link.owned_count).filter(|&position| exported[link.cells[position]]).collect();
+        }
+
+        for link in links.iter_mut() {
+            link.send_setup(terrain);
+        }
+        Cluster { workers: links, children }
+    }
+
+    pub fn step(&mut self, terrain: &mut Terrain, time_step: f64) {
+        for link in self.workers.iter_mut() {
+            link.writer.write_all(&[STEP]).unwrap();
+            write_f64(&mut link.writer, time_step);
+            link.writer.flush().unwrap();
+        }
+        for link in self.workers.iter_mut() {
+            for &position in link.exports.iter() {
+                let (height, depth) = (read_f64(&mut link.reader), read_f64(&mut link.reader));
+                terrain.set_cell(link.cells[position], height, depth);
+            }
+        }
+        for link in self.workers.iter_mut() {
+            for &position in link.imports.iter() {
+                let cell = terrain.get_cell(link.cells[position]);
+                write_f64(&mut link.writer, cell.height());
+                write_f64(&mut link.writer, cell.depth());
+            }
+            link.writer.flush().unwrap();
+        }
+    }
+
+    // brings the whole terrain up to date from the workers
+    pub fn gather(&mut self, terrain: &mut Terrain) {
+        for link in self.workers.iter_mut() {
+            link.writer.write_all(&[GATHER]).unwrap();
+            link.writer.flush().unwrap();
+        }
+        for link in self.workers.iter_mut() {
+            for &index in link.cells[..link.owned_count].iter() {
+                let (height, depth) = (read_f64(&mut link.reader), read_f64(&mut link.reader));
+                terrain.set_cell(index, height, depth);
+            }
+        }
+    }
+
+    // sends every worker the current state of all its cells
+    pub fn scatter(&mut self, terrain: &Terrain) {
+        for link in self.workers.iter_mut() {
+            link.writer.write_all(&[SCATTER]).unwrap();
+            for &index in link.cells.iter() {
+                let cell = terrain.get_cell(index);
+                write_f64(&mut link.writer, cell.height());
+                write_f64(&mut link.writer, cell.depth());
+            }
+            link.writer.flush().unwrap();
+        }
+    }
+
+    // true if every worker's flow took the parameter
+    pub fn set_param(&mut self, name: &str, value: f64) -> bool {
+        for link in self.workers.iter_mut() {
+            link.writer.write_all(&[SET_PARAM]).unwrap();
+            write_u64(&mut link.writer, name.len() as u64);
+            link.writer.write_all(name.as_bytes()).unwrap();
+            write_f64(&mut link.writer, value);
+            link.writer.flush().unwrap();
+        }
+        // every reply must be read, so no stopping at the first refusal
+        let replies: Vec<u8> = self.workers.iter_mut().map(|link| read_u8(&mut link.reader)).collect();
+        replies.iter().all(|&reply| reply == 1)
+    }
+
+    pub fn finish(mut self) {
+        for link in self.workers.iter_mut() {
+            link.writer.write_all(&[FINISH]).unwrap();
+            link.writer.flush().unwrap();
+        }
+        for mut child in self.children.drain(..) {
+            let status = child.wait().unwrap();
+            assert!(status.success(), "worker failed: {}", status);
+        }
+    }
+}
+
+impl WorkerLink {
+    // the worker's cells as (x, y, height, depth), its own cell count, and
+    // the links between its cells by position, then its exports and imports
+    // and the global index of each of its cells
+    fn send_setup(&mut self, terrain: &Terrain) {
+        let positions: HashMap<usize, usize> = self.cells.iter().enumerate().map(|(position, &index)| (index, position)).collect();
+        let w = &mut self.writer;
+        write_u64(w, self.cells.len() as u64);
+        write_u64(w, self.owned_count as u64);
+        for &index in self.cells.iter() {
+            let cell = terrain.get_cell(index);
+            for value in [cell.x(), cell.y(), cell.height(), cell.depth()] {
+                write_f64(w, value);
+            }
+        }
+
+        let mut links = Vec::new();
+        for (position, &index) in self.cells.iter().enumerate() {
+            for nd in terrain.get_cell(index).neighbor_data_iter() {
+                if let Some(&neighbor_position) = positions.get(&nd.index()) {
+                    if position < neighbor_position {
+                        links.push((position, neighbor_position, nd.distance()));
+                    }
+                }
+            }
+        }
+        write_u64(w, links.len() as u64);
+        for (a, b, distance) in links {
+            write_u64(w, a as u64);
+            write_u64(w, b as u64);
+            write_f64(w, distance);
+        }
+
+        for list in [&self.exports, &self.imports, &self.cells] {
+            write_u64(w, list.len() as u64);
+            for &position in list.iter() {
+                write_u64(w, position as u64);
+            }
+        }
+        w.flush().unwrap();
+    }
+}
+
+// joins the coordinator at addr and flows its share of the terrain with
+// the given strategy until told to finish, in the fixed delta order when
+// deterministic
+pub fn run_worker(addr: &str, mut strategy: Box<dyn Flow>, deterministic: bool) {
+    let stream = TcpStream::connect(addr).unwrap_or_else(|e| panic!("failed to join coordinator {}: {}", addr, e));
+    stream.set_nodelay(true).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = BufWriter::new(stream);
+
+    let cell_count = read_u64(&mut reader) as usize;
+    let owned_count = read_u64(&mut reader) as usize;
+    let mut points = Vec::with_capacity(cell_count);
+    let mut surface = Vec::with_capacity(cell_count);
+    for _ in 0..cell_count {
+        points.push(Point { x: read_f64(&mut reader), y: read_f64(&mut reader) });
+        surface.push((read_f64(&mut reader), read_f64(&mut reader)));
+    }
+    let links: Vec<(usize, usize, f64)> = (0..read_u64(&mut reader))
+        .map(|_| (read_u64(&mut reader) as usize, read_u64(&mut reader) as usize, read_f64(&mut reader)))
+        .collect();
+    let mut read_list = || -> Vec<usize> { (0..read_u64(&mut reader)).map(|_| read_u64(&mut reader) as usize).collect() };
+    let exports = read_list();
+    let imports = read_list();
+    strategy.set_global_indices(&read_list());
+
+    let mut terrain = Terrain::generate_with_neighbors(points.into_iter(), &links, |_| 0.0, |_| 0.0);
+    for (index, &(height, depth)) in surface.iter().enumerate() {
+        terrain.set_cell(index, height, depth);
+    }
+
+    loop {
+        match read_u8(&mut reader) {
+            STEP => {
+                let time_step = read_f64(&mut reader);
+                let mut deltas = strategy.flow(&terrain, time_step);
+                deltas.retain(|delta| delta.cell_index < owned_count);
+                if deterministic {
+                    flow::sort_deltas(&mut deltas);
+                }
+                for delta in deltas.iter_mut() {
+                    delta.height_delta *= time_step;
+                    delta.depth_delta *= time_step;
+                }
+                terrain.apply_deltas(&deltas);
+                strategy.update_layers(&mut terrain, time_step);
+                write_cells(&mut writer, &terrain, &exports);
+                writer.flush().unwrap();
+                for &position in imports.iter() {
+                    terrain.set_cell(position, read_f64(&mut reader), read_f64(&mut reader));
+                }
+            }
+            GATHER => {
+                write_cells(&mut writer, &terrain, &(0..owned_count).collect::<Vec<usize>>());
+                writer.flush().unwrap();
+            }
+            SCATTER => {
+                for index in 0..cell_count {
+                    terrain.set_cell(index, read_f64(&mut reader), read_f64(&mut reader));
+                }
+            }
+            SET_PARAM => {
+                let mut name = vec![0; read_u64(&mut reader) as usize];
+                reader.read_exact(&mut name).unwrap();
+                let name = String::from_utf8(name).unwrap();
+                let accepted = strategy.set_param(&name, read_f64(&mut reader));
+                writer.write_all(&[accepted as u8]).unwrap();
+                writer.flush().unwrap();
+            }
+            FINISH => return,
+            command => panic!("unknown command {} from coordinator", command),
+        }
+    }
+}
+
+// strips of equal cell count ordered by x
+fn partition(terrain: &Terrain, workers: usize) -> Vec<Vec<usize>> {
+    let mut order: Vec<usize> = (0..terrain.cells_len()).collect();
+    order.sort_by(|&a, &b| terrain.get_cell(a).x().partial_cmp(&terrain.get_cell(b).x()).unwrap());
+    let per_worker = order.len().div_ceil(workers);
+    let mut strips: Vec<Vec<usize>> = order.chunks(per_worker.max(1)).map(|chunk| chunk.to_vec()).collect();
+    strips.resize(workers, Vec::new());
+    strips
+}
+
+// cells within HALO_DEPTH links of the worker's own that belong to others
+fn halo(terrain: &Terrain, owned: &[usize]) -> Vec<usize> {
+    let mut seen: HashSet<usize> = owned.iter().cloned().collect();
+    let mut halo = Vec::new();
+    let mut frontier = owned.to_vec();
+    for _ in 0..HALO_DEPTH {
+        let mut next = Vec::new();
+        for &index in frontier.iter() {
+            for nd in terrain.get_cell(index).neighbor_data_iter() {
+                if seen.insert(nd.index()) {
+                    next.push(nd.index());
+                }
+            }
+        }
+        halo.extend(next.iter().cloned());
+        frontier = next;
+    }
+    halo
+}
+
+fn write_cells(w: &mut impl Write, terrain: &Terrain, positions: &[usize]) {
+    for &position in positions.iter() {
+        let cell = terrain.get_cell(position);
+        write_f64(w, cell.height());
+        write_f64(w, cell.depth());
+    }
+}
+
+fn write_u64(w: &mut impl Write, value: u64) {
+    w.write_all(&value.to_le_bytes()).unwrap();
+}
+
+fn write_f64(w: &mut impl Write, value: f64) {
+    w.write_all(&value.to_le_bytes()).unwrap();
+}
+
+fn read_u8(r: &mut impl Read) -> u8 {
+    let mut buf = [0; 1];
+    r.read_exact(&mut buf).unwrap();
+    buf[0]
+}
+
+fn read_u64(r: &mut impl Read) -> u64 {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf).unwrap();
+    u64::from_le_bytes(buf)
+}
+
+fn read_f64(r: &mut impl Read) -> f64 {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf).unwrap();
+    f64::from_le_bytes(buf)
+}
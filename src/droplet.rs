@@ -0,0 +1,141 @@
+use rand::Rng;
+use rand_chacha::ChaCha12Rng;
+
+use crate::flow::Flow;
+use crate::rng;
+use crate::terrain::{Terrain, TerrainDelta};
+
+// the longest walk a droplet takes
+const MAX_STEPS: u32 = 64;
+// the least slope a droplet's capacity is figured from, so it keeps some on
+// the flats
+const MIN_SLOPE: f64 = 0.01;
+// a droplet with less water than this has evaporated
+const MIN_WATER: f64 = 0.01;
+
+// Droplet erosion, as in games: each unit of time `droplets` droplets land
+// on random cells and walk from cell to cell down the steepest way,
+// speeding up as they drop and losing `evaporation` of their water each
+// cell. A droplet can carry `capacity` times its slope, speed and water in
+// sediment; below that it wears away `erosion_rate` of the shortfall from
+// the ground it leaves, never more than the drop to the next cell, and above
+// it sets down `deposition_rate` of the excess. It drops everything it
+// carries where it stops, in a pit, once dry or after its longest walk.
+// Droplets only shape the ground and ignore the water on the terrain, so
+// they stack on the flow as a cheap stand-in for channel erosion. Later
+// droplets in a step see the ground earlier ones left.
+pub struct DropletFlow {
+    droplets: u32,
+    capacity: f64,
+    erosion_rate: f64,
+    deposition_rate: f64,
+    evaporation: f64,
+    rng: ChaCha12Rng,
+}
+
+impl DropletFlow {
+    pub fn new(droplets: u32, capacity: f64, erosion_rate: f64, deposition_rate: f64, evaporation: f64) -> DropletFlow {
+        assert!(droplets > 0);
+        assert!(capacity >= 0.0 && capacity.is_finite());
+        assert!((0.0..=1.0).contains(&erosion_rate));
+        assert!((0.0..=1.0).contains(&deposition_rate));
+        assert!((0.0..1.0).contains(&evaporation));
+        DropletFlow { droplets, capacity, erosion_rate, deposition_rate, evaporation, rng: rng::chacha_rng(None) }
+    }
+
+    // lands the droplets where the seed says, so runs repeat
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = rng::chacha_rng(Some(seed));
+    }
+}
+
+impl Flow for DropletFlow {
+    fn flow(&mut self, terrain: &Terrain, _dt: f64) -> Vec<TerrainDelta> {
+        if terrain.cells_len() == 0 {
+            return Vec::new();
+        }
+        let original: Vec<f64> = terrain.cells_iter().map(|cell| cell.height()).collect();
+        let mut heights = original.clone();
+
+        for _ in 0..self.droplets {
+            let mut index = self.rng.gen_range(0..terrain.cells_len());
+            let (mut speed, mut water, mut sediment) = (1.0f64, 1.0, 0.0);
+            for _ in 0..MAX_STEPS {
+                let next = terrain.get_cell(index).neighbor_data_iter()
+                    .map(|nd| (nd.index(), (heights[index] - heights[nd.index()]) / nd.distance()))
+                    .max_by(|(_, a), (_, b)| a.total_cmp(b));
+                let (next_index, slope) = match next {
+                    Some((next_index, slope)) if slope > 0.0 => (next_index, slope),
+                    _ => break,
+                };
+                let drop = heights[index] - heights[next_index];
+
+                let capacity = self.capacity * slope.max(MIN_SLOPE) * speed * water;
+                if sediment > capacity {
+                    let deposited = self.deposition_rate * (sediment - capacity);
+                    heights[index] += deposited;
+                    sediment -= deposited;
+                } else {
+                    let eroded = (self.erosion_rate * (capacity - sediment)).min(drop);
+                    heights[index] -= eroded;
+                    sediment += eroded;
+                }
+
+                speed = (speed * speed + drop).sqrt();
+                water *= 1.0 - self.evaporation;
+                index = next_index;
+                if water < MIN_WATER {
+                    break;
+                }
+            }
+            heights[index] += sediment;
+        }
+
+        heights.iter().zip(original.iter()).enumerate()
+            .filter(|(_, (height, original))| height != original)
+            .map(|(index, (height, original))| {
+                let mut delta = TerrainDelta::new(index);
+                delta.height_delta = height - original;
+                delta
+            })
+            .collect()
+    }
+
+    fn random_state(&self) -> Vec<u8> {
+        let mut state = Vec::new();
+        rng::write_state(&self.rng, &mut state);
+        state
+    }
+
+    fn restore_random_state(&mut self, mut state: &[u8]) -> Result<(), String> {
+        self.rng = rng::read_state(&mut state)?;
+        if !state.is_empty() {
+            return Err(String::from("unexpected data after the droplets' random state"));
+        }
+        Ok(())
+    }
+
+    fn accepts_param(&self, name: &str, value: f64) -> bool {
+        match name {
+            "droplets" => value >= 1.0 && value.fract() == 0.0 && value <= u32::MAX as f64,
+            "droplet_capacity" => value >= 0.0 && value.is_finite(),
+            "droplet_erosion_rate" | "droplet_deposition_rate" => (0.0..=1.0).contains(&value),
+            "droplet_evaporation" => (0.0..1.0).contains(&value),
+            _ => false,
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f64) -> bool {
+        if !self.accepts_param(name, value) {
+            return false;
+        }
+        match name {
+            "droplets" => self.droplets = value as u32,
+            "droplet_capacity" => self.capacity = value,
+            "droplet_erosion_rate" => self.erosion_rate = value,
+            "droplet_deposition_rate" => self.deposition_rate = value,
+            _ => self.evaporation = value,
+        }
+        true
+    }
+}
@@ -0,0 +1,135 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::process::{Child, Command};
+
+use crate::raster;
+use crate::render::VerticalScale;
+use crate::stats::TerrainStats;
+use crate::terrain::Terrain;
+
+// set on the worker processes of a parallel ensemble to the member they run
+pub const MEMBER_ENV: &str = "TERRAIN_FLOW_ENSEMBLE_MEMBER";
+
+const STATS_HEADER: &str = "member,min_height,max_height,mean_height,relief,water_volume,wet_cells";
+const RASTERS: [&str; 2] = ["height", "depth"];
+
+// the ensemble member this process was started to run, if any
+pub fn member_from_env() -> Option<u32> {
+    env::var(MEMBER_ENV).ok().map(|member| member.parse().unwrap_or_else(|_| panic!("invalid {} {}", MEMBER_ENV, member)))
+}
+
+pub fn member_path(render_path: &str, member: u32) -> String {
+    format!("{}/member_{:03}", render_path, member)
+}
+
+// writes the final height above the datum and depth as ascii grids and the
+// final stats as a one row csv into the member's directory, for aggregate to
+// collect
+pub fn write_member_results(terrain: &Terrain, world_size: (usize, usize), vertical: VerticalScale, path: &str, member: u32) {
+    let (width, height) = world_size;
+    let heights: Vec<f64> = terrain.cells_iter().map(|cell| vertical.elevation(cell.height())).collect();
+    let depths: Vec<f64> = terrain.cells_iter().map(|cell| cell.depth()).collect();
+    for (name, values) in RASTERS.iter().zip([heights, depths].iter()) {
+        let grid = raster::sample_grid(terrain, values, width, height);
+        raster::write_ascii_grid(&format!("{}/final_{}.asc", path, name), width, height, &grid);
+    }
+
+    let stats = TerrainStats::of(terrain);
+    let mut w = BufWriter::new(File::create(format!("{}/final_stats.csv", path)).unwrap());
+    writeln!(w, "{}", STATS_HEADER).unwrap();
+    writeln!(
+        w,
+        "{},{},{},{},{},{},{}",
+        member,
+        stats.min_height,
+        stats.max_height,
+        stats.mean_height,
+        stats.relief(),
+        stats.water_volume,
+        stats.wet_cells,
+    ).unwrap();
+}
+
+// reruns this executable with the same arguments once per member, keeping
+// up to `processes` of them going at a time
+pub fn run_processes(members: u32, processes: u32) {
+    let exe = env::current_exe().unwrap();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut running: Vec<(u32, Child)> = Vec::new();
+    let mut next_member = 0;
+    while next_member < members || !running.is_empty() {
+        while next_member < members && running.len() < processes as usize {
+            println!("starting ensemble member {} of {}", next_member + 1, members);
+            let child = Command::new(&exe)
+                .args(&args)
+                .env(MEMBER_ENV, next_member.to_string())
+                .spawn()
+                .unwrap_or_else(|e| panic!("failed to start ensemble member {}: {}", next_member, e));
+            running.push((next_member, child));
+            next_member += 1;
+        }
+        let (member, mut child) = running.remove(0);
+        let status = child.wait().unwrap();
+        assert!(status.success(), "ensemble member {} failed: {}", member, status);
+    }
+}
+
+// combines the members' final grids into per-pixel mean and variance grids
+// (ensemble_height_mean.asc, ensemble_height_variance.asc and the same for
+// depth) and their stats into ensemble_stats.csv, with rows for every
+// member followed by the mean and standard deviation across members
+pub fn aggregate(render_path: &str, members: u32) {
+    assert!(members > 0);
+    for name in RASTERS.iter() {
+        let grids: Vec<(usize, usize, Vec<f64>)> = (0..members)
+            .map(|member| raster::read_ascii_grid(&format!("{}/final_{}.asc", member_path(render_path, member), name)))
+            .collect();
+        let (width, height) = (grids[0].0, grids[0].1);
+        assert!(grids.iter().all(|grid| grid.0 == width && grid.1 == height));
+
+        let values: Vec<Vec<f64>> = grids.into_iter().map(|grid| grid.2).collect();
+        let (mean, variance) = mean_and_variance(&values);
+        raster::write_ascii_grid(&format!("{}/ensemble_{}_mean.asc", render_path, name), width, height, &mean);
+        raster::write_ascii_grid(&format!("{}/ensemble_{}_variance.asc", render_path, name), width, height, &variance);
+    }
+
+    let rows: Vec<Vec<f64>> = (0..members)
+        .map(|member| {
+            let path = format!("{}/final_stats.csv", member_path(render_path, member));
+            let text = fs::read_to_string(&path).unwrap_or_else(|e| panic!("failed to read {}: {}", path, e));
+            let row = text.lines().nth(1).unwrap_or_else(|| panic!("{} has no stats", path));
+            row.split(',').skip(1).map(|value| value.parse().unwrap()).collect()
+        })
+        .collect();
+    let (mean, variance) = mean_and_variance(&rows);
+    let join = |values: &[f64]| values.iter().map(|value| value.to_string()).collect::<Vec<String>>().join(",");
+
+    let mut w = BufWriter::new(File::create(format!("{}/ensemble_stats.csv", render_path)).unwrap());
+    writeln!(w, "{}", STATS_HEADER).unwrap();
+    for (member, row) in rows.iter().enumerate() {
+        writeln!(w, "{},{}", member, join(row)).unwrap();
+    }
+    writeln!(w, "mean,{}", join(&mean)).unwrap();
+    let std_dev: Vec<f64> = variance.iter().map(|v| v.sqrt()).collect();
+    writeln!(w, "std,{}", join(&std_dev)).unwrap();
+}
+
+// elementwise over equally long samples, with the population variance
+fn mean_and_variance(samples: &[Vec<f64>]) -> (Vec<f64>, Vec<f64>) {
+    let n = samples.len() as f64;
+    let len = samples[0].len();
+    let mut mean = vec![0.0; len];
+    for sample in samples.iter() {
+        for (m, v) in mean.iter_mut().zip(sample.iter()) {
+            *m += v / n;
+        }
+    }
+    let mut variance = vec![0.0; len];
+    for sample in samples.iter() {
+        for ((var, v), m) in variance.iter_mut().zip(sample.iter()).zip(mean.iter()) {
+            *var += (v - m) * (v - m) / n;
+        }
+    }
+    (mean, variance)
+}
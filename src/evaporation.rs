@@ -0,0 +1,61 @@
+use crate::flow::Flow;
+use crate::invariants::MassBudget;
+use crate::terrain::{Terrain, TerrainDelta};
+
+// Standing water evaporating: each cell loses `rate` of depth per unit time
+// plus `depth_coefficient` of its depth, never more in a step than it has,
+// so lakes settle where their inflow and rain balance the loss from their
+// surface. The depth term stands in for a lake's surface growing as it
+// fills.
+pub struct Evaporation {
+    rate: f64,
+    depth_coefficient: f64,
+    // water taken by the last flow, per unit time
+    evaporated: f64,
+}
+
+impl Evaporation {
+    pub fn new(rate: f64, depth_coefficient: f64) -> Evaporation {
+        assert!(rate >= 0.0 && rate.is_finite());
+        assert!((0.0..=1.0).contains(&depth_coefficient));
+        Evaporation { rate, depth_coefficient, evaporated: 0.0 }
+    }
+}
+
+impl Flow for Evaporation {
+    fn flow(&mut self, terrain: &Terrain, dt: f64) -> Vec<TerrainDelta> {
+        let deltas: Vec<TerrainDelta> = terrain.cells_iter()
+            .filter(|cell| cell.depth() > 0.0)
+            .map(|cell| {
+                let mut delta = TerrainDelta::new(cell.index());
+                delta.depth_delta = -(self.rate + self.depth_coefficient * cell.depth()).min(cell.depth() / dt);
+                delta
+            })
+            .collect();
+        self.evaporated = -deltas.iter().map(|delta| delta.depth_delta).sum::<f64>();
+        deltas
+    }
+
+    fn budget(&self) -> MassBudget {
+        MassBudget { evaporation: self.evaporated, ..MassBudget::default() }
+    }
+
+    fn accepts_param(&self, name: &str, value: f64) -> bool {
+        match name {
+            "evaporation_rate" => value >= 0.0 && value.is_finite(),
+            "evaporation_depth_coefficient" => (0.0..=1.0).contains(&value),
+            _ => false,
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f64) -> bool {
+        if !self.accepts_param(name, value) {
+            return false;
+        }
+        match name {
+            "evaporation_rate" => self.rate = value,
+            _ => self.depth_coefficient = value,
+        }
+        true
+    }
+}
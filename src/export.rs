@@ -0,0 +1,419 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::raster;
+use crate::render::{FrameTime, Shade, VerticalScale};
+use crate::terrain::{Cell, Terrain};
+
+// deeper than this, a cell's water is part of the water surface
+const WET_DEPTH: f64 = 0.01;
+// the largest RAW heightmap, past what Unity or Unreal import
+pub const MAX_RAW_SIZE: usize = 8193;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MeshFormat {
+    Obj,
+    Ply,
+}
+
+// A surface over the faces of the mesh, a vertex to a cell, listed in
+// `cells`, in world units with z up from the datum as drawn, each triangle
+// counterclockwise seen from above so its face points up.
+// Triangles across the seam of a wrapping world are left out.
+pub struct Surface {
+    pub cells: Vec<usize>,
+    pub vertices: Vec<[f64; 3]>,
+    pub triangles: Vec<[usize; 3]>,
+}
+
+// Writes the ground as terrain.obj or terrain.ply and, if `water` is set,
+// the water's surface over every triangle with a wet corner as water.obj or
+// water.ply, for viewing in Blender and the like. OBJ files are y up, as
+// their importers expect, so the world's y runs along -z; PLY files keep z
+// up.
+#[derive(Clone, Copy, Debug)]
+pub struct MeshExporter {
+    format: MeshFormat,
+    water: bool,
+}
+
+// Writes the ground as a solid for 3D printing, a binary STL in
+// millimeters: scaled so the longer side of the world is `size` across,
+// with heights exaggerated as drawn, and closed with walls down to a flat
+// base `base` below its lowest point. Where the surface has holes, as along
+// the seam of a wrapping world, they're walled in too, so the solid is
+// always watertight.
+#[derive(Clone, Copy, Debug)]
+pub struct StlExporter {
+    size: f64,
+    base: f64,
+}
+
+// the binary chunk of a .glb as it's filled, with the JSON describing it
+struct GlbBuffer {
+    data: Vec<u8>,
+    views: Vec<String>,
+    accessors: Vec<String>,
+}
+
+impl Surface {
+    pub fn ground(terrain: &Terrain, world_size: (usize, usize), vertical: VerticalScale) -> Surface {
+        Surface::new(terrain, world_size, |cell| vertical.relief(cell.height()), |_| true)
+    }
+
+    pub fn water(terrain: &Terrain, world_size: (usize, usize), vertical: VerticalScale) -> Surface {
+        Surface::new(
+            terrain,
+            world_size,
+            |cell| vertical.relief(cell.height() + cell.depth()),
+            |cells| cells.iter().any(|cell| cell.depth() > WET_DEPTH),
+        )
+    }
+
+    // only the cells of kept triangles become vertices
+    fn new<E, K>(terrain: &Terrain, world_size: (usize, usize), elevation: E, keep: K) -> Surface
+        where E: Fn(&Cell) -> f64, K: Fn(&[Cell; 3]) -> bool {
+        let world_width = world_size.0 as f64;
+        let mut vertex_of: Vec<Option<usize>> = vec![None; terrain.cells_len()];
+        let mut surface = Surface { cells: Vec::new(), vertices: Vec::new(), triangles: Vec::new() };
+        for triangle in terrain.faces() {
+            let cells = triangle.map(|index| terrain.get_cell(index));
+            if (0..3).any(|corner| (cells[corner].x() - cells[(corner + 1) % 3].x()).abs() > world_width / 2.0)
+                || !keep(&cells) {
+                continue;
+            }
+            let [a, b, c] = &cells;
+            let turn = (b.x() - a.x()) * (c.y() - a.y()) - (b.y() - a.y()) * (c.x() - a.x());
+            let corners = if turn < 0.0 { [0, 2, 1] } else { [0, 1, 2] };
+            let mut vertices = [0; 3];
+            for (vertex, corner) in vertices.iter_mut().zip(corners) {
+                let cell = &cells[corner];
+                *vertex = match vertex_of[cell.index()] {
+                    Some(vertex) => vertex,
+                    None => {
+                        surface.cells.push(cell.index());
+                        surface.vertices.push([cell.x(), cell.y(), elevation(cell)]);
+                        vertex_of[cell.index()] = Some(surface.vertices.len() - 1);
+                        surface.vertices.len() - 1
+                    }
+                };
+            }
+            surface.triangles.push(vertices);
+        }
+        surface
+    }
+
+    fn write_obj(&self, path: &str) {
+        let mut w = BufWriter::new(File::create(path).unwrap_or_else(|e| panic!("couldn't create {}: {}", path, e)));
+        for [x, y, z] in self.vertices.iter() {
+            writeln!(w, "v {:.4} {:.4} {:.4}", x, z, -y).unwrap();
+        }
+        // indices count from 1
+        for [a, b, c] in self.triangles.iter() {
+            writeln!(w, "f {} {} {}", a + 1, b + 1, c + 1).unwrap();
+        }
+    }
+
+    // binary, little-endian
+    fn write_ply(&self, path: &str) {
+        let mut w = BufWriter::new(File::create(path).unwrap_or_else(|e| panic!("couldn't create {}: {}", path, e)));
+        writeln!(w, "ply").unwrap();
+        writeln!(w, "format binary_little_endian 1.0").unwrap();
+        writeln!(w, "element vertex {}", self.vertices.len()).unwrap();
+        writeln!(w, "property float x").unwrap();
+        writeln!(w, "property float y").unwrap();
+        writeln!(w, "property float z").unwrap();
+        writeln!(w, "element face {}", self.triangles.len()).unwrap();
+        writeln!(w, "property list uchar uint vertex_indices").unwrap();
+        writeln!(w, "end_header").unwrap();
+        for vertex in self.vertices.iter() {
+            for coordinate in vertex.iter() {
+                w.write_all(&(*coordinate as f32).to_le_bytes()).unwrap();
+            }
+        }
+        for triangle in self.triangles.iter() {
+            w.write_all(&[3]).unwrap();
+            for &vertex in triangle.iter() {
+                w.write_all(&(vertex as u32).to_le_bytes()).unwrap();
+            }
+        }
+    }
+}
+
+impl MeshExporter {
+    pub fn new(format: MeshFormat, water: bool) -> MeshExporter {
+        MeshExporter { format, water }
+    }
+
+    // into the directory at path
+    pub fn export(&self, terrain: &Terrain, world_size: (usize, usize), vertical: VerticalScale, path: &str) {
+        self.write(&Surface::ground(terrain, world_size, vertical), &format!("{}/terrain", path));
+        if self.water {
+            self.write(&Surface::water(terrain, world_size, vertical), &format!("{}/water", path));
+        }
+    }
+
+    fn write(&self, surface: &Surface, stem: &str) {
+        match self.format {
+            MeshFormat::Obj => surface.write_obj(&format!("{}.obj", stem)),
+            MeshFormat::Ply => surface.write_ply(&format!("{}.ply", stem)),
+        }
+    }
+}
+
+impl StlExporter {
+    pub fn new(size: f64, base: f64) -> StlExporter {
+        assert!(size > 0.0 && size.is_finite());
+        assert!(base > 0.0 && base.is_finite());
+        StlExporter { size, base }
+    }
+
+    pub fn export(&self, terrain: &Terrain, world_size: (usize, usize), vertical: VerticalScale, path: &str) {
+        let surface = Surface::ground(terrain, world_size, vertical);
+        let scale = self.size / world_size.0.max(world_size.1) as f64;
+        let top: Vec<[f64; 3]> = surface.vertices.iter().map(|&[x, y, z]| [x * scale, y * scale, z * scale]).collect();
+        let floor = top.iter().map(|vertex| vertex[2]).fold(f64::INFINITY, f64::min) - self.base;
+        let bottom: Vec<[f64; 3]> = top.iter().map(|&[x, y, _]| [x, y, floor]).collect();
+
+        let mut facets = Vec::new();
+        for &[a, b, c] in surface.triangles.iter() {
+            facets.push([top[a], top[b], top[c]]);
+            // facing down
+            facets.push([bottom[a], bottom[c], bottom[b]]);
+        }
+        // an edge only one triangle has is on the rim, where a wall goes
+        // down from it facing out, away from that triangle
+        let edges: HashSet<(usize, usize)> = surface.triangles.iter()
+            .flat_map(|&[a, b, c]| [(a, b), (b, c), (c, a)])
+            .collect();
+        for &(a, b) in edges.iter().filter(|&&(a, b)| !edges.contains(&(b, a))) {
+            facets.push([top[a], bottom[a], bottom[b]]);
+            facets.push([top[a], bottom[b], top[b]]);
+        }
+
+        let mut w = BufWriter::new(File::create(path).unwrap_or_else(|e| panic!("couldn't create {}: {}", path, e)));
+        let mut header = [b' '; 80];
+        header[..12].copy_from_slice(b"terrain_flow");
+        w.write_all(&header).unwrap();
+        w.write_all(&(facets.len() as u32).to_le_bytes()).unwrap();
+        for facet in facets.iter() {
+            for value in normal(facet).iter().chain(facet.iter().flatten()) {
+                w.write_all(&(*value as f32).to_le_bytes()).unwrap();
+            }
+            // no attributes
+            w.write_all(&0u16.to_le_bytes()).unwrap();
+        }
+    }
+}
+
+// Writes a glTF 2.0 binary, .glb, holding one mesh with two primitives: the
+// ground, its vertices colored by the shader, and the water's surface over
+// every triangle with a wet corner, in a translucent material of its own.
+// glTF is y up, so the world's y runs along -z.
+pub fn export_gltf<S: Shade + ?Sized>(shader: &S, terrain: &Terrain, time: FrameTime, world_size: (usize, usize), vertical: VerticalScale, path: &str) {
+    let mut buffer = GlbBuffer { data: Vec::new(), views: Vec::new(), accessors: Vec::new() };
+    let mut primitives = Vec::new();
+    let ground = Surface::ground(terrain, world_size, vertical);
+    let colors: Vec<[f64; 3]> = ground.cells.iter()
+        .map(|&index| {
+            let color = shader.shade_cell(&terrain.get_cell(index), terrain, time);
+            [linear(color.r), linear(color.g), linear(color.b)]
+        })
+        .collect();
+    primitives.push(format!(
+        r#"{{"attributes": {{"POSITION": {}, "COLOR_0": {}}}, "indices": {}, "material": 0}}"#,
+        buffer.positions(&ground),
+        buffer.vec3s(&colors, false),
+        buffer.indices(&ground),
+    ));
+    let water = Surface::water(terrain, world_size, vertical);
+    // accessors can't be empty
+    if !water.triangles.is_empty() {
+        primitives.push(format!(
+            r#"{{"attributes": {{"POSITION": {}}}, "indices": {}, "material": 1}}"#,
+            buffer.positions(&water),
+            buffer.indices(&water),
+        ));
+    }
+
+    let json = format!(
+        concat!(
+            r#"{{"asset": {{"version": "2.0", "generator": "terrain_flow"}}, "scene": 0, "scenes": [{{"nodes": [0]}}], "#,
+            r#""nodes": [{{"mesh": 0}}], "meshes": [{{"primitives": [{}]}}], "materials": ["#,
+            r#"{{"name": "ground", "pbrMetallicRoughness": {{"metallicFactor": 0, "roughnessFactor": 1}}}}, "#,
+            r#"{{"name": "water", "pbrMetallicRoughness": {{"baseColorFactor": [0.05, 0.2, 0.4, 0.6], "#,
+            r#""metallicFactor": 0, "roughnessFactor": 0.1}}, "alphaMode": "BLEND", "doubleSided": true}}], "#,
+            r#""buffers": [{{"byteLength": {}}}], "bufferViews": [{}], "accessors": [{}]}}"#,
+        ),
+        primitives.join(", "),
+        buffer.data.len(),
+        buffer.views.join(", "),
+        buffer.accessors.join(", "),
+    );
+
+    // chunks are padded to four bytes, the JSON with spaces
+    let mut json = json.into_bytes();
+    json.resize(json.len().next_multiple_of(4), b' ');
+    let mut data = buffer.data;
+    data.resize(data.len().next_multiple_of(4), 0);
+    let mut w = BufWriter::new(File::create(path).unwrap_or_else(|e| panic!("couldn't create {}: {}", path, e)));
+    w.write_all(b"glTF").unwrap();
+    w.write_all(&2u32.to_le_bytes()).unwrap();
+    w.write_all(&((12 + 8 + json.len() + 8 + data.len()) as u32).to_le_bytes()).unwrap();
+    w.write_all(&(json.len() as u32).to_le_bytes()).unwrap();
+    w.write_all(b"JSON").unwrap();
+    w.write_all(&json).unwrap();
+    w.write_all(&(data.len() as u32).to_le_bytes()).unwrap();
+    w.write_all(b"BIN\0").unwrap();
+    w.write_all(&data).unwrap();
+}
+
+// Writes the ground's heights above the datum resampled to a size by size
+// lattice over the world, size a power of two plus one, as a RAW heightmap
+// for Unity's Terrain or Unreal's Landscape, with its range and scales in a
+// json sidecar. The sidecar gives the spacing of the samples in world
+// units, and, taking a world unit as a meter, the scales Unreal's import
+// wants in percent: on import the range spans max - min, starting at min.
+pub fn export_raw(terrain: &Terrain, world_size: (usize, usize), vertical: VerticalScale, size: usize, path: &str) {
+    assert!(raw_size_valid(size));
+    let elevations: Vec<f64> = terrain.cells_iter().map(|cell| vertical.elevation(cell.height())).collect();
+    let grid = raster::sample_lattice(terrain, &elevations, world_size, size, size);
+    let range = grid.iter().cloned().fold(f64::NEG_INFINITY, f64::max) - grid.iter().cloned().fold(f64::INFINITY, f64::min);
+    let (spacing_x, spacing_y) = (world_size.0 as f64 / (size - 1) as f64, world_size.1 as f64 / (size - 1) as f64);
+    raster::write_raw16(path, size, size, &grid, &[
+        ("datum", vertical.datum),
+        ("world_width", world_size.0 as f64),
+        ("world_length", world_size.1 as f64),
+        ("spacing_x", spacing_x),
+        ("spacing_y", spacing_y),
+        // a meter is 100 units in Unreal, and its 16 bits span 512 of them
+        // at a z scale of 100
+        ("unreal_scale_x", spacing_x * 100.0),
+        ("unreal_scale_y", spacing_y * 100.0),
+        ("unreal_scale_z", range / 512.0 * 100.0),
+    ]);
+}
+
+impl GlbBuffer {
+    // in glTF's axes, with the bounds it requires
+    fn positions(&mut self, surface: &Surface) -> usize {
+        let positions: Vec<[f64; 3]> = surface.vertices.iter().map(|&[x, y, z]| [x, z, -y]).collect();
+        self.vec3s(&positions, true)
+    }
+
+    fn vec3s(&mut self, values: &[[f64; 3]], bounds: bool) -> usize {
+        let view = self.view(values.iter().flatten().flat_map(|&value| (value as f32).to_le_bytes()).collect(), 34962);
+        let bounds = if bounds {
+            let (mut min, mut max) = ([f32::INFINITY; 3], [f32::NEG_INFINITY; 3]);
+            for value in values.iter() {
+                for axis in 0..3 {
+                    min[axis] = min[axis].min(value[axis] as f32);
+                    max[axis] = max[axis].max(value[axis] as f32);
+                }
+            }
+            format!(r#", "min": {:?}, "max": {:?}"#, min, max)
+        } else {
+            String::new()
+        };
+        self.accessor(format!(r#"{{"bufferView": {}, "componentType": 5126, "count": {}, "type": "VEC3"{}}}"#, view, values.len(), bounds))
+    }
+
+    fn indices(&mut self, surface: &Surface) -> usize {
+        let view = self.view(surface.triangles.iter().flatten().flat_map(|&vertex| (vertex as u32).to_le_bytes()).collect(), 34963);
+        let count = surface.triangles.len() * 3;
+        self.accessor(format!(r#"{{"bufferView": {}, "componentType": 5125, "count": {}, "type": "SCALAR"}}"#, view, count))
+    }
+
+    // target is ARRAY_BUFFER, 34962, or ELEMENT_ARRAY_BUFFER, 34963
+    fn view(&mut self, bytes: Vec<u8>, target: u32) -> usize {
+        self.views.push(format!(
+            r#"{{"buffer": 0, "byteOffset": {}, "byteLength": {}, "target": {}}}"#,
+            self.data.len(),
+            bytes.len(),
+            target,
+        ));
+        self.data.extend(bytes);
+        self.views.len() - 1
+    }
+
+    fn accessor(&mut self, accessor: String) -> usize {
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+}
+
+// whether a RAW heightmap can be size by size: a power of two plus one, up
+// to MAX_RAW_SIZE
+pub fn raw_size_valid(size: usize) -> bool {
+    (3..=MAX_RAW_SIZE).contains(&size) && (size - 1).is_power_of_two()
+}
+
+// of a triangle, by the right hand rule
+fn normal([a, b, c]: &[[f64; 3]; 3]) -> [f64; 3] {
+    let (u, v) = ([b[0] - a[0], b[1] - a[1], b[2] - a[2]], [c[0] - a[0], c[1] - a[1], c[2] - a[2]]);
+    let n = [u[1] * v[2] - u[2] * v[1], u[2] * v[0] - u[0] * v[2], u[0] * v[1] - u[1] * v[0]];
+    let length = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+    if length > 0.0 { [n[0] / length, n[1] / length, n[2] / length] } else { [0.0; 3] }
+}
+
+// glTF's vertex colors are linear, where shaders' are sRGB
+fn linear(value: f64) -> f64 {
+    let value = value.clamp(0.0, 1.0);
+    if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::convert::TryInto;
+    use std::fs;
+
+    use super::*;
+    use crate::point::Point;
+
+    fn height_at(p: &Point) -> f64 {
+        1.0 + 0.3 * p.x.sin() + 0.1 * p.y
+    }
+
+    // every edge of a closed, consistently wound mesh is walked once each way
+    fn assert_watertight(terrain: &Terrain, name: &str) {
+        let path = std::env::temp_dir().join(format!("terrain_flow_{}_{}.stl", name, std::process::id()));
+        let path = path.to_str().unwrap();
+        StlExporter::new(10.0, 1.0).export(terrain, (12, 9), VerticalScale::new(0.0, 1.0), path);
+        let bytes = fs::read(path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        let count = u32::from_le_bytes(bytes[80..84].try_into().unwrap()) as usize;
+        assert_eq!(bytes.len(), 84 + count * 50);
+        let mut vertices = HashMap::new();
+        let mut edges = HashMap::new();
+        for facet in bytes[84..].chunks_exact(50) {
+            let corners: Vec<usize> = facet[12..48].chunks_exact(12)
+                .map(|corner| {
+                    let next = vertices.len();
+                    *vertices.entry(corner.to_vec()).or_insert(next)
+                })
+                .collect();
+            for corner in 0..3 {
+                *edges.entry((corners[corner], corners[(corner + 1) % 3])).or_insert(0) += 1;
+            }
+        }
+        for (&(a, b), &times) in edges.iter() {
+            assert_eq!(times, 1, "{}: edge {} -> {} walked {} times", name, a, b, times);
+            assert!(edges.contains_key(&(b, a)), "{}: edge {} -> {} has no way back", name, a, b);
+        }
+    }
+
+    #[test]
+    fn square_grid_stl_is_watertight() {
+        let terrain = Terrain::square_grid(12.0, 9.0, 1.0, height_at, |_| 0.0);
+        assert_watertight(&terrain, "square");
+    }
+
+    #[test]
+    fn hex_grid_stl_is_watertight() {
+        let terrain = Terrain::hex_grid(12.0, 9.0, 1.0, height_at, |_| 0.0);
+        assert_watertight(&terrain, "hex");
+    }
+}
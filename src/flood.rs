@@ -0,0 +1,27 @@
+use crate::terrain::{Terrain, TerrainDelta};
+
+// Adds `volume` of water at once, shared equally between the cells within
+// `radius` of (x, y), at the start of frame `frame`.
+#[derive(Clone)]
+pub struct FloodEvent {
+    pub frame: u32,
+    pub x: f64,
+    pub y: f64,
+    pub radius: f64,
+    pub volume: f64,
+}
+
+impl FloodEvent {
+    // returns the number of cells flooded
+    pub fn apply(&self, terrain: &mut Terrain) -> usize {
+        let cells: Vec<usize> = terrain.cells_iter()
+            .filter(|cell| (cell.x() - self.x).hypot(cell.y() - self.y) <= self.radius)
+            .map(|cell| cell.index())
+            .collect();
+        let depth_delta = self.volume / cells.len().max(1) as f64;
+        for &cell_index in cells.iter() {
+            terrain.apply_delta(&TerrainDelta { cell_index, height_delta: 0.0, depth_delta });
+        }
+        cells.len()
+    }
+}
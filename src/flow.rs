@@ -1,29 +1,459 @@
+use std::time::{Duration, Instant};
+
+use crate::active_cells::{ActiveCells, ActiveSet};
+use crate::checkpoint;
+use crate::invariants::{self, DepthPolicy, MassBudget, MassTotals, Violation};
+use crate::mass_audit::MassAudit;
+use crate::nan_guard::NanGuard;
 use crate::terrain::{Terrain, TerrainDelta};
 
+// water leaving each cell per unit time during the last step
+pub const DISCHARGE_LAYER: &str = "discharge";
+
 pub struct FlowEngine<S: Flow> {
     terrain: Terrain,
     strategy: S,
+    last_step: StepStats,
+    deterministic: bool,
+    guard: Option<NanGuard>,
+    adaptive: Option<AdaptiveStep>,
+    audit: Option<MassAudit>,
+    active: Option<ActiveSet>,
+}
+
+#[derive(Clone, Default)]
+pub struct StepStats {
+    pub duration: Duration,
+    pub delta_count: usize,
+    pub eroded: f64,
+    pub deposited: f64,
+    // the steps the flow was actually run in, more than one when adaptive
+    // stepping split it up
+    pub substeps: u32,
+    // the cells the step's last flow worked out what leaves, all of them
+    // unless tracking active cells
+    pub active_cells: usize,
+}
+
+// Splits a step into shorter ones wherever a whole step of the flow's rates
+// would take more than `max_share` of any cell's water, as large flow rates
+// or long steps otherwise overshoot, oscillate and blow up. Each substep
+// runs the flow afresh for the time left and takes as long as that allows,
+// but no shorter than the step over max_substeps, so a step never costs
+// more than that many flows.
+#[derive(Clone, Copy, Debug)]
+pub struct AdaptiveStep {
+    pub max_share: f64,
+    pub max_substeps: u32,
 }
 
 impl<S: Flow> FlowEngine<S> {
     pub fn new(terrain: Terrain, strategy: S) -> FlowEngine<S> {
-        FlowEngine { terrain, strategy }
+        FlowEngine {
+            terrain,
+            strategy,
+            last_step: StepStats::default(),
+            deterministic: false,
+            guard: None,
+            adaptive: None,
+            audit: None,
+            active: None,
+        }
+    }
+
+    // applies each step's deltas in a fixed order rather than the order the
+    // flow's threads produced them, so runs repeat exactly whatever the
+    // scheduling or core count
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.deterministic = deterministic;
+    }
+
+    pub fn set_guard(&mut self, guard: NanGuard) {
+        self.guard = Some(guard);
+    }
+
+    pub fn set_adaptive(&mut self, adaptive: AdaptiveStep) {
+        self.adaptive = Some(adaptive);
+    }
+
+    pub fn set_audit(&mut self, audit: MassAudit) {
+        self.audit = Some(audit);
+    }
+
+    pub fn audit(&self) -> Option<&MassAudit> {
+        self.audit.as_ref()
+    }
+
+    // has the flow skip cells that have gone quiet; see ActiveCells
+    pub fn set_active_cells(&mut self, active_cells: ActiveCells) {
+        self.active = Some(ActiveSet::new(active_cells));
+    }
+
+    // has the guard, if any, check the terrain after the processes that
+    // follow the flow each step
+    pub fn check_guard(&mut self) {
+        if let Some(guard) = self.guard.as_mut() {
+            guard.after_step(&mut self.terrain);
+        }
     }
 
     pub fn step(&mut self, time_delta: f64) {
-        let mut deltas = self.strategy.flow(&self.terrain);
+        if let Some(audit) = self.audit.as_mut() {
+            audit.begin_step(&self.terrain);
+        }
+        self.run_step(time_delta);
+        if let Some(audit) = self.audit.as_mut() {
+            audit.end_step(&self.terrain);
+        }
+    }
+
+    fn run_step(&mut self, time_delta: f64) {
+        let start = Instant::now();
+        let adaptive = match self.adaptive {
+            Some(adaptive) => adaptive,
+            None => {
+                let deltas = self.run_flow(time_delta);
+                self.apply_step(deltas, time_delta, start);
+                return;
+            }
+        };
+
+        let mut stats = StepStats::default();
+        let shortest = time_delta / adaptive.max_substeps as f64;
+        let mut remaining = time_delta;
+        while remaining > 0.0 {
+            let deltas = self.run_flow(remaining);
+            let mut dt = adaptive.stable_step(&self.terrain, &deltas).max(shortest).min(remaining);
+            // rather than leave a sliver of the step for another flow
+            if remaining - dt < shortest * 1e-6 {
+                dt = remaining;
+            }
+            self.apply_step(deltas, dt, start);
+            stats.delta_count += self.last_step.delta_count;
+            stats.eroded += self.last_step.eroded;
+            stats.deposited += self.last_step.deposited;
+            stats.substeps += 1;
+            stats.active_cells = self.last_step.active_cells;
+            remaining -= dt;
+        }
+        stats.duration = start.elapsed();
+        self.last_step = stats;
+    }
+
+    fn run_flow(&mut self, dt: f64) -> Vec<TerrainDelta> {
+        match self.active.as_mut() {
+            Some(active) => self.strategy.flow_active(&self.terrain, dt, active.begin_step(&self.terrain)),
+            None => self.strategy.flow(&self.terrain, dt),
+        }
+    }
+
+    // steps in one go, then reports the flow's deltas that aren't finite, the
+    // terrain's broken invariants and, given a tolerance, any change to total
+    // height or depth, for flows that should conserve both
+    pub fn step_validated(&mut self, time_delta: f64, depth_policy: DepthPolicy, mass_tolerance: Option<f64>) -> Vec<Violation> {
+        let start = Instant::now();
+        let before = MassTotals::of(&self.terrain);
+        let deltas = self.strategy.flow(&self.terrain, time_delta);
+        let mut violations = invariants::check_deltas(&self.terrain, &deltas);
+        if !violations.is_empty() {
+            return violations;
+        }
+        self.apply_step(deltas, time_delta, start);
+
+        if let Some(tolerance) = mass_tolerance {
+            violations.extend(MassTotals::of(&self.terrain).check(&before, tolerance));
+        }
+        violations.extend(self.terrain.validate(depth_policy));
+        violations
+    }
+
+    fn apply_step(&mut self, mut deltas: Vec<TerrainDelta>, time_delta: f64, start: Instant) {
+        let mut stats = StepStats::default();
+        if self.deterministic {
+            sort_deltas(&mut deltas);
+        }
+        let discharge = self.terrain.layer_mut(DISCHARGE_LAYER);
+        discharge.iter_mut().for_each(|d| *d = 0.0);
+        for delta in deltas.iter() {
+            if delta.depth_delta < 0.0 {
+                discharge[delta.cell_index] -= delta.depth_delta;
+            }
+        }
         for delta in deltas.iter_mut() {
             delta.height_delta *= time_delta;
             delta.depth_delta *= time_delta;
+            if delta.height_delta < 0.0 {
+                stats.eroded -= delta.height_delta;
+            } else {
+                stats.deposited += delta.height_delta;
+            }
+        }
+        if let Some(guard) = self.guard.as_mut() {
+            guard.before_flow(&self.terrain, &mut deltas);
         }
         self.terrain.apply_deltas(&deltas);
+        if let Some(active) = self.active.as_mut() {
+            active.after_flow(&deltas);
+        }
+        self.strategy.update_layers(&mut self.terrain, time_delta);
+        if let Some(audit) = self.audit.as_mut() {
+            audit.add_budget(&self.strategy.budget(), time_delta);
+        }
+        if let Some(guard) = self.guard.as_mut() {
+            guard.after_flow(&mut self.terrain, &deltas);
+        }
+
+        stats.delta_count = deltas.len();
+        stats.duration = start.elapsed();
+        stats.substeps = 1;
+        stats.active_cells = self.active.as_ref().map_or(self.terrain.cells_len(), ActiveSet::active_len);
+        self.last_step = stats;
     }
 
     pub fn terrain(&self) -> &Terrain {
         &self.terrain
     }
+
+    pub fn terrain_mut(&mut self) -> &mut Terrain {
+        &mut self.terrain
+    }
+
+    pub fn into_terrain(self) -> Terrain {
+        self.terrain
+    }
+
+    pub fn strategy(&self) -> &S {
+        &self.strategy
+    }
+
+    pub fn strategy_mut(&mut self) -> &mut S {
+        &mut self.strategy
+    }
+
+    pub fn last_step(&self) -> &StepStats {
+        &self.last_step
+    }
+}
+
+impl AdaptiveStep {
+    pub fn new(max_share: f64, max_substeps: u32) -> AdaptiveStep {
+        assert!(max_share > 0.0 && max_share <= 1.0);
+        assert!(max_substeps > 0);
+        AdaptiveStep { max_share, max_substeps }
+    }
+
+    // the longest time the deltas can be applied for without any cell
+    // losing more than max_share of its water
+    fn stable_step(&self, terrain: &Terrain, deltas: &[TerrainDelta]) -> f64 {
+        let mut net = vec![0.0; terrain.cells_len()];
+        for delta in deltas.iter() {
+            net[delta.cell_index] += delta.depth_delta;
+        }
+        net.iter().enumerate()
+            .filter(|(_, &net)| net < 0.0)
+            .map(|(index, &net)| self.max_share * terrain.get_cell(index).depth().max(0.0) / -net)
+            .fold(f64::INFINITY, f64::min)
+    }
+}
+
+// Puts deltas in an order that depends only on their values: by cell, then
+// by the deltas themselves. Each cell's deltas then sum the same way however
+// they were gathered.
+pub fn sort_deltas(deltas: &mut [TerrainDelta]) {
+    deltas.sort_unstable_by(|a, b| {
+        a.cell_index.cmp(&b.cell_index)
+            .then(a.height_delta.total_cmp(&b.height_delta))
+            .then(a.depth_delta.total_cmp(&b.depth_delta))
+    });
+}
+
+// A share of something to move per unit time, held down so that a step of
+// dt moves no more than all of it.
+pub fn within_step(rate: f64, dt: f64) -> f64 {
+    rate.min(dt.recip())
 }
 
+// Flows may keep whatever state they like from step to step, such as
+// random number generators or what their last flow left for update_layers.
 pub trait Flow {
-    fn flow(&self, terrain: &Terrain) -> Vec<TerrainDelta>;
+    // the changes to the terrain per unit time, which the engine scales by
+    // the step's dt once they're all gathered; dt is there for flows that
+    // must keep a step from overshooting, such as moving more water out of
+    // a cell than it holds
+    fn flow(&mut self, terrain: &Terrain, dt: f64) -> Vec<TerrainDelta>;
+
+    // like flow, but may leave out what leaves the cells not marked active,
+    // as nothing much has moved in or around them lately; what they gain
+    // from active neighbors still counts, and flows that can't tell the
+    // cells apart just work them all out
+    fn flow_active(&mut self, terrain: &Terrain, dt: f64, _active: &[bool]) -> Vec<TerrainDelta> {
+        self.flow(terrain, dt)
+    }
+
+    // updates any attribute layers the flow keeps once the deltas of its
+    // last flow are applied, with the same time delta they were scaled by
+    fn update_layers(&mut self, _terrain: &mut Terrain, _time_delta: f64) {}
+
+    // the water and ground the last flow brought in from outside the terrain
+    // or sent out of it, per unit time, so an audit can tell them from mass
+    // a flow gains or loses by mistake; flows that only move things around
+    // have none
+    fn budget(&self) -> MassBudget {
+        MassBudget::default()
+    }
+
+    // whatever the flow draws its random numbers from, as bytes, for a
+    // checkpoint to carry so a resumed run draws what the whole run would
+    // have; flows drawing none have none
+    fn random_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn restore_random_state(&mut self, state: &[u8]) -> Result<(), String> {
+        if state.is_empty() {
+            Ok(())
+        } else {
+            Err(String::from("random state for a flow that draws no random numbers"))
+        }
+    }
+
+    // the index each cell has in the whole terrain, when the flow is given
+    // only part of it, for flows whose random draws for a cell mustn't
+    // depend on how the terrain was split
+    fn set_global_indices(&mut self, _indices: &[usize]) {}
+
+    // whether set_param would take the parameter, leaving the strategy as
+    // it is
+    fn accepts_param(&self, _name: &str, _value: f64) -> bool {
+        false
+    }
+
+    // adjusts a named parameter between steps, returning false if the
+    // strategy has no such parameter or rejects the value
+    fn set_param(&mut self, _name: &str, _value: f64) -> bool {
+        false
+    }
+}
+
+// Runs several flows against the same terrain each step and applies all
+// of their deltas together, so independent processes can be layered on the
+// main hydraulic flow. The deltas come out flow by flow in the stack's
+// order, and each flow's layers are updated in that order too.
+pub struct FlowStack {
+    flows: Vec<Box<dyn Flow>>,
+}
+
+impl FlowStack {
+    pub fn new(flows: Vec<Box<dyn Flow>>) -> FlowStack {
+        assert!(!flows.is_empty());
+        FlowStack { flows }
+    }
+}
+
+impl Flow for FlowStack {
+    fn flow(&mut self, terrain: &Terrain, dt: f64) -> Vec<TerrainDelta> {
+        self.flows.iter_mut().flat_map(|flow| flow.flow(terrain, dt)).collect()
+    }
+
+    fn flow_active(&mut self, terrain: &Terrain, dt: f64, active: &[bool]) -> Vec<TerrainDelta> {
+        self.flows.iter_mut().flat_map(|flow| flow.flow_active(terrain, dt, active)).collect()
+    }
+
+    fn update_layers(&mut self, terrain: &mut Terrain, time_delta: f64) {
+        for flow in self.flows.iter_mut() {
+            flow.update_layers(terrain, time_delta);
+        }
+    }
+
+    fn budget(&self) -> MassBudget {
+        let mut budget = MassBudget::default();
+        for flow in self.flows.iter() {
+            budget.add(&flow.budget(), 1.0);
+        }
+        budget
+    }
+
+    // each flow's state in turn, after its length (u64)
+    fn random_state(&self) -> Vec<u8> {
+        let mut state = Vec::new();
+        for flow in self.flows.iter() {
+            let flow_state = flow.random_state();
+            state.extend_from_slice(&(flow_state.len() as u64).to_le_bytes());
+            state.extend_from_slice(&flow_state);
+        }
+        state
+    }
+
+    fn restore_random_state(&mut self, mut state: &[u8]) -> Result<(), String> {
+        for flow in self.flows.iter_mut() {
+            let len = checkpoint::read_u64(&mut state)? as usize;
+            if len > state.len() {
+                return Err(String::from("truncated random state"));
+            }
+            let (flow_state, rest) = state.split_at(len);
+            flow.restore_random_state(flow_state)?;
+            state = rest;
+        }
+        if !state.is_empty() {
+            return Err(String::from("random state for more flows than the stack has"));
+        }
+        Ok(())
+    }
+
+    fn set_global_indices(&mut self, indices: &[usize]) {
+        for flow in self.flows.iter_mut() {
+            flow.set_global_indices(indices);
+        }
+    }
+
+    fn accepts_param(&self, name: &str, value: f64) -> bool {
+        self.flows.iter().any(|flow| flow.accepts_param(name, value))
+    }
+
+    // offers the parameter to every flow in the stack
+    fn set_param(&mut self, name: &str, value: f64) -> bool {
+        let mut accepted = false;
+        for flow in self.flows.iter_mut() {
+            accepted |= flow.set_param(name, value);
+        }
+        accepted
+    }
+}
+
+impl<F: Flow + ?Sized> Flow for Box<F> {
+    fn flow(&mut self, terrain: &Terrain, dt: f64) -> Vec<TerrainDelta> {
+        (**self).flow(terrain, dt)
+    }
+
+    fn flow_active(&mut self, terrain: &Terrain, dt: f64, active: &[bool]) -> Vec<TerrainDelta> {
+        (**self).flow_active(terrain, dt, active)
+    }
+
+    fn update_layers(&mut self, terrain: &mut Terrain, time_delta: f64) {
+        (**self).update_layers(terrain, time_delta)
+    }
+
+    fn budget(&self) -> MassBudget {
+        (**self).budget()
+    }
+
+    fn random_state(&self) -> Vec<u8> {
+        (**self).random_state()
+    }
+
+    fn restore_random_state(&mut self, state: &[u8]) -> Result<(), String> {
+        (**self).restore_random_state(state)
+    }
+
+    fn set_global_indices(&mut self, indices: &[usize]) {
+        (**self).set_global_indices(indices)
+    }
+
+    fn accepts_param(&self, name: &str, value: f64) -> bool {
+        (**self).accepts_param(name, value)
+    }
+
+    fn set_param(&mut self, name: &str, value: f64) -> bool {
+        (**self).set_param(name, value)
+    }
 }
@@ -0,0 +1,51 @@
+use std::f64::consts::TAU;
+
+use crate::default_flow::{FLUX_X_LAYER, FLUX_Y_LAYER};
+use crate::render::{FrameTime, RGB, Shade};
+use crate::terrain::{Cell, Terrain};
+
+// about the flux of a stream
+pub const STREAM_FLUX: f64 = 0.001;
+
+// Draws where the water is going over another shader's ground, in gray: the
+// hue is the direction of a cell's flux, red flowing right across the frame
+// and going round through yellow as it turns up, and it shows through by
+// 1 - exp(-flux / scale), so a flux of `scale` is drawn about two thirds
+// full. Without the flux layers, see DefaultFlow::record_flux, the ground
+// is drawn as it is.
+pub struct FlowShader<S: Shade> {
+    ground: S,
+    scale: f64,
+}
+
+impl<S: Shade> FlowShader<S> {
+    pub fn new(ground: S, scale: f64) -> FlowShader<S> {
+        assert!(scale > 0.0 && scale.is_finite());
+        FlowShader { ground, scale }
+    }
+}
+
+impl<S: Shade> Shade for FlowShader<S> {
+    fn shade_cell(&self, cell: &Cell, terrain: &Terrain, time: FrameTime) -> RGB {
+        let ground = self.ground.shade_cell(cell, terrain, time);
+        let (flux_x, flux_y) = match (terrain.layer(FLUX_X_LAYER), terrain.layer(FLUX_Y_LAYER)) {
+            (Some(xs), Some(ys)) => (xs[cell.index()], ys[cell.index()]),
+            _ => return ground,
+        };
+        // gray, so only the flow has a hue
+        let gray = 0.3 * ground.r + 0.59 * ground.g + 0.11 * ground.b;
+        let strength = 1.0 - (-flux_x.hypot(flux_y) / self.scale).exp();
+        RGB { r: gray, g: gray, b: gray }.mix(&hue(flux_y.atan2(flux_x)), strength)
+    }
+}
+
+// a fully saturated color going round red, yellow, green, cyan, blue and
+// magenta with the angle
+fn hue(angle: f64) -> RGB {
+    let sector = angle.rem_euclid(TAU) / TAU * 6.0;
+    let channel = |offset: f64| {
+        let distance = ((sector - offset).rem_euclid(6.0) - 3.0).abs();
+        (distance - 1.0).clamp(0.0, 1.0)
+    };
+    RGB { r: channel(0.0), g: channel(2.0), b: channel(4.0) }
+}
@@ -0,0 +1,68 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::flow::DISCHARGE_LAYER;
+use crate::terrain::{Cell, Terrain};
+
+#[derive(Clone)]
+pub struct Probe<'a> {
+    pub name: &'a str,
+    pub x: f64,
+    pub y: f64,
+}
+
+// Records height, depth and discharge at each probe after every step, as
+// "time,probe,height,depth,discharge" lines. Values are interpolated by
+// inverse distance from the cell nearest the probe and its neighbors.
+pub struct GaugeRecorder<'a> {
+    writer: BufWriter<File>,
+    probes: Vec<(Probe<'a>, Vec<(usize, f64)>)>,
+}
+
+impl<'a> GaugeRecorder<'a> {
+    pub fn new(path: &str, probes: &[Probe<'a>], terrain: &Terrain) -> GaugeRecorder<'a> {
+        let mut writer = BufWriter::new(File::create(path).unwrap());
+        writeln!(writer, "time,probe,height,depth,discharge").unwrap();
+        let probes = probes.iter()
+            .map(|probe| (probe.clone(), interpolation_weights(probe, terrain)))
+            .collect();
+        GaugeRecorder { writer, probes }
+    }
+
+    pub fn record(&mut self, time: f64, terrain: &Terrain) {
+        let discharge = terrain.layer(DISCHARGE_LAYER);
+        for (probe, weights) in self.probes.iter() {
+            let mut values = [0.0; 3];
+            for &(index, weight) in weights.iter() {
+                let cell = terrain.get_cell(index);
+                values[0] += weight * cell.height();
+                values[1] += weight * cell.depth();
+                values[2] += weight * discharge.map_or(0.0, |layer| layer[index]);
+            }
+            writeln!(self.writer, "{},{},{},{},{}", time, probe.name, values[0], values[1], values[2]).unwrap();
+        }
+    }
+}
+
+fn interpolation_weights(probe: &Probe, terrain: &Terrain) -> Vec<(usize, f64)> {
+    let nearest = terrain.get_cell(terrain.nearest_cell_index(probe.x, probe.y).unwrap());
+    let candidates = std::iter::once(nearest.index())
+        .chain(nearest.neighbor_data_iter().map(|nd| nd.index()));
+
+    let mut weights = Vec::new();
+    for index in candidates {
+        let distance = distance_to(&terrain.get_cell(index), probe);
+        if distance < f64::EPSILON {
+            return vec![(index, 1.0)];
+        }
+        weights.push((index, distance.powi(2).recip()));
+    }
+    let total: f64 = weights.iter().map(|(_, weight)| weight).sum();
+    weights.iter().map(|&(index, weight)| (index, weight / total)).collect()
+}
+
+fn distance_to(cell: &Cell, probe: &Probe) -> f64 {
+    let x_dist = cell.x() - probe.x;
+    let y_dist = cell.y() - probe.y;
+    (x_dist * x_dist + y_dist * y_dist).sqrt()
+}
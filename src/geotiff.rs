@@ -0,0 +1,350 @@
+use std::convert::TryInto;
+use std::fs;
+
+const IMAGE_WIDTH: u16 = 256;
+const IMAGE_LENGTH: u16 = 257;
+const BITS_PER_SAMPLE: u16 = 258;
+const COMPRESSION: u16 = 259;
+const STRIP_OFFSETS: u16 = 273;
+const SAMPLES_PER_PIXEL: u16 = 277;
+const ROWS_PER_STRIP: u16 = 278;
+const STRIP_BYTE_COUNTS: u16 = 279;
+const PLANAR_CONFIGURATION: u16 = 284;
+const PREDICTOR: u16 = 317;
+const TILE_WIDTH: u16 = 322;
+const TILE_LENGTH: u16 = 323;
+const TILE_OFFSETS: u16 = 324;
+const TILE_BYTE_COUNTS: u16 = 325;
+const SAMPLE_FORMAT: u16 = 339;
+const MODEL_PIXEL_SCALE: u16 = 33550;
+const GDAL_NODATA: u16 = 42113;
+
+// A single band elevation model read from a GeoTIFF, with its values row by
+// row from the top (north) and the ones marked as no data left as NaN.
+//
+// Reads classic (not Big) TIFFs of either byte order with one sample per
+// pixel of 8 to 64-bit integers or 32 or 64-bit floats, in strips or tiles,
+// uncompressed or compressed with LZW or deflate, and with or without the
+// horizontal differencing predictor, which covers the DEMs GDAL writes by
+// default.
+pub struct Dem {
+    pub width: usize,
+    pub height: usize,
+    pub values: Vec<f64>,
+    // ground units per pixel across and down, when the file gives them
+    pub pixel_scale: Option<(f64, f64)>,
+}
+
+struct Tiff {
+    data: Vec<u8>,
+    big_endian: bool,
+}
+
+struct Entry {
+    field_type: u16,
+    count: usize,
+    // where the values are, inside the entry itself when they fit
+    offset: usize,
+}
+
+#[derive(Clone, Copy)]
+enum SampleFormat {
+    Unsigned,
+    Signed,
+    Float,
+}
+
+pub fn read(path: &str) -> Result<Dem, String> {
+    let data = fs::read(path).map_err(|e| e.to_string())?;
+    let big_endian = match data.get(..2) {
+        Some(b"II") => false,
+        Some(b"MM") => true,
+        _ => return Err(String::from("not a tiff file")),
+    };
+    let tiff = Tiff { data, big_endian };
+    match tiff.u16_at(2)? {
+        42 => {}
+        43 => return Err(String::from("BigTIFF files are not supported")),
+        _ => return Err(String::from("not a tiff file")),
+    }
+    let entries = tiff.entries(tiff.u32_at(4)? as usize)?;
+    tiff.read_dem(&entries)
+}
+
+impl Tiff {
+    fn bytes(&self, offset: usize, len: usize) -> Result<&[u8], String> {
+        self.data.get(offset..offset + len).ok_or_else(|| format!("truncated at byte {}", offset))
+    }
+
+    fn u16_at(&self, offset: usize) -> Result<u16, String> {
+        let bytes = self.bytes(offset, 2)?.try_into().unwrap();
+        Ok(if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    }
+
+    fn u32_at(&self, offset: usize) -> Result<u32, String> {
+        let bytes = self.bytes(offset, 4)?.try_into().unwrap();
+        Ok(if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    }
+
+    fn u64_at(&self, offset: usize) -> Result<u64, String> {
+        let bytes = self.bytes(offset, 8)?.try_into().unwrap();
+        Ok(if self.big_endian { u64::from_be_bytes(bytes) } else { u64::from_le_bytes(bytes) })
+    }
+
+    // the first image's entries by tag
+    fn entries(&self, ifd_offset: usize) -> Result<Vec<(u16, Entry)>, String> {
+        let count = self.u16_at(ifd_offset)? as usize;
+        let mut entries = Vec::new();
+        for i in 0..count {
+            let at = ifd_offset + 2 + i * 12;
+            let field_type = self.u16_at(at + 2)?;
+            let count = self.u32_at(at + 4)? as usize;
+            let size = match field_type {
+                1 | 2 | 6 | 7 => 1,
+                3 | 8 => 2,
+                4 | 9 | 11 => 4,
+                5 | 10 | 12 => 8,
+                // types this reader has no use for
+                _ => continue,
+            };
+            let offset = if size * count <= 4 { at + 8 } else { self.u32_at(at + 8)? as usize };
+            entries.push((self.u16_at(at)?, Entry { field_type, count, offset }));
+        }
+        Ok(entries)
+    }
+
+    // an entry's values as unsigned integers or floats
+    fn values(&self, entry: &Entry) -> Result<Vec<f64>, String> {
+        (0..entry.count)
+            .map(|i| match entry.field_type {
+                1 | 7 => Ok(self.bytes(entry.offset + i, 1)?[0] as f64),
+                3 => Ok(self.u16_at(entry.offset + i * 2)? as f64),
+                4 => Ok(self.u32_at(entry.offset + i * 4)? as f64),
+                11 => Ok(f32::from_bits(self.u32_at(entry.offset + i * 4)?) as f64),
+                12 => Ok(f64::from_bits(self.u64_at(entry.offset + i * 8)?)),
+                _ => Err(format!("unexpected field type {}", entry.field_type)),
+            })
+            .collect()
+    }
+
+    fn read_dem(&self, entries: &[(u16, Entry)]) -> Result<Dem, String> {
+        let find = |tag: u16| entries.iter().find(|(t, _)| *t == tag).map(|(_, entry)| entry);
+        let list = |tag: u16| -> Result<Option<Vec<f64>>, String> { find(tag).map(|entry| self.values(entry)).transpose() };
+        let single = |tag: u16, default: Option<f64>| -> Result<f64, String> {
+            match list(tag)? {
+                Some(values) if !values.is_empty() => Ok(values[0]),
+                _ => default.ok_or_else(|| format!("missing tag {}", tag)),
+            }
+        };
+
+        let width = single(IMAGE_WIDTH, None)? as usize;
+        let height = single(IMAGE_LENGTH, None)? as usize;
+        if single(SAMPLES_PER_PIXEL, Some(1.0))? != 1.0 {
+            return Err(String::from("only single band elevation models are supported"));
+        }
+        if single(PLANAR_CONFIGURATION, Some(1.0))? != 1.0 {
+            return Err(String::from("unsupported planar configuration"));
+        }
+        let bits = single(BITS_PER_SAMPLE, Some(1.0))? as usize;
+        let format = match single(SAMPLE_FORMAT, Some(1.0))? as u32 {
+            1 => SampleFormat::Unsigned,
+            2 => SampleFormat::Signed,
+            3 => SampleFormat::Float,
+            format => return Err(format!("unsupported sample format {}", format)),
+        };
+        let supported = match format {
+            SampleFormat::Float => bits == 32 || bits == 64,
+            _ => bits == 8 || bits == 16 || bits == 32 || bits == 64,
+        };
+        if !supported {
+            return Err(format!("unsupported {} bit samples", bits));
+        }
+        let compression = single(COMPRESSION, Some(1.0))? as u32;
+        let predictor = single(PREDICTOR, Some(1.0))? as u32;
+        if predictor != 1 && predictor != 2 {
+            return Err(format!("unsupported predictor {}", predictor));
+        }
+        if predictor == 2 && matches!(format, SampleFormat::Float) {
+            return Err(String::from("the horizontal predictor needs integer samples"));
+        }
+
+        // the image's blocks as (first column, first row, columns, rows)
+        // beside where their data is
+        let (blocks, offsets, byte_counts) = match list(TILE_OFFSETS)? {
+            Some(offsets) => {
+                let tile_width = single(TILE_WIDTH, None)? as usize;
+                let tile_length = single(TILE_LENGTH, None)? as usize;
+                let across = width.div_ceil(tile_width);
+                let blocks: Vec<(usize, usize, usize, usize)> = (0..offsets.len())
+                    .map(|i| ((i % across) * tile_width, (i / across) * tile_length, tile_width, tile_length))
+                    .collect();
+                (blocks, offsets, list(TILE_BYTE_COUNTS)?.ok_or("missing tile byte counts")?)
+            }
+            None => {
+                let offsets = list(STRIP_OFFSETS)?.ok_or("missing strip offsets")?;
+                let rows_per_strip = (single(ROWS_PER_STRIP, Some(height as f64))? as usize).min(height);
+                let blocks = (0..offsets.len()).map(|i| (0, i * rows_per_strip, width, rows_per_strip)).collect();
+                (blocks, offsets, list(STRIP_BYTE_COUNTS)?.ok_or("missing strip byte counts")?)
+            }
+        };
+        if offsets.len() != byte_counts.len() {
+            return Err(String::from("block offsets and byte counts differ in number"));
+        }
+
+        let sample_bytes = bits / 8;
+        let mut values = vec![f64::NAN; width * height];
+        for (&(x0, y0, columns, rows), (&offset, &byte_count)) in blocks.iter().zip(offsets.iter().zip(byte_counts.iter())) {
+            let raw = self.bytes(offset as usize, byte_count as usize)?;
+            let mut block = match compression {
+                1 => raw.to_vec(),
+                5 => decode_lzw(raw)?,
+                8 | 32946 => miniz_oxide::inflate::decompress_to_vec_zlib(raw)
+                    .map_err(|e| format!("corrupt deflate data: {:?}", e))?,
+                compression => return Err(format!("unsupported compression {}", compression)),
+            };
+            // strips at the bottom may be short; tiles are always whole
+            let rows = rows.min(height.saturating_sub(y0));
+            let needed = columns * rows * sample_bytes;
+            if block.len() < needed {
+                return Err(format!("block at byte {} holds {} of {} bytes", offset, block.len(), needed));
+            }
+            if predictor == 2 {
+                undo_differencing(&mut block[..needed], columns, sample_bytes, self.big_endian);
+            }
+            for row in 0..rows {
+                for column in 0..columns.min(width.saturating_sub(x0)) {
+                    let at = (row * columns + column) * sample_bytes;
+                    values[(y0 + row) * width + x0 + column] = self.sample(&block[at..at + sample_bytes], format);
+                }
+            }
+        }
+
+        if let Some(nodata) = find(GDAL_NODATA) {
+            let text = String::from_utf8_lossy(self.bytes(nodata.offset, nodata.count)?).to_string();
+            let nodata: f64 = text.trim_matches(char::from(0)).trim().parse()
+                .map_err(|_| format!("invalid no data value {}", text))?;
+            for value in values.iter_mut().filter(|value| **value == nodata) {
+                *value = f64::NAN;
+            }
+        }
+        let pixel_scale = list(MODEL_PIXEL_SCALE)?.filter(|scale| scale.len() >= 2).map(|scale| (scale[0], scale[1]));
+        Ok(Dem { width, height, values, pixel_scale })
+    }
+
+    fn sample(&self, bytes: &[u8], format: SampleFormat) -> f64 {
+        let mut buffer = [0; 8];
+        // widens to 8 bytes keeping the sign of signed samples
+        let fill = match format {
+            SampleFormat::Signed if self.most_significant(bytes) & 0x80 != 0 => 0xff,
+            _ => 0,
+        };
+        buffer.iter_mut().for_each(|b| *b = fill);
+        let bits = if self.big_endian {
+            buffer[8 - bytes.len()..].copy_from_slice(bytes);
+            u64::from_be_bytes(buffer)
+        } else {
+            buffer[..bytes.len()].copy_from_slice(bytes);
+            u64::from_le_bytes(buffer)
+        };
+        match (format, bytes.len()) {
+            (SampleFormat::Float, 4) => f32::from_bits(bits as u32) as f64,
+            (SampleFormat::Float, _) => f64::from_bits(bits),
+            (SampleFormat::Signed, _) => bits as i64 as f64,
+            (SampleFormat::Unsigned, _) => bits as f64,
+        }
+    }
+
+    fn most_significant(&self, bytes: &[u8]) -> u8 {
+        if self.big_endian { bytes[0] } else { bytes[bytes.len() - 1] }
+    }
+}
+
+// each sample after the first in a row is stored as the difference from the
+// one before it
+fn undo_differencing(block: &mut [u8], columns: usize, sample_bytes: usize, big_endian: bool) {
+    let read = |bytes: &[u8]| -> u64 {
+        let mut buffer = [0; 8];
+        if big_endian {
+            buffer[8 - bytes.len()..].copy_from_slice(bytes);
+            u64::from_be_bytes(buffer)
+        } else {
+            buffer[..bytes.len()].copy_from_slice(bytes);
+            u64::from_le_bytes(buffer)
+        }
+    };
+    let row_bytes = columns * sample_bytes;
+    for row in block.chunks_mut(row_bytes) {
+        for column in 1..row.len() / sample_bytes {
+            let at = column * sample_bytes;
+            let sum = read(&row[at - sample_bytes..at]).wrapping_add(read(&row[at..at + sample_bytes]));
+            let bytes = if big_endian { sum.to_be_bytes() } else { sum.to_le_bytes() };
+            let bytes = if big_endian { &bytes[8 - sample_bytes..] } else { &bytes[..sample_bytes] };
+            row[at..at + sample_bytes].copy_from_slice(bytes);
+        }
+    }
+}
+
+// TIFF's LZW: codes most significant bit first, growing from 9 to 12 bits one
+// code earlier than the table strictly needs
+fn decode_lzw(input: &[u8]) -> Result<Vec<u8>, String> {
+    const CLEAR: usize = 256;
+    const END: usize = 257;
+    let mut table: Vec<Vec<u8>> = Vec::new();
+    let reset = |table: &mut Vec<Vec<u8>>| {
+        table.clear();
+        table.extend((0..256).map(|b| vec![b as u8]));
+        table.push(Vec::new());
+        table.push(Vec::new());
+    };
+    reset(&mut table);
+
+    let mut output = Vec::new();
+    let mut previous: Option<usize> = None;
+    let mut width = 9;
+    let mut bit = 0;
+    while bit + width <= input.len() * 8 {
+        let mut code = 0;
+        for i in bit..bit + width {
+            code = (code << 1) | ((input[i / 8] >> (7 - i % 8)) & 1) as usize;
+        }
+        bit += width;
+
+        match code {
+            CLEAR => {
+                reset(&mut table);
+                width = 9;
+                previous = None;
+                continue;
+            }
+            END => break,
+            _ => {}
+        }
+        let entry = match previous {
+            None => table.get(code).cloned().ok_or("corrupt lzw data")?,
+            Some(previous) => {
+                let entry = if code < table.len() {
+                    table[code].clone()
+                } else if code == table.len() {
+                    let mut entry = table[previous].clone();
+                    entry.push(table[previous][0]);
+                    entry
+                } else {
+                    return Err(String::from("corrupt lzw data"));
+                };
+                let mut added = table[previous].clone();
+                added.push(entry[0]);
+                table.push(added);
+                entry
+            }
+        };
+        output.extend_from_slice(&entry);
+        previous = Some(code);
+        width = match table.len() + 1 {
+            len if len >= 2048 => 12,
+            len if len >= 1024 => 11,
+            len if len >= 512 => 10,
+            _ => 9,
+        };
+    }
+    Ok(output)
+}
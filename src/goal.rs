@@ -0,0 +1,93 @@
+use crate::flow::DISCHARGE_LAYER;
+use crate::stats::TerrainStats;
+use crate::terrain::Terrain;
+
+#[derive(Clone, Copy)]
+pub enum Metric {
+    // channel length per unit area, counting cells whose discharge exceeds
+    // the threshold as channel, each as long as its mean neighbor spacing
+    DrainageDensity { discharge_threshold: f64 },
+    // where mean height sits between the lowest and highest cell, from 0 for
+    // a flat plain with one spike to 1 for a plateau with one pit
+    HypsometricIntegral,
+    Relief,
+    WaterVolume,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Above,
+    Below,
+}
+
+// A target condition on a terrain metric that ends the run once reached.
+#[derive(Clone, Copy)]
+pub struct Goal {
+    pub metric: Metric,
+    pub comparison: Comparison,
+    pub target: f64,
+}
+
+impl Metric {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Metric::DrainageDensity { .. } => "drainage_density",
+            Metric::HypsometricIntegral => "hypsometric_integral",
+            Metric::Relief => "relief",
+            Metric::WaterVolume => "water_volume",
+        }
+    }
+
+    pub fn measure(&self, terrain: &Terrain) -> f64 {
+        match *self {
+            Metric::DrainageDensity { discharge_threshold } => drainage_density(terrain, discharge_threshold),
+            Metric::HypsometricIntegral => {
+                let stats = TerrainStats::of(terrain);
+                if stats.relief() > 0.0 { (stats.mean_height - stats.min_height) / stats.relief() } else { 0.0 }
+            }
+            Metric::Relief => TerrainStats::of(terrain).relief(),
+            Metric::WaterVolume => TerrainStats::of(terrain).water_volume,
+        }
+    }
+}
+
+impl Goal {
+    pub fn above(metric: Metric, target: f64) -> Goal {
+        Goal { metric, comparison: Comparison::Above, target }
+    }
+
+    pub fn below(metric: Metric, target: f64) -> Goal {
+        Goal { metric, comparison: Comparison::Below, target }
+    }
+
+    // the current value of the metric if the goal is met
+    pub fn check(&self, terrain: &Terrain) -> Option<f64> {
+        let value = self.metric.measure(terrain);
+        let met = match self.comparison {
+            Comparison::Above => value > self.target,
+            Comparison::Below => value < self.target,
+        };
+        if met { Some(value) } else { None }
+    }
+}
+
+fn drainage_density(terrain: &Terrain, discharge_threshold: f64) -> f64 {
+    let discharge = match terrain.layer(DISCHARGE_LAYER) {
+        Some(discharge) => discharge,
+        None => return 0.0,
+    };
+    let mut length = 0.0;
+    let mut area = 0.0;
+    for cell in terrain.cells_iter() {
+        let count = cell.neighbor_data_iter().count();
+        if count == 0 {
+            continue;
+        }
+        let spacing = cell.neighbor_data_iter().map(|nd| nd.distance()).sum::<f64>() / count as f64;
+        area += spacing * spacing;
+        if discharge[cell.index()] > discharge_threshold {
+            length += spacing;
+        }
+    }
+    if area > 0.0 { length / area } else { 0.0 }
+}
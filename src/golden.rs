@@ -0,0 +1,267 @@
+use std::fs::{self, File};
+use std::io::BufReader;
+
+use crate::default_flow::{DefaultFlow, DrainPolicy};
+use crate::default_shader::DefaultShader;
+use crate::flow::FlowEngine;
+use crate::point::Point;
+use crate::precipitation::Precipitation;
+use crate::render::{shade_pixels, FrameTime, Image, Projection, VerticalScale};
+use crate::terrain::{Terrain, Topology};
+use crate::terrain_preset::TerrainPreset;
+
+// the references, checked by the tests and rewritten after an intended
+// change by the bless_golden example
+pub const GOLDEN_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/golden");
+
+// heights and depths are stored rounded to this
+const STATE_QUANTUM: f64 = 1e-6;
+// how far a cell's height or depth may stray from its reference, loose
+// enough for summing in another order or on another platform
+const STATE_TOLERANCE: f64 = 1e-5;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum GoldenMode {
+    Check,
+    Bless,
+}
+
+// how far a rendered image may stray from its reference: any channel of a
+// pixel by up to `channel`, and beyond that only `pixel_fraction` of pixels
+#[derive(Clone, Copy)]
+pub struct Tolerance {
+    pub channel: u8,
+    pub pixel_fraction: f64,
+}
+
+// A small simulation run to a fixed step with a fixed shader. Precipitation
+// is random, so the cases go without it and start from standing water only.
+// Deltas are applied in the deterministic order.
+pub struct GoldenCase {
+    pub name: &'static str,
+    pub width: usize,
+    pub height: usize,
+    pub density: u32,
+    pub max_z: f64,
+    pub topology: Topology,
+    // a terrain preset seed, or the dome
+    pub seed: Option<u64>,
+    pub flow_rate: f64,
+    pub flow_erosion_rate: f64,
+    pub erosion_threshold: f64,
+    pub erosion_rate: f64,
+    pub steps: u32,
+    pub time_step: f64,
+    pub tolerance: Tolerance,
+}
+
+impl GoldenCase {
+    pub fn new(name: &'static str, topology: Topology, seed: Option<u64>) -> GoldenCase {
+        GoldenCase {
+            name,
+            width: 48,
+            height: 32,
+            density: 2,
+            max_z: 12.0,
+            topology,
+            seed,
+            flow_rate: 0.9,
+            flow_erosion_rate: 1.0,
+            erosion_threshold: 0.2,
+            erosion_rate: 0.5,
+            steps: 40,
+            time_step: 1.0,
+            tolerance: Tolerance { channel: 2, pixel_fraction: 0.001 },
+        }
+    }
+
+    pub fn simulate(&self) -> Terrain {
+        let preset = self.seed.map(|seed| TerrainPreset::random(seed, self.width as f64, self.height as f64, self.max_z));
+        let height_at = |p: &Point| -> f64 {
+            if let Some(preset) = preset.as_ref() {
+                return preset.height_at(p);
+            }
+            let x_term = -2.0 * p.x / self.width as f64 + 1.0;
+            let y_term = -2.0 * p.y / self.height as f64 + 1.0;
+            self.max_z * (-x_term * x_term + 1.0) * (-y_term * y_term + 1.0)
+        };
+        let depth_at = |p: &Point| -> f64 { (1.0 - height_at(p)).max(0.0) };
+        let (width, height, spacing) = (self.width as f64, self.height as f64, (self.density as f64).recip());
+        let terrain = match self.topology {
+            Topology::SquareGrid => Terrain::square_grid(width, height, spacing, height_at, depth_at),
+            Topology::HexGrid => Terrain::hex_grid(width, height, spacing, height_at, depth_at),
+            // delaunay points are generated randomly
+            Topology::Delaunay => panic!("golden case {} needs a grid topology", self.name),
+        };
+
+        let flow = DefaultFlow::new(
+            self.flow_rate,
+            self.flow_erosion_rate,
+            self.erosion_threshold,
+            self.erosion_rate,
+            Precipitation::Bernoulli { rate: 0.5, amount: 0.0 },
+            DrainPolicy::default(),
+        );
+        let mut flow_engine = FlowEngine::new(terrain, flow);
+        flow_engine.set_deterministic(true);
+        for _ in 0..self.steps {
+            flow_engine.step(self.time_step);
+        }
+        flow_engine.into_terrain()
+    }
+
+    pub fn render(&self, terrain: &Terrain) -> Image {
+        let size = (self.width, self.height);
+        let time = FrameTime { frame: 0, time: self.steps as f64 * self.time_step };
+        let pixels = shade_pixels(&DefaultShader::new(VerticalScale::default()), terrain, time, Projection::Equirectangular, size, size);
+        Image { width: self.width, height: self.height, pixels }
+    }
+
+    // checks against the references in path, returning what differs
+    pub fn check(&self, path: &str) -> Result<(), String> {
+        let terrain = self.simulate();
+        let mut failures = Vec::new();
+
+        let (width, height, expected) = read_png(&self.image_path(path));
+        let actual = self.render(&terrain).to_data();
+        if (width, height) != (self.width, self.height) {
+            failures.push(format!("image is {}x{} but the reference is {}x{}", self.width, self.height, width, height));
+        } else {
+            let pixel_count = width * height;
+            let mut max_diff = 0;
+            let mut off_pixels = 0;
+            for (a, b) in actual.chunks(3).zip(expected.chunks(3)) {
+                let diff = a.iter().zip(b.iter()).map(|(a, b)| (*a as i32 - *b as i32).unsigned_abs() as u8).max().unwrap();
+                max_diff = max_diff.max(diff);
+                if diff > self.tolerance.channel {
+                    off_pixels += 1;
+                }
+            }
+            if off_pixels as f64 > self.tolerance.pixel_fraction * pixel_count as f64 {
+                failures.push(format!(
+                    "{} of {} pixels differ by more than {} (at most {})",
+                    off_pixels,
+                    pixel_count,
+                    self.tolerance.channel,
+                    max_diff,
+                ));
+            }
+        }
+
+        let expected = read_state(&self.state_path(path));
+        let actual = state(&terrain);
+        if actual.len() != expected.len() {
+            failures.push(format!("state has {} cells but the reference has {}", actual.len(), expected.len()));
+        } else {
+            let diff = |a: i64, b: i64| (a - b).unsigned_abs() as f64 * STATE_QUANTUM;
+            let diffs: Vec<f64> = actual.iter().zip(expected.iter())
+                .map(|(a, b)| diff(a[0], b[0]).max(diff(a[1], b[1])))
+                .collect();
+            let off_cells = diffs.iter().filter(|&&diff| diff > STATE_TOLERANCE).count();
+            if off_cells > 0 {
+                failures.push(format!(
+                    "{} of {} cells differ by more than {} (at most {})",
+                    off_cells,
+                    diffs.len(),
+                    STATE_TOLERANCE,
+                    diffs.iter().cloned().fold(0.0, f64::max),
+                ));
+            }
+        }
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures.join("; "))
+        }
+    }
+
+    pub fn bless(&self, path: &str) {
+        let terrain = self.simulate();
+        self.render(&terrain).save(&self.image_path(path));
+        let lines: Vec<String> = state(&terrain).iter().map(|[height, depth]| format!("{} {}\n", height, depth)).collect();
+        fs::write(self.state_path(path), lines.concat()).unwrap();
+    }
+
+    fn image_path(&self, path: &str) -> String {
+        format!("{}/{}.png", path, self.name)
+    }
+
+    fn state_path(&self, path: &str) -> String {
+        format!("{}/{}.state", path, self.name)
+    }
+}
+
+pub fn cases() -> Vec<GoldenCase> {
+    let mut steep = GoldenCase::new("preset_square_steep", Topology::SquareGrid, Some(3));
+    steep.max_z = 24.0;
+    steep.erosion_threshold = 0.1;
+    vec![
+        GoldenCase::new("preset_square", Topology::SquareGrid, Some(11)),
+        GoldenCase::new("preset_hex", Topology::HexGrid, Some(7)),
+        steep,
+    ]
+}
+
+// checks or blesses every case, panicking with the failures after all of
+// them have run
+pub fn run(mode: GoldenMode, path: &str) {
+    fs::create_dir_all(path).unwrap();
+    let mut failures = Vec::new();
+    for case in cases() {
+        match mode {
+            GoldenMode::Bless => {
+                case.bless(path);
+                println!("blessed {}", case.name);
+            }
+            GoldenMode::Check => match case.check(path) {
+                Ok(()) => println!("{} ok", case.name),
+                Err(e) => {
+                    println!("{} FAILED: {}", case.name, e);
+                    failures.push(case.name);
+                }
+            },
+        }
+    }
+    assert!(failures.is_empty(), "golden cases failed: {}", failures.join(", "));
+}
+
+// the heights and depths in cell order, in quanta
+pub fn state(terrain: &Terrain) -> Vec<[i64; 2]> {
+    let quantize = |value: f64| (value / STATE_QUANTUM).round() as i64;
+    terrain.cells_iter().map(|cell| [quantize(cell.height()), quantize(cell.depth())]).collect()
+}
+
+// a state as written by bless: a line to a cell, its height and depth
+fn read_state(path: &str) -> Vec<[i64; 2]> {
+    let text = fs::read_to_string(path).unwrap_or_else(|e| panic!("failed to read {}: {}", path, e));
+    text.lines()
+        .map(|line| {
+            let values: Vec<i64> = line.split_whitespace()
+                .map(|value| value.parse().unwrap_or_else(|_| panic!("invalid value {} in {}", value, path)))
+                .collect();
+            assert_eq!(values.len(), 2, "invalid line {} in {}", line, path);
+            [values[0], values[1]]
+        })
+        .collect()
+}
+
+fn read_png(path: &str) -> (usize, usize, Vec<u8>) {
+    let file = File::open(path).unwrap_or_else(|e| panic!("failed to open {}: {}", path, e));
+    let (info, mut reader) = png::Decoder::new(BufReader::new(file)).read_info().unwrap();
+    assert_eq!(info.color_type, png::ColorType::RGB, "{} is not an rgb image", path);
+    let mut data = vec![0; info.buffer_size()];
+    reader.next_frame(&mut data).unwrap();
+    (info.width as usize, info.height as usize, data)
+}
+
+// the references are of double precision runs
+#[cfg(all(test, not(feature = "f32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn golden_cases() {
+        run(GoldenMode::Check, GOLDEN_PATH);
+    }
+}
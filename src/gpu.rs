@@ -0,0 +1,331 @@
+use wgpu::util::DeviceExt;
+
+use crate::terrain::Terrain;
+
+// cells handled by each GPU work group
+const WORKGROUP_CELLS: u32 = 64;
+
+// most work groups a dispatch may have along one side
+const MAX_DISPATCH: u32 = 65535;
+
+// Works out DefaultFlow's transfers between cells on the GPU: one kernel
+// finds the transfer weights of every cell and what it loses and sends each
+// neighbor, by slot, and a second gathers what every cell's neighbors sent
+// it. Heights and depths go up each step as single precision, so the
+// transfers come back less exact than DefaultFlow's own; the neighbor graph
+// goes up once for a terrain's cells.
+const SHADER: &str = r#"
+struct Params {
+    cell_count: u32,
+    row_cells: u32,
+    flow_share: f32,
+    flow_erosion_rate: f32,
+    erosion_threshold: f32,
+    erosion_share: f32,
+    delivered: f32,
+    unused: f32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> offsets: array<u32>;
+@group(0) @binding(2) var<storage, read> neighbors: array<u32>;
+@group(0) @binding(3) var<storage, read> distances: array<f32>;
+// height and depth
+@group(0) @binding(4) var<storage, read> cells: array<vec2<f32>>;
+// what each cell loses, as height and depth, and how much ground its water
+// wore away
+@group(0) @binding(5) var<storage, read_write> own: array<vec2<f32>>;
+@group(0) @binding(6) var<storage, read_write> eroded: array<f32>;
+// what each cell sends each neighbor, by slot
+@group(0) @binding(7) var<storage, read_write> sent: array<vec2<f32>>;
+// where in sent each neighbor's share for the cell is
+@group(0) @binding(8) var<storage, read> back: array<u32>;
+@group(0) @binding(9) var<storage, read_write> received: array<vec2<f32>>;
+
+fn cell_index(id: vec3<u32>) -> u32 {
+    return id.x + id.y * params.row_cells;
+}
+
+@compute @workgroup_size(64)
+fn outgoing(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = cell_index(id);
+    if (i >= params.cell_count) {
+        return;
+    }
+    let start = offsets[i];
+    let end = offsets[i + 1u];
+    let cell = cells[i];
+
+    var flow_weight = 0.0;
+    var flow_available = 3.4028235e38;
+    var erosion_weight = 0.0;
+    var erosion_available = 3.4028235e38;
+    for (var k = start; k < end; k = k + 1u) {
+        let neighbor = cells[neighbors[k]];
+        let diff = (cell.x + cell.y) - (neighbor.x + neighbor.y);
+        let slope = diff / distances[k];
+        if (slope > 0.0) {
+            flow_weight += slope * slope * slope;
+            flow_available = min(flow_available, min(cell.y, diff / 2.0));
+        }
+        let ground_diff = cell.x - neighbor.x;
+        let ground_slope = ground_diff / distances[k];
+        if (ground_slope > params.erosion_threshold) {
+            erosion_weight += ground_slope;
+            erosion_available = min(erosion_available, ground_diff / 2.0);
+        }
+    }
+
+    var lost = vec2<f32>(0.0, 0.0);
+    var flow_eroded = 0.0;
+    for (var k = start; k < end; k = k + 1u) {
+        let neighbor = cells[neighbors[k]];
+        var share = vec2<f32>(0.0, 0.0);
+        let slope = ((cell.x + cell.y) - (neighbor.x + neighbor.y)) / distances[k];
+        if (slope > 0.0) {
+            let depth_delta = (slope * slope * slope / flow_weight) * flow_available * params.flow_share;
+            if (depth_delta > 0.0) {
+                let height_delta = depth_delta * params.flow_erosion_rate;
+                share += vec2<f32>(height_delta * params.delivered, depth_delta);
+                lost -= vec2<f32>(height_delta, depth_delta);
+                flow_eroded += height_delta;
+            }
+        }
+        let ground_slope = (cell.x - neighbor.x) / distances[k];
+        if (ground_slope > params.erosion_threshold) {
+            let height_delta = (ground_slope / erosion_weight) * erosion_available * params.erosion_share;
+            if (height_delta > 0.0) {
+                share.x += height_delta;
+                lost.x -= height_delta;
+            }
+        }
+        sent[k] = share;
+    }
+    own[i] = lost;
+    eroded[i] = flow_eroded;
+}
+
+@compute @workgroup_size(64)
+fn gather(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = cell_index(id);
+    if (i >= params.cell_count) {
+        return;
+    }
+    var total = vec2<f32>(0.0, 0.0);
+    for (var k = offsets[i]; k < offsets[i + 1u]; k = k + 1u) {
+        total += sent[back[k]];
+    }
+    received[i] = total;
+}
+"#;
+
+pub struct GpuTransfers {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    outgoing: wgpu::ComputePipeline,
+    gather: wgpu::ComputePipeline,
+    buffers: Option<Buffers>,
+}
+
+// what DefaultFlow's transfers depend on, with the rates already scaled to
+// the step
+#[derive(Clone, Copy)]
+pub struct TransferParams {
+    pub flow_share: f64,
+    pub flow_erosion_rate: f64,
+    pub erosion_threshold: f64,
+    pub erosion_share: f64,
+    pub delivered: f64,
+}
+
+// Per cell, as (height, depth) where there are two: what it loses to its
+// neighbors, what they send it, and how much ground its water wore away.
+pub struct Transfers {
+    pub own: Vec<(f64, f64)>,
+    pub received: Vec<(f64, f64)>,
+    pub eroded: Vec<f64>,
+}
+
+// the buffers for one terrain's cells
+struct Buffers {
+    cells_len: usize,
+    neighbors_len: usize,
+    params: wgpu::Buffer,
+    cells: wgpu::Buffer,
+    own: wgpu::Buffer,
+    eroded: wgpu::Buffer,
+    received: wgpu::Buffer,
+    staging: wgpu::Buffer,
+    outgoing_group: wgpu::BindGroup,
+    gather_group: wgpu::BindGroup,
+}
+
+impl GpuTransfers {
+    pub fn new() -> GpuTransfers {
+        let instance = wgpu::Instance::default();
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            compatible_surface: None,
+            force_fallback_adapter: false,
+        })).expect("no GPU adapter found");
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("terrain_flow"),
+                required_features: wgpu::Features::empty(),
+                required_limits: adapter.limits(),
+            },
+            None,
+        )).unwrap();
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("transfers"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let pipeline = |entry_point| device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(entry_point),
+            layout: None,
+            module: &module,
+            entry_point,
+        });
+        let outgoing = pipeline("outgoing");
+        let gather = pipeline("gather");
+        GpuTransfers { device, queue, outgoing, gather, buffers: None }
+    }
+
+    pub fn transfers(&mut self, terrain: &Terrain, params: &TransferParams) -> Transfers {
+        let cells_len = terrain.cells_len();
+        if cells_len == 0 {
+            return Transfers { own: Vec::new(), received: Vec::new(), eroded: Vec::new() };
+        }
+        let stale = self.buffers.as_ref()
+            .is_none_or(|buffers| buffers.cells_len != cells_len || buffers.neighbors_len != terrain.neighbors().len());
+        if stale {
+            self.buffers = Some(self.upload_graph(terrain));
+        }
+        let buffers = self.buffers.as_ref().unwrap();
+
+        let groups = (cells_len as u32).div_ceil(WORKGROUP_CELLS);
+        let (x, y) = (groups.min(MAX_DISPATCH), groups.div_ceil(MAX_DISPATCH));
+        let header = [cells_len as u32, x * WORKGROUP_CELLS];
+        let rates = [params.flow_share, params.flow_erosion_rate, params.erosion_threshold, params.erosion_share, params.delivered, 0.0];
+        let mut param_bytes = u32_bytes(header.iter().copied());
+        param_bytes.extend(f32_bytes(rates.iter().copied()));
+        self.queue.write_buffer(&buffers.params, 0, &param_bytes);
+        let cell_values = terrain.cells_iter().flat_map(|cell| [cell.height(), cell.depth()]);
+        self.queue.write_buffer(&buffers.cells, 0, &f32_bytes(cell_values));
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        for (pipeline, group) in [(&self.outgoing, &buffers.outgoing_group), (&self.gather, &buffers.gather_group)] {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None, timestamp_writes: None });
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, group, &[]);
+            pass.dispatch_workgroups(x, y, 1);
+        }
+        let pairs_size = cells_len as u64 * 8;
+        encoder.copy_buffer_to_buffer(&buffers.own, 0, &buffers.staging, 0, pairs_size);
+        encoder.copy_buffer_to_buffer(&buffers.received, 0, &buffers.staging, pairs_size, pairs_size);
+        encoder.copy_buffer_to_buffer(&buffers.eroded, 0, &buffers.staging, pairs_size * 2, cells_len as u64 * 4);
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = buffers.staging.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |result| result.expect("GPU transfers can't be read back"));
+        self.device.poll(wgpu::Maintain::Wait);
+        let values: Vec<f64> = slice.get_mapped_range()
+            .chunks_exact(4)
+            .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64)
+            .collect();
+        buffers.staging.unmap();
+
+        let pairs = |values: &[f64]| values.chunks_exact(2).map(|pair| (pair[0], pair[1])).collect();
+        Transfers {
+            own: pairs(&values[..cells_len * 2]),
+            received: pairs(&values[cells_len * 2..cells_len * 4]),
+            eroded: values[cells_len * 4..].to_vec(),
+        }
+    }
+
+    fn upload_graph(&self, terrain: &Terrain) -> Buffers {
+        let cells_len = terrain.cells_len();
+        let offsets = terrain.neighbor_offsets();
+        let neighbors = terrain.neighbors();
+        // neighbors always list each other, as Terrain::validate checks
+        let back = (0..cells_len).flat_map(|cell_index| {
+            neighbors[offsets[cell_index]..offsets[cell_index + 1]].iter().map(move |nd| {
+                let theirs = &neighbors[offsets[nd.index()]..offsets[nd.index() + 1]];
+                let slot = theirs.iter().position(|back| back.index() == cell_index);
+                (offsets[nd.index()] + slot.expect("neighbors list each other")) as u32
+            })
+        });
+
+        let storage = wgpu::BufferUsages::STORAGE;
+        let readable = wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC;
+        let upload = |contents: Vec<u8>| self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: &padded(contents),
+            usage: storage,
+        });
+        let offsets_buffer = upload(u32_bytes(offsets.iter().map(|&offset| offset as u32)));
+        let neighbors_buffer = upload(u32_bytes(neighbors.iter().map(|nd| nd.index() as u32)));
+        let distances = upload(f32_bytes(neighbors.iter().map(|nd| nd.distance())));
+        let back = upload(u32_bytes(back));
+
+        let buffer = |size: usize, usage| self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: size.max(8) as u64,
+            usage,
+            mapped_at_creation: false,
+        });
+        let params = buffer(32, wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST);
+        let cells = buffer(cells_len * 8, storage | wgpu::BufferUsages::COPY_DST);
+        let own = buffer(cells_len * 8, readable);
+        let eroded = buffer(cells_len * 4, readable);
+        let sent = buffer(neighbors.len() * 8, storage);
+        let received = buffer(cells_len * 8, readable);
+        let staging = buffer(cells_len * 20, wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST);
+
+        let group = |pipeline: &wgpu::ComputePipeline, bound: &[(u32, &wgpu::Buffer)]| {
+            let entries: Vec<wgpu::BindGroupEntry> = bound.iter()
+                .map(|&(binding, buffer)| wgpu::BindGroupEntry { binding, resource: buffer.as_entire_binding() })
+                .collect();
+            self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &pipeline.get_bind_group_layout(0),
+                entries: &entries,
+            })
+        };
+        let outgoing_group = group(&self.outgoing, &[
+            (0, &params), (1, &offsets_buffer), (2, &neighbors_buffer), (3, &distances),
+            (4, &cells), (5, &own), (6, &eroded), (7, &sent),
+        ]);
+        let gather_group = group(&self.gather, &[
+            (0, &params), (1, &offsets_buffer), (7, &sent), (8, &back), (9, &received),
+        ]);
+        Buffers {
+            cells_len,
+            neighbors_len: neighbors.len(),
+            params,
+            cells,
+            own,
+            eroded,
+            received,
+            staging,
+            outgoing_group,
+            gather_group,
+        }
+    }
+}
+
+fn u32_bytes(values: impl Iterator<Item=u32>) -> Vec<u8> {
+    values.flat_map(u32::to_le_bytes).collect()
+}
+
+fn f32_bytes(values: impl Iterator<Item=f64>) -> Vec<u8> {
+    values.flat_map(|value| (value as f32).to_le_bytes()).collect()
+}
+
+// a buffer bound to a shader can't be empty, as those of a terrain without
+// neighbors would be
+fn padded(mut bytes: Vec<u8>) -> Vec<u8> {
+    bytes.resize(bytes.len().max(8), 0);
+    bytes
+}
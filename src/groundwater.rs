@@ -0,0 +1,102 @@
+use crate::terrain::{Terrain, TerrainDelta};
+
+pub const GROUNDWATER_LAYER: &str = "groundwater";
+pub const CONDUCTIVITY_LAYER: &str = "conductivity";
+// water seeping back out of the full aquifer per unit time during the last
+// step, which marks springs and the baseflow feeding rivers
+pub const SPRING_LAYER: &str = "spring_discharge";
+
+// An unconfined aquifer of `thickness` below the surface, holding water in
+// its pore space. Surface water soaks in at up to `infiltration_rate`, moves
+// between neighbors by Darcy's law, with flux proportional to the hydraulic
+// conductivity and the gradient of the water table, and seeps back out
+// wherever the aquifer is full, so highlands recharge it and valleys are
+// fed from it between rains.
+//
+// Stored water is kept in the groundwater layer as an equivalent surface
+// depth. Conductivity is read per cell from the conductivity layer, which is
+// filled with `conductivity` unless something else has set it first.
+#[derive(Clone)]
+pub struct Groundwater {
+    thickness: f64,
+    porosity: f64,
+    infiltration_rate: f64,
+    conductivity: f64,
+}
+
+impl Groundwater {
+    pub fn new(thickness: f64, porosity: f64, infiltration_rate: f64, conductivity: f64) -> Groundwater {
+        assert!(thickness > 0.0);
+        assert!(porosity > 0.0 && porosity <= 1.0);
+        assert!(infiltration_rate >= 0.0);
+        assert!(conductivity >= 0.0);
+        Groundwater { thickness, porosity, infiltration_rate, conductivity }
+    }
+
+    pub fn capacity(&self) -> f64 {
+        self.thickness * self.porosity
+    }
+
+    pub fn update(&self, terrain: &mut Terrain, time_step: f64) {
+        if terrain.layer(CONDUCTIVITY_LAYER).is_none() {
+            let conductivity = self.conductivity;
+            terrain.layer_mut(CONDUCTIVITY_LAYER).iter_mut().for_each(|k| *k = conductivity);
+        }
+        let capacity = self.capacity();
+        let mut stored = terrain.layer_mut(GROUNDWATER_LAYER).to_vec();
+        let mut surface = vec![0.0; terrain.cells_len()];
+
+        for cell in terrain.cells_iter() {
+            let i = cell.index();
+            let infiltration = cell.depth()
+                .min(self.infiltration_rate * time_step)
+                .min(capacity - stored[i])
+                .max(0.0);
+            stored[i] += infiltration;
+            surface[i] -= infiltration;
+        }
+
+        let conductivity = terrain.layer(CONDUCTIVITY_LAYER).unwrap();
+        let mut lateral = vec![0.0; terrain.cells_len()];
+        for cell in terrain.cells_iter() {
+            let i = cell.index();
+            let head_i = self.head(cell.height(), stored[i]);
+            let neighbor_count = cell.neighbor_data_iter().count() as f64;
+            for nd in cell.neighbor_data_iter() {
+                let j = nd.index();
+                let head_j = self.head(terrain.get_cell(j).height(), stored[j]);
+                if head_i <= head_j {
+                    continue;
+                }
+                let (k_i, k_j) = (conductivity[i], conductivity[j]);
+                let k = if k_i + k_j > 0.0 { 2.0 * k_i * k_j / (k_i + k_j) } else { 0.0 };
+                let flux = (k * (head_i - head_j) / nd.distance() * time_step).min(stored[i] / neighbor_count);
+                lateral[i] -= flux;
+                lateral[j] += flux;
+            }
+        }
+
+        let mut springs = vec![0.0; terrain.cells_len()];
+        for (i, water) in stored.iter_mut().enumerate() {
+            *water += lateral[i];
+            if *water > capacity {
+                springs[i] = (*water - capacity) / time_step;
+                surface[i] += *water - capacity;
+                *water = capacity;
+            }
+        }
+
+        for (cell_index, &depth_delta) in surface.iter().enumerate() {
+            if depth_delta != 0.0 {
+                terrain.apply_delta(&TerrainDelta { cell_index, height_delta: 0.0, depth_delta });
+            }
+        }
+        terrain.layer_mut(GROUNDWATER_LAYER).copy_from_slice(&stored);
+        terrain.layer_mut(SPRING_LAYER).copy_from_slice(&springs);
+    }
+
+    // elevation of the water table under a cell
+    fn head(&self, height: f64, stored: f64) -> f64 {
+        height - self.thickness + stored / self.porosity
+    }
+}
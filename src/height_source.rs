@@ -0,0 +1,129 @@
+use std::fs::File;
+use std::io::BufReader;
+
+use crate::geotiff;
+use crate::point::Point;
+use crate::terrain_preset::TerrainPreset;
+
+// Where a new terrain's cells take their starting heights from.
+pub trait HeightSource {
+    fn height_at(&self, p: &Point) -> f64;
+}
+
+// a paraboloid peaking at max_z in the middle of the world and falling to
+// zero at its edges
+pub struct Dome {
+    width: f64,
+    height: f64,
+    max_z: f64,
+}
+
+// A raster of heights stretched over the world, sampled bilinearly between
+// pixel centers. Its top row lies along the top (greatest y) edge of the
+// world, as in the heightmap exports.
+pub struct HeightGrid {
+    image_width: usize,
+    image_height: usize,
+    // per pixel, row by row from the top
+    heights: Vec<f64>,
+    width: f64,
+    height: f64,
+}
+
+// how a DEM's values become heights
+#[derive(Clone, Copy, Debug)]
+pub enum DemScale {
+    // lowest value at zero, highest at max_z
+    Fit,
+    // value * scale + offset
+    Linear { scale: f64, offset: f64 },
+}
+
+impl Dome {
+    pub fn new(width: f64, height: f64, max_z: f64) -> Dome {
+        Dome { width, height, max_z }
+    }
+}
+
+impl HeightSource for Dome {
+    fn height_at(&self, p: &Point) -> f64 {
+        let x_term = -2.0 * p.x / self.width + 1.0;
+        let y_term = -2.0 * p.y / self.height + 1.0;
+        self.max_z * (-x_term * x_term + 1.0) * (-y_term * y_term + 1.0)
+    }
+}
+
+impl HeightSource for TerrainPreset {
+    fn height_at(&self, p: &Point) -> f64 {
+        TerrainPreset::height_at(self, p)
+    }
+}
+
+impl HeightGrid {
+    // reads an 8 or 16-bit grayscale png, black at zero and white at max_z
+    pub fn load_png(path: &str, width: f64, height: f64, max_z: f64) -> HeightGrid {
+        let file = File::open(path).unwrap_or_else(|e| panic!("failed to open heightmap {}: {}", path, e));
+        let (info, mut reader) = png::Decoder::new(BufReader::new(file))
+            .read_info()
+            .unwrap_or_else(|e| panic!("failed to read heightmap {}: {}", path, e));
+        assert_eq!(info.color_type, png::ColorType::Grayscale, "heightmap {} is not a grayscale image", path);
+        let mut data = vec![0; info.buffer_size()];
+        reader.next_frame(&mut data).unwrap_or_else(|e| panic!("failed to read heightmap {}: {}", path, e));
+
+        let heights = match info.bit_depth {
+            png::BitDepth::Eight => data.iter().map(|&v| max_z * v as f64 / 255.0).collect(),
+            png::BitDepth::Sixteen => data.chunks(2).map(|v| max_z * u16::from_be_bytes([v[0], v[1]]) as f64 / 65535.0).collect(),
+            depth => panic!("heightmap {} has unsupported bit depth {:?}", path, depth),
+        };
+        HeightGrid { image_width: info.width as usize, image_height: info.height as usize, heights, width, height }
+    }
+
+    // reads a single band GeoTIFF elevation model, giving cells without data
+    // the lowest height there is
+    pub fn load_geotiff(path: &str, width: f64, height: f64, max_z: f64, scale: DemScale) -> HeightGrid {
+        let dem = geotiff::read(path).unwrap_or_else(|e| panic!("failed to read elevation model {}: {}", path, e));
+        let valid = || dem.values.iter().cloned().filter(|value| value.is_finite());
+        let min = valid().fold(f64::INFINITY, f64::min);
+        let max = valid().fold(f64::NEG_INFINITY, f64::max);
+        assert!(min.is_finite(), "elevation model {} has no data", path);
+        if let Some((x_scale, y_scale)) = dem.pixel_scale {
+            println!(
+                "elevation model covers {} by {} ground units, from {} to {}",
+                dem.width as f64 * x_scale, dem.height as f64 * y_scale, min, max,
+            );
+        }
+
+        let (scale, offset) = match scale {
+            DemScale::Fit if max > min => (max_z / (max - min), -min * max_z / (max - min)),
+            DemScale::Fit => (0.0, 0.0),
+            DemScale::Linear { scale, offset } => (scale, offset),
+        };
+        let lowest = (min * scale + offset).min(max * scale + offset);
+        let heights = dem.values.iter()
+            .map(|value| if value.is_finite() { value * scale + offset } else { lowest })
+            .collect();
+        HeightGrid { image_width: dem.width, image_height: dem.height, heights, width, height }
+    }
+
+    fn pixel(&self, column: usize, row: usize) -> f64 {
+        self.heights[row * self.image_width + column]
+    }
+}
+
+impl HeightSource for HeightGrid {
+    fn height_at(&self, p: &Point) -> f64 {
+        // in pixels from the center of the top left pixel, held within the
+        // outermost pixel centers
+        let max_u = (self.image_width - 1) as f64;
+        let max_v = (self.image_height - 1) as f64;
+        let u = (p.x / self.width * self.image_width as f64 - 0.5).max(0.0).min(max_u);
+        let v = ((self.height - p.y) / self.height * self.image_height as f64 - 0.5).max(0.0).min(max_v);
+
+        let (column, row) = (u.floor() as usize, v.floor() as usize);
+        let (next_column, next_row) = ((column + 1).min(self.image_width - 1), (row + 1).min(self.image_height - 1));
+        let (fu, fv) = (u - column as f64, v - row as f64);
+        let top = self.pixel(column, row) * (1.0 - fu) + self.pixel(next_column, row) * fu;
+        let bottom = self.pixel(column, next_row) * (1.0 - fu) + self.pixel(next_column, next_row) * fu;
+        top * (1.0 - fv) + bottom * fv
+    }
+}
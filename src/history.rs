@@ -0,0 +1,61 @@
+use crate::terrain::Terrain;
+
+pub const EXPOSURE_AGE_LAYER: &str = "exposure_age";
+pub const WETNESS_LAYER: &str = "wetness";
+
+// Ages each cell's surface by the simulated time since its height last moved
+// more than `threshold` away from where it settled, whether by erosion or
+// deposition.
+pub struct ExposureAge {
+    threshold: f64,
+    settled_heights: Vec<f64>,
+}
+
+// the fraction of simulated time each cell has spent deeper than `wet_depth`
+pub struct Wetness {
+    wet_depth: f64,
+    wet_times: Vec<f64>,
+    total_time: f64,
+}
+
+impl ExposureAge {
+    pub fn new(threshold: f64) -> ExposureAge {
+        ExposureAge { threshold, settled_heights: Vec::new() }
+    }
+
+    pub fn update(&mut self, terrain: &mut Terrain, time_step: f64) {
+        if self.settled_heights.len() != terrain.cells_len() {
+            self.settled_heights = terrain.cells_iter().map(|cell| cell.height()).collect();
+        }
+        let heights: Vec<f64> = terrain.cells_iter().map(|cell| cell.height()).collect();
+        let ages = terrain.layer_mut(EXPOSURE_AGE_LAYER);
+        for ((age, settled), height) in ages.iter_mut().zip(self.settled_heights.iter_mut()).zip(heights) {
+            if (height - *settled).abs() > self.threshold {
+                *age = 0.0;
+                *settled = height;
+            } else {
+                *age += time_step;
+            }
+        }
+    }
+}
+
+impl Wetness {
+    pub fn new(wet_depth: f64) -> Wetness {
+        Wetness { wet_depth, wet_times: Vec::new(), total_time: 0.0 }
+    }
+
+    pub fn update(&mut self, terrain: &mut Terrain, time_step: f64) {
+        self.wet_times.resize(terrain.cells_len(), 0.0);
+        self.total_time += time_step;
+        for (wet_time, cell) in self.wet_times.iter_mut().zip(terrain.cells_iter()) {
+            if cell.depth() > self.wet_depth {
+                *wet_time += time_step;
+            }
+        }
+        let total_time = self.total_time;
+        for (wetness, wet_time) in terrain.layer_mut(WETNESS_LAYER).iter_mut().zip(self.wet_times.iter()) {
+            *wetness = wet_time / total_time;
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use crate::terrain::{Terrain, TerrainDelta};
+
+pub const HUMIDITY_LAYER: &str = "humidity";
+pub const RAINFALL_LAYER: &str = "rainfall";
+
+// Carries humidity across the terrain with a prevailing wind and rains it out
+// as it goes. Air enters the upwind edge with `inflow_humidity` and loses a
+// fraction of its moisture for every unit of distance travelled
+// (`condensation_rate`) and every unit of rising terrain it is forced over
+// (`orographic_rate`), so windward slopes get wet and lee sides fall into a
+// rain shadow. Each unit of humidity rained out adds `rain_amount` of water
+// depth per unit of time.
+//
+// The wind is assumed fast compared to the flow, so the humidity field is
+// solved afresh each step in a single upwind-to-downwind sweep.
+#[derive(Clone)]
+pub struct HumidityModel {
+    wind: (f64, f64),
+    inflow_humidity: f64,
+    condensation_rate: f64,
+    orographic_rate: f64,
+    rain_amount: f64,
+    order: Vec<usize>,
+}
+
+impl HumidityModel {
+    // wind_direction is the heading the wind blows towards, in degrees
+    // counterclockwise from the +x axis
+    pub fn new(
+        wind_direction: f64,
+        inflow_humidity: f64,
+        condensation_rate: f64,
+        orographic_rate: f64,
+        rain_amount: f64,
+    ) -> HumidityModel {
+        assert!(wind_direction.is_finite());
+        assert!(inflow_humidity >= 0.0);
+        assert!(condensation_rate >= 0.0);
+        assert!(orographic_rate >= 0.0);
+        assert!(rain_amount >= 0.0);
+        let wind = (wind_direction.to_radians().cos(), wind_direction.to_radians().sin());
+        HumidityModel { wind, inflow_humidity, condensation_rate, orographic_rate, rain_amount, order: Vec::new() }
+    }
+
+    // sets wind_direction, inflow_humidity, condensation_rate,
+    // orographic_rate or rain_amount, returning false for any other name or
+    // a value out of range, so the wind can veer and the air dry out as a
+    // run goes on
+    pub fn accepts_param(&self, name: &str, value: f64) -> bool {
+        match name {
+            "wind_direction" => value.is_finite(),
+            "inflow_humidity" | "condensation_rate" | "orographic_rate" | "rain_amount" => {
+                value >= 0.0 && value.is_finite()
+            }
+            _ => false,
+        }
+    }
+
+    pub fn set_param(&mut self, name: &str, value: f64) -> bool {
+        if !self.accepts_param(name, value) {
+            return false;
+        }
+        match name {
+            "wind_direction" => {
+                self.wind = (value.to_radians().cos(), value.to_radians().sin());
+                self.order.clear();
+            }
+            "inflow_humidity" => self.inflow_humidity = value,
+            "condensation_rate" => self.condensation_rate = value,
+            "orographic_rate" => self.orographic_rate = value,
+            _ => self.rain_amount = value,
+        }
+        true
+    }
+
+    pub fn update(&mut self, terrain: &mut Terrain, time_step: f64) {
+        if self.order.len() != terrain.cells_len() {
+            self.order = self.sweep_order(terrain);
+        }
+
+        let mut humidity = vec![0.0; terrain.cells_len()];
+        let mut rainfall = vec![0.0; terrain.cells_len()];
+        for &index in self.order.iter() {
+            let cell = terrain.get_cell(index);
+            let mut weight_sum = 0.0;
+            let mut humidity_sum = 0.0;
+            let mut surface_sum = 0.0;
+            let mut distance_sum = 0.0;
+            for nd in cell.neighbor_data_iter() {
+                let neighbor = terrain.get_cell(nd.index());
+                if let Some(weight) = self.upwind_weight(neighbor.x(), neighbor.y(), cell.x(), cell.y()) {
+                    weight_sum += weight;
+                    humidity_sum += weight * humidity[nd.index()];
+                    surface_sum += weight * (neighbor.height() + neighbor.depth());
+                    distance_sum += weight * nd.distance();
+                }
+            }
+
+            let incoming = if weight_sum > 0.0 { humidity_sum / weight_sum } else { self.inflow_humidity };
+            let (uplift, distance) = if weight_sum > 0.0 {
+                let upwind_surface = surface_sum / weight_sum;
+                ((cell.height() + cell.depth() - upwind_surface).max(0.0), distance_sum / weight_sum)
+            } else {
+                (0.0, 0.0)
+            };
+            let fraction = 1.0 - (-(self.condensation_rate * distance + self.orographic_rate * uplift)).exp();
+            rainfall[index] = incoming * fraction;
+            humidity[index] = incoming - rainfall[index];
+        }
+
+        for (index, rain) in rainfall.iter().enumerate() {
+            terrain.apply_delta(&TerrainDelta {
+                cell_index: index,
+                height_delta: 0.0,
+                depth_delta: rain * self.rain_amount * time_step,
+            });
+        }
+        terrain.layer_mut(HUMIDITY_LAYER).copy_from_slice(&humidity);
+        terrain.layer_mut(RAINFALL_LAYER).copy_from_slice(&rainfall);
+    }
+
+    // how directly the wind blows from one location to another, or None if
+    // the first is not upwind of the second
+    fn upwind_weight(&self, from_x: f64, from_y: f64, to_x: f64, to_y: f64) -> Option<f64> {
+        let dx = to_x - from_x;
+        let dy = to_y - from_y;
+        let length = (dx * dx + dy * dy).sqrt();
+        let along = (dx * self.wind.0 + dy * self.wind.1) / length;
+        if along > 0.0 {
+            Some(along)
+        } else {
+            None
+        }
+    }
+
+    fn sweep_order(&self, terrain: &Terrain) -> Vec<usize> {
+        let mut order: Vec<(usize, f64)> = terrain.cells_iter()
+            .map(|cell| (cell.index(), cell.x() * self.wind.0 + cell.y() * self.wind.1))
+            .collect();
+        order.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap());
+        order.into_iter().map(|(index, _)| index).collect()
+    }
+}
@@ -0,0 +1,38 @@
+use crate::render::{ColorRamp, FrameTime, RGB, Shade, VerticalScale};
+use crate::terrain::{Cell, Terrain};
+
+// Tints the ground by its elevation above the datum through a color ramp,
+// flat, without the hillshading or water of the default shader.
+pub struct HypsometricShader {
+    ramp: ColorRamp,
+    vertical: VerticalScale,
+}
+
+impl HypsometricShader {
+    pub fn new(ramp: ColorRamp, vertical: VerticalScale) -> HypsometricShader {
+        HypsometricShader { ramp, vertical }
+    }
+
+    // an atlas style ramp from blues below the datum through greens, tans
+    // and browns to white at max_elevation
+    pub fn atlas_ramp(max_elevation: f64) -> ColorRamp {
+        assert!(max_elevation > 0.0);
+        let stops = [
+            (-0.5, 0.05, 0.15, 0.4),
+            (0.0, 0.45, 0.65, 0.85),
+            (0.0, 0.2, 0.45, 0.25),
+            (0.2, 0.55, 0.7, 0.35),
+            (0.45, 0.85, 0.75, 0.45),
+            (0.7, 0.6, 0.42, 0.28),
+            (0.9, 0.75, 0.72, 0.7),
+            (1.0, 0.97, 0.97, 0.97),
+        ];
+        ColorRamp::new(stops.iter().map(|&(at, r, g, b)| (at * max_elevation, RGB { r, g, b })).collect())
+    }
+}
+
+impl Shade for HypsometricShader {
+    fn shade_cell(&self, cell: &Cell, _terrain: &Terrain, _time: FrameTime) -> RGB {
+        self.ramp.color_at(self.vertical.elevation(cell.height()))
+    }
+}
@@ -0,0 +1,75 @@
+use crate::groundwater::GROUNDWATER_LAYER;
+use crate::terrain::{Terrain, TerrainDelta};
+
+pub const SOLUBILITY_LAYER: &str = "solubility";
+
+// a horizontal rock band between two elevations; cells whose surface lies
+// within it take its solubility, and anything outside every stratum is
+// insoluble
+#[derive(Clone)]
+pub struct Stratum {
+    pub bottom: f64,
+    pub top: f64,
+    pub solubility: f64,
+}
+
+// Dissolves soluble rock wherever water stands on it, regardless of slope,
+// at `dissolution_rate` per unit of depth and solubility. Cells whose water
+// surface is lower than all their neighbors dissolve `sinkhole_factor` times
+// faster and swallow up to `swallow_rate` of their water per unit time, so
+// closed depressions deepen into sinkholes. Swallowed water joins the
+// groundwater layer when groundwater is simulated, and is lost otherwise.
+#[derive(Clone)]
+pub struct Karst {
+    strata: Vec<Stratum>,
+    dissolution_rate: f64,
+    sinkhole_factor: f64,
+    swallow_rate: f64,
+}
+
+impl Karst {
+    pub fn new(strata: Vec<Stratum>, dissolution_rate: f64, sinkhole_factor: f64, swallow_rate: f64) -> Karst {
+        assert!(strata.iter().all(|stratum| stratum.bottom < stratum.top && stratum.solubility >= 0.0));
+        assert!(dissolution_rate >= 0.0);
+        assert!(sinkhole_factor >= 1.0);
+        assert!(swallow_rate >= 0.0);
+        Karst { strata, dissolution_rate, sinkhole_factor, swallow_rate }
+    }
+
+    pub fn solubility_at(&self, z: f64) -> f64 {
+        self.strata.iter()
+            .find(|stratum| z >= stratum.bottom && z < stratum.top)
+            .map_or(0.0, |stratum| stratum.solubility)
+    }
+
+    pub fn update(&self, terrain: &mut Terrain, time_step: f64) {
+        let solubility: Vec<f64> = terrain.cells_iter().map(|cell| self.solubility_at(cell.height())).collect();
+        let mut deltas = Vec::new();
+        let mut swallowed = vec![0.0; terrain.cells_len()];
+        for cell in terrain.cells_iter() {
+            let i = cell.index();
+            if solubility[i] <= 0.0 || cell.depth() <= 0.0 {
+                continue;
+            }
+            let surface = cell.height() + cell.depth();
+            let closed = cell.neighbor_data_iter().all(|nd| {
+                let neighbor = terrain.get_cell(nd.index());
+                neighbor.height() + neighbor.depth() > surface
+            });
+            let factor = if closed { self.sinkhole_factor } else { 1.0 };
+            let dissolved = self.dissolution_rate * solubility[i] * cell.depth() * factor * time_step;
+            if closed {
+                swallowed[i] = (self.swallow_rate * solubility[i] * time_step).min(cell.depth());
+            }
+            deltas.push(TerrainDelta { cell_index: i, height_delta: -dissolved, depth_delta: -swallowed[i] });
+        }
+
+        terrain.apply_deltas(&deltas);
+        terrain.layer_mut(SOLUBILITY_LAYER).copy_from_slice(&solubility);
+        if terrain.layer(GROUNDWATER_LAYER).is_some() {
+            for (stored, water) in terrain.layer_mut(GROUNDWATER_LAYER).iter_mut().zip(swallowed) {
+                *stored += water;
+            }
+        }
+    }
+}
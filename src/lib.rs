@@ -0,0 +1,83 @@
+pub mod point;
+pub mod point_gen;
+pub mod precipitation;
+pub mod terrain;
+pub mod terrain_gen;
+pub mod terrain_preset;
+pub mod flow;
+pub mod raster;
+pub mod render;
+pub mod rng;
+pub mod run;
+pub mod active_cells;
+pub mod animation;
+pub mod biome;
+pub mod brush;
+pub mod change;
+pub mod checkpoint;
+pub mod climate;
+pub mod contour_shader;
+pub mod dashboard;
+pub mod default_flow;
+pub mod default_shader;
+pub mod distributed;
+pub mod droplet;
+pub mod ensemble;
+pub mod evaporation;
+pub mod export;
+pub mod flood;
+pub mod flow_shader;
+pub mod gauge;
+pub mod geotiff;
+pub mod goal;
+pub mod golden;
+pub mod groundwater;
+pub mod height_source;
+pub mod history;
+pub mod humidity;
+pub mod hypsometric_shader;
+pub mod invariants;
+pub mod karst;
+pub mod manifest;
+pub mod mass_audit;
+pub mod nan_guard;
+pub mod pipe_model;
+pub mod diagnostic_shader;
+pub mod diffusion;
+pub mod salinity;
+pub mod scenario;
+pub mod schedule;
+pub mod sediment;
+pub mod shallow_water;
+pub mod snapshot;
+pub mod sphere;
+pub mod stats;
+pub mod stream_power;
+pub mod svg;
+pub mod thermal;
+pub mod tiles;
+pub mod tracer;
+pub mod view3d;
+pub mod water_shader;
+#[cfg(feature = "http")]
+pub mod server;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "plugins")]
+pub mod plugin;
+#[cfg(feature = "scripting")]
+pub mod script;
+#[cfg(feature = "gpu")]
+mod gpu;
+#[cfg(feature = "tui")]
+mod tui;
+#[cfg(feature = "window")]
+mod window;
+
+// the types most embedders reach for, beside the modules themselves
+pub use crate::flow::{Flow, FlowEngine};
+pub use crate::point::Point;
+pub use crate::point_gen::{Bounds, PointGenerator};
+pub use crate::render::{FrameTime, Image, Projection, Renderer, Shade, RGB};
+pub use crate::run::{Runner, RunnerBuilder};
+pub use crate::terrain::{Cell, Terrain, TerrainDelta, Topology};
@@ -0,0 +1,45 @@
+use std::fs;
+
+// A JSON record of a run's settings and the events that happened during it,
+// rewritten whenever it changes so it stays current if the run is cut short.
+pub struct RunManifest {
+    path: String,
+    settings: Vec<(String, String)>,
+    events: Vec<String>,
+}
+
+impl RunManifest {
+    pub fn new(path: String) -> RunManifest {
+        RunManifest { path, settings: Vec::new(), events: Vec::new() }
+    }
+
+    pub fn setting(&mut self, name: &str, value: impl ToString) {
+        self.settings.push((name.to_string(), value.to_string()));
+    }
+
+    pub fn record_event(&mut self, frame_num: u32, time: f64, event: &str, fields: &[(&str, f64)]) {
+        let mut entries = vec![
+            format!("\"event\": \"{}\"", event),
+            format!("\"frame\": {}", frame_num),
+            format!("\"time\": {}", time),
+        ];
+        entries.extend(fields.iter().map(|(name, value)| format!("\"{}\": {}", name, value)));
+        self.events.push(format!("{{{}}}", entries.join(", ")));
+        self.write();
+    }
+
+    pub fn write(&self) {
+        let settings: Vec<String> = self.settings.iter()
+            .map(|(name, value)| format!("    \"{}\": \"{}\"", name, value))
+            .collect();
+        let events: Vec<String> = self.events.iter()
+            .map(|event| format!("    {}", event))
+            .collect();
+        let json = format!(
+            "{{\n  \"settings\": {{\n{}\n  }},\n  \"events\": [\n{}\n  ]\n}}\n",
+            settings.join(",\n"),
+            events.join(",\n"),
+        );
+        fs::write(&self.path, json).unwrap();
+    }
+}
@@ -0,0 +1,111 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::default_flow::SUSPENDED_LOAD_LAYER;
+use crate::invariants::{MassBudget, MassTotals};
+use crate::pipe_model::PIPE_SEDIMENT_LAYER;
+use crate::terrain::Terrain;
+
+// layers holding ground that was worn away but not yet put down again
+const SEDIMENT_LAYERS: [&str; 2] = [SUSPENDED_LOAD_LAYER, PIPE_SEDIMENT_LAYER];
+
+// Tallies the total water and rock on the terrain around each flow step,
+// along with what the flow says it rained, evaporated, drained, raised to
+// the floor and carried off, and records what went unaccounted for as
+// drift, as "step,water,rock,precipitation,evaporation,drained,floored,
+// carried_off,water_drift,rock_drift" lines. Rock counts sediment held in
+// suspension or in the pipe model's water as well as the ground. Processes
+// run between flow steps, such as groundwater or karst, fall outside the
+// audit.
+pub struct MassAudit {
+    writer: BufWriter<File>,
+    step: u64,
+    first: Option<MassTotals>,
+    before: MassTotals,
+    budget: MassBudget,
+    total_budget: MassBudget,
+    drift: MassTotals,
+}
+
+impl MassAudit {
+    pub fn new(path: &str) -> MassAudit {
+        let mut writer = BufWriter::new(File::create(path).unwrap());
+        writeln!(writer, "step,water,rock,precipitation,evaporation,drained,floored,carried_off,water_drift,rock_drift").unwrap();
+        MassAudit {
+            writer,
+            step: 0,
+            first: None,
+            before: MassTotals { height: 0.0, depth: 0.0 },
+            budget: MassBudget::default(),
+            total_budget: MassBudget::default(),
+            drift: MassTotals { height: 0.0, depth: 0.0 },
+        }
+    }
+
+    pub fn begin_step(&mut self, terrain: &Terrain) {
+        self.before = totals(terrain);
+        self.first.get_or_insert(self.before);
+        self.budget = MassBudget::default();
+    }
+
+    // adds a flow's budget, per unit time, for the time it was applied
+    pub fn add_budget(&mut self, budget: &MassBudget, time_delta: f64) {
+        self.budget.add(budget, time_delta);
+    }
+
+    pub fn end_step(&mut self, terrain: &Terrain) {
+        self.step += 1;
+        let after = totals(terrain);
+        let net = self.budget.net();
+        let water_drift = after.depth - self.before.depth - net.depth;
+        let rock_drift = after.height - self.before.height - net.height;
+        self.drift.depth += water_drift;
+        self.drift.height += rock_drift;
+        self.total_budget.add(&self.budget, 1.0);
+
+        let budget = &self.budget;
+        writeln!(
+            self.writer,
+            "{},{},{},{},{},{},{},{},{},{}",
+            self.step,
+            after.depth,
+            after.height,
+            budget.precipitation,
+            budget.evaporation,
+            budget.drained,
+            budget.floored,
+            budget.carried_off,
+            water_drift,
+            rock_drift,
+        ).unwrap();
+    }
+
+    // the totals at the start and the budget and drift summed since
+    pub fn summary(&self) -> String {
+        let first = self.first.unwrap_or(self.before);
+        let budget = &self.total_budget;
+        format!(
+            "mass audit over {} steps:\n  \
+            water {} + {} rain - {} evaporated - {} drained, drifted {}\n  \
+            rock {} + {} floored - {} carried off, drifted {}",
+            self.step,
+            first.depth,
+            budget.precipitation,
+            budget.evaporation,
+            budget.drained,
+            self.drift.depth,
+            first.height,
+            budget.floored,
+            budget.carried_off,
+            self.drift.height,
+        )
+    }
+}
+
+fn totals(terrain: &Terrain) -> MassTotals {
+    let mut totals = MassTotals::of(terrain);
+    for name in SEDIMENT_LAYERS.iter() {
+        totals.height += terrain.layer(name).map_or(0.0, |layer| layer.iter().sum());
+    }
+    totals
+}
@@ -0,0 +1,68 @@
+use crate::flow::StepStats;
+use crate::stats::TerrainStats;
+
+#[derive(Default)]
+pub struct Metrics {
+    frames_completed: u64,
+    steps_completed: u64,
+    substeps_completed: u64,
+    step_seconds_sum: f64,
+    last_step_seconds: f64,
+    last_step_deltas: usize,
+    active_cells: usize,
+    eroded_total: f64,
+    deposited_total: f64,
+    water_volume: f64,
+    wet_cells: usize,
+    pending_commands: usize,
+}
+
+impl Metrics {
+    pub fn record_step(&mut self, step: &StepStats) {
+        let seconds = step.duration.as_secs_f64();
+        self.steps_completed += 1;
+        self.substeps_completed += step.substeps as u64;
+        self.step_seconds_sum += seconds;
+        self.last_step_seconds = seconds;
+        self.last_step_deltas = step.delta_count;
+        self.active_cells = step.active_cells;
+        self.eroded_total += step.eroded;
+        self.deposited_total += step.deposited;
+    }
+
+    pub fn record_frame(&mut self, stats: &TerrainStats) {
+        self.frames_completed += 1;
+        self.water_volume = stats.water_volume;
+        self.wet_cells = stats.wet_cells;
+    }
+
+    pub fn set_pending_commands(&mut self, pending_commands: usize) {
+        self.pending_commands = pending_commands;
+    }
+
+    // prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        metric(&mut out, "terrain_flow_frames_completed_total", "counter", "Frames rendered.", self.frames_completed as f64);
+        out.push_str("# HELP terrain_flow_step_duration_seconds Wall time spent computing flow steps.\n");
+        out.push_str("# TYPE terrain_flow_step_duration_seconds summary\n");
+        out.push_str(&format!("terrain_flow_step_duration_seconds_sum {}\n", self.step_seconds_sum));
+        out.push_str(&format!("terrain_flow_step_duration_seconds_count {}\n", self.steps_completed));
+        metric(&mut out, "terrain_flow_last_step_duration_seconds", "gauge", "Wall time of the most recent step.", self.last_step_seconds);
+        metric(&mut out, "terrain_flow_substeps_total", "counter", "Runs of the flow, more than the steps when adaptive stepping splits them.", self.substeps_completed as f64);
+        metric(&mut out, "terrain_flow_eroded_mass_total", "counter", "Height removed from cells.", self.eroded_total);
+        metric(&mut out, "terrain_flow_deposited_mass_total", "counter", "Height added to cells.", self.deposited_total);
+        metric(&mut out, "terrain_flow_water_volume", "gauge", "Total water depth over all cells.", self.water_volume);
+        metric(&mut out, "terrain_flow_wet_cells", "gauge", "Cells holding standing water.", self.wet_cells as f64);
+        metric(&mut out, "terrain_flow_step_delta_queue_depth", "gauge", "Deltas queued for application in the most recent step.", self.last_step_deltas as f64);
+        metric(&mut out, "terrain_flow_active_cells", "gauge", "Cells the most recent flow worked out.", self.active_cells as f64);
+        metric(&mut out, "terrain_flow_command_queue_depth", "gauge", "Control commands waiting for the runner.", self.pending_commands as f64);
+        out
+    }
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, value: f64) {
+    out.push_str(&format!("# HELP {} {}\n", name, help));
+    out.push_str(&format!("# TYPE {} {}\n", name, kind));
+    out.push_str(&format!("{} {}\n", name, value));
+}
@@ -0,0 +1,213 @@
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+
+use crate::terrain::{Cell, Terrain, TerrainDelta};
+
+// Watches every step for the first height, depth or delta that isn't finite
+// and, when one turns up, writes what led to it into `path` and aborts:
+//
+//   report.txt    the offending cell and its neighbors, their net change over
+//                 each of the last `history_steps` steps and the failing
+//                 step's deltas touching them
+//   snapshot.csv  every cell's state now and before the failing step
+//
+// Set to recover, it instead drops the deltas that aren't finite and puts
+// cells that aren't back to how they were before the step's flow (or empty
+// ground at zero if they weren't finite then either), noting each in
+// `path`/recoveries.txt, and the run goes on. That keeps a long run alive
+// through the odd bad cell at the cost of conserving mass there.
+//
+// Costs a copy of the terrain's heights and depths per step and one per
+// remembered step.
+pub struct NanGuard {
+    path: String,
+    history_steps: usize,
+    recover: bool,
+    step: u64,
+    previous: Vec<(f64, f64)>,
+    history: VecDeque<(u64, Vec<(f64, f64)>)>,
+}
+
+impl NanGuard {
+    pub fn new(path: &str, history_steps: usize) -> NanGuard {
+        assert!(history_steps > 0);
+        NanGuard {
+            path: path.to_string(),
+            history_steps,
+            recover: false,
+            step: 0,
+            previous: Vec::new(),
+            history: VecDeque::new(),
+        }
+    }
+
+    pub fn set_recover(&mut self, recover: bool) {
+        self.recover = recover;
+    }
+
+    // checks a flow step's scaled deltas before they are applied
+    pub fn before_flow(&mut self, terrain: &Terrain, deltas: &mut Vec<TerrainDelta>) {
+        self.step += 1;
+        self.previous = terrain.cells_iter().map(|cell| (cell.height(), cell.depth())).collect();
+        let is_bad = |delta: &TerrainDelta| {
+            delta.cell_index >= terrain.cells_len() || !delta.height_delta.is_finite() || !delta.depth_delta.is_finite()
+        };
+        if self.recover {
+            let mut dropped = Vec::new();
+            deltas.retain(|delta| {
+                if is_bad(delta) {
+                    dropped.push(format!(
+                        "dropped delta ({:e}, {:e}) for cell {}",
+                        delta.height_delta,
+                        delta.depth_delta,
+                        delta.cell_index,
+                    ));
+                }
+                !is_bad(delta)
+            });
+            self.record_recoveries(&dropped);
+            return;
+        }
+        if let Some(delta) = deltas.iter().find(|delta| is_bad(delta)) {
+            let reason = format!(
+                "flow produced delta ({:e}, {:e}) for cell {}",
+                delta.height_delta,
+                delta.depth_delta,
+                delta.cell_index,
+            );
+            self.abort(terrain, delta.cell_index.min(terrain.cells_len() - 1), deltas, &reason);
+        }
+    }
+
+    // records the step's change and checks the terrain the deltas left
+    pub fn after_flow(&mut self, terrain: &mut Terrain, deltas: &[TerrainDelta]) {
+        let mut change = vec![(0.0, 0.0); terrain.cells_len()];
+        for delta in deltas.iter() {
+            change[delta.cell_index].0 += delta.height_delta;
+            change[delta.cell_index].1 += delta.depth_delta;
+        }
+        self.history.push_back((self.step, change));
+        while self.history.len() > self.history_steps {
+            self.history.pop_front();
+        }
+        self.check(terrain, deltas, "after the flow");
+    }
+
+    // checks the terrain after the other processes of a step have had their
+    // turn at it
+    pub fn after_step(&mut self, terrain: &mut Terrain) {
+        self.check(terrain, &[], "after the step's other processes");
+    }
+
+    fn check(&mut self, terrain: &mut Terrain, deltas: &[TerrainDelta], stage: &str) {
+        let is_bad = |cell: &Cell| !cell.height().is_finite() || !cell.depth().is_finite();
+        if self.recover {
+            let bad_cells: Vec<(usize, f64, f64)> = terrain.cells_iter()
+                .filter(|cell| is_bad(cell))
+                .map(|cell| (cell.index(), cell.height(), cell.depth()))
+                .collect();
+            let mut restored = Vec::new();
+            for (index, height, depth) in bad_cells {
+                let (previous_height, previous_depth) = self.previous.get(index)
+                    .copied()
+                    .filter(|(height, depth)| height.is_finite() && depth.is_finite())
+                    .unwrap_or((0.0, 0.0));
+                terrain.set_cell(index, previous_height, previous_depth);
+                restored.push(format!(
+                    "restored cell {} from height {:e} and depth {:e} {} to {:e} and {:e}",
+                    index, height, depth, stage, previous_height, previous_depth,
+                ));
+            }
+            self.record_recoveries(&restored);
+            return;
+        }
+        if let Some(cell) = terrain.cells_iter().find(|cell| is_bad(cell)) {
+            let reason = format!("cell {} has height {:e} and depth {:e} {}", cell.index(), cell.height(), cell.depth(), stage);
+            self.abort(terrain, cell.index(), deltas, &reason);
+        }
+    }
+
+    fn record_recoveries(&self, recoveries: &[String]) {
+        if recoveries.is_empty() {
+            return;
+        }
+        fs::create_dir_all(&self.path).unwrap();
+        let file = OpenOptions::new().create(true).append(true).open(format!("{}/recoveries.txt", self.path)).unwrap();
+        let mut w = BufWriter::new(file);
+        for recovery in recoveries.iter() {
+            writeln!(w, "step {}: {}", self.step, recovery).unwrap();
+        }
+        println!("recovered {} non-finite values at step {}; see {}/recoveries.txt", recoveries.len(), self.step, self.path);
+    }
+
+    fn abort(&self, terrain: &Terrain, cell_index: usize, deltas: &[TerrainDelta], reason: &str) -> ! {
+        fs::create_dir_all(&self.path).unwrap();
+        self.write_report(terrain, cell_index, deltas, reason);
+        self.write_snapshot(terrain);
+        panic!("non-finite value at step {}: {}; see {}/report.txt", self.step, reason, self.path);
+    }
+
+    fn write_report(&self, terrain: &Terrain, cell_index: usize, deltas: &[TerrainDelta], reason: &str) {
+        let mut w = BufWriter::new(File::create(format!("{}/report.txt", self.path)).unwrap());
+        writeln!(w, "step {}: {}", self.step, reason).unwrap();
+
+        let cell = terrain.get_cell(cell_index);
+        let mut cells = vec![cell_index];
+        cells.extend(cell.neighbor_data_iter().map(|nd| nd.index()));
+        writeln!(w).unwrap();
+        writeln!(w, "cell x y distance height depth previous_height previous_depth").unwrap();
+        for &index in cells.iter() {
+            let other = terrain.get_cell(index);
+            let distance = cell.neighbor_data_iter().find(|nd| nd.index() == index).map_or(0.0, |nd| nd.distance());
+            let (previous_height, previous_depth) = self.previous.get(index).copied().unwrap_or((f64::NAN, f64::NAN));
+            writeln!(
+                w,
+                "{} {} {} {} {:e} {:e} {:e} {:e}",
+                index,
+                other.x(),
+                other.y(),
+                distance,
+                other.height(),
+                other.depth(),
+                previous_height,
+                previous_depth,
+            ).unwrap();
+        }
+
+        writeln!(w).unwrap();
+        writeln!(w, "net change by step").unwrap();
+        writeln!(w, "step cell height_change depth_change").unwrap();
+        for (step, change) in self.history.iter() {
+            for &index in cells.iter() {
+                writeln!(w, "{} {} {:e} {:e}", step, index, change[index].0, change[index].1).unwrap();
+            }
+        }
+
+        writeln!(w).unwrap();
+        writeln!(w, "deltas of step {}", self.step).unwrap();
+        writeln!(w, "cell height_delta depth_delta").unwrap();
+        for delta in deltas.iter().filter(|delta| cells.contains(&delta.cell_index)) {
+            writeln!(w, "{} {:e} {:e}", delta.cell_index, delta.height_delta, delta.depth_delta).unwrap();
+        }
+    }
+
+    fn write_snapshot(&self, terrain: &Terrain) {
+        let mut w = BufWriter::new(File::create(format!("{}/snapshot.csv", self.path)).unwrap());
+        writeln!(w, "cell,x,y,height,depth,previous_height,previous_depth").unwrap();
+        for cell in terrain.cells_iter() {
+            let (previous_height, previous_depth) = self.previous.get(cell.index()).copied().unwrap_or((f64::NAN, f64::NAN));
+            writeln!(
+                w,
+                "{},{},{},{},{},{},{}",
+                cell.index(),
+                cell.x(),
+                cell.y(),
+                cell.height(),
+                cell.depth(),
+                previous_height,
+                previous_depth,
+            ).unwrap();
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use crate::flow::Flow;
+use crate::terrain::{Terrain, TerrainDelta};
+
+// ground dissolved in the water of the pipe model
+pub const PIPE_SEDIMENT_LAYER: &str = "pipe_sediment";
+
+// water shallower than this can't carry sediment
+const MIN_DEPTH: f64 = 1e-6;
+// the least tilt sediment capacity is figured from, so flat beds still erode
+const MIN_TILT: f64 = 0.05;
+
+// The virtual pipes model of hydraulic erosion, as in Mei, Decaudin and Hu
+// (2007), on the mesh. Each cell is joined to each neighbor by a pipe whose
+// outflow accelerates with the drop in water surface along it, at `gravity`
+// per unit of drop and distance, and is scaled back so no cell sends out
+// more water than it holds. The net flow through a cell gives its water a
+// speed, and the water can hold `capacity` times that speed times the sine
+// of the ground's tilt in sediment. Below capacity it dissolves
+// `dissolving_rate` of the shortfall from the ground per unit time, above it
+// deposits `deposition_rate` of the excess, and the sediment it holds moves
+// on with the water leaving.
+//
+// Like ShallowWaterFlow it carries no rain or drains of its own, taking over
+// the water from a DefaultFlow with a flow rate of 0. The pipes' flows are
+// kept by the flow, not the terrain, so they start from rest again on a
+// resumed run.
+pub struct PipeModel {
+    gravity: f64,
+    capacity: f64,
+    dissolving_rate: f64,
+    deposition_rate: f64,
+    // per cell, the outflow per unit time to each neighbor in order
+    pipes: Vec<Vec<f64>>,
+    // change in sediment per cell from the last flow, per unit time
+    pending_sediment: Vec<(usize, f64)>,
+}
+
+impl PipeModel {
+    pub fn new(gravity: f64, capacity: f64, dissolving_rate: f64, deposition_rate: f64) -> PipeModel {
+        assert!(gravity > 0.0 && gravity.is_finite());
+        assert!(capacity >= 0.0 && capacity.is_finite());
+        assert!((0.0..=1.0).contains(&dissolving_rate));
+        assert!((0.0..=1.0).contains(&deposition_rate));
+        PipeModel {
+            gravity,
+            capacity,
+            dissolving_rate,
+            deposition_rate,
+            pipes: Vec::new(),
+            pending_sediment: Vec::new(),
+        }
+    }
+}
+
+impl Flow for PipeModel {
+    fn flow(&mut self, terrain: &Terrain, dt: f64) -> Vec<TerrainDelta> {
+        if self.pipes.len() != terrain.cells_len() {
+            self.pipes = terrain.cells_iter().map(|cell| vec![0.0; cell.neighbor_data_iter().count()]).collect();
+        }
+        let zeros = vec![0.0; terrain.cells_len()];
+        let sediment = terrain.layer(PIPE_SEDIMENT_LAYER).unwrap_or(&zeros);
+        let pipes = &self.pipes;
+        let pending = &mut self.pending_sediment;
+        pending.clear();
+
+        // the share of each cell's outflow that it has the water for
+        let limits: Vec<f64> = terrain.cells_iter()
+            .map(|cell| {
+                let outflow = pipes[cell.index()].iter().sum::<f64>() * dt;
+                if outflow > cell.depth() { cell.depth() / outflow } else { 1.0 }
+            })
+            .collect();
+
+        let mut deltas = Vec::new();
+        for cell in terrain.cells_iter() {
+            let i = cell.index();
+            let depth = cell.depth();
+            let mut self_delta = TerrainDelta::new(i);
+            let mut net_flow = (0.0, 0.0);
+            let mut tilt = (0.0, 0.0);
+            let scale = 2.0 / pipes[i].len().max(1) as f64;
+            let mut spacing = 0.0;
+            let mut carried_off = 0.0;
+            for (k, nd) in cell.neighbor_data_iter().enumerate() {
+                let neighbor = terrain.get_cell(nd.index());
+                let (dx, dy) = (neighbor.x() - cell.x(), neighbor.y() - cell.y());
+                let length = dx.hypot(dy);
+                if length <= 0.0 {
+                    continue;
+                }
+                let direction = (dx / length, dy / length);
+                spacing += scale * nd.distance() / 2.0;
+
+                let out = pipes[i][k] * limits[i];
+                let back = neighbor.neighbor_data_iter()
+                    .position(|back| back.index() == i)
+                    .map_or(0.0, |position| pipes[nd.index()][position] * limits[nd.index()]);
+                net_flow.0 += scale * (out - back) * direction.0;
+                net_flow.1 += scale * (out - back) * direction.1;
+                let rise = (neighbor.height() - cell.height()) / nd.distance();
+                tilt.0 += scale * rise * direction.0;
+                tilt.1 += scale * rise * direction.1;
+
+                if out > 0.0 {
+                    let mut neighbor_delta = TerrainDelta::new(nd.index());
+                    neighbor_delta.depth_delta = out;
+                    self_delta.depth_delta -= out;
+                    deltas.push(neighbor_delta);
+                    if depth >= MIN_DEPTH && sediment[i] > 0.0 {
+                        let carried = sediment[i] * out / depth;
+                        pending.push((nd.index(), carried));
+                        pending.push((i, -carried));
+                        carried_off += carried;
+                    }
+                }
+            }
+
+            if depth >= MIN_DEPTH {
+                let speed = net_flow.0.hypot(net_flow.1) * spacing / depth;
+                let slope = tilt.0.hypot(tilt.1);
+                let sine = (slope / (1.0 + slope * slope).sqrt()).max(MIN_TILT);
+                let capacity = self.capacity * speed * sine;
+                let exchanged = if capacity > sediment[i] {
+                    self.dissolving_rate * (capacity - sediment[i])
+                } else {
+                    // only what stays behind can settle
+                    -(self.deposition_rate * (sediment[i] - capacity)).min(sediment[i] - carried_off)
+                };
+                self_delta.height_delta -= exchanged;
+                pending.push((i, exchanged));
+            }
+            deltas.push(self_delta);
+        }
+        deltas
+    }
+
+    fn update_layers(&mut self, terrain: &mut Terrain, time_delta: f64) {
+        let sediment = terrain.layer_mut(PIPE_SEDIMENT_LAYER);
+        for &(cell_index, amount) in self.pending_sediment.iter() {
+            sediment[cell_index] += amount * time_delta;
+        }
+        sediment.iter_mut().for_each(|sediment| *sediment = sediment.max(0.0));
+
+        // the pipes accelerate with the drop in the water surface along them
+        let pipes = &mut self.pipes;
+        if pipes.len() != terrain.cells_len() {
+            return;
+        }
+        for cell in terrain.cells_iter() {
+            let surface = cell.height() + cell.depth();
+            for (k, nd) in cell.neighbor_data_iter().enumerate() {
+                let neighbor = terrain.get_cell(nd.index());
+                let drop = surface - (neighbor.height() + neighbor.depth());
+                let pipe = &mut pipes[cell.index()][k];
+                *pipe = (*pipe + time_delta * self.gravity * drop / nd.distance()).max(0.0);
+            }
+        }
+    }
+
+    fn accepts_param(&self, name: &str, value: f64) -> bool {
+        match name {
+            "pipe_gravity" => value > 0.0 && value.is_finite(),
+            "sediment_capacity" => value >= 0.0 && value.is_finite(),
+            "dissolving_rate" | "deposition_rate" => (0.0..=1.0).contains(&value),
+            _ => false,
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f64) -> bool {
+        if !self.accepts_param(name, value) {
+            return false;
+        }
+        match name {
+            "pipe_gravity" => self.gravity = value,
+            "sediment_capacity" => self.capacity = value,
+            "dissolving_rate" => self.dissolving_rate = value,
+            _ => self.deposition_rate = value,
+        }
+        true
+    }
+}
@@ -0,0 +1,225 @@
+use std::ffi::CString;
+use std::mem;
+use std::os::raw::{c_char, c_void};
+use std::ptr;
+
+use libloading::Library;
+
+use crate::flow::Flow;
+use crate::point::Point;
+use crate::render::{FrameTime, RGB, Shade};
+use crate::terrain::{Cell, NeighborData, Real, Terrain, TerrainDelta};
+
+// Plugins are shared libraries exporting a C ABI. Every plugin exports
+//
+//   uint32_t terrain_flow_abi_version(void);
+//
+// which must return PLUGIN_ABI_VERSION. Flow plugins additionally export
+//
+//   void *terrain_flow_create(void);  /* optional */
+//   void terrain_flow_destroy(void *instance);  /* optional */
+//   void terrain_flow_flow(void *instance, const TerrainView *view, double dt, double *height_deltas, double *depth_deltas);
+//   bool terrain_flow_set_param(void *instance, const char *name, double value);  /* optional */
+//   bool terrain_flow_accepts_param(void *instance, const char *name, double value);  /* optional */
+//
+// Every PluginFlow gets an instance of its own from create (null without
+// it), passed to each call and to destroy once the flow is dropped, so two
+// flows from one library keep their state apart. accepts_param answers
+// whether set_param would take a value without taking it, so a plugin
+// without it can't have its parameters scheduled. flow writes one height and
+// depth rate per cell into the zeroed output arrays, per unit time like any
+// flow; dt is the step they'll be scaled by, for keeping a step from
+// overshooting. The view borrows the terrain's own arrays, so heights,
+// depths and neighbor distances are floats rather than doubles with the
+// f32 feature, as its real_size says. Shader plugins export
+//
+//   void terrain_flow_shade(const CellView *cell, const NeighborView *neighbors, size_t neighbor_count, uint32_t frame, double time, double *rgb);
+//
+// writing three color components for the cell in the given frame, drawn at
+// the given simulation time, called for several cells at once from
+// different threads.
+pub const PLUGIN_ABI_VERSION: u32 = 2;
+
+const ABI_VERSION_SYMBOL: &[u8] = b"terrain_flow_abi_version\0";
+const ACCEPTS_PARAM_SYMBOL: &[u8] = b"terrain_flow_accepts_param\0";
+const CREATE_SYMBOL: &[u8] = b"terrain_flow_create\0";
+const DESTROY_SYMBOL: &[u8] = b"terrain_flow_destroy\0";
+const FLOW_SYMBOL: &[u8] = b"terrain_flow_flow\0";
+const SET_PARAM_SYMBOL: &[u8] = b"terrain_flow_set_param\0";
+const SHADE_SYMBOL: &[u8] = b"terrain_flow_shade\0";
+
+type AbiVersionFn = unsafe extern "C" fn() -> u32;
+type AcceptsParamFn = unsafe extern "C" fn(*mut c_void, *const c_char, f64) -> bool;
+type CreateFn = unsafe extern "C" fn() -> *mut c_void;
+type DestroyFn = unsafe extern "C" fn(*mut c_void);
+type FlowFn = unsafe extern "C" fn(*mut c_void, *const TerrainView, f64, *mut f64, *mut f64);
+type SetParamFn = unsafe extern "C" fn(*mut c_void, *const c_char, f64) -> bool;
+type ShadeFn = unsafe extern "C" fn(*const CellView, *const NeighborView, usize, u32, f64, *mut f64);
+
+#[repr(C)]
+pub struct TerrainView {
+    pub cell_count: usize,
+    // the size of a height, depth or neighbor distance: 8, or 4 with f32
+    pub real_size: usize,
+    // each cell's x and y
+    pub locations: *const Point,
+    pub heights: *const Real,
+    pub depths: *const Real,
+    // neighbors of cell i are entries neighbor_offsets[i]..neighbor_offsets[i + 1],
+    // each a uint32_t index followed by a distance
+    pub neighbor_offsets: *const usize,
+    pub neighbors: *const NeighborData,
+}
+
+#[repr(C)]
+pub struct CellView {
+    pub x: f64,
+    pub y: f64,
+    pub height: f64,
+    pub depth: f64,
+}
+
+#[repr(C)]
+pub struct NeighborView {
+    pub index: usize,
+    pub cell: CellView,
+    pub distance: f64,
+}
+
+pub struct PluginFlow {
+    instance: *mut c_void,
+    flow_fn: FlowFn,
+    set_param_fn: Option<SetParamFn>,
+    accepts_param_fn: Option<AcceptsParamFn>,
+    destroy_fn: Option<DestroyFn>,
+    _library: Library,
+}
+
+pub struct PluginShader {
+    shade_fn: ShadeFn,
+    _library: Library,
+}
+
+impl PluginFlow {
+    pub fn load(path: &str) -> PluginFlow {
+        let library = load_library(path);
+        unsafe {
+            let flow_fn = *library.get::<FlowFn>(FLOW_SYMBOL)
+                .unwrap_or_else(|e| panic!("flow plugin {} is missing terrain_flow_flow: {}", path, e));
+            let set_param_fn = library.get::<SetParamFn>(SET_PARAM_SYMBOL).ok().map(|symbol| *symbol);
+            let accepts_param_fn = library.get::<AcceptsParamFn>(ACCEPTS_PARAM_SYMBOL).ok().map(|symbol| *symbol);
+            let destroy_fn = library.get::<DestroyFn>(DESTROY_SYMBOL).ok().map(|symbol| *symbol);
+            let instance = match library.get::<CreateFn>(CREATE_SYMBOL) {
+                Ok(create_fn) => create_fn(),
+                Err(_) => ptr::null_mut(),
+            };
+            PluginFlow { instance, flow_fn, set_param_fn, accepts_param_fn, destroy_fn, _library: library }
+        }
+    }
+}
+
+impl Drop for PluginFlow {
+    fn drop(&mut self) {
+        if let Some(destroy_fn) = self.destroy_fn {
+            unsafe { destroy_fn(self.instance) }
+        }
+    }
+}
+
+impl Flow for PluginFlow {
+    fn flow(&mut self, terrain: &Terrain, dt: f64) -> Vec<TerrainDelta> {
+        let view = TerrainView {
+            cell_count: terrain.cells_len(),
+            real_size: mem::size_of::<Real>(),
+            locations: terrain.locations().as_ptr(),
+            heights: terrain.heights().as_ptr(),
+            depths: terrain.depths().as_ptr(),
+            neighbor_offsets: terrain.neighbor_offsets().as_ptr(),
+            neighbors: terrain.neighbors().as_ptr(),
+        };
+        let mut height_deltas = vec![0.0; terrain.cells_len()];
+        let mut depth_deltas = vec![0.0; terrain.cells_len()];
+        unsafe {
+            (self.flow_fn)(self.instance, &view, dt, height_deltas.as_mut_ptr(), depth_deltas.as_mut_ptr());
+        }
+
+        height_deltas.iter()
+            .zip(depth_deltas.iter())
+            .enumerate()
+            .filter(|(_, (&height_delta, &depth_delta))| height_delta != 0.0 || depth_delta != 0.0)
+            .map(|(cell_index, (&height_delta, &depth_delta))| {
+                TerrainDelta { cell_index, height_delta, depth_delta }
+            })
+            .collect()
+    }
+
+    fn accepts_param(&self, name: &str, value: f64) -> bool {
+        match (self.accepts_param_fn, CString::new(name)) {
+            (Some(accepts_param_fn), Ok(name)) => unsafe { accepts_param_fn(self.instance, name.as_ptr(), value) },
+            _ => false,
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f64) -> bool {
+        match (self.set_param_fn, CString::new(name)) {
+            (Some(set_param_fn), Ok(name)) => unsafe { set_param_fn(self.instance, name.as_ptr(), value) },
+            _ => false,
+        }
+    }
+}
+
+impl PluginShader {
+    pub fn load(path: &str) -> PluginShader {
+        let library = load_library(path);
+        unsafe {
+            let shade_fn = *library.get::<ShadeFn>(SHADE_SYMBOL)
+                .unwrap_or_else(|e| panic!("shader plugin {} is missing terrain_flow_shade: {}", path, e));
+            PluginShader { shade_fn, _library: library }
+        }
+    }
+}
+
+impl Shade for PluginShader {
+    fn shade_cell(&self, cell: &Cell, terrain: &Terrain, time: FrameTime) -> RGB {
+        let cell_view = CellView {
+            x: cell.x(),
+            y: cell.y(),
+            height: cell.height(),
+            depth: cell.depth(),
+        };
+        let neighbors: Vec<NeighborView> = cell.neighbor_data_iter()
+            .map(|nd| {
+                let neighbor = terrain.get_cell(nd.index());
+                NeighborView {
+                    index: nd.index(),
+                    cell: CellView {
+                        x: neighbor.x(),
+                        y: neighbor.y(),
+                        height: neighbor.height(),
+                        depth: neighbor.depth(),
+                    },
+                    distance: nd.distance(),
+                }
+            })
+            .collect();
+        let mut rgb = [0.0; 3];
+        unsafe {
+            (self.shade_fn)(&cell_view, neighbors.as_ptr(), neighbors.len(), time.frame, time.time, rgb.as_mut_ptr());
+        }
+        RGB { r: rgb[0], g: rgb[1], b: rgb[2] }
+    }
+}
+
+fn load_library(path: &str) -> Library {
+    unsafe {
+        let library = Library::new(path)
+            .unwrap_or_else(|e| panic!("failed to load plugin {}: {}", path, e));
+        let abi_version = library.get::<AbiVersionFn>(ABI_VERSION_SYMBOL)
+            .unwrap_or_else(|e| panic!("plugin {} is missing terrain_flow_abi_version: {}", path, e))();
+        assert_eq!(
+            abi_version, PLUGIN_ABI_VERSION,
+            "plugin {} targets ABI version {}, expected {}", path, abi_version, PLUGIN_ABI_VERSION,
+        );
+        library
+    }
+}
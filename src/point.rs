@@ -1,4 +1,6 @@
+// laid out as two doubles, as plugins see a terrain's locations
 #[derive(Clone)]
+#[repr(C)]
 pub struct Point {
     pub x: f64,
     pub y: f64,
@@ -1,12 +1,15 @@
 use std::f64::consts::TAU;
-use std::io::{Read, Write};
+use std::fmt;
+use std::io::{self, ErrorKind, Read, Write};
 
 use kdtree::{distance, KdTree};
+use rand::rngs::StdRng;
 use rand::Rng;
 
 use crate::point::Point;
 
 const GEN_SEARCH_STEP_COUNT: i32 = 100;
+const POINT_BYTES: usize = 16;
 
 pub struct PointGenerator {
     x_bounds: Bounds,
@@ -16,6 +19,7 @@ pub struct PointGenerator {
     point_queue: Vec<Point>,
     kd_tree: KdTree<f64, (), [f64; 2]>,
     init: bool,
+    rng: StdRng,
 }
 
 pub struct PointsWriter<W: Write> {
@@ -24,6 +28,16 @@ pub struct PointsWriter<W: Write> {
 
 pub struct PointsReader<R: Read> {
     reader: R,
+    offset: u64,
+    failed: bool,
+}
+
+#[derive(Debug)]
+pub enum PointsError {
+    Io { offset: u64, error: io::Error },
+    // the file ends partway through the point starting at offset
+    Truncated { offset: u64, bytes: usize },
+    NonFinite { offset: u64 },
 }
 
 pub struct Bounds {
@@ -32,12 +46,12 @@ pub struct Bounds {
 }
 
 impl PointGenerator {
-    pub fn new(x_bounds: Bounds, y_bounds: Bounds, min_spacing: f64) -> PointGenerator {
+    pub fn new(x_bounds: Bounds, y_bounds: Bounds, min_spacing: f64, rng: StdRng) -> PointGenerator {
         let point_queue = Vec::new();
         let kd_tree = KdTree::new(2);
         let min_spacing_sq = min_spacing * min_spacing;
 
-        PointGenerator { x_bounds, y_bounds, min_spacing, min_spacing_sq, point_queue, kd_tree, init: false }
+        PointGenerator { x_bounds, y_bounds, min_spacing, min_spacing_sq, point_queue, kd_tree, init: false, rng }
     }
 
     fn next_point(&mut self) -> Option<Point> {
@@ -49,7 +63,7 @@ impl PointGenerator {
     }
 
     fn gen_init_point(&mut self) -> Point {
-        assert_eq!(self.init, false);
+        assert!(!self.init);
 
         self.init = true;
 
@@ -81,9 +95,9 @@ impl PointGenerator {
         point
     }
 
-    fn gen_neighbor_point(&self, anchor_point: &Point) -> Option<Point> {
-        let base_angle = rand::thread_rng().gen::<f64>() * TAU;
-        let step_dir = if rand::thread_rng().gen::<bool>() { 1.0 } else { -1.0 };
+    fn gen_neighbor_point(&mut self, anchor_point: &Point) -> Option<Point> {
+        let base_angle = self.rng.gen::<f64>() * TAU;
+        let step_dir = if self.rng.gen::<bool>() { 1.0 } else { -1.0 };
         let step_delta_angle = step_dir * TAU / (GEN_SEARCH_STEP_COUNT as f64);
 
         let mut step_num = 0;
@@ -135,33 +149,82 @@ impl<W: Write> PointsWriter<W> {
 
     pub fn write_points(&mut self, points: impl Iterator<Item=Point>) {
         for point in points {
-            self.writer.write(&point.x.to_le_bytes()).unwrap();
-            self.writer.write(&point.y.to_le_bytes()).unwrap();
+            self.writer.write_all(&point.x.to_le_bytes()).unwrap();
+            self.writer.write_all(&point.y.to_le_bytes()).unwrap();
         }
+        self.writer.flush().unwrap();
     }
 }
 
 impl<R: Read> PointsReader<R> {
     pub fn new(reader: R) -> PointsReader<R> {
-        PointsReader { reader }
+        PointsReader { reader, offset: 0, failed: false }
+    }
+
+    // fills the buffer unless the reader ends first, returning how much it
+    // filled
+    fn read_full(&mut self, buffer: &mut [u8]) -> Result<usize, PointsError> {
+        let mut filled = 0;
+        while filled < buffer.len() {
+            match self.reader.read(&mut buffer[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(error) => return Err(PointsError::Io { offset: self.offset + filled as u64, error }),
+            }
+        }
+        Ok(filled)
+    }
+
+    fn read_point(&mut self) -> Option<Result<Point, PointsError>> {
+        let mut buffer = [0; POINT_BYTES];
+        let filled = match self.read_full(&mut buffer) {
+            Ok(filled) => filled,
+            Err(e) => return Some(Err(e)),
+        };
+        if filled == 0 {
+            return None;
+        }
+        if filled < POINT_BYTES {
+            return Some(Err(PointsError::Truncated { offset: self.offset, bytes: filled }));
+        }
+
+        let mut x_bytes = [0; 8];
+        let mut y_bytes = [0; 8];
+        x_bytes.copy_from_slice(&buffer[..8]);
+        y_bytes.copy_from_slice(&buffer[8..]);
+        let point = Point { x: f64::from_le_bytes(x_bytes), y: f64::from_le_bytes(y_bytes) };
+        if !point.x.is_finite() || !point.y.is_finite() {
+            return Some(Err(PointsError::NonFinite { offset: self.offset }));
+        }
+        self.offset += POINT_BYTES as u64;
+        Some(Ok(point))
     }
 }
 
+// yields points until the end of the reader, or a single error after which
+// it stops
 impl<R: Read> Iterator for PointsReader<R> {
-    type Item = Point;
+    type Item = Result<Point, PointsError>;
 
-    fn next(&mut self) -> Option<Point> {
-        let mut x_buffer = [0; 8];
-        let mut y_buffer = [0; 8];
-        let x_num = self.reader.read(&mut x_buffer).unwrap();
-        let y_num = self.reader.read(&mut y_buffer).unwrap();
-        if x_num == 8 && y_num == 8 {
-            Some(Point {
-                x: f64::from_le_bytes(x_buffer),
-                y: f64::from_le_bytes(y_buffer),
-            })
-        } else {
-            None
+    fn next(&mut self) -> Option<Result<Point, PointsError>> {
+        if self.failed {
+            return None;
+        }
+        let result = self.read_point();
+        self.failed = matches!(result, Some(Err(_)));
+        result
+    }
+}
+
+impl fmt::Display for PointsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PointsError::Io { offset, error } => write!(f, "read failed at byte {}: {}", offset, error),
+            PointsError::Truncated { offset, bytes } => {
+                write!(f, "truncated at byte {}, {} of the point's {} bytes present", offset, bytes, POINT_BYTES)
+            }
+            PointsError::NonFinite { offset } => write!(f, "point at byte {} is not finite", offset),
         }
     }
 }
@@ -0,0 +1,228 @@
+use std::io::Read;
+
+use rand::Rng;
+
+use crate::checkpoint;
+use crate::height_source::{HeightGrid, HeightSource};
+use crate::point::Point;
+use crate::terrain::Terrain;
+
+// how hard it rains on each cell relative to the rest, scaling whatever the
+// distribution draws there; cells without it get 1
+pub const PRECIPITATION_MAP_LAYER: &str = "precipitation_map";
+
+// How uniform rain falls on each cell, per step.
+#[derive(Clone, Copy, Debug)]
+pub enum Precipitation {
+    // `amount` with probability `rate`
+    Bernoulli { rate: f64, amount: f64 },
+    // `amount` every step
+    Drizzle { amount: f64 },
+    // `amount` on every cell for `duration` steps at a time, separated by dry
+    // spells of exponentially distributed length averaging `mean_interval`
+    // steps
+    Bursts { mean_interval: f64, duration: u32, amount: f64 },
+    // with probability `rate`, an intensity drawn from a gamma distribution
+    // with the given shape and scale (mean shape * scale)
+    Gamma { rate: f64, shape: f64, scale: f64 },
+}
+
+// Rain intensity over the world, for rain shadows and wet and dry regions.
+#[derive(Clone, Copy)]
+pub enum PrecipitationMap<'a> {
+    // the intensity at each (x, y)
+    Field(&'a dyn Fn(f64, f64) -> f64),
+    // a grayscale png stretched over the world, dry at black and at the given
+    // intensity at white
+    Raster(&'a str, f64),
+}
+
+// where a Bursts distribution is between steps
+#[derive(Clone, Copy, Debug)]
+pub struct BurstState {
+    dry_steps_left: f64,
+    burst_steps_left: u32,
+}
+
+impl BurstState {
+    // the dry steps left (f64) and burst steps left (u32), little endian
+    pub fn write(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&self.dry_steps_left.to_le_bytes());
+        state.extend_from_slice(&self.burst_steps_left.to_le_bytes());
+    }
+
+    pub fn read(reader: &mut impl Read) -> Result<BurstState, String> {
+        Ok(BurstState { dry_steps_left: checkpoint::read_f64(reader)?, burst_steps_left: checkpoint::read_u32(reader)? })
+    }
+}
+
+impl Precipitation {
+    pub fn validate(&self) {
+        assert!(self.is_valid(), "invalid {} precipitation {:?}", self.name(), self);
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Precipitation::Bernoulli { .. } => "bernoulli",
+            Precipitation::Drizzle { .. } => "drizzle",
+            Precipitation::Bursts { .. } => "bursts",
+            Precipitation::Gamma { .. } => "gamma",
+        }
+    }
+
+    pub fn fields(&self) -> Vec<(&'static str, f64)> {
+        match *self {
+            Precipitation::Bernoulli { rate, amount } => vec![("precipitation_rate", rate), ("precipitation_amount", amount)],
+            Precipitation::Drizzle { amount } => vec![("precipitation_amount", amount)],
+            Precipitation::Bursts { mean_interval, duration, amount } => vec![
+                ("precipitation_interval", mean_interval),
+                ("precipitation_duration", duration as f64),
+                ("precipitation_amount", amount),
+            ],
+            Precipitation::Gamma { rate, shape, scale } => vec![
+                ("precipitation_rate", rate),
+                ("precipitation_shape", shape),
+                ("precipitation_scale", scale),
+            ],
+        }
+    }
+
+    // sets one of the parameters listed by fields, other than the duration,
+    // returning false if this distribution has no such parameter or the value
+    // is out of range
+    pub fn accepts_param(&self, name: &str, value: f64) -> bool {
+        let mut changed = *self;
+        changed.set_param(name, value)
+    }
+
+    pub fn set_param(&mut self, name: &str, value: f64) -> bool {
+        let mut changed = *self;
+        let param = match (&mut changed, name) {
+            (Precipitation::Bernoulli { rate, .. }, "precipitation_rate") => rate,
+            (Precipitation::Gamma { rate, .. }, "precipitation_rate") => rate,
+            (Precipitation::Bernoulli { amount, .. }, "precipitation_amount") => amount,
+            (Precipitation::Drizzle { amount }, "precipitation_amount") => amount,
+            (Precipitation::Bursts { amount, .. }, "precipitation_amount") => amount,
+            (Precipitation::Bursts { mean_interval, .. }, "precipitation_interval") => mean_interval,
+            (Precipitation::Gamma { shape, .. }, "precipitation_shape") => shape,
+            (Precipitation::Gamma { scale, .. }, "precipitation_scale") => scale,
+            _ => return false,
+        };
+        *param = value;
+        if !changed.is_valid() {
+            return false;
+        }
+        *self = changed;
+        true
+    }
+
+    pub fn is_valid(&self) -> bool {
+        match *self {
+            Precipitation::Bernoulli { rate, amount } => rate > 0.0 && rate < 1.0 && amount.is_finite(),
+            Precipitation::Drizzle { amount } => amount.is_finite(),
+            Precipitation::Bursts { mean_interval, duration, amount } => {
+                mean_interval > 0.0 && mean_interval.is_finite() && duration > 0 && amount.is_finite()
+            }
+            Precipitation::Gamma { rate, shape, scale } => {
+                rate > 0.0 && rate <= 1.0 && shape > 0.0 && shape.is_finite() && scale >= 0.0 && scale.is_finite()
+            }
+        }
+    }
+
+    pub fn burst_state<R: Rng + ?Sized>(&self, rng: &mut R) -> BurstState {
+        let dry_steps_left = match *self {
+            Precipitation::Bursts { mean_interval, .. } => sample_exponential(mean_interval, rng),
+            _ => 0.0,
+        };
+        BurstState { dry_steps_left, burst_steps_left: 0 }
+    }
+
+    // moves bursts on by a step, returning whether this step is in one; other
+    // distributions always return true
+    pub fn advance<R: Rng + ?Sized>(&self, state: &mut BurstState, rng: &mut R) -> bool {
+        let (mean_interval, duration) = match *self {
+            Precipitation::Bursts { mean_interval, duration, .. } => (mean_interval, duration),
+            _ => return true,
+        };
+        if state.burst_steps_left > 0 {
+            state.burst_steps_left -= 1;
+            return true;
+        }
+        state.dry_steps_left -= 1.0;
+        if state.dry_steps_left > 0.0 {
+            return false;
+        }
+        state.burst_steps_left = duration - 1;
+        state.dry_steps_left = sample_exponential(mean_interval, rng);
+        true
+    }
+
+    // the rain falling on a cell this step, if any
+    pub fn sample<R: Rng + ?Sized>(&self, raining: bool, rng: &mut R) -> Option<f64> {
+        match *self {
+            Precipitation::Bernoulli { rate, amount } => {
+                if rng.gen::<f64>() < rate { Some(amount) } else { None }
+            }
+            Precipitation::Drizzle { amount } => Some(amount),
+            Precipitation::Bursts { amount, .. } => if raining { Some(amount) } else { None },
+            Precipitation::Gamma { rate, shape, scale } => {
+                if rng.gen::<f64>() < rate { Some(sample_gamma(shape, rng) * scale) } else { None }
+            }
+        }
+    }
+}
+
+impl<'a> PrecipitationMap<'a> {
+    pub fn name(&self) -> &'a str {
+        match *self {
+            PrecipitationMap::Field(_) => "field",
+            PrecipitationMap::Raster(path, _) => path,
+        }
+    }
+
+    // sets the intensity of every cell of a world width by height
+    pub fn apply(&self, terrain: &mut Terrain, width: f64, height: f64) {
+        let intensities: Vec<f64> = match *self {
+            PrecipitationMap::Field(intensity_at) => terrain.cells_iter().map(|cell| intensity_at(cell.x(), cell.y())).collect(),
+            PrecipitationMap::Raster(path, max_intensity) => {
+                let grid = HeightGrid::load_png(path, width, height, max_intensity);
+                terrain.cells_iter().map(|cell| grid.height_at(&Point { x: cell.x(), y: cell.y() })).collect()
+            }
+        };
+        assert!(intensities.iter().all(|&i| i >= 0.0 && i.is_finite()), "precipitation intensities must be positive or zero");
+        terrain.layer_mut(PRECIPITATION_MAP_LAYER).copy_from_slice(&intensities);
+    }
+}
+
+fn sample_exponential<R: Rng + ?Sized>(mean: f64, rng: &mut R) -> f64 {
+    // 1 - u is in (0, 1], keeping the log finite
+    -(1.0 - rng.gen::<f64>()).ln() * mean
+}
+
+// Marsaglia and Tsang's method, boosting shapes below 1
+fn sample_gamma<R: Rng + ?Sized>(shape: f64, rng: &mut R) -> f64 {
+    if shape < 1.0 {
+        let u: f64 = 1.0 - rng.gen::<f64>();
+        return sample_gamma(shape + 1.0, rng) * u.powf(shape.recip());
+    }
+    let d = shape - 1.0 / 3.0;
+    let c = (9.0 * d).sqrt().recip();
+    loop {
+        let x = sample_normal(rng);
+        let v = (1.0 + c * x).powi(3);
+        if v <= 0.0 {
+            continue;
+        }
+        let u: f64 = 1.0 - rng.gen::<f64>();
+        if u.ln() < 0.5 * x * x + d - d * v + d * v.ln() {
+            return d * v;
+        }
+    }
+}
+
+// Box-Muller
+fn sample_normal<R: Rng + ?Sized>(rng: &mut R) -> f64 {
+    let u: f64 = 1.0 - rng.gen::<f64>();
+    let v: f64 = rng.gen();
+    (-2.0 * u.ln()).sqrt() * (std::f64::consts::TAU * v).cos()
+}
@@ -0,0 +1,138 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use kdtree::{distance, KdTree};
+
+use crate::terrain::Terrain;
+
+// Resamples per-cell values onto a regular grid of unit pixels covering the
+// world, rows from the top edge down, taking each pixel's value from the
+// nearest cell.
+pub fn sample_grid(terrain: &Terrain, values: &[f64], width: usize, height: usize) -> Vec<f64> {
+    let points = (0..height).flat_map(|row| {
+        (0..width).map(move |column| (column as f64 + 0.5, height as f64 - row as f64 - 0.5))
+    });
+    sample(terrain, values, points)
+}
+
+// Resamples per-cell values onto a lattice of columns by rows points running
+// edge to edge of the world, rows from the top edge down, as game engines
+// lay out heightmaps, taking each point's value from the nearest cell.
+pub fn sample_lattice(terrain: &Terrain, values: &[f64], world_size: (usize, usize), columns: usize, rows: usize) -> Vec<f64> {
+    assert!(columns > 1 && rows > 1);
+    let (width, height) = (world_size.0 as f64, world_size.1 as f64);
+    let points = (0..rows).flat_map(|row| {
+        (0..columns).map(move |column| {
+            (column as f64 * width / (columns - 1) as f64, height - row as f64 * height / (rows - 1) as f64)
+        })
+    });
+    sample(terrain, values, points)
+}
+
+// writes an ESRI ASCII grid with unit cells and its lower left corner at the
+// world origin, which GIS tools read directly
+pub fn write_ascii_grid(path: &str, width: usize, height: usize, grid: &[f64]) {
+    assert_eq!(grid.len(), width * height);
+    let mut w = BufWriter::new(File::create(path).unwrap());
+    writeln!(w, "ncols {}", width).unwrap();
+    writeln!(w, "nrows {}", height).unwrap();
+    writeln!(w, "xllcorner 0").unwrap();
+    writeln!(w, "yllcorner 0").unwrap();
+    writeln!(w, "cellsize 1").unwrap();
+    for row in grid.chunks(width) {
+        let line: Vec<String> = row.iter().map(|value| value.to_string()).collect();
+        writeln!(w, "{}", line.join(" ")).unwrap();
+    }
+}
+
+// writes a 16-bit grayscale png spanning the grid's range, black at its
+// lowest value and white at its highest, and beside it a json sidecar with
+// that range and any extra fields, so value = min + pixel / 65535 * (max - min)
+pub fn write_png16(path: &str, width: usize, height: usize, grid: &[f64], extra: &[(&str, f64)]) {
+    assert_eq!(grid.len(), width * height);
+    let (min, max, samples) = quantize16(grid);
+    let data: Vec<u8> = samples.iter().flat_map(|sample| sample.to_be_bytes()).collect();
+
+    let w = BufWriter::new(File::create(path).unwrap());
+    let mut encoder = png::Encoder::new(w, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Sixteen);
+    encoder.write_header().unwrap().write_image_data(&data).unwrap();
+    write_sidecar(path, width, height, min, max, extra);
+}
+
+// writes the grid as headerless 16-bit little-endian samples spanning its
+// range, the RAW heightmaps Unity and Unreal import, with the same json
+// sidecar as write_png16
+pub fn write_raw16(path: &str, width: usize, height: usize, grid: &[f64], extra: &[(&str, f64)]) {
+    assert_eq!(grid.len(), width * height);
+    let (min, max, samples) = quantize16(grid);
+    let data: Vec<u8> = samples.iter().flat_map(|sample| sample.to_le_bytes()).collect();
+    fs::write(path, data).unwrap();
+    write_sidecar(path, width, height, min, max, extra);
+}
+
+// the grid's range and its values scaled to fill 16 bits over it
+fn quantize16(grid: &[f64]) -> (f64, f64, Vec<u16>) {
+    assert!(grid.iter().all(|value| value.is_finite()));
+    let min = grid.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = grid.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let range = max - min;
+    let samples = grid.iter()
+        .map(|value| if range > 0.0 { ((value - min) / range * 65535.0).round() as u16 } else { 0 })
+        .collect();
+    (min, max, samples)
+}
+
+// a json file beside path, with its extension
+fn write_sidecar(path: &str, width: usize, height: usize, min: f64, max: f64, extra: &[(&str, f64)]) {
+    let mut fields = vec![
+        format!("  \"width\": {}", width),
+        format!("  \"height\": {}", height),
+        format!("  \"min\": {}", min),
+        format!("  \"max\": {}", max),
+    ];
+    fields.extend(extra.iter().map(|(name, value)| format!("  \"{}\": {}", name, value)));
+    let sidecar_path = Path::new(path).with_extension("json");
+    fs::write(sidecar_path, format!("{{\n{}\n}}\n", fields.join(",\n"))).unwrap();
+}
+
+// reads a grid written by write_ascii_grid, returning its width, height and
+// values
+pub fn read_ascii_grid(path: &str) -> (usize, usize, Vec<f64>) {
+    let text = fs::read_to_string(path).unwrap_or_else(|e| panic!("failed to read grid {}: {}", path, e));
+    let mut lines = text.lines();
+    let mut header = |key: &str| -> f64 {
+        let line = lines.next().unwrap_or_else(|| panic!("grid {} is missing {}", path, key));
+        let (name, value) = line.split_once(' ').unwrap_or_else(|| panic!("invalid grid header line {}", line));
+        assert_eq!(name, key, "grid {} has {} where {} belongs", path, name, key);
+        value.trim().parse().unwrap_or_else(|_| panic!("invalid {} in grid {}", key, path))
+    };
+    let width = header("ncols") as usize;
+    let height = header("nrows") as usize;
+    header("xllcorner");
+    header("yllcorner");
+    header("cellsize");
+    let grid: Vec<f64> = lines
+        .flat_map(|line| line.split_whitespace())
+        .map(|value| value.parse().unwrap_or_else(|_| panic!("invalid value {} in grid {}", value, path)))
+        .collect();
+    assert_eq!(grid.len(), width * height, "grid {} has the wrong number of values", path);
+    (width, height, grid)
+}
+
+// each point's value from the nearest cell
+fn sample<I: Iterator<Item=(f64, f64)>>(terrain: &Terrain, values: &[f64], points: I) -> Vec<f64> {
+    assert_eq!(values.len(), terrain.cells_len());
+    let mut kd_tree = KdTree::new(2);
+    for cell in terrain.cells_iter() {
+        kd_tree.add([cell.x(), cell.y()], cell.index()).unwrap();
+    }
+    points
+        .map(|(x, y)| {
+            let nearest = kd_tree.nearest(&[x, y], 1, &distance::squared_euclidean).unwrap();
+            nearest.first().map_or(0.0, |(_, &index)| values[index])
+        })
+        .collect()
+}
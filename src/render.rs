@@ -1,18 +1,176 @@
-use std::fs::File;
-use std::io::BufWriter;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::ops::Range;
 use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, SyncSender};
+use std::thread::{self, JoinHandle};
 
+use crossbeam;
+use kdtree::{distance, KdTree};
+
+use crate::animation::{Animation, AnimationFormat};
+use crate::sphere;
 use crate::terrain::{Cell, Terrain};
 
-pub struct Renderer<'a, S: Shade> {
+pub struct Renderer<S: Shade> {
     width: usize,
     height: usize,
     shader: S,
-    render_path: &'a str,
+    render_path: String,
+    projection: Projection,
+    raster: Raster,
+    viewport: Option<Viewport>,
+    mesh_overlay: Option<MeshOverlay>,
+    interpolated_frames: u32,
+    previous_frame: Option<Frame>,
+    output: Output,
+    video: Option<VideoSink>,
+    animation: Option<Animation>,
+}
+
+// Where a renderer's images go: image files numbered by frame in the render
+// path, a video in the render path at `fps` frames per second, encoded by
+// ffmpeg, which must be on the PATH, in whatever format the file's
+// extension names, or an APNG or GIF in the render path showing each image
+// for `delay_ms` at 1 / `scale` of the size; see Animation.
+#[derive(Clone, Debug)]
+pub enum Output {
+    Frames(FrameFormat),
+    Video { file: String, fps: u32 },
+    Animation { file: String, format: AnimationFormat, delay_ms: u32, scale: u32 },
+}
+
+// The format of numbered frames: 8-bit PNG, 16-bit PNG, or OpenEXR, which
+// keeps the shader's colors as 32-bit floats without clamping them, for
+// regrading or for shaders whose colors stand for values such as heights.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameFormat {
+    Png,
+    Png16,
+    Exr,
+}
+
+// a rendered image, as bytes for 8-bit outputs, blended after clamping as
+// they'll be seen, or as the shader's colors for the deeper ones
+enum Frame {
+    Data(Vec<u8>),
+    Colors(Vec<RGB>),
+}
+
+// an ffmpeg process taking raw RGB frames on its standard input, which
+// finishes the file when dropped
+pub struct VideoSink {
+    path: String,
+    ffmpeg: Child,
+}
+
+// Renders frames on a thread of its own from snapshots of the terrain, so
+// the flow can go on with the next steps while a frame is shaded and
+// encoded. At most one snapshot waits behind the frame being drawn, past
+// which render waits for the renderer to catch up. Dropping it waits for
+// every frame sent to be written.
+pub struct BackgroundRenderer {
+    frames: Option<SyncSender<(Terrain, FrameTime)>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[derive(Clone, Copy)]
+pub enum Projection {
+    Equirectangular,
+    // a globe view centered on the given longitude and latitude in degrees;
+    // only meaningful for spherical terrains
+    Orthographic { center_lon: f64, center_lat: f64 },
+}
+
+// How cells become pixels: each cell's color spread over the 2x2 pixels
+// nearest it, which suits cells packed more densely than pixels, each
+// triangle of neighboring cells filled with their colors blended by
+// barycentric weights, which leaves no gaps where cells are sparser than
+// pixels, or each pixel the color of the cell nearest it, which shows cells
+// as the crisp regions the flow sees. Triangles draw nothing outside the
+// cells' hull.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Raster {
+    Splat,
+    Triangles,
+    Voronoi,
+}
+
+// Draws the mesh the flow works on over frames, to show where points are
+// too sparse or triangles degenerate: the edges between neighbors, colored
+// from blue for the shortest through white to red for those twice the mean
+// distance between neighbors or more when `distances` is set, and each
+// cell's point.
+#[derive(Clone, Copy, Debug)]
+pub struct MeshOverlay {
+    pub distances: bool,
+}
+
+// A rectangle of the world, in world units up and right from its bottom
+// left corner, drawn to fill the image in the equirectangular projection;
+// parts of it beyond the world's edges are left empty.
+#[derive(Clone, Copy, Debug)]
+pub struct Viewport {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+// Colors for a range of values, blended between stops given in order of
+// value and held beyond the first and last; two stops at one value make a
+// hard edge there.
+#[derive(Clone, Debug)]
+pub struct ColorRamp {
+    stops: Vec<(f64, RGB)>,
 }
 
-pub trait Shade {
-    fn shade_cell(&self, cell: &Cell, terrain: &Terrain) -> RGB;
+// How elevations are presented: heights are measured up from `datum`,
+// which shaders treat as sea level and heightmap exports write as zero, and
+// multiplied by `exaggeration` wherever relief is drawn.
+#[derive(Clone, Copy, Debug)]
+pub struct VerticalScale {
+    pub datum: f64,
+    pub exaggeration: f64,
+}
+
+// When the terrain being shaded was reached: the number of the frame it's
+// drawn for and the simulation time that had passed by then.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameTime {
+    pub frame: u32,
+    pub time: f64,
+}
+
+// shared by the threads shading a frame, each handed its own cells, and
+// sent along with its renderer to draw frames in the background
+pub trait Shade: Send + Sync {
+    fn shade_cell(&self, cell: &Cell, terrain: &Terrain, time: FrameTime) -> RGB;
+}
+
+impl<S: Shade + ?Sized> Shade for Box<S> {
+    fn shade_cell(&self, cell: &Cell, terrain: &Terrain, time: FrameTime) -> RGB {
+        (**self).shade_cell(cell, terrain, time)
+    }
+}
+
+// Shaders layered from the bottom up, each blended over the colors of those
+// beneath it and then mixed over them by its opacity. A stack shades like
+// any other shader, so it can be the ground of another or a layer itself.
+pub struct ShaderStack {
+    base: Box<dyn Shade>,
+    layers: Vec<(Box<dyn Shade>, Blend, f64)>,
+}
+
+// How a layer's colors combine with those beneath: alpha covers them,
+// multiply darkens them by the layer's, and overlay multiplies their darks
+// and screens their lights, deepening the layer's contrast over them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Blend {
+    Alpha,
+    Multiply,
+    Overlay,
 }
 
 struct Pixels {
@@ -27,37 +185,451 @@ struct Pixel {
     total_weight: f64,
 }
 
-#[derive(Clone)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<RGB>,
+}
+
+#[derive(Clone, Debug)]
 pub struct RGB {
     pub r: f64,
     pub g: f64,
     pub b: f64,
 }
 
-impl<'a, S: Shade> Renderer<'a, S> {
-    pub fn new(width: usize, height: usize, shader: S, render_path: &'a str) -> Renderer<'a, S> {
-        Renderer { width, height, shader, render_path }
+impl VerticalScale {
+    pub fn new(datum: f64, exaggeration: f64) -> VerticalScale {
+        assert!(datum.is_finite());
+        assert!(exaggeration.is_normal() && exaggeration.is_sign_positive());
+        VerticalScale { datum, exaggeration }
+    }
+
+    // height above the datum, as exported
+    pub fn elevation(&self, height: f64) -> f64 {
+        height - self.datum
+    }
+
+    // height above the datum, as drawn
+    pub fn relief(&self, height: f64) -> f64 {
+        (height - self.datum) * self.exaggeration
+    }
+}
+
+impl ColorRamp {
+    pub fn new(stops: Vec<(f64, RGB)>) -> ColorRamp {
+        assert!(!stops.is_empty());
+        assert!(stops.iter().all(|(value, _)| value.is_finite()));
+        assert!(stops.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+        ColorRamp { stops }
+    }
+
+    pub fn color_at(&self, value: f64) -> RGB {
+        match self.stops.iter().position(|(stop, _)| *stop > value) {
+            Some(0) => self.stops[0].1.clone(),
+            Some(upper) => {
+                let (low, low_color) = &self.stops[upper - 1];
+                let (high, high_color) = &self.stops[upper];
+                low_color.mix(high_color, (value - low) / (high - low))
+            }
+            None => self.stops[self.stops.len() - 1].1.clone(),
+        }
+    }
+}
+
+impl ShaderStack {
+    pub fn new(base: Box<dyn Shade>) -> ShaderStack {
+        ShaderStack { base, layers: Vec::new() }
+    }
+
+    pub fn push(&mut self, shader: Box<dyn Shade>, blend: Blend, opacity: f64) {
+        assert!((0.0..=1.0).contains(&opacity));
+        self.layers.push((shader, blend, opacity));
+    }
+}
+
+impl Shade for ShaderStack {
+    fn shade_cell(&self, cell: &Cell, terrain: &Terrain, time: FrameTime) -> RGB {
+        let mut color = self.base.shade_cell(cell, terrain, time);
+        for (shader, blend, opacity) in self.layers.iter() {
+            let layer = shader.shade_cell(cell, terrain, time);
+            let blended = RGB {
+                r: blend.apply(color.r, layer.r),
+                g: blend.apply(color.g, layer.g),
+                b: blend.apply(color.b, layer.b),
+            };
+            color = color.mix(&blended, *opacity);
+        }
+        color
     }
+}
+
+impl Blend {
+    fn apply(&self, below: f64, layer: f64) -> f64 {
+        match self {
+            Blend::Alpha => layer,
+            Blend::Multiply => below * layer,
+            Blend::Overlay if below < 0.5 => 2.0 * below * layer,
+            Blend::Overlay => 1.0 - 2.0 * (1.0 - below) * (1.0 - layer),
+        }
+    }
+}
+
+impl Default for VerticalScale {
+    fn default() -> VerticalScale {
+        VerticalScale { datum: 0.0, exaggeration: 1.0 }
+    }
+}
+
+impl<S: Shade> Renderer<S> {
+    pub fn new(width: usize, height: usize, shader: S, render_path: &str) -> Renderer<S> {
+        Renderer {
+            width,
+            height,
+            shader,
+            render_path: render_path.to_string(),
+            projection: Projection::Equirectangular,
+            raster: Raster::Splat,
+            viewport: None,
+            mesh_overlay: None,
+            interpolated_frames: 0,
+            previous_frame: None,
+            output: Output::Frames(FrameFormat::Png),
+            video: None,
+            animation: None,
+        }
+    }
+
+    pub fn set_output(&mut self, output: Output) {
+        self.output = output;
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+    }
+
+    pub fn set_raster(&mut self, raster: Raster) {
+        self.raster = raster;
+    }
+
+    // draws just this part of the world, or all of it when none; it can be
+    // moved between frames to follow something
+    pub fn set_viewport(&mut self, viewport: Option<Viewport>) {
+        if let Some(viewport) = viewport {
+            assert!(matches!(self.projection, Projection::Equirectangular));
+            assert!(viewport.width > 0.0 && viewport.height > 0.0);
+        }
+        self.viewport = viewport;
+    }
+
+    pub fn set_mesh_overlay(&mut self, mesh_overlay: Option<MeshOverlay>) {
+        self.mesh_overlay = mesh_overlay;
+    }
+
+    // blends this many extra images between each pair of rendered frames,
+    // numbering the output so frames and blends form one smooth sequence
+    pub fn set_interpolated_frames(&mut self, interpolated_frames: u32) {
+        self.interpolated_frames = interpolated_frames;
+    }
+
+    pub fn render(&mut self, terrain: &Terrain, time: FrameTime) {
+        let mut colors = self.shade(terrain, time, self.width, self.height).to_rgb();
+        if let Some(mesh_overlay) = self.mesh_overlay {
+            let world_size = (self.width, self.height);
+            let viewport = self.viewport.unwrap_or_else(|| Viewport::whole(world_size));
+            mesh_overlay.draw(&mut colors, terrain, self.projection, world_size, &viewport);
+        }
+        let frame = match self.output {
+            Output::Frames(FrameFormat::Png16 | FrameFormat::Exr) => Frame::Colors(colors),
+            _ => Frame::Data(colors.iter().flat_map(|color| color.to_data()).collect()),
+        };
+        let image_num = self.image_num(time.frame);
+        if let Some(previous) = self.previous_frame.take() {
+            let steps = self.interpolated_frames + 1;
+            for step in 1..steps {
+                let blended = previous.blend(&frame, step as f64 / steps as f64);
+                self.write_image(image_num - steps + step, &blended);
+            }
+        }
+        self.write_image(image_num, &frame);
+        if self.interpolated_frames > 0 {
+            self.previous_frame = Some(frame);
+        }
+    }
+
+    pub fn shade_pixels(&self, terrain: &Terrain, time: FrameTime, width: usize, height: usize) -> Vec<RGB> {
+        self.shade(terrain, time, width, height).to_rgb()
+    }
+
+    fn shade(&self, terrain: &Terrain, time: FrameTime, width: usize, height: usize) -> Pixels {
+        let world_size = (self.width, self.height);
+        let viewport = self.viewport.unwrap_or_else(|| Viewport::whole(world_size));
+        let shade = |cell: &Cell| self.shader.shade_cell(cell, terrain, time);
+        shade_to_pixels(&shade, terrain, self.projection, self.raster, world_size, viewport, (width, height))
+    }
+
+    pub fn frame_path(&self, frame_num: u32) -> String {
+        self.image_path(self.image_num(frame_num))
+    }
+
+    fn image_num(&self, frame_num: u32) -> u32 {
+        frame_num * (self.interpolated_frames + 1)
+    }
+
+    fn image_path(&self, image_num: u32) -> String {
+        let extension = match self.output {
+            Output::Frames(FrameFormat::Exr) => "exr",
+            _ => "png",
+        };
+        format!("{}/frame_{:06}.{}", self.render_path, image_num, extension)
+    }
+
+    // video and animation frames are written in the order they come, which
+    // is image order as frames are rendered in turn
+    fn write_image(&mut self, image_num: u32, frame: &Frame) {
+        let (width, height) = (self.width, self.height);
+        match (&self.output, frame) {
+            (Output::Frames(FrameFormat::Png), Frame::Data(data)) =>
+                save_png(&self.image_path(image_num), width, height, data),
+            (Output::Frames(FrameFormat::Png16), Frame::Colors(colors)) =>
+                save_png16(&self.image_path(image_num), width, height, colors),
+            (Output::Frames(FrameFormat::Exr), Frame::Colors(colors)) =>
+                save_exr(&self.image_path(image_num), width, height, colors),
+            (Output::Video { file, fps }, Frame::Data(data)) => {
+                let path = format!("{}/{}", self.render_path, file);
+                let fps = *fps;
+                self.video
+                    .get_or_insert_with(|| VideoSink::start(&path, width, height, fps))
+                    .write_frame(data);
+            }
+            (Output::Animation { file, format, delay_ms, scale }, Frame::Data(data)) => {
+                let path = format!("{}/{}", self.render_path, file);
+                let (format, delay_ms, scale) = (*format, *delay_ms, *scale);
+                self.animation
+                    .get_or_insert_with(|| Animation::start(&path, format, width, height, delay_ms, scale))
+                    .add_frame(data);
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Frame {
+    fn blend(&self, other: &Frame, t: f64) -> Frame {
+        match (self, other) {
+            (Frame::Data(a), Frame::Data(b)) => Frame::Data(a.iter()
+                .zip(b.iter())
+                .map(|(&a, &b)| (a as f64 + (b as f64 - a as f64) * t).round() as u8)
+                .collect()),
+            (Frame::Colors(a), Frame::Colors(b)) => Frame::Colors(a.iter().zip(b.iter()).map(|(a, b)| a.mix(b, t)).collect()),
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl FrameFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FrameFormat::Png => "png",
+            FrameFormat::Png16 => "png16",
+            FrameFormat::Exr => "exr",
+        }
+    }
+}
 
-    pub fn render(&self, terrain: &Terrain, frame_num: u32) {
-        let mut pixels = Pixels::new(self.width, self.height);
+impl VideoSink {
+    pub fn start(path: &str, width: usize, height: usize, fps: u32) -> VideoSink {
+        assert!(fps > 0);
+        let ffmpeg = Command::new("ffmpeg")
+            .args(["-loglevel", "error", "-y", "-f", "rawvideo", "-pixel_format", "rgb24"])
+            .args(["-video_size", &format!("{}x{}", width, height), "-framerate", &fps.to_string(), "-i", "-"])
+            // players mostly want 4:2:0 chroma, which needs even dimensions
+            .args(["-vf", "pad=ceil(iw/2)*2:ceil(ih/2)*2", "-pix_fmt", "yuv420p", path])
+            .stdin(Stdio::piped())
+            .spawn()
+            .unwrap_or_else(|e| panic!("couldn't start ffmpeg for {}: {}", path, e));
+        VideoSink { path: path.to_string(), ffmpeg }
+    }
+
+    pub fn write_frame(&mut self, pixel_data: &[u8]) {
+        let stdin = self.ffmpeg.stdin.as_mut().unwrap();
+        if let Err(e) = stdin.write_all(pixel_data) {
+            panic!("ffmpeg stopped taking frames for {}: {}", self.path, e);
+        }
+    }
+}
+
+impl Drop for VideoSink {
+    fn drop(&mut self) {
+        drop(self.ffmpeg.stdin.take());
+        let status = self.ffmpeg.wait().unwrap();
+        if !status.success() && !thread::panicking() {
+            panic!("ffmpeg failed to write {}: {}", self.path, status);
+        }
+    }
+}
+
+impl BackgroundRenderer {
+    pub fn new<S: Shade + 'static>(mut renderer: Renderer<S>) -> BackgroundRenderer {
+        let (frames, snapshots) = mpsc::sync_channel::<(Terrain, FrameTime)>(1);
+        let thread = thread::spawn(move || {
+            for (terrain, time) in snapshots.iter() {
+                renderer.render(&terrain, time);
+            }
+        });
+        BackgroundRenderer { frames: Some(frames), thread: Some(thread) }
+    }
+
+    pub fn render(&self, terrain: &Terrain, time: FrameTime) {
+        self.frames.as_ref().unwrap().send((terrain.clone(), time)).unwrap();
+    }
+}
+
+impl Drop for BackgroundRenderer {
+    fn drop(&mut self) {
+        drop(self.frames.take());
+        if let Some(thread) = self.thread.take() {
+            // the renderer's panic, if it had one, has been printed already
+            if thread.join().is_err() && !thread::panicking() {
+                panic!("background renderer failed");
+            }
+        }
+    }
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, fill: RGB) -> Image {
+        Image { width, height, pixels: vec![fill; width * height] }
+    }
+
+    pub fn to_data(&self) -> Vec<u8> {
+        self.pixels.iter().flat_map(|pixel| pixel.to_data()).collect()
+    }
+
+    pub fn save(&self, path: &str) {
+        save_png(path, self.width, self.height, &self.to_data());
+    }
+}
+
+impl Viewport {
+    pub fn whole(world_size: (usize, usize)) -> Viewport {
+        Viewport { x: 0.0, y: 0.0, width: world_size.0 as f64, height: world_size.1 as f64 }
+    }
+}
+
+impl MeshOverlay {
+    // over colors the size of the world, in rows from the top
+    fn draw(&self, colors: &mut [RGB], terrain: &Terrain, projection: Projection, world_size: (usize, usize), viewport: &Viewport) {
+        let neighbors = terrain.neighbors();
+        let mean_distance = neighbors.iter().map(|nd| nd.distance()).sum::<f64>() / neighbors.len().max(1) as f64;
+        let ramp = ColorRamp::new(vec![
+            (0.0, RGB { r: 0.1, g: 0.3, b: 1.0 }),
+            (mean_distance, RGB { r: 1.0, g: 1.0, b: 1.0 }),
+            (2.0 * mean_distance, RGB { r: 1.0, g: 0.1, b: 0.1 }),
+        ]);
+        let edge_color = RGB { r: 0.85, g: 0.85, b: 0.85 };
+        let point_color = RGB { r: 0.1, g: 0.1, b: 0.1 };
+        let project = |cell: &Cell| projection.project(cell.x(), cell.y(), world_size, viewport, world_size);
+
+        for cell in terrain.cells_iter() {
+            let from = match project(&cell) {
+                Some(from) => from,
+                None => continue,
+            };
+            // each edge once, from the cell listed first
+            for nd in cell.neighbor_data_iter().filter(|nd| nd.index() > cell.index()) {
+                let neighbor = terrain.get_cell(nd.index());
+                // edges across the seam of a wrapping world would be drawn
+                // the long way across the image
+                if (neighbor.x() - cell.x()).abs() > world_size.0 as f64 / 2.0 {
+                    continue;
+                }
+                if let Some(to) = project(&neighbor) {
+                    let color = if self.distances { ramp.color_at(nd.distance()) } else { edge_color.clone() };
+                    draw_line(colors, world_size, from, to, &color);
+                }
+            }
+        }
         for cell in terrain.cells_iter() {
-            pixels.add_color(cell.x(), cell.y(), &self.shader.shade_cell(cell, terrain));
+            if let Some((x, y)) = project(&cell) {
+                set_pixel(colors, world_size, x, y, &point_color);
+            }
         }
-        self.save_image(frame_num, &pixels.to_data());
     }
+}
 
-    fn save_image(&self, frame_num: u32, pixel_data: &Vec<u8>) {
-        let path_string = format!("{}/frame_{:06}.png", self.render_path, frame_num);
-        let path = Path::new(&path_string);
-        let file = File::create(path).unwrap();
-        let w = &mut BufWriter::new(file);
+impl Projection {
+    fn project(&self, x: f64, y: f64, world_size: (usize, usize), viewport: &Viewport, size: (usize, usize))
+        -> Option<(f64, f64)> {
+        let (world_width, world_height) = (world_size.0 as f64, world_size.1 as f64);
+        let (width, height) = (size.0 as f64, size.1 as f64);
+        match *self {
+            Projection::Equirectangular =>
+                Some(((x - viewport.x) * width / viewport.width, (y - viewport.y) * height / viewport.height)),
+            Projection::Orthographic { center_lon, center_lat } => {
+                let (lon, lat) = sphere::lon_lat(x, y, world_width, world_height);
+                let (lon0, lat0) = (center_lon.to_radians(), center_lat.to_radians());
+                let cos_c = lat0.sin() * lat.sin() + lat0.cos() * lat.cos() * (lon - lon0).cos();
+                if cos_c < 0.0 {
+                    return None;
+                }
+                let radius = width.min(height) / 2.0;
+                let px = lat.cos() * (lon - lon0).sin();
+                let py = lat0.cos() * lat.sin() - lat0.sin() * lat.cos() * (lon - lon0).cos();
+                Some((width / 2.0 + radius * px, height / 2.0 + radius * py))
+            }
+        }
+    }
 
-        let mut encoder = png::Encoder::new(w, self.width as u32, self.height as u32);
-        encoder.set_color(png::ColorType::RGB);
-        encoder.set_depth(png::BitDepth::Eight);
-        let mut writer = encoder.write_header().unwrap();
-        writer.write_image_data(&pixel_data).unwrap();
+    // whether a point in pixels shows some of the world
+    fn in_view(&self, x: f64, y: f64, size: (usize, usize)) -> bool {
+        match *self {
+            Projection::Equirectangular => true,
+            Projection::Orthographic { .. } => {
+                let (width, height) = (size.0 as f64, size.1 as f64);
+                let radius = width.min(height) / 2.0;
+                (x - width / 2.0).powi(2) + (y - height / 2.0).powi(2) <= radius * radius
+            }
+        }
+    }
+
+    // A triangle's corners in pixels, none when a corner is out of sight,
+    // and twice, once from each edge, when it spans the seam of a world that
+    // wraps, which shows as neighbors nearer together than their x
+    // coordinates are. Triangles that still look stretched once unwrapped
+    // fan around a pole and are left out.
+    fn project_triangle(
+        &self,
+        terrain: &Terrain,
+        triangle: &[usize; 3],
+        world_size: (usize, usize),
+        viewport: &Viewport,
+        size: (usize, usize),
+    ) -> Vec<[(f64, f64); 3]> {
+        let world_width = world_size.0 as f64;
+        let cells = triangle.map(|cell_index| terrain.get_cell(cell_index));
+        let wraps = (0..3).any(|corner| {
+            let (a, b) = (&cells[corner], &cells[(corner + 1) % 3]);
+            (a.x() - b.x()).abs() > world_width / 2.0
+                && a.neighbor_data_iter().any(|nd| nd.index() == b.index() && nd.distance() < world_width / 2.0)
+        });
+        let xs = cells.each_ref().map(|cell| {
+            if wraps && cell.x() < world_width / 2.0 { cell.x() + world_width } else { cell.x() }
+        });
+        if wraps && (0..3).any(|corner| (xs[corner] - xs[(corner + 1) % 3]).abs() > world_width / 2.0) {
+            return Vec::new();
+        }
+
+        let shifts: &[f64] = match self {
+            Projection::Equirectangular if wraps => &[0.0, -world_width],
+            _ => &[0.0],
+        };
+        shifts.iter()
+            .filter_map(|shift| {
+                let corner = |corner: usize| self.project(xs[corner] + shift, cells[corner].y(), world_size, viewport, size);
+                Some([corner(0)?, corner(1)?, corner(2)?])
+            })
+            .collect()
     }
 }
 
@@ -81,23 +653,66 @@ impl Pixels {
                 if px >= 0 && px < self.width as i32 && py >= 0 && py < self.height as i32 {
                     let wx = 1.0 - (px as f64 + 0.5 - x).abs();
                     let wy = 1.0 - (py as f64 + 0.5 - y).abs();
-                    let pw = wx * wy;
-                    let index = (self.height - py as usize - 1) * self.width + px as usize;
-                    self.pixels[index].total_rgb.r += color.r * pw;
-                    self.pixels[index].total_rgb.g += color.g * pw;
-                    self.pixels[index].total_rgb.b += color.b * pw;
-                    self.pixels[index].total_weight += pw;
+                    self.add_to_pixel(px as usize, py as usize, color, wx * wy);
+                }
+            }
+        }
+    }
+
+    // adds the corners' colors, weighted by how near each is, to every pixel
+    // whose center falls inside the triangle
+    fn fill_triangle(&mut self, corners: [(f64, f64); 3], colors: [&RGB; 3]) {
+        let [(x0, y0), (x1, y1), (x2, y2)] = corners;
+        let area = (x1 - x0) * (y2 - y0) - (x2 - x0) * (y1 - y0);
+        if area == 0.0 {
+            return;
+        }
+        let (min_x, max_x) = (x0.min(x1).min(x2), x0.max(x1).max(x2));
+        let (min_y, max_y) = (y0.min(y1).min(y2), y0.max(y1).max(y2));
+        let px_start = (min_x - 0.5).ceil().max(0.0) as usize;
+        let px_end = ((max_x - 0.5).floor() + 1.0).clamp(0.0, self.width as f64) as usize;
+        let py_start = (min_y - 0.5).ceil().max(0.0) as usize;
+        let py_end = ((max_y - 0.5).floor() + 1.0).clamp(0.0, self.height as f64) as usize;
+        for py in py_start..py_end {
+            let y = py as f64 + 0.5;
+            for px in px_start..px_end {
+                let x = px as f64 + 0.5;
+                let w0 = ((x1 - x) * (y2 - y) - (x2 - x) * (y1 - y)) / area;
+                let w1 = ((x2 - x) * (y0 - y) - (x0 - x) * (y2 - y)) / area;
+                let w2 = 1.0 - w0 - w1;
+                // pixels on an edge take a share from both its triangles
+                if w0 >= -1e-9 && w1 >= -1e-9 && w2 >= -1e-9 {
+                    let color = RGB {
+                        r: colors[0].r * w0 + colors[1].r * w1 + colors[2].r * w2,
+                        g: colors[0].g * w0 + colors[1].g * w1 + colors[2].g * w2,
+                        b: colors[0].b * w0 + colors[1].b * w1 + colors[2].b * w2,
+                    };
+                    self.add_to_pixel(px, py, &color, 1.0);
                 }
             }
         }
     }
 
-    fn to_data(&self) -> Vec<u8> {
-        let mut data: Vec<u8> = Vec::with_capacity(self.width * self.height * 3);
-        for pixel in self.pixels.iter() {
-            data.extend_from_slice(&pixel.render().to_data());
+    // pixel coordinates run up from the bottom row
+    fn add_to_pixel(&mut self, px: usize, py: usize, color: &RGB, weight: f64) {
+        let index = (self.height - py - 1) * self.width + px;
+        self.pixels[index].total_rgb.r += color.r * weight;
+        self.pixels[index].total_rgb.g += color.g * weight;
+        self.pixels[index].total_rgb.b += color.b * weight;
+        self.pixels[index].total_weight += weight;
+    }
+
+    fn merge(&mut self, other: &Pixels) {
+        for (pixel, other) in self.pixels.iter_mut().zip(other.pixels.iter()) {
+            pixel.total_rgb.r += other.total_rgb.r;
+            pixel.total_rgb.g += other.total_rgb.g;
+            pixel.total_rgb.b += other.total_rgb.b;
+            pixel.total_weight += other.total_weight;
         }
-        data
+    }
+
+    fn to_rgb(&self) -> Vec<RGB> {
+        self.pixels.iter().map(|pixel| pixel.render()).collect()
     }
 }
 
@@ -114,7 +729,16 @@ impl Pixel {
 }
 
 impl RGB {
-    fn to_data(&self) -> [u8; 3] {
+    // this color moved a fraction t of the way to other
+    pub fn mix(&self, other: &RGB, t: f64) -> RGB {
+        RGB {
+            r: self.r + (other.r - self.r) * t,
+            g: self.g + (other.g - self.g) * t,
+            b: self.b + (other.b - self.b) * t,
+        }
+    }
+
+    pub fn to_data(&self) -> [u8; 3] {
         [
             RGB::normalize(self.r),
             RGB::normalize(self.g),
@@ -122,10 +746,220 @@ impl RGB {
         ]
     }
 
+    pub fn to_data16(&self) -> [u16; 3] {
+        [
+            RGB::normalize16(self.r),
+            RGB::normalize16(self.g),
+            RGB::normalize16(self.b)
+        ]
+    }
+
+    fn normalize16(n: f64) -> u16 {
+        (n * 65536.0).floor().clamp(0.0, 65535.0) as u16
+    }
+
     fn normalize(n: f64) -> u8 {
-        let mut n = (n * 256.0).floor();
-        if n < 0.0 { n = 0.0; }
-        if n > 255.0 { n = 255.0; }
-        n as u8
+        (n * 256.0).floor().clamp(0.0, 255.0) as u8
+    }
+}
+
+pub fn save_png(path: &str, width: usize, height: usize, pixel_data: &[u8]) {
+    let file = File::create(Path::new(path)).unwrap();
+    let w = &mut BufWriter::new(file);
+
+    let mut encoder = png::Encoder::new(w, width as u32, height as u32);
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(pixel_data).unwrap();
+}
+
+// 16-bit samples go in big-endian
+pub fn save_png16(path: &str, width: usize, height: usize, colors: &[RGB]) {
+    let file = File::create(Path::new(path)).unwrap();
+    let w = &mut BufWriter::new(file);
+
+    let mut encoder = png::Encoder::new(w, width as u32, height as u32);
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Sixteen);
+    let mut writer = encoder.write_header().unwrap();
+    let data: Vec<u8> = colors.iter().flat_map(|color| color.to_data16()).flat_map(u16::to_be_bytes).collect();
+    writer.write_image_data(&data).unwrap();
+}
+
+// Writes an uncompressed single part scanline OpenEXR file with R, G and B
+// channels of 32-bit floats, one scanline to a chunk.
+pub fn save_exr(path: &str, width: usize, height: usize, colors: &[RGB]) {
+    assert!(width > 0 && height > 0 && width <= i32::MAX as usize && height <= i32::MAX as usize);
+    let mut data = Vec::new();
+    data.extend_from_slice(&[0x76, 0x2f, 0x31, 0x01]);
+    data.extend_from_slice(&2u32.to_le_bytes());
+
+    let mut header_attribute = |name: &str, kind: &str, value: &[u8]| {
+        for text in [name, kind] {
+            data.extend_from_slice(text.as_bytes());
+            data.push(0);
+        }
+        data.extend_from_slice(&(value.len() as i32).to_le_bytes());
+        data.extend_from_slice(value);
+    };
+    // channels go in alphabetical order; pixel type 2 is FLOAT, then not
+    // linear, three bytes reserved and sampled at every pixel
+    let mut channels = Vec::new();
+    for name in ["B", "G", "R"] {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        channels.extend_from_slice(&2i32.to_le_bytes());
+        channels.extend_from_slice(&[0; 4]);
+        channels.extend_from_slice(&1i32.to_le_bytes());
+        channels.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channels.push(0);
+    let window: Vec<u8> = [0, 0, width as i32 - 1, height as i32 - 1].iter().flat_map(|n| n.to_le_bytes()).collect();
+    header_attribute("channels", "chlist", &channels);
+    header_attribute("compression", "compression", &[0]);
+    header_attribute("dataWindow", "box2i", &window);
+    header_attribute("displayWindow", "box2i", &window);
+    header_attribute("lineOrder", "lineOrder", &[0]);
+    header_attribute("pixelAspectRatio", "float", &1f32.to_le_bytes());
+    header_attribute("screenWindowCenter", "v2f", &[0; 8]);
+    header_attribute("screenWindowWidth", "float", &1f32.to_le_bytes());
+    data.push(0);
+
+    // the offset of each scanline's chunk, then the chunks, each the line's
+    // blues, greens and reds
+    let line_len = 8 + width * 3 * 4;
+    let first_line = data.len() + height * 8;
+    for y in 0..height {
+        data.extend_from_slice(&((first_line + y * line_len) as u64).to_le_bytes());
     }
+    for (y, row) in colors.chunks(width).enumerate() {
+        data.extend_from_slice(&(y as i32).to_le_bytes());
+        data.extend_from_slice(&((width * 3 * 4) as i32).to_le_bytes());
+        for channel in [|c: &RGB| c.b, |c: &RGB| c.g, |c: &RGB| c.r] {
+            for color in row.iter() {
+                data.extend_from_slice(&(channel(color) as f32).to_le_bytes());
+            }
+        }
+    }
+    fs::write(path, data).unwrap_or_else(|e| panic!("couldn't write {}: {}", path, e));
+}
+
+pub fn shade_pixels<S: Shade + ?Sized>(
+    shader: &S,
+    terrain: &Terrain,
+    time: FrameTime,
+    projection: Projection,
+    world_size: (usize, usize),
+    size: (usize, usize),
+) -> Vec<RGB> {
+    let shade = |cell: &Cell| shader.shade_cell(cell, terrain, time);
+    shade_to_pixels(&shade, terrain, projection, Raster::Splat, world_size, Viewport::whole(world_size), size).to_rgb()
+}
+
+// shade gives each cell's color
+fn shade_to_pixels<F: Fn(&Cell) -> RGB + Sync>(
+    shade: &F,
+    terrain: &Terrain,
+    projection: Projection,
+    raster: Raster,
+    world_size: (usize, usize),
+    viewport: Viewport,
+    size: (usize, usize),
+) -> Pixels {
+    let parts = match raster {
+        Raster::Splat => in_ranges(terrain.cells_len(), |cells| {
+            let mut pixels = Pixels::new(size.0, size.1);
+            for cell_index in cells {
+                let cell = terrain.get_cell(cell_index);
+                if let Some((x, y)) = projection.project(cell.x(), cell.y(), world_size, &viewport, size) {
+                    pixels.add_color(x, y, &shade(&cell));
+                }
+            }
+            pixels
+        }),
+        Raster::Triangles => {
+            let colors = shade_cells(shade, terrain);
+            let triangles = terrain.triangles();
+            in_ranges(triangles.len(), |range| {
+                let mut pixels = Pixels::new(size.0, size.1);
+                for triangle in triangles[range].iter() {
+                    let colors = [&colors[triangle[0]], &colors[triangle[1]], &colors[triangle[2]]];
+                    for corners in projection.project_triangle(terrain, triangle, world_size, &viewport, size) {
+                        pixels.fill_triangle(corners, colors);
+                    }
+                }
+                pixels
+            })
+        }
+        Raster::Voronoi => {
+            let colors = shade_cells(shade, terrain);
+            let mut kd_tree = KdTree::new(2);
+            for cell in terrain.cells_iter() {
+                if let Some((x, y)) = projection.project(cell.x(), cell.y(), world_size, &viewport, size) {
+                    kd_tree.add([x, y], cell.index()).unwrap();
+                }
+            }
+            in_ranges(size.1, |rows| {
+                let mut pixels = Pixels::new(size.0, size.1);
+                for py in rows {
+                    for px in 0..size.0 {
+                        let (x, y) = (px as f64 + 0.5, py as f64 + 0.5);
+                        if !projection.in_view(x, y, size) {
+                            continue;
+                        }
+                        let nearest = kd_tree.nearest(&[x, y], 1, &distance::squared_euclidean).unwrap();
+                        if let Some((_, &cell_index)) = nearest.first() {
+                            pixels.add_to_pixel(px, py, &colors[cell_index], 1.0);
+                        }
+                    }
+                }
+                pixels
+            })
+        }
+    };
+
+    let mut parts = parts.into_iter();
+    let mut pixels = parts.next().unwrap_or_else(|| Pixels::new(size.0, size.1));
+    for part in parts {
+        pixels.merge(&part);
+    }
+    pixels
+}
+
+fn shade_cells<F: Fn(&Cell) -> RGB + Sync>(shade: &F, terrain: &Terrain) -> Vec<RGB> {
+    in_ranges(terrain.cells_len(), |cells| {
+        cells.map(|cell_index| shade(&terrain.get_cell(cell_index))).collect::<Vec<_>>()
+    }).concat()
+}
+
+// sets every pixel the segment between two points passes through
+fn draw_line(colors: &mut [RGB], size: (usize, usize), from: (f64, f64), to: (f64, f64), color: &RGB) {
+    let steps = (to.0 - from.0).abs().max((to.1 - from.1).abs()).ceil().max(1.0) as usize;
+    for step in 0..=steps {
+        let t = step as f64 / steps as f64;
+        set_pixel(colors, size, from.0 + (to.0 - from.0) * t, from.1 + (to.1 - from.1) * t, color);
+    }
+}
+
+// pixel coordinates run up from the bottom row, as in Pixels, but colors
+// are in rows from the top
+fn set_pixel(colors: &mut [RGB], size: (usize, usize), x: f64, y: f64, color: &RGB) {
+    if x >= 0.0 && y >= 0.0 && x < size.0 as f64 && y < size.1 as f64 {
+        colors[(size.1 - y as usize - 1) * size.0 + x as usize] = color.clone();
+    }
+}
+
+// Each thread works through a range of 0..len, and the results come back in
+// the ranges' order, so summing them makes a frame come out the same every
+// time.
+fn in_ranges<T: Send>(len: usize, work: impl Fn(Range<usize>) -> T + Sync) -> Vec<T> {
+    let range_len = len.div_ceil(num_cpus::get()).max(1);
+    let work = &work;
+    crossbeam::scope(|s| {
+        let handles: Vec<_> = (0..len).step_by(range_len)
+            .map(|start| s.spawn(move |_| work(start..(start + range_len).min(len))))
+            .collect();
+        handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+    }).unwrap()
 }
@@ -0,0 +1,91 @@
+use std::io::Read;
+
+use rand::rngs::StdRng;
+use rand::{Error, RngCore, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+
+use crate::checkpoint;
+
+// A small, fast generator (SplitMix64) for the many short streams a seeded
+// run needs, such as one per cell per step, where what a cell draws mustn't
+// depend on which thread gets to it first.
+#[derive(Clone)]
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> SplitMix64 {
+        SplitMix64 { state: seed }
+    }
+
+    // the index'th of a family of independent streams sharing a seed
+    pub fn stream(seed: u64, index: u64) -> SplitMix64 {
+        SplitMix64::new(derive(seed, index))
+    }
+}
+
+impl RngCore for SplitMix64 {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        mix(self.state)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+// a seed for one part of a seeded run, unrelated to the seeds of its other
+// parts
+pub fn derive(seed: u64, part: u64) -> u64 {
+    mix(seed ^ mix(part.wrapping_add(0x9e37_79b9_7f4a_7c15)))
+}
+
+// a generator from the seed, or from entropy for unseeded runs
+pub fn std_rng(seed: Option<u64>) -> StdRng {
+    seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64)
+}
+
+// the generator StdRng wraps, so it draws the same numbers from the same
+// seed, for generators a checkpoint needs to save where they'd got to
+pub fn chacha_rng(seed: Option<u64>) -> ChaCha12Rng {
+    seed.map_or_else(ChaCha12Rng::from_entropy, ChaCha12Rng::seed_from_u64)
+}
+
+// the generator's key (32 bytes), stream (u64) and position in it (u128),
+// little endian
+pub fn write_state(rng: &ChaCha12Rng, state: &mut Vec<u8>) {
+    state.extend_from_slice(&rng.get_seed());
+    state.extend_from_slice(&rng.get_stream().to_le_bytes());
+    state.extend_from_slice(&rng.get_word_pos().to_le_bytes());
+}
+
+pub fn read_state(reader: &mut impl Read) -> Result<ChaCha12Rng, String> {
+    let mut seed = [0; 32];
+    checkpoint::read_bytes(reader, &mut seed)?;
+    let mut rng = ChaCha12Rng::from_seed(seed);
+    rng.set_stream(checkpoint::read_u64(reader)?);
+    let mut word_pos = [0; 16];
+    checkpoint::read_bytes(reader, &mut word_pos)?;
+    rng.set_word_pos(u128::from_le_bytes(word_pos));
+    Ok(rng)
+}
+
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
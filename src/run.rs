@@ -1,27 +1,145 @@
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{BufReader, BufWriter};
 use std::path::Path;
 
-use crate::default_flow::DefaultFlow;
-use crate::default_shader::DefaultShader;
-use crate::flow::FlowEngine;
+use crate::active_cells::ActiveCells;
+use crate::biome::{BiomeClassifier, MoistureTracker};
+#[cfg(feature = "window")]
+use crate::biome::BiomeShader;
+use crate::checkpoint::{self, Checkpoint, Origin};
+use crate::change::{ChangeShader, CumulativeChange, FrameDifference};
+use crate::climate::Climate;
+use crate::contour_shader::ContourShader;
+use crate::dashboard::Dashboard;
+use crate::default_flow::{DefaultFlow, DrainPolicy, ErodedRouting, SuspendedTransport};
+#[cfg(feature = "window")]
+use crate::default_flow::SUSPENDED_LOAD_LAYER;
+use crate::distributed::{self, Cluster};
+use crate::droplet::DropletFlow;
+use crate::ensemble;
+use crate::evaporation::Evaporation;
+use crate::export::{export_gltf, export_raw, raw_size_valid, MeshExporter, MeshFormat, StlExporter};
+use crate::flood::FloodEvent;
+use crate::flow_shader::FlowShader;
+use crate::gauge::{GaugeRecorder, Probe};
+use crate::goal::Goal;
+use crate::groundwater::Groundwater;
+#[cfg(feature = "window")]
+use crate::groundwater::{GROUNDWATER_LAYER, SPRING_LAYER};
+use crate::history::{ExposureAge, Wetness};
+#[cfg(feature = "window")]
+use crate::history::EXPOSURE_AGE_LAYER;
+use crate::height_source::{DemScale, Dome, HeightGrid, HeightSource};
+use crate::humidity::HumidityModel;
+#[cfg(feature = "window")]
+use crate::humidity::{HUMIDITY_LAYER, RAINFALL_LAYER};
+use crate::hypsometric_shader::HypsometricShader;
+use crate::default_shader::{DefaultShader, HillshadeShader, ReliefShader};
+use crate::diffusion::HillslopeDiffusion;
+use crate::flow::{AdaptiveStep, Flow, FlowEngine, FlowStack};
+use crate::karst::Karst;
+#[cfg(feature = "window")]
+use crate::karst::SOLUBILITY_LAYER;
+use crate::manifest::RunManifest;
+use crate::mass_audit::MassAudit;
+use crate::nan_guard::NanGuard;
+use crate::pipe_model::PipeModel;
 use crate::point::Point;
 use crate::point_gen::{Bounds, PointGenerator, PointsReader, PointsWriter};
-use crate::render::Renderer;
-use crate::terrain::Terrain;
+use crate::precipitation::{Precipitation, PrecipitationMap};
+#[cfg(feature = "plugins")]
+use crate::plugin::{PluginFlow, PluginShader};
+use crate::render::{shade_pixels, BackgroundRenderer, Blend, ColorRamp, FrameFormat, FrameTime, Image, MeshOverlay, Output, Projection, Raster, Renderer, Shade, ShaderStack, VerticalScale, Viewport, RGB};
+use crate::rng;
+use crate::salinity::Salinity;
+#[cfg(feature = "window")]
+use crate::salinity::SalinityShader;
+use crate::scenario::{Action, Scenario};
+use crate::schedule::ParameterSchedule;
+use crate::sediment::SedimentModel;
+#[cfg(feature = "window")]
+use crate::sediment::SubstrateShader;
+use crate::shallow_water::ShallowWaterFlow;
+use crate::snapshot;
+use crate::sphere::SphereTopology;
+use crate::stream_power::StreamPower;
+use crate::svg::SvgExporter;
+use crate::terrain::{Terrain, Topology};
+use crate::thermal::ThermalFlow;
+use crate::tiles::TileExporter;
+use crate::tracer::{TracerRegion, Tracers};
+#[cfg(feature = "window")]
+use crate::tracer::TRACER_LAYER;
+use crate::terrain_gen::NoiseTerrain;
+use crate::terrain_preset::{InitialTerrain, TerrainPreset};
+use crate::water_shader::{WaterShader, CLEAR_WATER_SCATTERED};
+use crate::view3d::{OrbitCamera, Stereo, View3d};
+#[cfg(feature = "tui")]
+use crate::tui::{TuiAction, TuiViewer};
+#[cfg(feature = "window")]
+use crate::diagnostic_shader::{DepthShader, HeightShader, LayerShader, TemperatureShader, WetnessShader};
+#[cfg(feature = "scripting")]
+use crate::script::ScriptControl;
+#[cfg(feature = "http")]
+use crate::server::{Command, ControlServer};
+#[cfg(any(feature = "http", feature = "scripting"))]
+use crate::stats::TerrainStats;
+#[cfg(feature = "window")]
+use crate::window::{WindowAction, WindowViewer};
+
+// the parts of a seeded run that draw random numbers, each seeded apart
+const POINTS_SEED: u64 = 0;
+const FLOW_SEED: u64 = 1;
+const TRACERS_SEED: u64 = 2;
+const DROPLETS_SEED: u64 = 3;
+
+// What frames are drawn with when no shader plugin is loaded: the default
+// hillshading, elevation tints through a ramp, by default
+// HypsometricShader::atlas_ramp up to the initial peak, the hillshading
+// under water absorbing each channel by this much per unit depth, or the
+// hillshading under the direction and strength of the flow's flux, drawn
+// about two thirds full at `scale`; see FlowShader. Relief is the
+// hillshading's lighting alone, in gray, for layering over the others, and
+// the erosion rate and net change are heatmaps of the last step's change in
+// height per unit time and the change over the run, saturating at `scale`;
+// see CumulativeChange.
+#[derive(Clone, Debug)]
+pub enum FrameShader {
+    Default,
+    Hypsometric(Option<ColorRamp>),
+    Water { absorption: RGB },
+    Flow { scale: f64 },
+    Relief,
+    ErosionRate { scale: f64 },
+    NetChange { scale: f64 },
+}
 
 pub struct Runner<'a> {
     width: usize,
     height: usize,
     density: u32,
     max_z: f64,
+    initial_terrain: InitialTerrain<'a>,
+    spherical: bool,
+    topology: Topology,
+    projection: Projection,
+    raster: Raster,
+    viewport: Option<Viewport>,
+    mesh_overlay: Option<MeshOverlay>,
+    frame_shader: FrameShader,
+    shader_layers: Vec<(FrameShader, Blend, f64)>,
+    contours: Option<f64>,
+    vertical: VerticalScale,
 
     flow_rate: f64,
     flow_erosion_rate: f64,
     erosion_threshold: f64,
     erosion_rate: f64,
-    precipitation_rate: f64,
-    precipitation_amount: f64,
+    precipitation: Precipitation,
+    precipitation_map: Option<PrecipitationMap<'a>>,
+    drain_policy: DrainPolicy,
+    eroded_routing: ErodedRouting,
+    suspended_transport: Option<SuspendedTransport>,
 
     render_step: f64,
     frame_skip: u32,
@@ -29,6 +147,73 @@ pub struct Runner<'a> {
 
     data_path: &'a str,
     render_path: &'a str,
+
+    schedules: Vec<(&'a str, ParameterSchedule)>,
+    climate: Option<Climate>,
+    biomes: Option<(BiomeClassifier, f64)>,
+    humidity: Option<HumidityModel>,
+    probes: Vec<Probe<'a>>,
+    tracers: Option<(Vec<TracerRegion>, f64)>,
+    exposure_threshold: Option<f64>,
+    wet_depth: Option<f64>,
+    floods: Vec<FloodEvent>,
+    groundwater: Option<Groundwater>,
+    sediment: Option<SedimentModel>,
+    karst: Option<Karst>,
+    diffusivity: Option<f64>,
+    thermal: Option<(f64, f64)>,
+    evaporation: Option<(f64, f64)>,
+    droplets: Option<(u32, f64, f64, f64, f64)>,
+    shallow_water: Option<(f64, f64, f64)>,
+    pipe_model: Option<(f64, f64, f64, f64)>,
+    stacked_flows: Vec<&'a dyn Fn() -> Box<dyn Flow>>,
+    stream_power: Option<(StreamPower, f64)>,
+    salinity: Option<Salinity>,
+    interpolated_frames: u32,
+    view_3d: Option<(OrbitCamera, Stereo)>,
+    map_tiles: Option<(u32, u32)>,
+    dashboard: bool,
+    change_maps: Option<(f64, f64)>,
+    cumulative_change: bool,
+    export_heightmap: bool,
+    export_svg: Option<SvgExporter>,
+    export_mesh: Option<MeshExporter>,
+    export_stl: Option<StlExporter>,
+    export_raw: Option<usize>,
+    // whether every frame is exported, or just the end
+    export_gltf: Option<bool>,
+    goal: Option<Goal>,
+    scenario: Option<&'a str>,
+    ensemble: Option<(u32, u32)>,
+    distributed: Option<(&'a str, u32, bool)>,
+    deterministic: bool,
+    adaptive_step: Option<AdaptiveStep>,
+    active_cells: Option<ActiveCells>,
+    seed: Option<u64>,
+    nan_guard: Option<usize>,
+    nan_recovery: bool,
+    mass_audit: bool,
+    pipelined_render: bool,
+    output: Output,
+    checkpoint_every: Option<u32>,
+    resume: Option<&'a str>,
+    snapshot_path: Option<&'a str>,
+    replay: Option<&'a str>,
+
+    #[cfg(feature = "tui")]
+    tui: bool,
+    #[cfg(feature = "window")]
+    window: bool,
+    #[cfg(feature = "http")]
+    http_addr: Option<&'a str>,
+    #[cfg(feature = "plugins")]
+    flow_plugin: Option<&'a str>,
+    #[cfg(feature = "plugins")]
+    shader_plugin: Option<&'a str>,
+    #[cfg(feature = "scripting")]
+    script: Option<&'a str>,
+    #[cfg(feature = "gpu")]
+    gpu: bool,
 }
 
 pub struct RunnerBuilder<'a> {
@@ -36,6 +221,17 @@ pub struct RunnerBuilder<'a> {
     height: Option<usize>,
     density: Option<u32>,
     max_z: Option<f64>,
+    initial_terrain: InitialTerrain<'a>,
+    spherical: bool,
+    topology: Topology,
+    projection: Projection,
+    raster: Raster,
+    viewport: Option<Viewport>,
+    mesh_overlay: Option<MeshOverlay>,
+    frame_shader: FrameShader,
+    shader_layers: Vec<(FrameShader, Blend, f64)>,
+    contours: Option<f64>,
+    vertical: VerticalScale,
 
     flow_rate: Option<f64>,
     flow_erosion_rate: Option<f64>,
@@ -43,6 +239,11 @@ pub struct RunnerBuilder<'a> {
     erosion_rate: Option<f64>,
     precipitation_rate: Option<f64>,
     precipitation_amount: Option<f64>,
+    precipitation: Option<Precipitation>,
+    precipitation_map: Option<PrecipitationMap<'a>>,
+    drain_policy: DrainPolicy,
+    eroded_routing: ErodedRouting,
+    suspended_transport: Option<SuspendedTransport>,
 
     render_step: Option<f64>,
     frame_skip: Option<u32>,
@@ -50,34 +251,218 @@ pub struct RunnerBuilder<'a> {
 
     data_path: Option<&'a str>,
     render_path: Option<&'a str>,
+
+    schedules: Vec<(&'a str, ParameterSchedule)>,
+    climate: Option<Climate>,
+    biomes: Option<(BiomeClassifier, f64)>,
+    humidity: Option<HumidityModel>,
+    probes: Vec<Probe<'a>>,
+    tracers: Option<(Vec<TracerRegion>, f64)>,
+    exposure_threshold: Option<f64>,
+    wet_depth: Option<f64>,
+    floods: Vec<FloodEvent>,
+    groundwater: Option<Groundwater>,
+    sediment: Option<SedimentModel>,
+    karst: Option<Karst>,
+    diffusivity: Option<f64>,
+    thermal: Option<(f64, f64)>,
+    evaporation: Option<(f64, f64)>,
+    droplets: Option<(u32, f64, f64, f64, f64)>,
+    shallow_water: Option<(f64, f64, f64)>,
+    pipe_model: Option<(f64, f64, f64, f64)>,
+    stacked_flows: Vec<&'a dyn Fn() -> Box<dyn Flow>>,
+    stream_power: Option<(StreamPower, f64)>,
+    salinity: Option<Salinity>,
+    interpolated_frames: u32,
+    view_3d: Option<(OrbitCamera, Stereo)>,
+    map_tiles: Option<(u32, u32)>,
+    dashboard: bool,
+    change_maps: Option<(f64, f64)>,
+    cumulative_change: bool,
+    export_heightmap: bool,
+    export_svg: Option<SvgExporter>,
+    export_mesh: Option<MeshExporter>,
+    export_stl: Option<StlExporter>,
+    export_raw: Option<usize>,
+    // whether every frame is exported, or just the end
+    export_gltf: Option<bool>,
+    goal: Option<Goal>,
+    scenario: Option<&'a str>,
+    ensemble: Option<(u32, u32)>,
+    distributed: Option<(&'a str, u32, bool)>,
+    deterministic: bool,
+    adaptive_step: Option<AdaptiveStep>,
+    active_cells: Option<ActiveCells>,
+    seed: Option<u64>,
+    nan_guard: Option<usize>,
+    nan_recovery: bool,
+    mass_audit: bool,
+    pipelined_render: bool,
+    output: Output,
+    checkpoint_every: Option<u32>,
+    resume: Option<&'a str>,
+    snapshot_path: Option<&'a str>,
+    replay: Option<&'a str>,
+
+    #[cfg(feature = "tui")]
+    tui: bool,
+    #[cfg(feature = "window")]
+    window: bool,
+    #[cfg(feature = "http")]
+    http_addr: Option<&'a str>,
+    #[cfg(feature = "plugins")]
+    flow_plugin: Option<&'a str>,
+    #[cfg(feature = "plugins")]
+    shader_plugin: Option<&'a str>,
+    #[cfg(feature = "scripting")]
+    script: Option<&'a str>,
+    #[cfg(feature = "gpu")]
+    gpu: bool,
 }
 
 impl<'a> Runner<'a> {
-    pub fn run(&mut self) {
+    // runs as configured, failing only on what the run can't start without,
+    // such as the control API's address
+    pub fn run(&mut self) -> Result<(), String> {
+        if let Some(path) = self.replay {
+            self.replay_from(path);
+            return Ok(());
+        }
+        if let (Some(_), Some(addr)) = (self.distributed, distributed::coordinator_from_env()) {
+            distributed::run_worker(&addr, self.flow_strategy(None), self.deterministic);
+            return Ok(());
+        }
+        match (self.ensemble, ensemble::member_from_env()) {
+            (Some(_), Some(member)) => self.run_member(member),
+            (Some((members, processes)), None) => {
+                self.generate_points();
+                if processes > 1 {
+                    ensemble::run_processes(members, processes);
+                } else {
+                    for member in 0..members {
+                        println!("running ensemble member {} of {}", member + 1, members);
+                        self.run_member(member)?;
+                    }
+                }
+                ensemble::aggregate(self.render_path, members);
+                Ok(())
+            }
+            (None, _) => match self.resume {
+                Some(path) => self.resume_from(path),
+                None => self.simulate(self.render_path, None, None),
+            },
+        }
+    }
+
+    // continues a run from a checkpoint it wrote, stepping and rendering on
+    // from the checkpoint's frame with the runner's settings
+    pub fn resume_from(&mut self, path: &str) -> Result<(), String> {
+        assert!(self.ensemble.is_none(), "ensemble runs can't be resumed");
+        let checkpoint = checkpoint::read(path)?;
+        if checkpoint.origin != self.origin() {
+            return Err(format!("checkpoint {} is of {}, not {}", path, checkpoint.origin, self.origin()));
+        }
+        println!("resuming from frame {} of {}", checkpoint.frame, self.frame_count);
+        self.simulate(self.render_path, None, Some(checkpoint))
+    }
+
+    // renders a run again from the snapshots it wrote, through this runner's
+    // shaders and render settings, as frames numbered as the run's were
+    pub fn replay_from(&mut self, path: &str) {
+        let mut terrain = snapshot::read_mesh(path).unwrap_or_else(|e| panic!("{}", e));
+        let paths = snapshot::list(path).unwrap_or_else(|e| panic!("{}", e));
+        let mut renderer = self.renderer(self.render_path);
+        // the change between snapshots stands in for the change each step
+        let mut change = if draws_change(&self.frame_shader, &self.shader_layers) {
+            Some(CumulativeChange::new(&terrain))
+        } else {
+            None
+        };
+        let mut previous_time = None;
+        for (n, snapshot_path) in paths.iter().enumerate() {
+            println!("frame {} of {}", n + 1, paths.len());
+            let snapshot = snapshot::read(snapshot_path).unwrap_or_else(|e| panic!("{}", e));
+            assert!(
+                snapshot.world_size == (self.width, self.height) && snapshot.spherical == self.spherical,
+                "snapshot {} is of a {}x{}{} world",
+                snapshot_path,
+                snapshot.world_size.0,
+                snapshot.world_size.1,
+                if snapshot.spherical { " spherical" } else { "" },
+            );
+            snapshot.apply(&mut terrain);
+            if let Some(change) = change.as_mut() {
+                let elapsed = snapshot.time - previous_time.unwrap_or(0.0);
+                if elapsed > 0.0 {
+                    change.update(&mut terrain, elapsed);
+                }
+            }
+            previous_time = Some(snapshot.time);
+            renderer.render(&terrain, FrameTime { frame: snapshot.frame, time: snapshot.time });
+        }
+    }
+
+    fn run_member(&mut self, member: u32) -> Result<(), String> {
+        let path = ensemble::member_path(self.render_path, member);
+        fs::create_dir_all(&path).unwrap();
+        self.simulate(&path, Some(member), None)
+    }
+
+    // generates the planar points file if it is needed and missing,
+    // returning its path
+    fn generate_points(&self) -> String {
+        let seed = self.seed_for(None, POINTS_SEED);
         let points_file_path = format!(
-            "{}/points_{}x{}x{}.dat",
+            "{}/points_{}x{}x{}{}.dat",
             self.data_path,
             self.width,
             self.height,
             self.density,
+            self.seed.map_or(String::new(), |seed| format!("_seed{}", seed)),
         );
 
-        if !Path::new(&points_file_path).exists() {
-            let mut pw = PointsWriter::new(BufWriter::new(File::create(&points_file_path).unwrap()));
+        let planar_points = !self.spherical && self.topology == Topology::Delaunay;
+        if planar_points && !Path::new(&points_file_path).exists() {
+            // written aside and moved into place, so an interrupted run
+            // doesn't leave a partial file behind
+            let partial_path = format!("{}.partial", points_file_path);
+            let mut pw = PointsWriter::new(BufWriter::new(File::create(&partial_path).unwrap()));
             println!("generating points");
             pw.write_points(PointGenerator::new(
                 Bounds::new(0f64, self.width as f64),
                 Bounds::new(0f64, self.height as f64),
                 (self.density as f64).recip(),
+                rng::std_rng(seed),
             ));
+            drop(pw);
+            fs::rename(&partial_path, &points_file_path).unwrap();
         }
+        points_file_path
+    }
 
-        println!("configuring flow engine");
-        let height_at = |p: &Point| -> f64 {
-            let x_term = -2.0 * p.x / self.width as f64 + 1.0;
-            let y_term = -2.0 * p.y / self.height as f64 + 1.0;
-            self.max_z * (-x_term * x_term + 1.0) * (-y_term * y_term + 1.0)
+    // the terrain a run starts from
+    fn generate_terrain(&self) -> Terrain {
+        let points_file_path = self.generate_points();
+
+        let (width, height) = (self.width as f64, self.height as f64);
+        let source: Box<dyn HeightSource> = match self.initial_terrain {
+            InitialTerrain::Dome => Box::new(Dome::new(width, height, self.max_z)),
+            InitialTerrain::Random(seed) => {
+                let preset = TerrainPreset::random(seed, width, height, self.max_z);
+                println!("using {} terrain preset from seed {}", preset.name(), seed);
+                Box::new(preset)
+            }
+            InitialTerrain::Heightmap(path) => {
+                println!("using heightmap {}", path);
+                Box::new(HeightGrid::load_png(path, width, height, self.max_z))
+            }
+            InitialTerrain::GeoTiff(path, scale) => {
+                println!("using elevation model {}", path);
+                Box::new(HeightGrid::load_geotiff(path, width, height, self.max_z, scale))
+            }
+            InitialTerrain::Noise(params) => Box::new(NoiseTerrain::new(params, self.max_z)),
         };
+        let height_at = |p: &Point| -> f64 { source.height_at(p) };
         let depth_at = |p: &Point| -> f64 {
             let z = height_at(p);
             if z < 1.0 {
@@ -86,39 +471,785 @@ impl<'a> Runner<'a> {
                 0.0
             }
         };
-        let mut flow_engine = FlowEngine::new(
-            Terrain::generate(
-                PointsReader::new(BufReader::new(File::open(points_file_path).unwrap())),
-                height_at,
-                depth_at,
-            ),
-            DefaultFlow::new(
-                self.flow_rate,
-                self.flow_erosion_rate,
-                self.erosion_threshold,
-                self.erosion_rate,
-                self.precipitation_rate,
-                self.precipitation_amount,
-            ),
-        );
+        let spacing = (self.density as f64).recip();
+        let mut terrain = if self.spherical {
+            println!("generating sphere");
+            let sphere = SphereTopology::generate(self.width, self.height, self.density);
+            Terrain::generate_with_neighbors(sphere.points.into_iter(), &sphere.neighbors, height_at, depth_at)
+        } else {
+            match self.topology {
+                Topology::Delaunay => Terrain::generate(read_points(&points_file_path).into_iter(), height_at, depth_at),
+                Topology::SquareGrid => Terrain::square_grid(self.width as f64, self.height as f64, spacing, height_at, depth_at),
+                Topology::HexGrid => Terrain::hex_grid(self.width as f64, self.height as f64, spacing, height_at, depth_at),
+            }
+        };
+        if let Some(map) = self.precipitation_map {
+            map.apply(&mut terrain, width, height);
+        }
+        terrain
+    }
 
-        let renderer = Renderer::new(
-            self.width,
-            self.height,
-            DefaultShader {},
-            self.render_path,
-        );
+    // runs the simulation writing its output to render_path, and its final
+    // state for aggregation when it is an ensemble member, from the start or
+    // from a checkpoint
+    fn simulate(&mut self, render_path: &str, member: Option<u32>, checkpoint: Option<Checkpoint>) -> Result<(), String> {
+        // bound first, so a taken address fails the run before any work
+        #[cfg(feature = "http")]
+        let server = self.http_addr.map(ControlServer::start).transpose()?;
+        let (terrain, start_frame, mut steps, mut time, flow_state) = match checkpoint {
+            Some(checkpoint) => {
+                (checkpoint.terrain, checkpoint.frame, checkpoint.steps, checkpoint.time, Some(checkpoint.flow_state))
+            }
+            None => (self.generate_terrain(), 0, 0, 0.0, None),
+        };
+
+        println!("configuring flow engine");
+        let mut flow_engine = FlowEngine::new(terrain, self.flow_strategy(member));
+        // distributed workers draw theirs afresh from the seed
+        if let Some(flow_state) = flow_state {
+            flow_engine.strategy_mut().restore_random_state(&flow_state)
+                .map_err(|e| format!("checkpoint's flow state doesn't fit this run's flow: {}", e))?;
+        }
+        flow_engine.set_deterministic(self.deterministic);
+        if let Some(adaptive_step) = self.adaptive_step {
+            flow_engine.set_adaptive(adaptive_step);
+        }
+        if let Some(active_cells) = self.active_cells {
+            flow_engine.set_active_cells(active_cells);
+        }
+        if let Some(history_steps) = self.nan_guard {
+            let mut guard = NanGuard::new(&format!("{}/nan_dump", render_path), history_steps);
+            guard.set_recover(self.nan_recovery);
+            flow_engine.set_guard(guard);
+        }
+        if self.mass_audit {
+            flow_engine.set_audit(MassAudit::new(&format!("{}/mass_audit.csv", render_path)));
+        }
+
+        // checked against the run's own flow before any worker starts
+        let accepts = |name: &str, value| accepts_param(&flow_engine, &self.humidity, name, value);
+        for (name, schedule) in self.schedules.iter().filter(|(name, _)| *name != "render_step") {
+            if let Some(&(_, value)) = schedule.keyframes().iter().find(|&&(_, value)| !accepts(name, value)) {
+                return Err(format!("schedule sets invalid parameter {} = {}", name, value));
+            }
+        }
+        let mut scenario = self.scenario.map(|path| Scenario::load(path, accepts)).transpose()?;
+        #[cfg(feature = "scripting")]
+        let mut script = self.script.map(ScriptControl::load).transpose()?;
+
+        let mut cluster = self.distributed.map(|(addr, workers, spawn_local)| {
+            Cluster::start(addr, workers, spawn_local, flow_engine.terrain())
+        });
+
+        let mut renderer = self.renderer(render_path);
+        // with its own renderer, so the one here can still shade for viewers
+        let background = if self.pipelined_render {
+            Some(BackgroundRenderer::new(self.renderer(render_path)))
+        } else {
+            None
+        };
+
+        let mut view_3d = self.view_3d.map(|(camera, stereo)| {
+            (View3d::new(self.width, self.height, camera, stereo, self.vertical), self.shader())
+        });
+
+        let gltf = self.export_gltf.map(|every_frame| (every_frame, self.shader()));
+
+        let mut dashboard = if self.dashboard {
+            Some((Dashboard::new(self.width, self.height, self.max_z, self.vertical), self.shader()))
+        } else {
+            None
+        };
+
+        let mut manifest = self.manifest(render_path);
+        manifest.write();
+        if start_frame > 0 {
+            manifest.record_event(start_frame, time, "resume", &[("steps", steps as f64)]);
+        }
+
+        if let Some(path) = self.snapshot_path {
+            fs::create_dir_all(path).unwrap();
+            snapshot::write_mesh(path, &self.origin(), flow_engine.terrain());
+        }
 
         println!("rendering");
 
-        for frame_num in 0..self.frame_count {
-            println!("frame {} of {}", frame_num + 1, self.frame_count);
-            renderer.render(flow_engine.terrain(), frame_num);
+        #[cfg(feature = "tui")]
+        let mut tui = if self.tui { Some(TuiViewer::new()) } else { None };
+        if let Some(climate) = self.climate.as_ref() {
+            climate.update(flow_engine.terrain_mut(), self.width as f64, self.height as f64);
+        }
+        let moisture = self.biomes.as_ref().map(|(_, timescale)| MoistureTracker::new(*timescale));
+        let mut humidity = self.humidity.clone();
+        let mut tracers = self.tracers.as_ref().map(|(regions, mixing_depth)| {
+            let path = format!("{}/tracers.csv", render_path);
+            Tracers::seed(regions, *mixing_depth, flow_engine.terrain_mut(), &path, rng::std_rng(self.seed_for(member, TRACERS_SEED)))
+        });
+        let mut frame_difference = self.change_maps.map(|_| FrameDifference::new(flow_engine.terrain()));
+        let mut salinity = self.salinity.clone();
+        let tracks_change = self.cumulative_change || draws_change(&self.frame_shader, &self.shader_layers);
+        let mut cumulative_change = if tracks_change { Some(CumulativeChange::new(flow_engine.terrain())) } else { None };
+        let mut exposure_age = self.exposure_threshold.map(ExposureAge::new);
+        let mut wetness = self.wet_depth.map(Wetness::new);
+        let mut gauges = if self.probes.is_empty() {
+            None
+        } else {
+            let path = format!("{}/gauges.csv", render_path);
+            Some(GaugeRecorder::new(&path, &self.probes, flow_engine.terrain()))
+        };
+        #[cfg(feature = "window")]
+        let mut window = if self.window {
+            let mut shaders: Vec<(&'static str, Box<dyn Shade>)> = vec![
+                ("default", Box::new(DefaultShader::new(self.vertical))),
+                ("height", Box::new(HeightShader::new(self.max_z, self.vertical))),
+                ("depth", Box::new(DepthShader {})),
+                ("hypsometric", Box::new(HypsometricShader::new(self.atlas_ramp(), self.vertical))),
+                ("water", Box::new(WaterShader::new(HillshadeShader::new(self.vertical)))),
+                ("relief", Box::new(ReliefShader::new(self.vertical))),
+            ];
+            if self.climate.is_some() {
+                shaders.push(("temperature", Box::new(TemperatureShader::new(-20.0, 40.0))));
+            }
+            if self.humidity.is_some() {
+                shaders.push(("humidity", Box::new(LayerShader::new(HUMIDITY_LAYER, 1.0))));
+                shaders.push(("rainfall", Box::new(LayerShader::new(RAINFALL_LAYER, 0.05))));
+            }
+            if self.tracers.is_some() {
+                shaders.push(("tracers", Box::new(LayerShader::new(TRACER_LAYER, 1.0))));
+            }
+            if self.exposure_threshold.is_some() {
+                let run_time = self.render_step * (self.frame_skip * self.frame_count) as f64;
+                shaders.push(("exposure age", Box::new(LayerShader::new(EXPOSURE_AGE_LAYER, run_time))));
+            }
+            if let Some(wet_depth) = self.wet_depth {
+                shaders.push(("wetness", Box::new(WetnessShader::new(wet_depth))));
+            }
+            if let Some(groundwater) = self.groundwater.as_ref() {
+                shaders.push(("groundwater", Box::new(LayerShader::new(GROUNDWATER_LAYER, groundwater.capacity()))));
+                shaders.push(("springs", Box::new(LayerShader::new(SPRING_LAYER, 0.01))));
+            }
+            if self.sediment.is_some() {
+                shaders.push(("substrate", Box::new(SubstrateShader {})));
+            }
+            // those needing data kept only for them, as the frames draw them
+            for frame_shader in frame_shaders(&self.frame_shader, &self.shader_layers) {
+                match frame_shader {
+                    FrameShader::Flow { .. } => shaders.push(("flow", self.built_in_shader(frame_shader))),
+                    FrameShader::ErosionRate { .. } => shaders.push(("erosion rate", self.built_in_shader(frame_shader))),
+                    FrameShader::NetChange { .. } => shaders.push(("net change", self.built_in_shader(frame_shader))),
+                    _ => (),
+                }
+            }
+            if self.eroded_routing.suspended > 0.0 {
+                shaders.push(("suspended load", Box::new(LayerShader::new(SUSPENDED_LOAD_LAYER, 1.0))));
+            }
+            if self.karst.is_some() {
+                shaders.push(("solubility", Box::new(LayerShader::new(SOLUBILITY_LAYER, 1.0))));
+            }
+            if self.salinity.is_some() {
+                shaders.push(("salinity", Box::new(SalinityShader {})));
+            }
+            if let Some((height_scale, depth_scale)) = self.change_maps {
+                shaders.push(("height change", Box::new(ChangeShader::height(height_scale))));
+                shaders.push(("depth change", Box::new(ChangeShader::depth(depth_scale))));
+            }
+            if let Some((classifier, _)) = self.biomes.as_ref() {
+                shaders.push(("biome", Box::new(BiomeShader::new(classifier.clone()))));
+            }
+            Some(WindowViewer::new(self.width, self.height, shaders))
+        } else {
+            None
+        };
+        #[cfg(feature = "http")]
+        let mut checkpoint_replies = Vec::new();
+        let mut goal_met = false;
+        #[cfg(feature = "tui")]
+        let quiet = tui.is_some();
+        #[cfg(not(feature = "tui"))]
+        let quiet = false;
+
+        for frame_num in start_frame..self.frame_count {
+            if !quiet {
+                println!("frame {} of {}", frame_num + 1, self.frame_count);
+            }
+            if let Some(cluster) = cluster.as_mut() {
+                cluster.gather(flow_engine.terrain_mut());
+            }
+            for flood in self.floods.iter().filter(|flood| flood.frame == frame_num) {
+                let cells = flood.apply(flow_engine.terrain_mut());
+                manifest.record_event(frame_num, time, "flood", &flood_fields(flood, cells));
+            }
+            let frame_time = FrameTime { frame: frame_num, time };
+            match (self.snapshot_path, background.as_ref()) {
+                (Some(path), _) => {
+                    snapshot::write(path, (self.width, self.height), self.spherical, frame_num, time, flow_engine.terrain());
+                }
+                (None, Some(background)) => background.render(flow_engine.terrain(), frame_time),
+                (None, None) => renderer.render(flow_engine.terrain(), frame_time),
+            }
+            if let (Some(frame_difference), Some((height_scale, depth_scale))) = (frame_difference.as_mut(), self.change_maps) {
+                frame_difference.update(flow_engine.terrain_mut());
+                let maps = [("height_change", ChangeShader::height(height_scale)), ("depth_change", ChangeShader::depth(depth_scale))];
+                for (name, shader) in maps.iter() {
+                    let size = (self.width, self.height);
+                    let pixels = shade_pixels(shader, flow_engine.terrain(), frame_time, self.projection, size, size);
+                    let path = format!("{}/{}_{:06}.png", render_path, name, frame_num);
+                    Image { width: self.width, height: self.height, pixels }.save(&path);
+                }
+            }
+            if let Some((view, shader)) = view_3d.as_mut() {
+                let path = format!("{}/view3d_{:06}.png", render_path, frame_num);
+                view.render(shader.as_ref(), flow_engine.terrain(), frame_time).save(&path);
+            }
+            if let Some((dashboard, shader)) = dashboard.as_mut() {
+                let path = format!("{}/dashboard_{:06}.png", render_path, frame_num);
+                dashboard.render(shader.as_ref(), flow_engine.terrain(), self.projection, frame_time).save(&path);
+            }
+            if let Some((tile_frame, max_zoom)) = self.map_tiles.filter(|(tile_frame, _)| *tile_frame == frame_num) {
+                let path = format!("{}/tiles_{:06}", render_path, tile_frame);
+                let size = (self.width, self.height);
+                TileExporter::new(max_zoom).export(&self.shader(), flow_engine.terrain(), frame_time, size, &path);
+            }
+            if let Some((true, shader)) = gltf.as_ref() {
+                let path = format!("{}/terrain_{:06}.glb", render_path, frame_num);
+                export_gltf(shader, flow_engine.terrain(), frame_time, (self.width, self.height), self.vertical, &path);
+            }
+            if let Some(tracers) = tracers.as_mut() {
+                tracers.write_positions(frame_num, flow_engine.terrain());
+            }
+
+            #[cfg(feature = "tui")]
+            if let Some(tui) = tui.as_mut() {
+                if let TuiAction::Quit = tui.update(&renderer, flow_engine.terrain(), frame_time, self.frame_count) {
+                    break;
+                }
+            }
+
+            #[cfg(feature = "window")]
+            if let Some(window) = window.as_mut() {
+                if let WindowAction::Quit = window.update(flow_engine.terrain_mut(), frame_time, self.frame_count) {
+                    break;
+                }
+            }
+
+            #[cfg(feature = "http")]
+            if let Some(server) = server.as_ref() {
+                let stats = TerrainStats::of(flow_engine.terrain());
+                server.publish(frame_num, self.frame_count, renderer.frame_path(frame_num), stats);
+                for command in server.pending_commands() {
+                    match command {
+                        Command::SetParams { params, reply } => {
+                            let refused = params.iter().find(|(name, value)| !accepts_param(&flow_engine, &humidity, name, *value));
+                            let result = match refused {
+                                Some((name, value)) => Err(format!("rejected {} = {}, so none were set", name, value)),
+                                None => {
+                                    for (name, value) in params.iter() {
+                                        set_param(&mut flow_engine, &mut cluster, &mut humidity, name, *value);
+                                    }
+                                    Ok(())
+                                }
+                            };
+                            reply.send(result).unwrap_or(());
+                        }
+                        // answered once the frame's steps are done
+                        Command::Checkpoint { reply } => checkpoint_replies.push(reply),
+                        Command::Flood { event, reply } => {
+                            let cells = event.apply(flow_engine.terrain_mut());
+                            manifest.record_event(frame_num, time, "flood", &flood_fields(&event, cells));
+                            reply.send(cells).unwrap_or(());
+                        }
+                        Command::Brush { brush, reply } => {
+                            let cells = brush.apply(flow_engine.terrain_mut());
+                            manifest.record_event(frame_num, time, brush.op.name(), &brush.fields(cells));
+                            reply.send(cells).unwrap_or(());
+                        }
+                    }
+                }
+            }
+
+            if let Some(cluster) = cluster.as_mut() {
+                cluster.scatter(flow_engine.terrain());
+            }
             for _ in 0..self.frame_skip {
-                flow_engine.step(self.render_step);
+                let mut time_step = self.render_step;
+                for (name, schedule) in self.schedules.iter() {
+                    let value = schedule.value_at(time);
+                    if *name == "render_step" {
+                        time_step = value;
+                    } else {
+                        // the keyframes were checked before the run, and
+                        // values between them are taken alike
+                        set_param(&mut flow_engine, &mut cluster, &mut humidity, name, value);
+                    }
+                }
+
+                if let Some(scenario) = scenario.as_mut() {
+                    for action in scenario.due_actions(time) {
+                        let cells = match &action {
+                            // checked when the scenario was loaded
+                            Action::SetParam(name, value) => {
+                                set_param(&mut flow_engine, &mut cluster, &mut humidity, name, *value);
+                                None
+                            }
+                            _ => scenario.apply(&action, flow_engine.terrain_mut()),
+                        };
+                        let mut fields = action.fields();
+                        fields.extend(cells.map(|cells| ("cells", cells as f64)));
+                        manifest.record_event(frame_num, time, action.name(), &fields);
+                    }
+                    scenario.update(flow_engine.terrain_mut(), time_step);
+                    if let (Some(salinity), Some(sea_level)) = (salinity.as_mut(), scenario.sea_level()) {
+                        salinity.set_sea_level(sea_level);
+                    }
+                }
+                if let Some(humidity) = humidity.as_mut() {
+                    humidity.update(flow_engine.terrain_mut(), time_step);
+                }
+                if let Some(groundwater) = self.groundwater.as_ref() {
+                    groundwater.update(flow_engine.terrain_mut(), time_step);
+                }
+                match cluster.as_mut() {
+                    Some(cluster) => cluster.step(flow_engine.terrain_mut(), time_step),
+                    None => flow_engine.step(time_step),
+                }
+                time += time_step;
+                steps += 1;
+                if let Some(sediment) = self.sediment.as_ref() {
+                    sediment.update(flow_engine.terrain_mut(), time_step);
+                }
+                if let Some(karst) = self.karst.as_ref() {
+                    karst.update(flow_engine.terrain_mut(), time_step);
+                }
+                if let Some(salinity) = salinity.as_ref() {
+                    salinity.update(flow_engine.terrain_mut(), time_step);
+                }
+                if let Some(cumulative_change) = cumulative_change.as_mut() {
+                    cumulative_change.update(flow_engine.terrain_mut(), time_step);
+                }
+                if let Some(climate) = self.climate.as_ref() {
+                    climate.update(flow_engine.terrain_mut(), self.width as f64, self.height as f64);
+                }
+                if let Some(moisture) = moisture.as_ref() {
+                    moisture.update(flow_engine.terrain_mut(), time_step);
+                }
+                if let Some(tracers) = tracers.as_mut() {
+                    tracers.update(flow_engine.terrain_mut());
+                }
+                if let Some(exposure_age) = exposure_age.as_mut() {
+                    exposure_age.update(flow_engine.terrain_mut(), time_step);
+                }
+                if let Some(wetness) = wetness.as_mut() {
+                    wetness.update(flow_engine.terrain_mut(), time_step);
+                }
+                flow_engine.check_guard();
+                if let Some(gauges) = gauges.as_mut() {
+                    gauges.record(time, flow_engine.terrain());
+                }
+
+                #[cfg(feature = "metrics")]
+                if let Some(server) = server.as_ref() {
+                    server.record_step(flow_engine.last_step());
+                }
+
+                #[cfg(feature = "scripting")]
+                if let Some(script) = script.as_mut() {
+                    let stats = TerrainStats::of(flow_engine.terrain());
+                    // a failed call changes nothing, not even the brushes it got to
+                    let (changes, brushes) = match script.update(time, &stats) {
+                        Ok(changes) => (changes, script.take_brushes()),
+                        Err(e) => {
+                            eprintln!("script error at time {}: {}, ignored", time, e);
+                            script.take_brushes();
+                            (Vec::new(), Vec::new())
+                        }
+                    };
+                    for (name, value) in changes {
+                        if !set_param(&mut flow_engine, &mut cluster, &mut humidity, &name, value) {
+                            eprintln!("script error at time {}: invalid parameter {} = {}, ignored", time, name, value);
+                        }
+                    }
+                    for brush in brushes {
+                        let cells = brush.apply(flow_engine.terrain_mut());
+                        manifest.record_event(frame_num, time, brush.op.name(), &brush.fields(cells));
+                    }
+                }
+
+                if let Some(goal) = self.goal.as_ref() {
+                    if let Some(value) = goal.check(flow_engine.terrain()) {
+                        println!("goal met after {} steps at time {}: {} = {}", steps, time, goal.metric.name(), value);
+                        manifest.record_event(frame_num, time, "goal", &[("steps", steps as f64), (goal.metric.name(), value)]);
+                        goal_met = true;
+                        break;
+                    }
+                }
+            }
+            if goal_met {
+                break;
+            }
+
+            let next_frame = frame_num + 1;
+            let due = self.checkpoint_every.is_some_and(|every| next_frame % every == 0);
+            #[cfg(feature = "http")]
+            let due = due || !checkpoint_replies.is_empty();
+            if due {
+                if let Some(cluster) = cluster.as_mut() {
+                    cluster.gather(flow_engine.terrain_mut());
+                }
+                let path = format!("{}/checkpoint_{:06}.dat", render_path, next_frame);
+                let flow_state = flow_engine.strategy().random_state();
+                checkpoint::write(&path, &self.origin(), next_frame, steps, time, &flow_state, flow_engine.terrain());
+                #[cfg(feature = "http")]
+                for reply in checkpoint_replies.drain(..) {
+                    reply.send(Some(path.clone())).unwrap_or(());
+                }
+            }
+        }
+
+        if let Some(mut cluster) = cluster {
+            cluster.gather(flow_engine.terrain_mut());
+            cluster.finish();
+        }
+        if let Some(audit) = flow_engine.audit() {
+            println!("{}", audit.summary());
+        }
+        if let Some(goal) = self.goal.as_ref().filter(|_| !goal_met) {
+            println!("goal not met after {} steps: {} = {}", steps, goal.metric.name(), goal.metric.measure(flow_engine.terrain()));
+        }
+        if let Some((classifier, _)) = self.biomes.as_ref() {
+            classifier.write_labels(flow_engine.terrain(), &format!("{}/biomes.csv", render_path));
+        }
+        if let Some(cumulative_change) = cumulative_change.as_ref().filter(|_| self.cumulative_change) {
+            let end = FrameTime { frame: self.frame_count, time };
+            cumulative_change.export(flow_engine.terrain(), end, (self.width, self.height), self.projection, render_path);
+        }
+        if self.export_heightmap {
+            let path = format!("{}/heightmap.png", render_path);
+            flow_engine.terrain().export_heightmap(&path, (self.width, self.height), self.vertical);
+        }
+        if let Some(svg) = self.export_svg {
+            let path = format!("{}/mesh.svg", render_path);
+            let end = FrameTime { frame: self.frame_count, time };
+            svg.export(&self.shader(), flow_engine.terrain(), end, (self.width, self.height), &path);
+        }
+        if let Some(mesh) = self.export_mesh {
+            mesh.export(flow_engine.terrain(), (self.width, self.height), self.vertical, render_path);
+        }
+        if let Some(stl) = self.export_stl {
+            let path = format!("{}/terrain.stl", render_path);
+            stl.export(flow_engine.terrain(), (self.width, self.height), self.vertical, &path);
+        }
+        if let Some(size) = self.export_raw {
+            let path = format!("{}/terrain.raw", render_path);
+            export_raw(flow_engine.terrain(), (self.width, self.height), self.vertical, size, &path);
+        }
+        if let Some((false, shader)) = gltf.as_ref() {
+            let path = format!("{}/terrain.glb", render_path);
+            let end = FrameTime { frame: self.frame_count, time };
+            export_gltf(shader, flow_engine.terrain(), end, (self.width, self.height), self.vertical, &path);
+        }
+        if let Some(member) = member {
+            ensemble::write_member_results(flow_engine.terrain(), (self.width, self.height), self.vertical, render_path, member);
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Runner<'a> {
+    // the seed for one part of the run, if seeded; each ensemble member gets
+    // its own, so members differ as they would unseeded
+    // what a checkpoint must share with the run resuming from it
+    fn origin(&self) -> Origin {
+        Origin { world_size: (self.width, self.height), spherical: self.spherical, topology: self.topology, seed: self.seed }
+    }
+
+    fn seed_for(&self, member: Option<u32>, part: u64) -> Option<u64> {
+        let seed = self.seed?;
+        let run_seed = member.map_or(seed, |member| rng::derive(seed, member as u64 + 1));
+        Some(rng::derive(run_seed, part))
+    }
+
+    fn flow_strategy(&self, member: Option<u32>) -> Box<dyn Flow> {
+        let mut flows = vec![self.base_flow_strategy(member)];
+        if let Some((gravity, friction, erosion_rate)) = self.shallow_water {
+            flows.push(Box::new(ShallowWaterFlow::new(gravity, friction, erosion_rate)));
+        }
+        if let Some((gravity, capacity, dissolving_rate, deposition_rate)) = self.pipe_model {
+            flows.push(Box::new(PipeModel::new(gravity, capacity, dissolving_rate, deposition_rate)));
+        }
+        if let Some((stream_power, blend)) = self.stream_power.as_ref() {
+            let mut stream_power = stream_power.clone();
+            stream_power.set_cell_area((self.density as f64).powi(-2));
+            stream_power.scale_k(*blend);
+            flows.push(Box::new(stream_power));
+        }
+        if let Some(diffusivity) = self.diffusivity {
+            flows.push(Box::new(HillslopeDiffusion::new(diffusivity)));
+        }
+        if let Some((repose_angle, rate)) = self.thermal {
+            flows.push(Box::new(ThermalFlow::new(repose_angle, rate)));
+        }
+        if let Some((rate, depth_coefficient)) = self.evaporation {
+            flows.push(Box::new(Evaporation::new(rate, depth_coefficient)));
+        }
+        if let Some((droplets, capacity, erosion_rate, deposition_rate, evaporation)) = self.droplets {
+            let mut flow = DropletFlow::new(droplets, capacity, erosion_rate, deposition_rate, evaporation);
+            if let Some(seed) = self.seed_for(member, DROPLETS_SEED) {
+                flow.set_seed(seed);
+            }
+            flows.push(Box::new(flow));
+        }
+        flows.extend(self.stacked_flows.iter().map(|make| make()));
+        if flows.len() == 1 {
+            flows.pop().unwrap()
+        } else {
+            Box::new(FlowStack::new(flows))
+        }
+    }
+
+    fn base_flow_strategy(&self, member: Option<u32>) -> Box<dyn Flow> {
+        #[cfg(feature = "plugins")]
+        if let Some(path) = self.flow_plugin {
+            return Box::new(PluginFlow::load(path));
+        }
+
+        // stream power erosion takes over its blended share of erosion, and
+        // shallow water or the pipe model all the moving of water
+        let slope_rule = 1.0 - self.stream_power.as_ref().map_or(0.0, |(_, blend)| *blend);
+        let flow_rule = if self.shallow_water.is_some() || self.pipe_model.is_some() { 0.0 } else { 1.0 };
+        let mut flow = DefaultFlow::new(
+            self.flow_rate * flow_rule,
+            self.flow_erosion_rate * slope_rule * flow_rule,
+            self.erosion_threshold,
+            self.erosion_rate * slope_rule,
+            self.precipitation,
+            self.drain_policy,
+        );
+        flow.set_eroded_routing(self.eroded_routing);
+        if let Some(suspended_transport) = self.suspended_transport {
+            flow.set_suspended_transport(suspended_transport);
+        }
+        if flux_scale(&self.frame_shader, &self.shader_layers).is_some() {
+            flow.record_flux(if self.spherical { Some(self.width as f64) } else { None });
+        }
+        #[cfg(feature = "gpu")]
+        if self.gpu {
+            flow.use_gpu();
+        }
+        if let Some(seed) = self.seed_for(member, FLOW_SEED) {
+            flow.set_seed(seed);
+        }
+        Box::new(flow)
+    }
+
+    fn manifest(&self, render_path: &str) -> RunManifest {
+        let mut manifest = RunManifest::new(format!("{}/manifest.json", render_path));
+        manifest.setting("width", self.width);
+        manifest.setting("height", self.height);
+        manifest.setting("density", self.density);
+        manifest.setting("max_z", self.max_z);
+        manifest.setting("datum", self.vertical.datum);
+        manifest.setting("vertical_exaggeration", self.vertical.exaggeration);
+        manifest.setting("flow_rate", self.flow_rate);
+        manifest.setting("flow_erosion_rate", self.flow_erosion_rate);
+        manifest.setting("erosion_threshold", self.erosion_threshold);
+        manifest.setting("erosion_rate", self.erosion_rate);
+        manifest.setting("precipitation", self.precipitation.name());
+        for (name, value) in self.precipitation.fields() {
+            manifest.setting(name, value);
+        }
+        if let Some(map) = self.precipitation_map {
+            manifest.setting("precipitation_map", map.name());
+        }
+        manifest.setting("floor_height", self.drain_policy.floor_height);
+        manifest.setting("floor_rate", self.drain_policy.floor_rate);
+        manifest.setting("max_depth", self.drain_policy.max_depth);
+        manifest.setting("drain_rate", self.drain_policy.drain_rate);
+        manifest.setting("eroded_delivered", self.eroded_routing.delivered);
+        manifest.setting("eroded_suspended", self.eroded_routing.suspended);
+        if let Some(transport) = self.suspended_transport {
+            manifest.setting("suspended_capacity", transport.capacity);
+            manifest.setting("settling_rate", transport.settling_rate);
+        }
+        if let Some((gravity, friction, erosion_rate)) = self.shallow_water {
+            manifest.setting("gravity", gravity);
+            manifest.setting("friction", friction);
+            manifest.setting("shallow_erosion_rate", erosion_rate);
+        }
+        if let Some((gravity, capacity, dissolving_rate, deposition_rate)) = self.pipe_model {
+            manifest.setting("pipe_gravity", gravity);
+            manifest.setting("sediment_capacity", capacity);
+            manifest.setting("dissolving_rate", dissolving_rate);
+            manifest.setting("deposition_rate", deposition_rate);
+        }
+        manifest.setting("render_step", self.render_step);
+        manifest.setting("frame_skip", self.frame_skip);
+        manifest.setting("frame_count", self.frame_count);
+        match &self.output {
+            Output::Frames(format) => manifest.setting("frame_format", format.name()),
+            Output::Video { file, fps } => {
+                manifest.setting("video", file.as_str());
+                manifest.setting("video_fps", *fps);
+            }
+            Output::Animation { file, format, delay_ms, scale } => {
+                manifest.setting(format.name(), file.as_str());
+                manifest.setting("frame_delay_ms", *delay_ms);
+                manifest.setting("animation_scale", *scale);
+            }
+        }
+        manifest.setting("data_path", self.data_path);
+        manifest.setting("deterministic", self.deterministic);
+        #[cfg(feature = "f32")]
+        manifest.setting("terrain_precision", "f32");
+        if let Some(adaptive_step) = self.adaptive_step {
+            manifest.setting("adaptive_max_share", adaptive_step.max_share);
+            manifest.setting("adaptive_max_substeps", adaptive_step.max_substeps);
+        }
+        if let Some(active_cells) = self.active_cells {
+            manifest.setting("active_threshold", active_cells.threshold);
+            manifest.setting("active_linger", active_cells.linger);
+            manifest.setting("active_sweep_every", active_cells.sweep_every);
+        }
+        if let Some(seed) = self.seed {
+            manifest.setting("seed", seed);
+        }
+        if self.mass_audit {
+            manifest.setting("mass_audit", true);
+        }
+        #[cfg(feature = "gpu")]
+        if self.gpu {
+            manifest.setting("gpu", true);
+        }
+        if let Some(frames) = self.checkpoint_every {
+            manifest.setting("checkpoint_every", frames);
+        }
+        if let Some(path) = self.resume {
+            manifest.setting("resume", path);
+        }
+        if let Some(path) = self.snapshot_path {
+            manifest.setting("snapshot_path", path);
+        }
+        manifest
+    }
+
+    fn renderer(&self, render_path: &str) -> Renderer<Box<dyn Shade>> {
+        let mut renderer = Renderer::new(self.width, self.height, self.shader(), render_path);
+        renderer.set_projection(self.projection);
+        renderer.set_raster(self.raster);
+        renderer.set_viewport(self.viewport);
+        renderer.set_mesh_overlay(self.mesh_overlay);
+        renderer.set_interpolated_frames(self.interpolated_frames);
+        renderer.set_output(self.output.clone());
+        renderer
+    }
+
+    fn shader(&self) -> Box<dyn Shade> {
+        let mut shader = self.base_shader();
+        if !self.shader_layers.is_empty() {
+            let mut stack = ShaderStack::new(shader);
+            for (frame_shader, blend, opacity) in self.shader_layers.iter() {
+                stack.push(self.built_in_shader(frame_shader), *blend, *opacity);
             }
+            shader = Box::new(stack);
+        }
+        match self.contours {
+            Some(interval) => Box::new(ContourShader::new(shader, interval, self.vertical)),
+            None => shader,
         }
     }
+
+    fn base_shader(&self) -> Box<dyn Shade> {
+        #[cfg(feature = "plugins")]
+        if let Some(path) = self.shader_plugin {
+            return Box::new(PluginShader::load(path));
+        }
+
+        self.built_in_shader(&self.frame_shader)
+    }
+
+    fn built_in_shader(&self, frame_shader: &FrameShader) -> Box<dyn Shade> {
+        match frame_shader {
+            FrameShader::Default => Box::new(DefaultShader::new(self.vertical)),
+            FrameShader::Hypsometric(ramp) => {
+                let ramp = ramp.clone().unwrap_or_else(|| self.atlas_ramp());
+                Box::new(HypsometricShader::new(ramp, self.vertical))
+            }
+            FrameShader::Water { absorption } => {
+                let ground = HillshadeShader::new(self.vertical);
+                Box::new(WaterShader::with_optics(ground, absorption.clone(), CLEAR_WATER_SCATTERED))
+            }
+            FrameShader::Flow { scale } => Box::new(FlowShader::new(HillshadeShader::new(self.vertical), *scale)),
+            FrameShader::Relief => Box::new(ReliefShader::new(self.vertical)),
+            FrameShader::ErosionRate { scale } => Box::new(ChangeShader::height_rate(*scale)),
+            FrameShader::NetChange { scale } => Box::new(ChangeShader::net_change(*scale)),
+        }
+    }
+
+    fn atlas_ramp(&self) -> ColorRamp {
+        HypsometricShader::atlas_ramp(self.vertical.elevation(self.max_z).max(1.0))
+    }
+}
+
+// the scale of the first flow shader frames are drawn with, if any, which
+// needs the flow to keep its flux
+fn flux_scale(frame_shader: &FrameShader, shader_layers: &[(FrameShader, Blend, f64)]) -> Option<f64> {
+    frame_shaders(frame_shader, shader_layers).find_map(|frame_shader| match frame_shader {
+        FrameShader::Flow { scale } => Some(*scale),
+        _ => None,
+    })
+}
+
+// whether frames are drawn with a heatmap of CumulativeChange's layers
+fn draws_change(frame_shader: &FrameShader, shader_layers: &[(FrameShader, Blend, f64)]) -> bool {
+    frame_shaders(frame_shader, shader_layers)
+        .any(|frame_shader| matches!(frame_shader, FrameShader::ErosionRate { .. } | FrameShader::NetChange { .. }))
+}
+
+fn frame_shaders<'s>(frame_shader: &'s FrameShader, shader_layers: &'s [(FrameShader, Blend, f64)]) -> impl Iterator<Item=&'s FrameShader> {
+    std::iter::once(frame_shader).chain(shader_layers.iter().map(|(frame_shader, _, _)| frame_shader))
+}
+
+// a bad cache file fails the run rather than producing a mangled terrain;
+// delete it to have it generated again
+fn read_points(path: &str) -> Vec<Point> {
+    let points: Vec<Point> = PointsReader::new(BufReader::new(File::open(path).unwrap()))
+        .collect::<Result<_, _>>()
+        .unwrap_or_else(|e| panic!("corrupt points file {}: {}", path, e));
+    assert!(!points.is_empty(), "points file {} is empty", path);
+    points
+}
+
+fn flood_fields(flood: &FloodEvent, cells: usize) -> [(&'static str, f64); 5] {
+    [("x", flood.x), ("y", flood.y), ("radius", flood.radius), ("volume", flood.volume), ("cells", cells as f64)]
+}
+
+// whether set_param would take a parameter, leaving everything as it is
+fn accepts_param(
+    flow_engine: &FlowEngine<Box<dyn Flow>>,
+    humidity: &Option<HumidityModel>,
+    name: &str,
+    value: f64,
+) -> bool {
+    humidity.as_ref().is_some_and(|humidity| humidity.accepts_param(name, value))
+        || flow_engine.strategy().accepts_param(name, value)
+}
+
+// sets a humidity parameter, or else a flow parameter here and, in
+// distributed runs, on every worker
+fn set_param(
+    flow_engine: &mut FlowEngine<Box<dyn Flow>>,
+    cluster: &mut Option<Cluster>,
+    humidity: &mut Option<HumidityModel>,
+    name: &str,
+    value: f64,
+) -> bool {
+    if humidity.as_mut().is_some_and(|humidity| humidity.set_param(name, value)) {
+        return true;
+    }
+    let accepted = flow_engine.strategy_mut().set_param(name, value);
+    match cluster.as_mut() {
+        Some(cluster) => cluster.set_param(name, value) && accepted,
+        None => accepted,
+    }
 }
 
 impl<'a> RunnerBuilder<'a> {
@@ -128,17 +1259,97 @@ impl<'a> RunnerBuilder<'a> {
             height: None,
             density: None,
             max_z: None,
+            initial_terrain: InitialTerrain::Dome,
+            spherical: false,
+            topology: Topology::Delaunay,
+            projection: Projection::Equirectangular,
+            raster: Raster::Splat,
+            viewport: None,
+            mesh_overlay: None,
+            frame_shader: FrameShader::Default,
+            shader_layers: Vec::new(),
+            contours: None,
+            vertical: VerticalScale::default(),
             flow_rate: None,
             flow_erosion_rate: None,
             erosion_threshold: None,
             erosion_rate: None,
             precipitation_rate: None,
             precipitation_amount: None,
+            precipitation: None,
+            precipitation_map: None,
+            drain_policy: DrainPolicy::default(),
+            eroded_routing: ErodedRouting::default(),
+            suspended_transport: None,
             render_step: None,
             frame_skip: None,
             frame_count: None,
             data_path: None,
             render_path: None,
+            schedules: Vec::new(),
+            climate: None,
+            biomes: None,
+            humidity: None,
+            probes: Vec::new(),
+            tracers: None,
+            exposure_threshold: None,
+            wet_depth: None,
+            floods: Vec::new(),
+            groundwater: None,
+            sediment: None,
+            karst: None,
+            diffusivity: None,
+            thermal: None,
+            evaporation: None,
+            droplets: None,
+            shallow_water: None,
+            pipe_model: None,
+            stacked_flows: Vec::new(),
+            stream_power: None,
+            salinity: None,
+            interpolated_frames: 0,
+            view_3d: None,
+            map_tiles: None,
+            dashboard: false,
+            change_maps: None,
+            cumulative_change: false,
+            export_heightmap: false,
+            export_svg: None,
+            export_mesh: None,
+            export_stl: None,
+            export_raw: None,
+            export_gltf: None,
+            goal: None,
+            scenario: None,
+            ensemble: None,
+            distributed: None,
+            deterministic: false,
+            adaptive_step: None,
+            active_cells: None,
+            seed: None,
+            nan_guard: None,
+            nan_recovery: false,
+            mass_audit: false,
+            pipelined_render: false,
+            output: Output::Frames(FrameFormat::Png),
+            checkpoint_every: None,
+            resume: None,
+            snapshot_path: None,
+            replay: None,
+            #[cfg(feature = "tui")]
+            tui: false,
+            #[cfg(feature = "window")]
+            window: false,
+            #[cfg(feature = "http")]
+            http_addr: None,
+            #[cfg(feature = "plugins")]
+            flow_plugin: None,
+            #[cfg(feature = "plugins")]
+            shader_plugin: None,
+            #[cfg(feature = "scripting")]
+            script: None,
+            #[cfg(feature = "gpu")]
+            gpu: false,
         }
     }
 
@@ -166,6 +1377,91 @@ impl<'a> RunnerBuilder<'a> {
         self
     }
 
+    pub fn initial_terrain(&mut self, initial_terrain: InitialTerrain<'a>) -> &mut RunnerBuilder<'a> {
+        match initial_terrain {
+            InitialTerrain::Noise(params) => params.validate(),
+            InitialTerrain::GeoTiff(_, DemScale::Linear { scale, offset }) => assert!(scale.is_finite() && offset.is_finite()),
+            _ => {}
+        }
+        self.initial_terrain = initial_terrain;
+        self
+    }
+
+    // wraps the world onto a sphere whose equator is `width` long, with x as
+    // longitude and y as latitude
+    pub fn spherical(&mut self, spherical: bool) -> &mut RunnerBuilder<'a> {
+        self.spherical = spherical;
+        self
+    }
+
+    // how cells are laid out over a flat world; spherical worlds always use
+    // their own lattice
+    pub fn topology(&mut self, topology: Topology) -> &mut RunnerBuilder<'a> {
+        self.topology = topology;
+        self
+    }
+
+    pub fn projection(&mut self, projection: Projection) -> &mut RunnerBuilder<'a> {
+        if let Projection::Orthographic { center_lon, center_lat } = projection {
+            assert!(center_lon.is_finite());
+            assert!((-90.0..=90.0).contains(&center_lat));
+        }
+        self.projection = projection;
+        self
+    }
+
+    // how the rendered frames turn cells into pixels; see Raster
+    pub fn raster(&mut self, raster: Raster) -> &mut RunnerBuilder<'a> {
+        self.raster = raster;
+        self
+    }
+
+    // draws the frames with this shader; see FrameShader
+    pub fn frame_shader(&mut self, frame_shader: FrameShader) -> &mut RunnerBuilder<'a> {
+        self.frame_shader = frame_shader;
+        self
+    }
+
+    // layers another shader over the frames, above any added before it;
+    // see ShaderStack
+    pub fn shader_layer(&mut self, frame_shader: FrameShader, blend: Blend, opacity: f64) -> &mut RunnerBuilder<'a> {
+        assert!((0.0..=1.0).contains(&opacity));
+        self.shader_layers.push((frame_shader, blend, opacity));
+        self
+    }
+
+    // draws contour lines every `interval` of elevation over the frames,
+    // whatever they're drawn with
+    pub fn contours(&mut self, interval: f64) -> &mut RunnerBuilder<'a> {
+        assert!(interval > 0.0 && interval.is_finite());
+        self.contours = Some(interval);
+        self
+    }
+
+    // frames show only this part of the world, stretched over the whole
+    // image
+    pub fn viewport(&mut self, viewport: Viewport) -> &mut RunnerBuilder<'a> {
+        assert!(viewport.x.is_finite() && viewport.y.is_finite());
+        assert!(viewport.width > 0.0 && viewport.width.is_finite());
+        assert!(viewport.height > 0.0 && viewport.height.is_finite());
+        self.viewport = Some(viewport);
+        self
+    }
+
+    // draws the mesh over the frames; see MeshOverlay
+    pub fn mesh_overlay(&mut self, mesh_overlay: MeshOverlay) -> &mut RunnerBuilder<'a> {
+        self.mesh_overlay = Some(mesh_overlay);
+        self
+    }
+
+    // the height treated as sea level by shaders and written as zero in
+    // heightmap exports, and how much relief is stretched wherever it is
+    // drawn, in the map's hillshading and the 3D views alike
+    pub fn vertical_scale(&mut self, datum: f64, exaggeration: f64) -> &mut RunnerBuilder<'a> {
+        self.vertical = VerticalScale::new(datum, exaggeration);
+        self
+    }
+
     pub fn flow_rate(&mut self, flow_rate: f64) -> &mut RunnerBuilder<'a> {
         assert!(flow_rate.is_finite());
         self.flow_rate = Some(flow_rate);
@@ -204,6 +1500,43 @@ impl<'a> RunnerBuilder<'a> {
         self
     }
 
+    // draws rain from the given distribution in place of precipitation_rate
+    // and precipitation_amount, which then needn't be set
+    pub fn precipitation(&mut self, precipitation: Precipitation) -> &mut RunnerBuilder<'a> {
+        precipitation.validate();
+        self.precipitation = Some(precipitation);
+        self
+    }
+
+    // scales the rain on each cell by the map's intensity there, set when
+    // the terrain is generated and carried in checkpoints from then on
+    pub fn precipitation_map(&mut self, precipitation_map: PrecipitationMap<'a>) -> &mut RunnerBuilder<'a> {
+        if let PrecipitationMap::Raster(_, max_intensity) = precipitation_map {
+            assert!(max_intensity >= 0.0 && max_intensity.is_finite());
+        }
+        self.precipitation_map = Some(precipitation_map);
+        self
+    }
+
+    // where ground is topped up and water drained away; see DrainPolicy
+    pub fn drain_policy(&mut self, drain_policy: DrainPolicy) -> &mut RunnerBuilder<'a> {
+        self.drain_policy = drain_policy;
+        self
+    }
+
+    // what becomes of ground worn away by flowing water; see ErodedRouting
+    pub fn eroded_routing(&mut self, eroded_routing: ErodedRouting) -> &mut RunnerBuilder<'a> {
+        self.eroded_routing = eroded_routing;
+        self
+    }
+
+    // carries the suspended share of eroded ground downstream, dropping it
+    // where the water slows; see SuspendedTransport
+    pub fn suspended_transport(&mut self, suspended_transport: SuspendedTransport) -> &mut RunnerBuilder<'a> {
+        self.suspended_transport = Some(suspended_transport);
+        self
+    }
+
     pub fn render_step(&mut self, render_step: f64) -> &mut RunnerBuilder<'a> {
         assert!(render_step.is_normal());
         assert!(render_step.is_sign_positive());
@@ -223,6 +1556,13 @@ impl<'a> RunnerBuilder<'a> {
         self
     }
 
+    // renders this many blended images between simulated frames for smooth
+    // video at no extra simulation cost
+    pub fn interpolated_frames(&mut self, interpolated_frames: u32) -> &mut RunnerBuilder<'a> {
+        self.interpolated_frames = interpolated_frames;
+        self
+    }
+
     pub fn data_path(&mut self, data_path: &'a str) -> &mut RunnerBuilder<'a> {
         assert!(Path::new(data_path).is_dir());
         self.data_path = Some(data_path);
@@ -235,7 +1575,495 @@ impl<'a> RunnerBuilder<'a> {
         self
     }
 
-    pub fn build(&self) -> Runner {
+    // overrides a numeric flow parameter, or render_step, with a value that
+    // follows the schedule over simulated time
+    pub fn schedule(&mut self, name: &'a str, schedule: ParameterSchedule) -> &mut RunnerBuilder<'a> {
+        if name == "render_step" {
+            assert!(schedule.keyframes().iter().all(|&(_, value)| value > 0.0));
+        }
+        self.schedules.push((name, schedule));
+        self
+    }
+
+    // maintains a per-cell temperature layer for shaders and the biome map
+    pub fn climate(&mut self, climate: Climate) -> &mut RunnerBuilder<'a> {
+        self.climate = Some(climate);
+        self
+    }
+
+    // tracks moisture over the run, writing the final biome of every cell to
+    // biomes.csv in the render path
+    pub fn biomes(&mut self, classifier: BiomeClassifier, moisture_timescale: f64) -> &mut RunnerBuilder<'a> {
+        assert!(moisture_timescale.is_normal());
+        assert!(moisture_timescale.is_sign_positive());
+        self.biomes = Some((classifier, moisture_timescale));
+        self
+    }
+
+    // replaces uniform precipitation with rain carried in by a prevailing
+    // wind; set precipitation_amount to zero to turn the uniform rain off
+    pub fn humidity(&mut self, humidity: HumidityModel) -> &mut RunnerBuilder<'a> {
+        self.humidity = Some(humidity);
+        self
+    }
+
+    // records a time series at (x, y) to gauges.csv in the render path
+    pub fn probe(&mut self, name: &'a str, x: f64, y: f64) -> &mut RunnerBuilder<'a> {
+        assert!(!name.contains(','));
+        assert!(self.probes.iter().all(|probe| probe.name != name));
+        assert!(x.is_finite() && y.is_finite());
+        self.probes.push(Probe { name, x, y });
+        self
+    }
+
+    // seeds tracer particles that follow eroded material, writing their
+    // positions each frame to tracers.csv in the render path
+    pub fn tracers(&mut self, regions: &[TracerRegion], mixing_depth: f64) -> &mut RunnerBuilder<'a> {
+        assert!(!regions.is_empty());
+        assert!(regions.iter().all(|region| region.radius > 0.0 && region.count > 0));
+        assert!(mixing_depth.is_normal());
+        assert!(mixing_depth.is_sign_positive());
+        self.tracers = Some((regions.to_vec(), mixing_depth));
+        self
+    }
+
+    // tracks how long each cell's surface has gone without its height moving
+    // by more than the threshold
+    pub fn exposure_age(&mut self, threshold: f64) -> &mut RunnerBuilder<'a> {
+        assert!(threshold.is_finite());
+        assert!(threshold > 0.0);
+        self.exposure_threshold = Some(threshold);
+        self
+    }
+
+    // tracks the fraction of time each cell is deeper than wet_depth, which
+    // also feeds the biome map's moisture
+    pub fn wetness(&mut self, wet_depth: f64) -> &mut RunnerBuilder<'a> {
+        assert!(wet_depth.is_finite());
+        assert!(wet_depth >= 0.0);
+        self.wet_depth = Some(wet_depth);
+        self
+    }
+
+    pub fn flood(&mut self, flood: FloodEvent) -> &mut RunnerBuilder<'a> {
+        assert!(flood.x.is_finite() && flood.y.is_finite());
+        assert!(flood.radius > 0.0);
+        assert!(flood.volume > 0.0 && flood.volume.is_finite());
+        self.floods.push(flood);
+        self
+    }
+
+    pub fn groundwater(&mut self, groundwater: Groundwater) -> &mut RunnerBuilder<'a> {
+        self.groundwater = Some(groundwater);
+        self
+    }
+
+    // transports sand, silt and clay on top of the flow's own erosion, which
+    // can be turned down with flow_erosion_rate and erosion_rate
+    pub fn sediment(&mut self, sediment: SedimentModel) -> &mut RunnerBuilder<'a> {
+        self.sediment = Some(sediment);
+        self
+    }
+
+    pub fn karst(&mut self, karst: Karst) -> &mut RunnerBuilder<'a> {
+        self.karst = Some(karst);
+        self
+    }
+
+    // stacks soil creep on top of the flow
+    pub fn hillslope_diffusion(&mut self, diffusivity: f64) -> &mut RunnerBuilder<'a> {
+        assert!(diffusivity.is_finite());
+        assert!(diffusivity >= 0.0);
+        self.diffusivity = Some(diffusivity);
+        self
+    }
+
+    // stacks talus slides on top of the flow, moving rate of the ground
+    // above the angle of repose (in degrees) to lower neighbors per unit time
+    pub fn thermal_erosion(&mut self, repose_angle: f64, rate: f64) -> &mut RunnerBuilder<'a> {
+        assert!(repose_angle > 0.0 && repose_angle < 90.0);
+        assert!(rate >= 0.0 && rate.is_finite());
+        self.thermal = Some((repose_angle, rate));
+        self
+    }
+
+    // moves water with momentum in place of the flow's slope-following
+    // transfer; see ShallowWaterFlow
+    pub fn shallow_water(&mut self, gravity: f64, friction: f64, erosion_rate: f64) -> &mut RunnerBuilder<'a> {
+        assert!(gravity > 0.0 && gravity.is_finite());
+        assert!(friction >= 0.0 && friction.is_finite());
+        assert!(erosion_rate >= 0.0 && erosion_rate.is_finite());
+        self.shallow_water = Some((gravity, friction, erosion_rate));
+        self
+    }
+
+    // moves water through virtual pipes between neighbors in place of the
+    // flow's transfer, dissolving and depositing ground by the water's
+    // sediment capacity; see PipeModel
+    pub fn pipe_model(&mut self, gravity: f64, capacity: f64, dissolving_rate: f64, deposition_rate: f64) -> &mut RunnerBuilder<'a> {
+        assert!(gravity > 0.0 && gravity.is_finite());
+        assert!(capacity >= 0.0 && capacity.is_finite());
+        assert!((0.0..=1.0).contains(&dissolving_rate));
+        assert!((0.0..=1.0).contains(&deposition_rate));
+        self.pipe_model = Some((gravity, capacity, dissolving_rate, deposition_rate));
+        self
+    }
+
+    // stacks evaporation on top of the flow, taking rate plus
+    // depth_coefficient of the depth from standing water per unit time
+    pub fn evaporation(&mut self, rate: f64, depth_coefficient: f64) -> &mut RunnerBuilder<'a> {
+        assert!(rate >= 0.0 && rate.is_finite());
+        assert!((0.0..=1.0).contains(&depth_coefficient));
+        self.evaporation = Some((rate, depth_coefficient));
+        self
+    }
+
+    // stacks droplet erosion on top of the flow, with that many droplets
+    // walking down the ground per unit time; see DropletFlow
+    pub fn droplets(
+        &mut self,
+        droplets: u32,
+        capacity: f64,
+        erosion_rate: f64,
+        deposition_rate: f64,
+        evaporation: f64,
+    ) -> &mut RunnerBuilder<'a> {
+        assert!(droplets > 0);
+        assert!(capacity >= 0.0 && capacity.is_finite());
+        assert!((0.0..=1.0).contains(&erosion_rate));
+        assert!((0.0..=1.0).contains(&deposition_rate));
+        assert!((0.0..1.0).contains(&evaporation));
+        self.droplets = Some((droplets, capacity, erosion_rate, deposition_rate, evaporation));
+        self
+    }
+
+    // stacks another process, such as wind erosion, on top of the flow and
+    // the other stacked processes, made afresh for every run of the flow
+    // (each ensemble member and distributed worker)
+    pub fn stacked_flow(&mut self, make: &'a dyn Fn() -> Box<dyn Flow>) -> &mut RunnerBuilder<'a> {
+        self.stacked_flows.push(make);
+        self
+    }
+
+    // blends stream power erosion with the flow's slope-difference erosion:
+    // 0 keeps only the slope rule and 1 replaces it entirely; the blend
+    // scales the starting rates, not later parameter changes
+    pub fn stream_power(&mut self, stream_power: StreamPower, blend: f64) -> &mut RunnerBuilder<'a> {
+        assert!((0.0..=1.0).contains(&blend));
+        self.stream_power = Some((stream_power, blend));
+        self
+    }
+
+    pub fn salinity(&mut self, salinity: Salinity) -> &mut RunnerBuilder<'a> {
+        self.salinity = Some(salinity);
+        self
+    }
+
+    // also renders every frame in perspective from an orbiting camera, as
+    // view3d_NNNNNN.png beside the map images, with relief stretched as set
+    // by vertical_scale
+    pub fn view_3d(&mut self, camera: OrbitCamera, stereo: Stereo) -> &mut RunnerBuilder<'a> {
+        self.view_3d = Some((camera, stereo));
+        self
+    }
+
+    // also writes dashboard_NNNNNN.png every frame, the shaded view beside a
+    // minimap, a 3D thumbnail and plots of water volume and relief
+    pub fn dashboard(&mut self, dashboard: bool) -> &mut RunnerBuilder<'a> {
+        self.dashboard = dashboard;
+        self
+    }
+
+    // also writes height_change_NNNNNN.png and depth_change_NNNNNN.png every
+    // frame, heatmaps of the change since the previous frame that saturate at
+    // the given scales
+    pub fn change_maps(&mut self, height_scale: f64, depth_scale: f64) -> &mut RunnerBuilder<'a> {
+        assert!(height_scale > 0.0 && depth_scale > 0.0);
+        self.change_maps = Some((height_scale, depth_scale));
+        self
+    }
+
+    // totals erosion and deposition per cell over the run, writing them to
+    // the render path at the end as erosion_total and deposition_total
+    // images and ascii grids
+    pub fn cumulative_change(&mut self, cumulative_change: bool) -> &mut RunnerBuilder<'a> {
+        self.cumulative_change = cumulative_change;
+        self
+    }
+
+    // writes the final heights above the datum to the render path as
+    // heightmap.png, a 16-bit grayscale image, with their range in
+    // heightmap.json
+    pub fn export_heightmap(&mut self, export_heightmap: bool) -> &mut RunnerBuilder<'a> {
+        self.export_heightmap = export_heightmap;
+        self
+    }
+
+    // writes the final mesh to the render path as mesh.svg, its triangles
+    // filled with the frame shader's colors if shaded
+    pub fn export_svg(&mut self, shaded: bool) -> &mut RunnerBuilder<'a> {
+        self.export_svg = Some(SvgExporter::new(shaded));
+        self
+    }
+
+    // writes the final ground to the render path as terrain.obj or
+    // terrain.ply, and the water's surface as water.obj or water.ply if
+    // water is set, heights exaggerated as drawn
+    pub fn export_mesh(&mut self, format: MeshFormat, water: bool) -> &mut RunnerBuilder<'a> {
+        self.export_mesh = Some(MeshExporter::new(format, water));
+        self
+    }
+
+    // writes the final ground to the render path as terrain.stl, a solid
+    // for printing with its longer side size millimeters across and a base
+    // base millimeters thick under its lowest point
+    pub fn export_stl(&mut self, size: f64, base: f64) -> &mut RunnerBuilder<'a> {
+        self.export_stl = Some(StlExporter::new(size, base));
+        self
+    }
+
+    // writes the final heights above the datum to the render path as
+    // terrain.raw, size by size 16-bit samples for Unity or Unreal, with
+    // their range and scales in terrain.json; size is a power of two plus
+    // one, like 513 or 1025
+    pub fn export_raw(&mut self, size: usize) -> &mut RunnerBuilder<'a> {
+        assert!(raw_size_valid(size));
+        self.export_raw = Some(size);
+        self
+    }
+
+    // writes the ground and water as a glTF binary colored by the frame
+    // shader, to the render path as terrain.glb at the end or as
+    // terrain_NNNNNN.glb every frame
+    pub fn export_gltf(&mut self, every_frame: bool) -> &mut RunnerBuilder<'a> {
+        self.export_gltf = Some(every_frame);
+        self
+    }
+
+    // ends the run as soon as the goal is met, reporting the steps taken;
+    // the frame count becomes the limit for runs that never get there
+    pub fn goal(&mut self, goal: Goal) -> &mut RunnerBuilder<'a> {
+        self.goal = Some(goal);
+        self
+    }
+
+    // runs the timed actions in a scenario file, see Scenario for the format
+    pub fn scenario(&mut self, scenario: &'a str) -> &mut RunnerBuilder<'a> {
+        assert!(Path::new(scenario).is_file());
+        self.scenario = Some(scenario);
+        self
+    }
+
+    // runs the configuration `members` times, each into its own member_NNN
+    // directory under the render path, then writes the mean and variance of
+    // the final height and depth as ensemble_*.asc grids and the final stats
+    // of every member with their mean and spread to ensemble_stats.csv.
+    // Members run one after another in this process, or with processes > 1
+    // that many at a time in copies of this executable.
+    pub fn ensemble(&mut self, members: u32, processes: u32) -> &mut RunnerBuilder<'a> {
+        assert!(members > 0);
+        assert!(processes > 0);
+        self.ensemble = Some((members, processes));
+        self
+    }
+
+    // splits every step across `workers` processes, which join by connecting
+    // to addr; see Cluster. With spawn_local they are started here, otherwise
+    // run this executable with the same arguments on the worker machines,
+    // with TERRAIN_FLOW_COORDINATOR set to addr. Only the flow is split, so
+    // processes that act on the terrain every step are not supported, nor
+    // are flows that reach further than a cell's neighbors
+    pub fn distributed(&mut self, addr: &'a str, workers: u32, spawn_local: bool) -> &mut RunnerBuilder<'a> {
+        assert!(workers > 0);
+        self.distributed = Some((addr, workers, spawn_local));
+        self
+    }
+
+    // sums each step's deltas in a fixed order so reruns match exactly,
+    // across core counts too, at some cost in speed; the rain and other
+    // random draws still differ unless seeded
+    pub fn deterministic(&mut self, deterministic: bool) -> &mut RunnerBuilder<'a> {
+        self.deterministic = deterministic;
+        self
+    }
+
+    // splits steps that would take too much of a cell's water into shorter
+    // ones; see AdaptiveStep
+    pub fn adaptive_step(&mut self, adaptive_step: AdaptiveStep) -> &mut RunnerBuilder<'a> {
+        self.adaptive_step = Some(adaptive_step);
+        self
+    }
+
+    // has the flow skip cells that have gone quiet, sweeping them all now
+    // and then; see ActiveCells
+    pub fn active_cells(&mut self, active_cells: ActiveCells) -> &mut RunnerBuilder<'a> {
+        self.active_cells = Some(active_cells);
+        self
+    }
+
+    // draws every random number of the run, from point placement to rain
+    // and tracers, from the seed, and applies deltas in a fixed order as
+    // deterministic does, so runs with the same settings match exactly
+    pub fn seed(&mut self, seed: u64) -> &mut RunnerBuilder<'a> {
+        self.seed = Some(seed);
+        self
+    }
+
+    // checks every step for heights, depths and deltas that aren't finite,
+    // stopping the run at the first with a dump in nan_dump/ of the cell,
+    // its neighbors and their changes over the last history_steps steps
+    pub fn nan_guard(&mut self, history_steps: usize) -> &mut RunnerBuilder<'a> {
+        assert!(history_steps > 0);
+        self.nan_guard = Some(history_steps);
+        self
+    }
+
+    // has the nan guard drop the deltas and restore the cells that aren't
+    // finite and carry on, listing each in nan_dump/recoveries.txt, rather
+    // than stop the run
+    pub fn nan_recovery(&mut self, nan_recovery: bool) -> &mut RunnerBuilder<'a> {
+        self.nan_recovery = nan_recovery;
+        self
+    }
+
+    // tallies total water and rock around every flow step against what the
+    // flows rained, evaporated, drained, raised and carried off, writing
+    // each step's drift to mass_audit.csv and the run's to the console
+    pub fn mass_audit(&mut self, mass_audit: bool) -> &mut RunnerBuilder<'a> {
+        self.mass_audit = mass_audit;
+        self
+    }
+
+    // draws and writes each frame on a thread of its own from a copy of the
+    // terrain while the next frame's steps run, holding up the steps only
+    // when a second frame is waiting to be drawn
+    pub fn pipelined_render(&mut self, pipelined_render: bool) -> &mut RunnerBuilder<'a> {
+        self.pipelined_render = pipelined_render;
+        self
+    }
+
+    // writes the frames as numbered PNGs or straight into a video or an
+    // animated image; see Output
+    pub fn output(&mut self, output: Output) -> &mut RunnerBuilder<'a> {
+        match &output {
+            Output::Frames(_) => {}
+            Output::Video { file, fps } => assert!(!file.is_empty() && *fps > 0),
+            Output::Animation { file, scale, .. } => assert!(!file.is_empty() && *scale > 0),
+        }
+        self.output = output;
+        self
+    }
+
+    // writes checkpoint_NNNNNN.dat to the render path after every this many
+    // frames, holding the terrain as it stands before frame NNNNNN
+    pub fn checkpoint_every(&mut self, frames: u32) -> &mut RunnerBuilder<'a> {
+        assert!(frames > 0);
+        self.checkpoint_every = Some(frames);
+        self
+    }
+
+    // runs on from a checkpoint instead of from a fresh terrain; the other
+    // settings should match those of the run that wrote it
+    pub fn resume(&mut self, path: &'a str) -> &mut RunnerBuilder<'a> {
+        self.resume = Some(path);
+        self
+    }
+
+    // writes each frame's heights and depths to snapshot_NNNNNN.dat files in
+    // the directory, with the mesh in mesh.dat, instead of drawing it, so the
+    // run can be rendered as often as wanted with replay
+    pub fn snapshot_path(&mut self, path: &'a str) -> &mut RunnerBuilder<'a> {
+        self.snapshot_path = Some(path);
+        self
+    }
+
+    // renders the frames of a run from the snapshots it wrote to the
+    // directory instead of simulating; the world's size and shape must match
+    // the run's
+    pub fn replay(&mut self, path: &'a str) -> &mut RunnerBuilder<'a> {
+        self.replay = Some(path);
+        self
+    }
+
+    // exports the given frame as map tiles under tiles_NNNNNN/{z}/{x}/{y}.png
+    // for zoom levels 0 through max_zoom
+    pub fn map_tiles(&mut self, frame: u32, max_zoom: u32) -> &mut RunnerBuilder<'a> {
+        assert!(max_zoom <= 10);
+        self.map_tiles = Some((frame, max_zoom));
+        self
+    }
+
+    #[cfg(feature = "tui")]
+    pub fn tui(&mut self, tui: bool) -> &mut RunnerBuilder<'a> {
+        self.tui = tui;
+        self
+    }
+
+    #[cfg(feature = "window")]
+    pub fn window(&mut self, window: bool) -> &mut RunnerBuilder<'a> {
+        self.window = window;
+        self
+    }
+
+    #[cfg(feature = "http")]
+    pub fn http_addr(&mut self, http_addr: &'a str) -> &mut RunnerBuilder<'a> {
+        self.http_addr = Some(http_addr);
+        self
+    }
+
+    #[cfg(feature = "plugins")]
+    pub fn flow_plugin(&mut self, flow_plugin: &'a str) -> &mut RunnerBuilder<'a> {
+        assert!(Path::new(flow_plugin).is_file());
+        self.flow_plugin = Some(flow_plugin);
+        self
+    }
+
+    #[cfg(feature = "plugins")]
+    pub fn shader_plugin(&mut self, shader_plugin: &'a str) -> &mut RunnerBuilder<'a> {
+        assert!(Path::new(shader_plugin).is_file());
+        self.shader_plugin = Some(shader_plugin);
+        self
+    }
+
+    #[cfg(feature = "scripting")]
+    pub fn script(&mut self, script: &'a str) -> &mut RunnerBuilder<'a> {
+        assert!(Path::new(script).is_file());
+        self.script = Some(script);
+        self
+    }
+
+    // works out the default flow's transfers on the GPU, in single precision
+    #[cfg(feature = "gpu")]
+    pub fn gpu(&mut self, gpu: bool) -> &mut RunnerBuilder<'a> {
+        self.gpu = gpu;
+        self
+    }
+
+    // whether anything besides the flow reads or changes the terrain every
+    // step
+    fn has_step_processes(&self) -> bool {
+        #[cfg(feature = "scripting")]
+        if self.script.is_some() {
+            return true;
+        }
+        self.climate.is_some()
+            || self.humidity.is_some()
+            || !self.probes.is_empty()
+            || self.tracers.is_some()
+            || self.exposure_threshold.is_some()
+            || self.wet_depth.is_some()
+            || self.groundwater.is_some()
+            || self.sediment.is_some()
+            || self.karst.is_some()
+            || self.stream_power.is_some()
+            || self.salinity.is_some()
+            || self.cumulative_change
+            || draws_change(&self.frame_shader, &self.shader_layers)
+            || self.goal.is_some()
+            || self.scenario.is_some()
+    }
+
+    pub fn build(&self) -> Runner<'_> {
         assert!(self.width.is_some());
         assert!(self.height.is_some());
         assert!(self.density.is_some());
@@ -244,30 +2072,172 @@ impl<'a> RunnerBuilder<'a> {
         assert!(self.flow_erosion_rate.is_some());
         assert!(self.erosion_threshold.is_some());
         assert!(self.erosion_rate.is_some());
-        assert!(self.precipitation_rate.is_some());
-        assert!(self.precipitation_amount.is_some());
+        assert!(self.precipitation.is_some() || (self.precipitation_rate.is_some() && self.precipitation_amount.is_some()));
         assert!(self.render_step.is_some());
         assert!(self.frame_skip.is_some());
         assert!(self.frame_count.is_some());
         assert!(self.data_path.is_some());
         assert!(self.render_path.is_some());
+        assert!(self.spherical || matches!(self.projection, Projection::Equirectangular));
+        // a shader plugin takes the place of the built in shaders
+        #[cfg(feature = "plugins")]
+        assert!(self.shader_plugin.is_none() || matches!(self.frame_shader, FrameShader::Default));
+        // a globe view always shows the whole visible hemisphere
+        assert!(self.viewport.is_none() || matches!(self.projection, Projection::Equirectangular));
+        assert!(!self.spherical || self.topology == Topology::Delaunay);
+        assert!(self.biomes.is_none() || self.climate.is_some());
+        assert!(self.map_tiles.is_none_or(|(frame, _)| frame < self.frame_count.unwrap()));
+        assert!(self.distributed.is_none() || (self.ensemble.is_none() && !self.has_step_processes()));
+        assert!(self.resume.is_none() || self.ensemble.is_none());
+        // every member would write to the same snapshots
+        assert!(self.snapshot_path.is_none() || self.ensemble.is_none());
+        assert!(self.replay.is_none() || (self.snapshot_path.is_none() && self.resume.is_none() && self.ensemble.is_none()));
+        assert!(!self.nan_recovery || self.nan_guard.is_some());
+        // distributed workers step on their own
+        assert!(self.distributed.is_none()
+            || (self.adaptive_step.is_none() && self.active_cells.is_none() && !self.mass_audit && self.nan_guard.is_none()));
+        // and hold only their cells' heights and depths, without layers or
+        // the rest of the terrain for droplets to roam
+        assert!(self.distributed.is_none()
+            || (self.precipitation_map.is_none() && self.eroded_routing.suspended == 0.0 && self.droplets.is_none()));
+        assert!(self.suspended_transport.is_none() || self.eroded_routing.suspended > 0.0);
+        // suspended load rides on the flow's transfer, which shallow water
+        // and the pipe model replace
+        assert!(self.suspended_transport.is_none() || (self.shallow_water.is_none() && self.pipe_model.is_none()));
+        assert!(self.shallow_water.is_none() || self.pipe_model.is_none());
+        let draws_flux = flux_scale(&self.frame_shader, &self.shader_layers).is_some();
+        // the flux is what the flow itself moves from cell to cell, which
+        // shallow water and the pipe model take over and distributed
+        // workers keep to themselves
+        assert!(!draws_flux
+            || (self.shallow_water.is_none() && self.pipe_model.is_none() && self.distributed.is_none()));
+        // every member would try to serve on the same address
+        #[cfg(feature = "http")]
+        assert!(self.ensemble.is_none() || self.http_addr.is_none());
+        // the server hands out each frame's image as soon as it's reached
+        #[cfg(feature = "http")]
+        assert!(!self.pipelined_render || self.http_addr.is_none());
+        // nor is there a PNG to hand out when they go into a video, an
+        // animation or EXR files
+        #[cfg(feature = "http")]
+        assert!(matches!(self.output, Output::Frames(FrameFormat::Png | FrameFormat::Png16)) || self.http_addr.is_none());
+        // or at all, when snapshots are written instead
+        #[cfg(feature = "http")]
+        assert!(self.snapshot_path.is_none() || self.http_addr.is_none());
+        // the GPU transfers carry no suspended load
+        #[cfg(feature = "gpu")]
+        assert!(!self.gpu || self.eroded_routing.suspended == 0.0);
+        // nor which neighbor each cell's water went to
+        #[cfg(feature = "gpu")]
+        assert!(!self.gpu || !draws_flux);
+        // the flux layers are kept by the built in flow
+        #[cfg(feature = "plugins")]
+        assert!(self.flow_plugin.is_none() || !draws_flux);
 
         Runner {
             width: self.width.unwrap(),
             height: self.height.unwrap(),
             density: self.density.unwrap(),
             max_z: self.max_z.unwrap(),
+            initial_terrain: self.initial_terrain,
+            spherical: self.spherical,
+            topology: self.topology,
+            projection: self.projection,
+            raster: self.raster,
+            viewport: self.viewport,
+            mesh_overlay: self.mesh_overlay,
+            frame_shader: self.frame_shader.clone(),
+            shader_layers: self.shader_layers.clone(),
+            contours: self.contours,
+            vertical: self.vertical,
             flow_rate: self.flow_rate.unwrap(),
             flow_erosion_rate: self.flow_erosion_rate.unwrap(),
             erosion_threshold: self.erosion_threshold.unwrap(),
             erosion_rate: self.erosion_rate.unwrap(),
-            precipitation_rate: self.precipitation_rate.unwrap(),
-            precipitation_amount: self.precipitation_amount.unwrap(),
+            precipitation: self.precipitation.unwrap_or_else(|| Precipitation::Bernoulli {
+                rate: self.precipitation_rate.unwrap(),
+                amount: self.precipitation_amount.unwrap(),
+            }),
+            precipitation_map: self.precipitation_map,
+            drain_policy: self.drain_policy,
+            eroded_routing: self.eroded_routing,
+            suspended_transport: self.suspended_transport,
             render_step: self.render_step.unwrap(),
             frame_skip: self.frame_skip.unwrap(),
             frame_count: self.frame_count.unwrap(),
             data_path: self.data_path.unwrap(),
             render_path: self.render_path.unwrap(),
+            schedules: self.schedules.clone(),
+            climate: self.climate.clone(),
+            biomes: self.biomes.clone(),
+            humidity: self.humidity.clone(),
+            probes: self.probes.clone(),
+            tracers: self.tracers.clone(),
+            exposure_threshold: self.exposure_threshold,
+            wet_depth: self.wet_depth,
+            floods: self.floods.clone(),
+            groundwater: self.groundwater.clone(),
+            sediment: self.sediment.clone(),
+            karst: self.karst.clone(),
+            diffusivity: self.diffusivity,
+            thermal: self.thermal,
+            evaporation: self.evaporation,
+            droplets: self.droplets,
+            shallow_water: self.shallow_water,
+            pipe_model: self.pipe_model,
+            stacked_flows: self.stacked_flows.clone(),
+            stream_power: self.stream_power.clone(),
+            salinity: self.salinity.clone(),
+            interpolated_frames: self.interpolated_frames,
+            view_3d: self.view_3d,
+            map_tiles: self.map_tiles,
+            dashboard: self.dashboard,
+            change_maps: self.change_maps,
+            cumulative_change: self.cumulative_change,
+            export_heightmap: self.export_heightmap,
+            export_svg: self.export_svg,
+            export_mesh: self.export_mesh,
+            export_stl: self.export_stl,
+            export_raw: self.export_raw,
+            export_gltf: self.export_gltf,
+            goal: self.goal,
+            scenario: self.scenario,
+            ensemble: self.ensemble,
+            distributed: self.distributed,
+            // seeded runs only repeat if the deltas sum the same way too
+            deterministic: self.deterministic || self.seed.is_some(),
+            adaptive_step: self.adaptive_step,
+            active_cells: self.active_cells,
+            seed: self.seed,
+            nan_guard: self.nan_guard,
+            nan_recovery: self.nan_recovery,
+            mass_audit: self.mass_audit,
+            pipelined_render: self.pipelined_render,
+            output: self.output.clone(),
+            checkpoint_every: self.checkpoint_every,
+            resume: self.resume,
+            snapshot_path: self.snapshot_path,
+            replay: self.replay,
+            #[cfg(feature = "tui")]
+            tui: self.tui,
+            #[cfg(feature = "window")]
+            window: self.window,
+            #[cfg(feature = "http")]
+            http_addr: self.http_addr,
+            #[cfg(feature = "plugins")]
+            flow_plugin: self.flow_plugin,
+            #[cfg(feature = "plugins")]
+            shader_plugin: self.shader_plugin,
+            #[cfg(feature = "scripting")]
+            script: self.script,
+            #[cfg(feature = "gpu")]
+            gpu: self.gpu,
         }
     }
 }
+
+impl<'a> Default for RunnerBuilder<'a> {
+    fn default() -> RunnerBuilder<'a> {
+        RunnerBuilder::new()
+    }
+}
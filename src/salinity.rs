@@ -0,0 +1,111 @@
+use crate::flow::DISCHARGE_LAYER;
+use crate::render::{FrameTime, RGB, Shade};
+use crate::terrain::{Cell, Terrain};
+
+pub const SALINITY_LAYER: &str = "salinity";
+
+const WET_DEPTH: f64 = 0.01;
+
+// Tracks how salty the water in each cell is, from 0 for rain to 1 for sea
+// water. Cells below `sea_level` are open ocean and held at 1. Salt travels
+// downstream with the water leaving each cell and mixes where it arrives,
+// and neighboring wet cells also exchange salinity at `mixing_rate`, which
+// lets sea water push up into estuaries against the river.
+#[derive(Clone)]
+pub struct Salinity {
+    sea_level: f64,
+    mixing_rate: f64,
+}
+
+pub struct SalinityShader;
+
+impl Salinity {
+    pub fn new(sea_level: f64, mixing_rate: f64) -> Salinity {
+        assert!(sea_level.is_finite());
+        assert!(mixing_rate >= 0.0);
+        Salinity { sea_level, mixing_rate }
+    }
+
+    pub fn set_sea_level(&mut self, sea_level: f64) {
+        assert!(sea_level.is_finite());
+        self.sea_level = sea_level;
+    }
+
+    pub fn update(&self, terrain: &mut Terrain, time_step: f64) {
+        let salinity = terrain.layer_mut(SALINITY_LAYER).to_vec();
+        let discharge = terrain.layer(DISCHARGE_LAYER);
+        let mut inflow = vec![0.0; terrain.cells_len()];
+        let mut salt_in = vec![0.0; terrain.cells_len()];
+
+        for cell in terrain.cells_iter() {
+            let i = cell.index();
+            let leaving = discharge.map_or(0.0, |discharge| (discharge[i] * time_step).min(cell.depth()));
+            if leaving <= 0.0 {
+                continue;
+            }
+            let surface = cell.height() + cell.depth();
+            let downhill: Vec<(usize, f64)> = cell.neighbor_data_iter()
+                .map(|nd| {
+                    let neighbor = terrain.get_cell(nd.index());
+                    (nd.index(), (surface - neighbor.height() - neighbor.depth()) / nd.distance())
+                })
+                .filter(|&(_, slope)| slope > 0.0)
+                .collect();
+            let weight_sum: f64 = downhill.iter().map(|&(_, slope)| slope).sum();
+            for (j, slope) in downhill {
+                let share = leaving * slope / weight_sum;
+                inflow[j] += share;
+                salt_in[j] += share * salinity[i];
+            }
+        }
+
+        let mut mixed: Vec<f64> = terrain.cells_iter()
+            .map(|cell| {
+                let i = cell.index();
+                let depth = cell.depth();
+                if depth <= 0.0 {
+                    return 0.0;
+                }
+                let staying = (depth - inflow[i]).max(0.0);
+                (staying * salinity[i] + salt_in[i]) / depth.max(inflow[i])
+            })
+            .collect();
+
+        let advected = mixed.clone();
+        for cell in terrain.cells_iter() {
+            let i = cell.index();
+            if cell.height() < self.sea_level {
+                mixed[i] = 1.0;
+                continue;
+            }
+            if cell.depth() <= WET_DEPTH {
+                continue;
+            }
+            let wet: Vec<usize> = cell.neighbor_data_iter()
+                .map(|nd| nd.index())
+                .filter(|&j| terrain.get_cell(j).depth() > WET_DEPTH)
+                .collect();
+            if !wet.is_empty() {
+                let exchange: f64 = wet.iter().map(|&j| advected[j] - advected[i]).sum::<f64>() / wet.len() as f64;
+                mixed[i] += exchange * (self.mixing_rate * time_step).min(1.0);
+            }
+        }
+
+        for value in mixed.iter_mut() {
+            *value = value.clamp(0.0, 1.0);
+        }
+        terrain.layer_mut(SALINITY_LAYER).copy_from_slice(&mixed);
+    }
+}
+
+// fresh water in light blue through brackish green-blue to deep sea blue,
+// with dry land in gray
+impl Shade for SalinityShader {
+    fn shade_cell(&self, cell: &Cell, terrain: &Terrain, _time: FrameTime) -> RGB {
+        if cell.depth() <= WET_DEPTH {
+            return RGB { r: 0.5, g: 0.5, b: 0.5 };
+        }
+        let s = terrain.layer(SALINITY_LAYER).map_or(0.0, |layer| layer[cell.index()]);
+        RGB { r: 0.4 * (1.0 - s), g: 0.8 - 0.6 * s, b: 0.9 - 0.4 * s }
+    }
+}
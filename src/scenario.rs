@@ -0,0 +1,258 @@
+use std::fs;
+
+use crate::brush::{Brush, BrushOp, Falloff};
+use crate::flood::FloodEvent;
+use crate::terrain::{Terrain, TerrainDelta};
+
+// A scenario file lists timed actions, one per line as
+//
+//   <at_time> <action> <arguments...>
+//
+// with blank lines and anything after a # ignored. The actions are
+//
+//   sea_level <level>                      hold cells below level under water
+//   uplift <rate>                          raise all ground at rate per unit
+//                                          time from now on (0 stops it)
+//   storm <x> <y> <radius> <rate> <duration>
+//                                          rain rate depth per unit time
+//                                          within radius for duration
+//   dam <x0> <y0> <x1> <y1> <width> <crest>
+//                                          build ground up to crest height
+//                                          along the line, width wide
+//   flood <x> <y> <radius> <volume>
+//   brush <op> <x> <y> <radius> <strength>
+//   set <parameter> <value>                set a flow parameter
+//
+// and fire at the start of the first step at or after their time, in file
+// order for equal times.
+pub struct Scenario {
+    events: Vec<(f64, Action)>,
+    next_event: usize,
+    sea_level: Option<f64>,
+    uplift_rate: f64,
+    storms: Vec<Storm>,
+}
+
+#[derive(Clone)]
+pub enum Action {
+    SeaLevel(f64),
+    Uplift(f64),
+    Storm(Storm),
+    Dam { from: (f64, f64), to: (f64, f64), width: f64, crest: f64 },
+    Flood(FloodEvent),
+    Brush(Brush),
+    SetParam(String, f64),
+}
+
+#[derive(Clone)]
+pub struct Storm {
+    x: f64,
+    y: f64,
+    radius: f64,
+    rate: f64,
+    remaining: f64,
+}
+
+impl Scenario {
+    // reads and parses the file, offering each parameter it sets to accepts
+    // so one the run won't take fails the load rather than the step it's
+    // due in
+    pub fn load(path: &str, mut accepts: impl FnMut(&str, f64) -> bool) -> Result<Scenario, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("failed to read scenario {}: {}", path, e))?;
+        let scenario = Scenario::parse(&text).map_err(|e| format!("invalid scenario {}: {}", path, e))?;
+        for (time, action) in scenario.events.iter() {
+            if let Action::SetParam(name, value) = action {
+                if !accepts(name, *value) {
+                    return Err(format!("invalid scenario {}: at {} sets invalid parameter {} = {}", path, time, name, value));
+                }
+            }
+        }
+        Ok(scenario)
+    }
+
+    pub fn parse(text: &str) -> Result<Scenario, String> {
+        let mut events = Vec::new();
+        for (line_num, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let event = parse_event(line).map_err(|e| format!("line {}: {}", line_num + 1, e))?;
+            events.push(event);
+        }
+        // stable, so equal times keep file order
+        events.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap());
+        Ok(Scenario { events, next_event: 0, sea_level: None, uplift_rate: 0.0, storms: Vec::new() })
+    }
+
+    pub fn sea_level(&self) -> Option<f64> {
+        self.sea_level
+    }
+
+    // the actions whose time has come, each returned once
+    pub fn due_actions(&mut self, time: f64) -> Vec<Action> {
+        let start = self.next_event;
+        while self.next_event < self.events.len() && self.events[self.next_event].0 <= time {
+            self.next_event += 1;
+        }
+        self.events[start..self.next_event].iter().map(|(_, action)| action.clone()).collect()
+    }
+
+    // carries out an action other than setting a parameter, which is left
+    // to the caller; returns the number of cells changed by actions that
+    // change cells at once
+    pub fn apply(&mut self, action: &Action, terrain: &mut Terrain) -> Option<usize> {
+        match action {
+            Action::SeaLevel(level) => self.sea_level = Some(*level),
+            Action::Uplift(rate) => self.uplift_rate = *rate,
+            Action::Storm(storm) => self.storms.push(storm.clone()),
+            Action::Dam { from, to, width, crest } => return Some(build_dam(terrain, *from, *to, *width, *crest)),
+            Action::Flood(flood) => return Some(flood.apply(terrain)),
+            Action::Brush(brush) => return Some(brush.apply(terrain)),
+            Action::SetParam(_, _) => {}
+        }
+        None
+    }
+
+    // the ongoing effects of earlier actions over one step
+    pub fn update(&mut self, terrain: &mut Terrain, time_step: f64) {
+        let mut deltas: Vec<TerrainDelta> = terrain.cells_iter()
+            .map(|cell| TerrainDelta { cell_index: cell.index(), height_delta: self.uplift_rate * time_step, depth_delta: 0.0 })
+            .collect();
+        for storm in self.storms.iter_mut() {
+            let duration = storm.remaining.min(time_step);
+            storm.remaining -= duration;
+            for cell in terrain.cells_iter() {
+                if (cell.x() - storm.x).hypot(cell.y() - storm.y) <= storm.radius {
+                    deltas[cell.index()].depth_delta += storm.rate * duration;
+                }
+            }
+        }
+        self.storms.retain(|storm| storm.remaining > 0.0);
+        terrain.apply_deltas(&deltas);
+
+        if let Some(sea_level) = self.sea_level {
+            let fills: Vec<TerrainDelta> = terrain.cells_iter()
+                .filter(|cell| cell.height() + cell.depth() < sea_level)
+                .map(|cell| TerrainDelta {
+                    cell_index: cell.index(),
+                    height_delta: 0.0,
+                    depth_delta: sea_level - cell.height() - cell.depth(),
+                })
+                .collect();
+            terrain.apply_deltas(&fills);
+        }
+    }
+}
+
+impl Action {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::SeaLevel(_) => "sea_level",
+            Action::Uplift(_) => "uplift",
+            Action::Storm(_) => "storm",
+            Action::Dam { .. } => "dam",
+            Action::Flood(_) => "flood",
+            Action::Brush(brush) => brush.op.name(),
+            Action::SetParam(_, _) => "set",
+        }
+    }
+
+    pub fn fields(&self) -> Vec<(&str, f64)> {
+        match self {
+            Action::SeaLevel(level) => vec![("level", *level)],
+            Action::Uplift(rate) => vec![("rate", *rate)],
+            Action::Storm(storm) => vec![
+                ("x", storm.x),
+                ("y", storm.y),
+                ("radius", storm.radius),
+                ("rate", storm.rate),
+                ("duration", storm.remaining),
+            ],
+            Action::Dam { from, to, width, crest } => vec![
+                ("x0", from.0),
+                ("y0", from.1),
+                ("x1", to.0),
+                ("y1", to.1),
+                ("width", *width),
+                ("crest", *crest),
+            ],
+            Action::Flood(flood) => vec![("x", flood.x), ("y", flood.y), ("radius", flood.radius), ("volume", flood.volume)],
+            Action::Brush(brush) => vec![("x", brush.x), ("y", brush.y), ("radius", brush.radius), ("strength", brush.strength)],
+            Action::SetParam(name, value) => vec![(name.as_str(), *value)],
+        }
+    }
+}
+
+fn parse_event(line: &str) -> Result<(f64, Action), String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let number = |index: usize| -> Result<f64, String> {
+        let word = words.get(index).ok_or_else(|| format!("missing arguments for {}", words[1]))?;
+        word.parse::<f64>().ok().filter(|value| value.is_finite()).ok_or_else(|| format!("invalid number {}", word))
+    };
+    let at_time = number(0)?;
+    let action_name = *words.get(1).ok_or("missing action")?;
+    let (action, arg_count) = match action_name {
+        "sea_level" => (Action::SeaLevel(number(2)?), 1),
+        "uplift" => (Action::Uplift(number(2)?), 1),
+        "storm" => {
+            let storm = Storm { x: number(2)?, y: number(3)?, radius: number(4)?, rate: number(5)?, remaining: number(6)? };
+            if storm.radius <= 0.0 || storm.rate < 0.0 || storm.remaining <= 0.0 {
+                return Err(String::from("storm needs a positive radius and duration"));
+            }
+            (Action::Storm(storm), 5)
+        }
+        "dam" => {
+            let (width, crest) = (number(6)?, number(7)?);
+            if width <= 0.0 {
+                return Err(String::from("dam needs a positive width"));
+            }
+            (Action::Dam { from: (number(2)?, number(3)?), to: (number(4)?, number(5)?), width, crest }, 6)
+        }
+        "flood" => {
+            let flood = FloodEvent { frame: 0, x: number(2)?, y: number(3)?, radius: number(4)?, volume: number(5)? };
+            if !(flood.radius > 0.0 && flood.volume > 0.0 && flood.volume.is_finite()) {
+                return Err(String::from("flood needs a positive radius and volume"));
+            }
+            (Action::Flood(flood), 4)
+        }
+        "brush" => {
+            let op_name = words.get(2).ok_or("brush needs 5 arguments")?;
+            let op = BrushOp::parse(op_name).ok_or_else(|| format!("unknown brush op {}", op_name))?;
+            let (radius, strength) = (number(5)?, number(6)?);
+            if radius <= 0.0 || strength < 0.0 {
+                return Err(String::from("brush needs a positive radius and strength"));
+            }
+            (Action::Brush(Brush::new(op, number(3)?, number(4)?, radius, strength, Falloff::Smooth)), 5)
+        }
+        "set" => {
+            let name = words.get(2).ok_or("set needs 2 arguments")?;
+            (Action::SetParam(name.to_string(), number(3)?), 2)
+        }
+        _ => return Err(format!("unknown action {}", action_name)),
+    };
+    if words.len() > arg_count + 2 {
+        return Err(format!("too many arguments for {}", action_name));
+    }
+    Ok((at_time, action))
+}
+
+// raises cells within width / 2 of the segment to the crest height
+fn build_dam(terrain: &mut Terrain, from: (f64, f64), to: (f64, f64), width: f64, crest: f64) -> usize {
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let length_sq = dx * dx + dy * dy;
+    let deltas: Vec<TerrainDelta> = terrain.cells_iter()
+        .filter(|cell| {
+            let t = if length_sq > 0.0 {
+                (((cell.x() - from.0) * dx + (cell.y() - from.1) * dy) / length_sq).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let distance = (cell.x() - from.0 - t * dx).hypot(cell.y() - from.1 - t * dy);
+            distance <= width / 2.0 && cell.height() < crest
+        })
+        .map(|cell| TerrainDelta { cell_index: cell.index(), height_delta: crest - cell.height(), depth_delta: 0.0 })
+        .collect();
+    terrain.apply_deltas(&deltas);
+    deltas.len()
+}
@@ -0,0 +1,46 @@
+#[derive(Clone)]
+pub struct ParameterSchedule {
+    interpolation: Interpolation,
+    keyframes: Vec<(f64, f64)>,
+}
+
+#[derive(Clone, Copy)]
+pub enum Interpolation {
+    Step,
+    Linear,
+    Smooth,
+}
+
+impl ParameterSchedule {
+    // keyframes are (time, value) pairs in strictly increasing time order;
+    // the value is held constant before the first and after the last keyframe
+    pub fn new(interpolation: Interpolation, keyframes: Vec<(f64, f64)>) -> ParameterSchedule {
+        assert!(!keyframes.is_empty());
+        assert!(keyframes.iter().all(|&(time, value)| time.is_finite() && value.is_finite()));
+        assert!(keyframes.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        ParameterSchedule { interpolation, keyframes }
+    }
+
+    pub fn keyframes(&self) -> &[(f64, f64)] {
+        &self.keyframes
+    }
+
+    pub fn value_at(&self, time: f64) -> f64 {
+        let next = self.keyframes.iter().position(|&(key_time, _)| key_time > time);
+        match next {
+            None => self.keyframes[self.keyframes.len() - 1].1,
+            Some(0) => self.keyframes[0].1,
+            Some(next) => {
+                let (t0, v0) = self.keyframes[next - 1];
+                let (t1, v1) = self.keyframes[next];
+                let f = (time - t0) / (t1 - t0);
+                let f = match self.interpolation {
+                    Interpolation::Step => 0.0,
+                    Interpolation::Linear => f,
+                    Interpolation::Smooth => f * f * (3.0 - 2.0 * f),
+                };
+                v0 + (v1 - v0) * f
+            }
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, Map, Scope, AST};
+
+use crate::brush::{Brush, BrushOp, Falloff};
+use crate::stats::TerrainStats;
+
+const UPDATE_FN: &str = "update";
+
+// A run-control script is a rhai file defining
+//
+//   fn update(time, stats) { ... }
+//
+// which is called after every step with the simulated time and a map of
+// terrain stats, and returns a map of parameter names to new values (or
+// nothing). A failed call, values that aren't numbers and parameters the
+// run refuses are reported as script errors and skipped. State that should
+// survive between calls can be kept on `this`, which starts out as an
+// empty map. Scripts can also sculpt the terrain by calling
+//
+//   brush(op, x, y, radius, strength)
+//
+// with op one of "raise", "lower", "smooth" or "add_water" and numbers that
+// may be integers, applied with a smooth falloff once update returns.
+pub struct ScriptControl {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    state: Dynamic,
+    brushes: Rc<RefCell<Vec<Brush>>>,
+}
+
+impl ScriptControl {
+    pub fn load(path: &str) -> Result<ScriptControl, String> {
+        let mut engine = Engine::new();
+        let brushes = Rc::new(RefCell::new(Vec::new()));
+        let queue = brushes.clone();
+        engine.register_fn("brush", move |op: &str, x: Dynamic, y: Dynamic, radius: Dynamic, strength: Dynamic| {
+            let op = BrushOp::parse(op).ok_or_else(|| format!("unknown brush op {}", op))?;
+            let [x, y, radius, strength] = [x, y, radius, strength].map(|value| number(&value));
+            let (x, y, radius, strength) = match (x, y, radius, strength) {
+                (Some(x), Some(y), Some(radius), Some(strength)) => (x, y, radius, strength),
+                _ => return Err(Box::<EvalAltResult>::from("brush needs numbers for its position, radius and strength")),
+            };
+            if !(radius > 0.0 && strength >= 0.0) {
+                return Err(Box::<EvalAltResult>::from("brush needs a positive radius and strength"));
+            }
+            queue.borrow_mut().push(Brush::new(op, x, y, radius, strength, Falloff::Smooth));
+            Ok(())
+        });
+        let ast = engine.compile_file(PathBuf::from(path))
+            .map_err(|e| format!("failed to compile script {}: {}", path, e))?;
+        let mut scope = Scope::new();
+        engine.run_ast_with_scope(&mut scope, &ast)
+            .map_err(|e| format!("failed to run script {}: {}", path, e))?;
+        Ok(ScriptControl { engine, ast, scope, state: Dynamic::from_map(Map::new()), brushes })
+    }
+
+    // the brush strokes called for since the last take
+    pub fn take_brushes(&mut self) -> Vec<Brush> {
+        self.brushes.borrow_mut().drain(..).collect()
+    }
+
+    // the parameters to change, reporting and leaving out any that aren't
+    // numbers; an Err if the call fails or returns something else
+    pub fn update(&mut self, time: f64, stats: &TerrainStats) -> Result<Vec<(String, f64)>, String> {
+        let options = CallFnOptions::new()
+            .eval_ast(false)
+            .bind_this_ptr(&mut self.state);
+        let result: Dynamic = self.engine
+            .call_fn_with_options(options, &mut self.scope, &self.ast, UPDATE_FN, (time, stats_map(stats)))
+            .map_err(|e| format!("update failed: {}", e))?;
+
+        if result.is_unit() {
+            return Ok(Vec::new());
+        }
+        let changes = result.try_cast::<Map>()
+            .ok_or_else(|| "update must return a map of parameters".to_string())?;
+        Ok(changes.into_iter()
+            .filter_map(|(name, value)| match number(&value) {
+                Some(value) => Some((name.to_string(), value)),
+                None => {
+                    eprintln!("script error at time {}: parameter {} is not a number, ignored", time, name);
+                    None
+                }
+            })
+            .collect())
+    }
+}
+
+// a float or an integer as a float
+fn number(value: &Dynamic) -> Option<f64> {
+    value.as_float().or_else(|_| value.as_int().map(|i| i as f64)).ok()
+}
+
+fn stats_map(stats: &TerrainStats) -> Map {
+    let mut map = Map::new();
+    map.insert("cells".into(), (stats.cell_count as i64).into());
+    map.insert("wet_cells".into(), (stats.wet_cells as i64).into());
+    map.insert("water_volume".into(), stats.water_volume.into());
+    map.insert("min_height".into(), stats.min_height.into());
+    map.insert("mean_height".into(), stats.mean_height.into());
+    map.insert("max_height".into(), stats.max_height.into());
+    map.insert("relief".into(), stats.relief().into());
+    map
+}
@@ -0,0 +1,186 @@
+use crate::flow::DISCHARGE_LAYER;
+use crate::render::{FrameTime, RGB, Shade};
+use crate::terrain::{Cell, Terrain, TerrainDelta};
+
+pub const GRAIN_CLASS_NAMES: [&str; 3] = ["sand", "silt", "clay"];
+pub const SUSPENDED_LAYERS: [&str; 3] = ["suspended_sand", "suspended_silt", "suspended_clay"];
+pub const SUBSTRATE_LAYERS: [&str; 3] = ["substrate_sand", "substrate_silt", "substrate_clay"];
+
+// sand is hard to lift and drops out quickly; clay stays in suspension
+// until it reaches still water
+pub const DEFAULT_GRAIN_CLASSES: [GrainClass; 3] = [
+    GrainClass { capacity: 0.2, settling_rate: 0.5 },
+    GrainClass { capacity: 0.5, settling_rate: 0.1 },
+    GrainClass { capacity: 1.0, settling_rate: 0.02 },
+];
+
+const SUBSTRATE_COLORS: [(f64, f64, f64); 3] = [(0.9, 0.8, 0.5), (0.6, 0.5, 0.4), (0.7, 0.4, 0.3)];
+
+#[derive(Clone, Copy)]
+pub struct GrainClass {
+    // suspended load water can carry per unit of depth and surface slope
+    pub capacity: f64,
+    // fraction of any load above capacity that settles per unit time
+    pub settling_rate: f64,
+}
+
+// Carries sand, silt and clay separately. Water below its carrying capacity
+// for a class picks it up from the bed at `pickup_rate`, limited by how much
+// of that class the active surface layer of thickness `active_layer` holds;
+// water above capacity drops the excess at the class's settling rate. Loads
+// move downstream with the water leaving each cell, so coarse grains settle
+// near steep channels while fines reach lakes. Suspended loads and the
+// substrate composition of each cell are kept in attribute layers.
+//
+// Where loaded water runs into slower water, such as a river entering a lake
+// or leaving a canyon onto a plain, up to `entry_deposition` of the load is
+// dropped straight away in the receiving cell, in proportion to how much the
+// flow slows and how quickly each class settles. That builds deltas and fans
+// out from the entry point rather than spreading the load over the water
+// body.
+#[derive(Clone)]
+pub struct SedimentModel {
+    classes: [GrainClass; 3],
+    pickup_rate: f64,
+    active_layer: f64,
+    entry_deposition: f64,
+}
+
+pub struct SubstrateShader;
+
+impl SedimentModel {
+    pub fn new(classes: [GrainClass; 3], pickup_rate: f64, active_layer: f64, entry_deposition: f64) -> SedimentModel {
+        assert!(classes.iter().all(|class| class.capacity >= 0.0 && class.settling_rate >= 0.0));
+        assert!(pickup_rate >= 0.0);
+        assert!(active_layer > 0.0);
+        assert!((0.0..=1.0).contains(&entry_deposition));
+        SedimentModel { classes, pickup_rate, active_layer, entry_deposition }
+    }
+
+    pub fn update(&self, terrain: &mut Terrain, time_step: f64) {
+        if terrain.layer(SUBSTRATE_LAYERS[0]).is_none() {
+            for name in SUBSTRATE_LAYERS.iter() {
+                terrain.layer_mut(name).iter_mut().for_each(|fraction| *fraction = 1.0 / 3.0);
+            }
+        }
+        let mut suspended: Vec<Vec<f64>> = SUSPENDED_LAYERS.iter().map(|name| terrain.layer_mut(name).to_vec()).collect();
+        let mut substrate: Vec<Vec<f64>> = SUBSTRATE_LAYERS.iter().map(|name| terrain.layer_mut(name).to_vec()).collect();
+        let discharge = terrain.layer(DISCHARGE_LAYER);
+        let mut height_deltas = vec![0.0; terrain.cells_len()];
+        let mut moved: Vec<Vec<f64>> = vec![vec![0.0; terrain.cells_len()]; 3];
+        let mut dropped: Vec<Vec<f64>> = vec![vec![0.0; terrain.cells_len()]; 3];
+        let downhill: Vec<Vec<(usize, f64)>> = terrain.cells_iter().map(|cell| downhill_weights(&cell, terrain)).collect();
+        let speeds: Vec<f64> = terrain.cells_iter()
+            .map(|cell| max_slope(&downhill[cell.index()]) * cell.depth().max(0.0).sqrt())
+            .collect();
+        let max_settling = self.classes.iter().map(|class| class.settling_rate).fold(0.0, f64::max);
+
+        for cell in terrain.cells_iter() {
+            let i = cell.index();
+            let downhill = &downhill[i];
+            let slope = max_slope(downhill);
+
+            let mut masses = [0.0; 3];
+            for c in 0..3 {
+                masses[c] = substrate[c][i] * self.active_layer;
+                let class = &self.classes[c];
+                let capacity = class.capacity * cell.depth() * slope;
+                let load = suspended[c][i];
+                let change = if load < capacity {
+                    -((capacity - load) * self.pickup_rate * time_step).min(masses[c])
+                } else {
+                    (load - capacity) * (class.settling_rate * time_step).min(1.0)
+                };
+                suspended[c][i] -= change;
+                masses[c] += change;
+                height_deltas[i] += change;
+            }
+            let total: f64 = masses.iter().sum();
+            if total > 0.0 {
+                for c in 0..3 {
+                    substrate[c][i] = masses[c] / total;
+                }
+            }
+
+            let leaving = match discharge {
+                Some(discharge) if cell.depth() > 0.0 => (discharge[i] * time_step / cell.depth()).min(1.0),
+                _ => 0.0,
+            };
+            let weight_sum: f64 = downhill.iter().map(|&(_, slope)| slope).sum();
+            if leaving > 0.0 && weight_sum > 0.0 {
+                for c in 0..3 {
+                    let load = suspended[c][i] * leaving;
+                    moved[c][i] -= load;
+                    for &(j, slope) in downhill.iter() {
+                        let share = load * slope / weight_sum;
+                        let slowdown = if speeds[i] > 0.0 { (1.0 - speeds[j] / speeds[i]).max(0.0) } else { 0.0 };
+                        let settling = if max_settling > 0.0 { self.classes[c].settling_rate / max_settling } else { 0.0 };
+                        let drop = share * self.entry_deposition * slowdown * settling;
+                        moved[c][j] += share - drop;
+                        dropped[c][j] += drop;
+                    }
+                }
+            }
+        }
+
+        for j in 0..terrain.cells_len() {
+            let mut masses = [0.0; 3];
+            for c in 0..3 {
+                masses[c] = substrate[c][j] * self.active_layer + dropped[c][j];
+                height_deltas[j] += dropped[c][j];
+            }
+            let total: f64 = masses.iter().sum();
+            if total > 0.0 {
+                for c in 0..3 {
+                    substrate[c][j] = masses[c] / total;
+                }
+            }
+        }
+
+        for (cell_index, &height_delta) in height_deltas.iter().enumerate() {
+            if height_delta != 0.0 {
+                terrain.apply_delta(&TerrainDelta { cell_index, height_delta, depth_delta: 0.0 });
+            }
+        }
+        for c in 0..3 {
+            for (load, delta) in suspended[c].iter_mut().zip(moved[c].iter()) {
+                *load += delta;
+            }
+            terrain.layer_mut(SUSPENDED_LAYERS[c]).copy_from_slice(&suspended[c]);
+            terrain.layer_mut(SUBSTRATE_LAYERS[c]).copy_from_slice(&substrate[c]);
+        }
+    }
+}
+
+// neighbors with a lower water surface, with the slope down to each
+fn downhill_weights(cell: &Cell, terrain: &Terrain) -> Vec<(usize, f64)> {
+    let surface = cell.height() + cell.depth();
+    cell.neighbor_data_iter()
+        .filter_map(|nd| {
+            let neighbor = terrain.get_cell(nd.index());
+            let slope = (surface - neighbor.height() - neighbor.depth()) / nd.distance();
+            if slope > 0.0 {
+                Some((nd.index(), slope))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+fn max_slope(downhill: &[(usize, f64)]) -> f64 {
+    downhill.iter().map(|&(_, slope)| slope).fold(0.0, f64::max)
+}
+
+impl Shade for SubstrateShader {
+    fn shade_cell(&self, cell: &Cell, terrain: &Terrain, _time: FrameTime) -> RGB {
+        let mut color = RGB { r: 0.0, g: 0.0, b: 0.0 };
+        for (name, &(r, g, b)) in SUBSTRATE_LAYERS.iter().zip(SUBSTRATE_COLORS.iter()) {
+            let fraction = terrain.layer(name).map_or(1.0 / 3.0, |layer| layer[cell.index()]);
+            color.r += fraction * r;
+            color.g += fraction * g;
+            color.b += fraction * b;
+        }
+        color
+    }
+}
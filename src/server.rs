@@ -0,0 +1,276 @@
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::brush::{Brush, BrushOp, Falloff};
+use crate::flood::FloodEvent;
+#[cfg(feature = "metrics")]
+use crate::flow::StepStats;
+#[cfg(feature = "metrics")]
+use crate::metrics::Metrics;
+use crate::stats::TerrainStats;
+
+pub struct ControlServer {
+    shared: Arc<Shared>,
+    commands: Receiver<Command>,
+}
+
+pub enum Command {
+    // set all or, with the first one refused, none
+    SetParams { params: Vec<(String, f64)>, reply: Sender<Result<(), String>> },
+    Checkpoint { reply: Sender<Option<String>> },
+    Flood { event: FloodEvent, reply: Sender<usize> },
+    Brush { brush: Brush, reply: Sender<usize> },
+}
+
+struct Shared {
+    status: Mutex<RunStatus>,
+    pending_commands: AtomicUsize,
+    #[cfg(feature = "metrics")]
+    metrics: Mutex<Metrics>,
+}
+
+#[derive(Clone, Default)]
+struct RunStatus {
+    frame_num: u32,
+    frame_count: u32,
+    frame_path: Option<String>,
+    stats: Option<TerrainStats>,
+}
+
+impl ControlServer {
+    pub fn start(addr: &str) -> Result<ControlServer, String> {
+        let server = Server::http(addr).map_err(|e| format!("couldn't serve on {}: {}", addr, e))?;
+        let shared = Arc::new(Shared {
+            status: Mutex::new(RunStatus::default()),
+            pending_commands: AtomicUsize::new(0),
+            #[cfg(feature = "metrics")]
+            metrics: Mutex::new(Metrics::default()),
+        });
+        let (tx_command, rx_command) = mpsc::channel();
+
+        // each request on a thread of its own, as commands wait for the run
+        // to get round to them and shouldn't hold up the rest
+        let thread_shared = shared.clone();
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let shared = thread_shared.clone();
+                let commands = tx_command.clone();
+                thread::spawn(move || handle_request(request, &shared, &commands));
+            }
+        });
+
+        Ok(ControlServer { shared, commands: rx_command })
+    }
+
+    pub fn publish(&self, frame_num: u32, frame_count: u32, frame_path: String, stats: TerrainStats) {
+        #[cfg(feature = "metrics")]
+        self.shared.metrics.lock().unwrap().record_frame(&stats);
+
+        let mut status = self.shared.status.lock().unwrap();
+        status.frame_num = frame_num;
+        status.frame_count = frame_count;
+        status.frame_path = Some(frame_path);
+        status.stats = Some(stats);
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn record_step(&self, step: &StepStats) {
+        self.shared.metrics.lock().unwrap().record_step(step);
+    }
+
+    pub fn pending_commands(&self) -> impl Iterator<Item=Command> + '_ {
+        self.commands.try_iter().inspect(move |_| {
+            self.shared.pending_commands.fetch_sub(1, Ordering::SeqCst);
+        })
+    }
+}
+
+fn handle_request(request: Request, shared: &Shared, commands: &Sender<Command>) {
+    let status = &shared.status;
+    let url = request.url().to_string();
+    let (path, query) = match url.split_once('?') {
+        Some((path, query)) => (path.to_string(), query.to_string()),
+        None => (url.clone(), String::new()),
+    };
+
+    let response = match (request.method(), path.as_str()) {
+        (Method::Get, "/status") => {
+            let status = status.lock().unwrap().clone();
+            text_response(200, "application/json", status_json(&status))
+        }
+        (Method::Get, "/frame.png") => {
+            let frame_path = status.lock().unwrap().frame_path.clone();
+            match frame_path.and_then(|path| fs::read(path).ok()) {
+                Some(data) => Response::from_data(data)
+                    .with_header(content_type("image/png"))
+                    .boxed(),
+                None => text_response(404, "text/plain", String::from("no frame rendered yet")),
+            }
+        }
+        #[cfg(feature = "metrics")]
+        (Method::Get, "/metrics") => {
+            let mut metrics = shared.metrics.lock().unwrap();
+            metrics.set_pending_commands(shared.pending_commands.load(Ordering::SeqCst));
+            text_response(200, "text/plain; version=0.0.4", metrics.render())
+        }
+        (Method::Post, "/params") => set_params(&query, shared, commands),
+        (Method::Post, "/checkpoint") => {
+            let (reply, rx_reply) = mpsc::channel();
+            send_command(shared, commands, Command::Checkpoint { reply });
+            match rx_reply.recv() {
+                Ok(Some(path)) => text_response(200, "text/plain", path),
+                Ok(None) => text_response(501, "text/plain", String::from("checkpoints are not supported")),
+                Err(_) => text_response(503, "text/plain", String::from("run has finished")),
+            }
+        }
+        (Method::Post, "/flood") => flood(&query, shared, commands),
+        (Method::Post, "/brush") => brush(&query, shared, commands),
+        _ => text_response(404, "text/plain", String::from("not found")),
+    };
+
+    request.respond(response).unwrap_or(());
+}
+
+// sets every parameter in the query or, if any is invalid, none of them
+fn set_params(query: &str, shared: &Shared, commands: &Sender<Command>) -> Response<Box<dyn std::io::Read + Send>> {
+    let mut params = Vec::new();
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (name, value) = match pair.split_once('=') {
+            Some((name, value)) => (name, value),
+            None => return text_response(400, "text/plain", format!("missing value for {}", pair)),
+        };
+        let value = match value.parse::<f64>() {
+            Ok(value) => value,
+            Err(_) => return text_response(400, "text/plain", format!("invalid value for {}", name)),
+        };
+        params.push((name.to_string(), value));
+    }
+
+    let results: Vec<String> = params.iter().map(|(name, value)| format!("{} = {}", name, value)).collect();
+    let (reply, rx_reply) = mpsc::channel();
+    send_command(shared, commands, Command::SetParams { params, reply });
+    match rx_reply.recv() {
+        Ok(Ok(())) => text_response(200, "text/plain", results.join("\n")),
+        Ok(Err(e)) => text_response(400, "text/plain", e),
+        Err(_) => text_response(503, "text/plain", String::from("run has finished")),
+    }
+}
+
+// floods the region given by x, y, radius and volume at the current frame
+fn flood(query: &str, shared: &Shared, commands: &Sender<Command>) -> Response<Box<dyn std::io::Read + Send>> {
+    let mut event = FloodEvent { frame: 0, x: f64::NAN, y: f64::NAN, radius: f64::NAN, volume: f64::NAN };
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let value = pair.split_once('=').and_then(|(name, value)| Some((name, value.parse::<f64>().ok()?)));
+        let field = match value {
+            Some(("x", _)) => &mut event.x,
+            Some(("y", _)) => &mut event.y,
+            Some(("radius", _)) => &mut event.radius,
+            Some(("volume", _)) => &mut event.volume,
+            _ => return text_response(400, "text/plain", format!("invalid flood parameter {}", pair)),
+        };
+        *field = value.unwrap().1;
+    }
+    if !(event.x.is_finite() && event.y.is_finite() && event.radius > 0.0 && event.volume > 0.0 && event.volume.is_finite()) {
+        return text_response(400, "text/plain", String::from("flood needs x, y, and a positive radius and volume"));
+    }
+
+    let (reply, rx_reply) = mpsc::channel();
+    send_command(shared, commands, Command::Flood { event, reply });
+    match rx_reply.recv() {
+        Ok(cells) => text_response(200, "text/plain", format!("flooded {} cells", cells)),
+        Err(_) => text_response(503, "text/plain", String::from("run has finished")),
+    }
+}
+
+// applies op (raise, lower, smooth or add_water) at x, y with the given
+// radius and strength, and optionally falloff (smooth by default)
+fn brush(query: &str, shared: &Shared, commands: &Sender<Command>) -> Response<Box<dyn std::io::Read + Send>> {
+    let mut brush = Brush {
+        op: BrushOp::Raise,
+        x: f64::NAN,
+        y: f64::NAN,
+        radius: f64::NAN,
+        strength: f64::NAN,
+        falloff: Falloff::Smooth,
+    };
+    let mut op = None;
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let parsed = match name {
+            "op" => BrushOp::parse(value).map(|value| op = Some(value)),
+            "falloff" => Falloff::parse(value).map(|value| brush.falloff = value),
+            "x" => value.parse().ok().map(|value| brush.x = value),
+            "y" => value.parse().ok().map(|value| brush.y = value),
+            "radius" => value.parse().ok().map(|value| brush.radius = value),
+            "strength" => value.parse().ok().map(|value| brush.strength = value),
+            _ => None,
+        };
+        if parsed.is_none() {
+            return text_response(400, "text/plain", format!("invalid brush parameter {}", pair));
+        }
+    }
+    brush.op = match op {
+        Some(op) => op,
+        None => return text_response(400, "text/plain", String::from("brush needs an op")),
+    };
+    if !(brush.x.is_finite() && brush.y.is_finite() && brush.radius > 0.0 && brush.strength >= 0.0) {
+        return text_response(400, "text/plain", String::from("brush needs x, y, radius and strength"));
+    }
+
+    let (reply, rx_reply) = mpsc::channel();
+    send_command(shared, commands, Command::Brush { brush, reply });
+    match rx_reply.recv() {
+        Ok(cells) => text_response(200, "text/plain", format!("brushed {} cells", cells)),
+        Err(_) => text_response(503, "text/plain", String::from("run has finished")),
+    }
+}
+
+// once the run has finished the command is dropped, and its reply with it,
+// so waiting on the reply fails and the request is answered with a 503
+fn send_command(shared: &Shared, commands: &Sender<Command>, command: Command) {
+    shared.pending_commands.fetch_add(1, Ordering::SeqCst);
+    if commands.send(command).is_err() {
+        shared.pending_commands.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn status_json(status: &RunStatus) -> String {
+    let mut fields = vec![
+        format!("\"frame\": {}", status.frame_num),
+        format!("\"frame_count\": {}", status.frame_count),
+    ];
+    if let Some(stats) = status.stats.as_ref() {
+        fields.push(format!("\"cells\": {}", stats.cell_count));
+        fields.push(format!("\"wet_cells\": {}", stats.wet_cells));
+        fields.push(format!("\"water_volume\": {}", json_number(stats.water_volume)));
+        fields.push(format!("\"min_height\": {}", json_number(stats.min_height)));
+        fields.push(format!("\"mean_height\": {}", json_number(stats.mean_height)));
+        fields.push(format!("\"max_height\": {}", json_number(stats.max_height)));
+    }
+    format!("{{{}}}", fields.join(", "))
+}
+
+// json has no infinities or nans
+fn json_number(value: f64) -> String {
+    if value.is_finite() {
+        value.to_string()
+    } else {
+        String::from("null")
+    }
+}
+
+fn text_response(status: u16, content_type_value: &str, body: String) -> Response<Box<dyn std::io::Read + Send>> {
+    Response::from_string(body)
+        .with_status_code(status)
+        .with_header(content_type(content_type_value))
+        .boxed()
+}
+
+fn content_type(value: &str) -> Header {
+    Header::from_bytes(&b"Content-Type"[..], value.as_bytes()).unwrap()
+}
@@ -0,0 +1,173 @@
+use crate::flow::Flow;
+use crate::terrain::{Terrain, TerrainDelta};
+
+// depth-averaged water velocity per cell
+pub const VELOCITY_X_LAYER: &str = "velocity_x";
+pub const VELOCITY_Y_LAYER: &str = "velocity_y";
+
+// water shallower than this holds no momentum
+const MIN_DEPTH: f64 = 1e-6;
+
+// Moves water with momentum, a simplified form of the shallow water
+// equations on the mesh. Each cell keeps a velocity that is accelerated down
+// the slope of the water surface by `gravity`, slowed by `friction` of
+// itself per unit time and mixed with the velocity of the water flowing in.
+// Water leaves a cell towards the neighbors its velocity points at rather
+// than straight downhill, so it can run on past a low point, slosh back in
+// waves and swing wide around bends; it climbs onto higher ground only as
+// far as its speed carries it. Where it leaves for lower ground it wears
+// away `erosion_rate` times its speed times the water moved, which it
+// deposits where it goes.
+//
+// It carries no rain or drains of its own, so it takes over the water from
+// a DefaultFlow with a flow rate of 0. No step moves more water out of a
+// cell than it holds.
+pub struct ShallowWaterFlow {
+    gravity: f64,
+    friction: f64,
+    erosion_rate: f64,
+    pending: Pending,
+}
+
+// what the last flow leaves for update_layers, per unit time
+#[derive(Default)]
+struct Pending {
+    // per cell
+    accelerations: Vec<(f64, f64)>,
+    // water moved into a cell and the velocity it arrives with
+    inflows: Vec<(usize, f64, (f64, f64))>,
+}
+
+impl ShallowWaterFlow {
+    pub fn new(gravity: f64, friction: f64, erosion_rate: f64) -> ShallowWaterFlow {
+        assert!(gravity > 0.0 && gravity.is_finite());
+        assert!(friction >= 0.0 && friction.is_finite());
+        assert!(erosion_rate >= 0.0 && erosion_rate.is_finite());
+        ShallowWaterFlow { gravity, friction, erosion_rate, pending: Pending::default() }
+    }
+}
+
+impl Flow for ShallowWaterFlow {
+    fn flow(&mut self, terrain: &Terrain, dt: f64) -> Vec<TerrainDelta> {
+        let zeros = vec![0.0; terrain.cells_len()];
+        let velocity_x = terrain.layer(VELOCITY_X_LAYER).unwrap_or(&zeros);
+        let velocity_y = terrain.layer(VELOCITY_Y_LAYER).unwrap_or(&zeros);
+        let pending = &mut self.pending;
+        pending.accelerations = vec![(0.0, 0.0); terrain.cells_len()];
+        pending.inflows.clear();
+
+        let mut deltas = Vec::new();
+        for cell in terrain.cells_iter() {
+            if cell.depth() < MIN_DEPTH {
+                continue;
+            }
+            let i = cell.index();
+            let velocity = (velocity_x[i], velocity_y[i]);
+            let speed = velocity.0.hypot(velocity.1);
+            let surface = cell.height() + cell.depth();
+            // the head the water's speed lets it climb
+            let reach = surface + speed * speed / (2.0 * self.gravity);
+
+            // for neighbors evenly spread around a cell, the outer products
+            // of their directions sum to half their count times the
+            // identity, which normalizes both the gradient and the outflow
+            let scale = 2.0 / cell.neighbor_data_iter().count().max(1) as f64;
+            let mut gradient = (0.0, 0.0);
+            let mut outflows = Vec::new();
+            for nd in cell.neighbor_data_iter() {
+                let neighbor = terrain.get_cell(nd.index());
+                let (dx, dy) = (neighbor.x() - cell.x(), neighbor.y() - cell.y());
+                let length = dx.hypot(dy);
+                if length <= 0.0 {
+                    continue;
+                }
+                let direction = (dx / length, dy / length);
+                let rise = (neighbor.height() + neighbor.depth() - surface) / nd.distance();
+                gradient.0 += scale * rise * direction.0;
+                gradient.1 += scale * rise * direction.1;
+
+                let along = velocity.0 * direction.0 + velocity.1 * direction.1;
+                if along > 0.0 && neighbor.height() < reach {
+                    outflows.push((nd.index(), scale * along / nd.distance(), cell.height() - neighbor.height()));
+                }
+            }
+            pending.accelerations[i] = (-self.gravity * gradient.0, -self.gravity * gradient.1);
+
+            let total: f64 = outflows.iter().map(|&(_, rate, _)| rate).sum();
+            let limit = if total * dt > 1.0 { (total * dt).recip() } else { 1.0 };
+            let mut self_delta = TerrainDelta::new(i);
+            for &(neighbor_index, rate, drop) in outflows.iter() {
+                let moved = cell.depth() * rate * limit;
+                let mut neighbor_delta = TerrainDelta::new(neighbor_index);
+                neighbor_delta.depth_delta = moved;
+                self_delta.depth_delta -= moved;
+                if drop > 0.0 {
+                    let eroded = (self.erosion_rate * speed * moved).min(drop / 2.0);
+                    neighbor_delta.height_delta = eroded;
+                    self_delta.height_delta -= eroded;
+                }
+                deltas.push(neighbor_delta);
+                pending.inflows.push((neighbor_index, moved, velocity));
+            }
+            if !outflows.is_empty() {
+                deltas.push(self_delta);
+            }
+        }
+        deltas
+    }
+
+    fn update_layers(&mut self, terrain: &mut Terrain, time_delta: f64) {
+        let pending = &self.pending;
+        if pending.accelerations.len() != terrain.cells_len() {
+            return;
+        }
+        let mut arrived = vec![0.0; terrain.cells_len()];
+        let mut momentum = vec![(0.0, 0.0); terrain.cells_len()];
+        for &(index, moved, (vx, vy)) in pending.inflows.iter() {
+            arrived[index] += moved * time_delta;
+            momentum[index].0 += moved * time_delta * vx;
+            momentum[index].1 += moved * time_delta * vy;
+        }
+
+        let depths: Vec<f64> = terrain.cells_iter().map(|cell| cell.depth()).collect();
+        let mut velocity_x = terrain.layer_mut(VELOCITY_X_LAYER).to_vec();
+        let mut velocity_y = terrain.layer_mut(VELOCITY_Y_LAYER).to_vec();
+        for (i, &depth) in depths.iter().enumerate() {
+            if depth < MIN_DEPTH {
+                velocity_x[i] = 0.0;
+                velocity_y[i] = 0.0;
+                continue;
+            }
+            // the water that was already here keeps its velocity, mixed with
+            // that of the water arriving
+            let stayed = (depth - arrived[i]).max(0.0);
+            let mixed = stayed + arrived[i];
+            let (ax, ay) = pending.accelerations[i];
+            let damping = 1.0 + self.friction * time_delta;
+            velocity_x[i] = ((stayed * velocity_x[i] + momentum[i].0) / mixed + ax * time_delta) / damping;
+            velocity_y[i] = ((stayed * velocity_y[i] + momentum[i].1) / mixed + ay * time_delta) / damping;
+        }
+        terrain.layer_mut(VELOCITY_X_LAYER).copy_from_slice(&velocity_x);
+        terrain.layer_mut(VELOCITY_Y_LAYER).copy_from_slice(&velocity_y);
+    }
+
+    fn accepts_param(&self, name: &str, value: f64) -> bool {
+        match name {
+            "gravity" => value > 0.0 && value.is_finite(),
+            "friction" | "shallow_erosion_rate" => value >= 0.0 && value.is_finite(),
+            _ => false,
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f64) -> bool {
+        if !self.accepts_param(name, value) {
+            return false;
+        }
+        match name {
+            "gravity" => self.gravity = value,
+            "friction" => self.friction = value,
+            _ => self.erosion_rate = value,
+        }
+        true
+    }
+}
@@ -0,0 +1,135 @@
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+
+use crate::checkpoint::{self, Origin};
+use crate::terrain::Terrain;
+
+const MAGIC: &[u8; 8] = b"TFSNAP01";
+
+// The heights and water depths of a run's terrain at one frame, all that
+// changes of it between frames, for rendering the run again without
+// simulating it. The rest of the terrain, its mesh, is written once beside
+// the snapshots; see write_mesh. Attribute layers aren't kept, so shaders
+// drawing one see it as it was when the mesh was written.
+//
+// Stored little endian as the magic bytes, the world's width and height
+// (u32) and whether it's spherical (u8), the frame (u32) and time (f64),
+// then the cell count (u64) and every cell's height followed by every
+// cell's depth (f32).
+pub struct Snapshot {
+    pub world_size: (usize, usize),
+    pub spherical: bool,
+    pub frame: u32,
+    pub time: f64,
+    pub heights: Vec<f32>,
+    pub depths: Vec<f32>,
+}
+
+impl Snapshot {
+    // sets the terrain's cells to the snapshot's, the terrain read back by
+    // read_mesh from the same directory
+    pub fn apply(&self, terrain: &mut Terrain) {
+        assert_eq!(self.heights.len(), terrain.cells_len(), "snapshot is of a different mesh");
+        for (index, (&height, &depth)) in self.heights.iter().zip(self.depths.iter()).enumerate() {
+            terrain.set_cell(index, height as f64, depth as f64);
+        }
+    }
+}
+
+// writes the mesh of a run's snapshots to the directory, as a checkpoint of
+// its terrain
+pub fn write_mesh(dir: &str, origin: &Origin, terrain: &Terrain) {
+    checkpoint::write(&mesh_path(dir), origin, 0, 0, 0.0, &[], terrain);
+}
+
+pub fn read_mesh(dir: &str) -> Result<Terrain, String> {
+    checkpoint::read(&mesh_path(dir)).map(|checkpoint| checkpoint.terrain)
+}
+
+pub fn write(dir: &str, world_size: (usize, usize), spherical: bool, frame: u32, time: f64, terrain: &Terrain) {
+    let path = format!("{}/snapshot_{:06}.dat", dir, frame);
+    let mut writer = BufWriter::new(File::create(&path).unwrap_or_else(|e| panic!("couldn't create {}: {}", path, e)));
+    let mut put = |bytes: &[u8]| writer.write_all(bytes).unwrap();
+    put(MAGIC);
+    put(&(world_size.0 as u32).to_le_bytes());
+    put(&(world_size.1 as u32).to_le_bytes());
+    put(&[spherical as u8]);
+    put(&frame.to_le_bytes());
+    put(&time.to_le_bytes());
+    put(&(terrain.cells_len() as u64).to_le_bytes());
+    for cell in terrain.cells_iter() {
+        put(&(cell.height() as f32).to_le_bytes());
+    }
+    for cell in terrain.cells_iter() {
+        put(&(cell.depth() as f32).to_le_bytes());
+    }
+}
+
+pub fn read(path: &str) -> Result<Snapshot, String> {
+    let file = File::open(path).map_err(|e| format!("snapshot {}: {}", path, e))?;
+    read_from(&mut BufReader::new(file)).map_err(|e| format!("snapshot {}: {}", path, e))
+}
+
+// the paths of the snapshots in the directory, in frame order
+pub fn list(dir: &str) -> Result<Vec<String>, String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("snapshots {}: {}", dir, e))?;
+    let mut paths: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with("snapshot_") && name.ends_with(".dat"))
+        .map(|name| format!("{}/{}", dir, name))
+        .collect();
+    if paths.is_empty() {
+        return Err(format!("no snapshots in {}", dir));
+    }
+    // the frame numbers are zero padded
+    paths.sort();
+    Ok(paths)
+}
+
+fn mesh_path(dir: &str) -> String {
+    format!("{}/mesh.dat", dir)
+}
+
+fn read_from(reader: &mut impl Read) -> Result<Snapshot, String> {
+    let mut magic = [0; 8];
+    read_bytes(reader, &mut magic)?;
+    if &magic != MAGIC {
+        return Err(String::from("not a snapshot file"));
+    }
+    let mut word = [0; 4];
+    read_bytes(reader, &mut word)?;
+    let width = u32::from_le_bytes(word) as usize;
+    read_bytes(reader, &mut word)?;
+    let height = u32::from_le_bytes(word) as usize;
+    let mut flag = [0; 1];
+    read_bytes(reader, &mut flag)?;
+    read_bytes(reader, &mut word)?;
+    let frame = u32::from_le_bytes(word);
+    let mut long = [0; 8];
+    read_bytes(reader, &mut long)?;
+    let time = f64::from_le_bytes(long);
+    read_bytes(reader, &mut long)?;
+    let cell_count = u64::from_le_bytes(long) as usize;
+
+    let mut values = vec![0; cell_count * 8];
+    read_bytes(reader, &mut values)?;
+    if reader.read(&mut [0]).map_err(|e| e.to_string())? != 0 {
+        return Err(String::from("unexpected data after the last depth"));
+    }
+    let values: Vec<f32> = values.chunks_exact(4).map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap())).collect();
+    let (heights, depths) = values.split_at(cell_count);
+    Ok(Snapshot {
+        world_size: (width, height),
+        spherical: flag[0] != 0,
+        frame,
+        time,
+        heights: heights.to_vec(),
+        depths: depths.to_vec(),
+    })
+}
+
+fn read_bytes(reader: &mut impl Read, buffer: &mut [u8]) -> Result<(), String> {
+    reader.read_exact(buffer).map_err(|e| format!("truncated: {}", e))
+}
@@ -0,0 +1,100 @@
+use std::f64::consts::{FRAC_PI_2, PI, TAU};
+
+use delaunator::{Point as DelPoint, triangulate};
+
+use crate::point::Point;
+
+// Cells on the sphere are located by equirectangular coordinates, with
+// longitude spanning [0, width) and latitude spanning [0, height), so the
+// flow model and planar renderer work on them unchanged. The sphere radius
+// is chosen so that the equator is `width` units long.
+pub struct SphereTopology {
+    pub points: Vec<Point>,
+    pub neighbors: Vec<(usize, usize, f64)>,
+}
+
+impl SphereTopology {
+    pub fn generate(width: usize, height: usize, density: u32) -> SphereTopology {
+        let radius = width as f64 / TAU;
+        let spacing = (density as f64).recip();
+        let count = ((2.0 * TAU * radius * radius) / (spacing * spacing)).round() as usize;
+        assert!(count > 4);
+
+        let unit_points = fibonacci_points(count);
+        let points = unit_points.iter()
+            .map(|v| to_equirectangular(v, width as f64, height as f64))
+            .collect();
+
+        let mut neighbors = Vec::new();
+        for (a, b) in spherical_edges(&unit_points) {
+            let dot = dot(&unit_points[a], &unit_points[b]).clamp(-1.0, 1.0);
+            neighbors.push((a, b, radius * dot.acos()));
+        }
+
+        SphereTopology { points, neighbors }
+    }
+}
+
+// converts equirectangular world coordinates back to (longitude, latitude)
+// in radians
+pub fn lon_lat(x: f64, y: f64, width: f64, height: f64) -> (f64, f64) {
+    (x / width * TAU - PI, y / height * PI - FRAC_PI_2)
+}
+
+// a fibonacci lattice with its first point replaced by the exact north pole,
+// which serves as the projection pivot when triangulating
+fn fibonacci_points(count: usize) -> Vec<[f64; 3]> {
+    let golden_angle = PI * (3.0 - 5f64.sqrt());
+    let mut points: Vec<[f64; 3]> = (0..count)
+        .map(|i| {
+            let z = 1.0 - (2 * i + 1) as f64 / count as f64;
+            let r = (1.0 - z * z).sqrt();
+            let theta = golden_angle * i as f64;
+            [r * theta.cos(), r * theta.sin(), z]
+        })
+        .collect();
+    points[0] = [0.0, 0.0, 1.0];
+    points
+}
+
+// delaunay edges on the sphere: project every point but the pole
+// stereographically from the pole, triangulate in the plane, then connect the
+// pole to the convex hull of the projected points
+fn spherical_edges(points: &[[f64; 3]]) -> Vec<(usize, usize)> {
+    let projected: Vec<DelPoint> = points[1..].iter()
+        .map(|p| DelPoint { x: p[0] / (1.0 - p[2]), y: p[1] / (1.0 - p[2]) })
+        .collect();
+    let triangulation = triangulate(&projected).unwrap();
+
+    let mut edges = Vec::new();
+    for triangle in triangulation.triangles.chunks(3) {
+        for k in 0..3 {
+            let a = triangle[k] + 1;
+            let b = triangle[(k + 1) % 3] + 1;
+            if a < b {
+                edges.push((a, b));
+            } else {
+                edges.push((b, a));
+            }
+        }
+    }
+    for &hull_index in triangulation.hull.iter() {
+        edges.push((0, hull_index + 1));
+    }
+    edges.sort_unstable();
+    edges.dedup();
+    edges
+}
+
+fn to_equirectangular(v: &[f64; 3], width: f64, height: f64) -> Point {
+    let lon = v[1].atan2(v[0]);
+    let lat = v[2].clamp(-1.0, 1.0).asin();
+    Point {
+        x: ((lon + PI) / TAU * width).min(width - f64::EPSILON * width),
+        y: ((lat + FRAC_PI_2) / PI * height).min(height - f64::EPSILON * height),
+    }
+}
+
+fn dot(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
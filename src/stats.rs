@@ -0,0 +1,44 @@
+use crate::terrain::Terrain;
+
+const WET_DEPTH: f64 = 0.1;
+
+#[derive(Clone)]
+pub struct TerrainStats {
+    pub cell_count: usize,
+    pub min_height: f64,
+    pub max_height: f64,
+    pub mean_height: f64,
+    pub water_volume: f64,
+    pub wet_cells: usize,
+}
+
+impl TerrainStats {
+    pub fn of(terrain: &Terrain) -> TerrainStats {
+        let mut stats = TerrainStats {
+            cell_count: terrain.cells_len(),
+            min_height: f64::MAX,
+            max_height: f64::MIN,
+            mean_height: 0.0,
+            water_volume: 0.0,
+            wet_cells: 0,
+        };
+        let mut height_sum = 0.0;
+        for cell in terrain.cells_iter() {
+            stats.min_height = stats.min_height.min(cell.height());
+            stats.max_height = stats.max_height.max(cell.height());
+            height_sum += cell.height();
+            stats.water_volume += cell.depth();
+            if cell.depth() > WET_DEPTH {
+                stats.wet_cells += 1;
+            }
+        }
+        if stats.cell_count > 0 {
+            stats.mean_height = height_sum / stats.cell_count as f64;
+        }
+        stats
+    }
+
+    pub fn relief(&self) -> f64 {
+        self.max_height - self.min_height
+    }
+}
@@ -0,0 +1,119 @@
+use crate::flow::Flow;
+use crate::invariants::MassBudget;
+use crate::terrain::{Terrain, TerrainDelta};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ErosionMode {
+    // bedrock is carved at E = K * A^m * S^n and the material leaves
+    DetachmentLimited,
+    // water carries up to K * A^m * S^n downstream, eroding where that
+    // capacity grows and depositing where it shrinks
+    TransportLimited,
+}
+
+// Stream power erosion, the standard landscape evolution model rule. A is
+// the upstream drainage area found by routing each cell's area to its
+// steepest downhill neighbor, and S is the slope to that neighbor.
+#[derive(Clone)]
+pub struct StreamPower {
+    mode: ErosionMode,
+    k: f64,
+    m: f64,
+    n: f64,
+    cell_area: f64,
+    // ground carved away by the last flow in detachment limited mode, per
+    // unit time
+    carried_off: f64,
+}
+
+impl StreamPower {
+    pub fn new(mode: ErosionMode, k: f64, m: f64, n: f64) -> StreamPower {
+        assert!(k.is_finite() && k >= 0.0);
+        assert!(m.is_finite() && n.is_finite());
+        StreamPower { mode, k, m, n, cell_area: 1.0, carried_off: 0.0 }
+    }
+
+    pub fn set_cell_area(&mut self, cell_area: f64) {
+        assert!(cell_area > 0.0);
+        self.cell_area = cell_area;
+    }
+
+    pub fn scale_k(&mut self, factor: f64) {
+        self.k *= factor;
+    }
+}
+
+impl Flow for StreamPower {
+    fn flow(&mut self, terrain: &Terrain, _dt: f64) -> Vec<TerrainDelta> {
+        let receivers: Vec<Option<(usize, f64)>> = terrain.cells_iter()
+            .map(|cell| {
+                cell.neighbor_data_iter()
+                    .map(|nd| (nd.index(), (cell.height() - terrain.get_cell(nd.index()).height()) / nd.distance()))
+                    .filter(|&(_, slope)| slope > 0.0)
+                    .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+            })
+            .collect();
+
+        let mut order: Vec<usize> = (0..terrain.cells_len()).collect();
+        order.sort_by(|&a, &b| terrain.get_cell(b).height().partial_cmp(&terrain.get_cell(a).height()).unwrap());
+
+        let mut areas = vec![self.cell_area; terrain.cells_len()];
+        let mut fluxes = vec![0.0; terrain.cells_len()];
+        let mut deltas = Vec::new();
+        self.carried_off = 0.0;
+        for &i in order.iter() {
+            let (receiver, slope) = match receivers[i] {
+                Some(receiver) => receiver,
+                None => {
+                    if self.mode == ErosionMode::TransportLimited && fluxes[i] > 0.0 {
+                        deltas.push(height_delta(i, fluxes[i] / self.cell_area));
+                    }
+                    continue;
+                }
+            };
+            areas[receiver] += areas[i];
+            let power = self.k * areas[i].powf(self.m) * slope.powf(self.n);
+            match self.mode {
+                ErosionMode::DetachmentLimited => {
+                    deltas.push(height_delta(i, -power));
+                    self.carried_off += power;
+                }
+                ErosionMode::TransportLimited => {
+                    let capacity = power * self.cell_area;
+                    deltas.push(height_delta(i, (fluxes[i] - capacity) / self.cell_area));
+                    fluxes[receiver] += capacity;
+                }
+            }
+        }
+        deltas
+    }
+
+    fn budget(&self) -> MassBudget {
+        MassBudget { carried_off: self.carried_off, ..MassBudget::default() }
+    }
+
+    fn accepts_param(&self, name: &str, value: f64) -> bool {
+        match name {
+            "stream_power_k" => value >= 0.0 && value.is_finite(),
+            "stream_power_m" | "stream_power_n" => value.is_finite(),
+            _ => false,
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f64) -> bool {
+        if !self.accepts_param(name, value) {
+            return false;
+        }
+        let param = match name {
+            "stream_power_k" => &mut self.k,
+            "stream_power_m" => &mut self.m,
+            _ => &mut self.n,
+        };
+        *param = value;
+        true
+    }
+}
+
+fn height_delta(cell_index: usize, height_delta: f64) -> TerrainDelta {
+    TerrainDelta { cell_index, height_delta, depth_delta: 0.0 }
+}
@@ -0,0 +1,71 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::render::{FrameTime, RGB, Shade};
+use crate::terrain::Terrain;
+
+// stroke width of the edges, in world units
+const EDGE_WIDTH: f64 = 0.05;
+// how much the edges show over shaded triangles
+const SHADED_EDGE_OPACITY: f64 = 0.3;
+
+// Writes the faces of the mesh as an SVG the size of the world, a unit to a
+// world unit with y counting down from the top edge, for figures and for
+// looking over the mesh at any zoom. Shaded, each triangle is filled with
+// the mean of its corners' colors under the shader and the edges are drawn
+// faintly over them; otherwise only the edges are drawn. Triangles across
+// the seam of a wrapping world are left out, as they would stretch the
+// whole width of the drawing.
+#[derive(Clone, Copy, Debug)]
+pub struct SvgExporter {
+    shaded: bool,
+}
+
+impl SvgExporter {
+    pub fn new(shaded: bool) -> SvgExporter {
+        SvgExporter { shaded }
+    }
+
+    pub fn export<S: Shade + ?Sized>(&self, shader: &S, terrain: &Terrain, time: FrameTime, world_size: (usize, usize), path: &str) {
+        let (world_width, world_height) = (world_size.0 as f64, world_size.1 as f64);
+        let colors: Vec<RGB> = if self.shaded {
+            terrain.cells_iter().map(|cell| shader.shade_cell(&cell, terrain, time)).collect()
+        } else {
+            Vec::new()
+        };
+
+        let mut w = BufWriter::new(File::create(path).unwrap_or_else(|e| panic!("couldn't create {}: {}", path, e)));
+        writeln!(
+            w,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{0}" height="{1}" viewBox="0 0 {0} {1}">"#,
+            world_size.0, world_size.1,
+        ).unwrap();
+        if self.shaded {
+            writeln!(
+                w,
+                r##"<g stroke="#000" stroke-width="{}" stroke-opacity="{}" stroke-linejoin="round">"##,
+                EDGE_WIDTH, SHADED_EDGE_OPACITY,
+            ).unwrap();
+        } else {
+            writeln!(w, r##"<g fill="none" stroke="#000" stroke-width="{}" stroke-linejoin="round">"##, EDGE_WIDTH).unwrap();
+        }
+        for triangle in terrain.faces() {
+            let cells = triangle.map(|index| terrain.get_cell(index));
+            if (0..3).any(|corner| (cells[corner].x() - cells[(corner + 1) % 3].x()).abs() > world_width / 2.0) {
+                continue;
+            }
+            let points: Vec<String> = cells.iter()
+                .map(|cell| format!("{:.3},{:.3}", cell.x(), world_height - cell.y()))
+                .collect();
+            if self.shaded {
+                let [a, b, c] = triangle.map(|index| &colors[index]);
+                let [r, g, b] = a.mix(b, 0.5).mix(c, 1.0 / 3.0).to_data();
+                writeln!(w, r##"<polygon points="{}" fill="#{:02x}{:02x}{:02x}"/>"##, points.join(" "), r, g, b).unwrap();
+            } else {
+                writeln!(w, r#"<polygon points="{}"/>"#, points.join(" ")).unwrap();
+            }
+        }
+        writeln!(w, "</g>").unwrap();
+        writeln!(w, "</svg>").unwrap();
+    }
+}
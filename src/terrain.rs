@@ -1,16 +1,44 @@
 use delaunator::{Point as DelPoint, triangulate};
 
+use crate::invariants::{self, DepthPolicy, Violation};
 use crate::point::Point;
+use crate::raster;
+use crate::render::VerticalScale;
 
+// What the terrain keeps its heights, depths and neighbor distances in:
+// single precision with the f32 feature, halving them for huge point sets
+// where the last digits don't matter. They're read and changed as f64
+// either way, so only what's stored is rounded.
+#[cfg(not(feature = "f32"))]
+pub type Real = f64;
+#[cfg(feature = "f32")]
+pub type Real = f32;
+
+// Cells are stored a field at a time, with each cell's neighbors in a
+// shared list: cell i's are neighbors[neighbor_offsets[i]..neighbor_offsets[i + 1]].
+#[derive(Clone)]
 pub struct Terrain {
-    cells: Vec<Cell>,
+    locations: Vec<Point>,
+    heights: Vec<Real>,
+    depths: Vec<Real>,
+    neighbor_offsets: Vec<usize>,
+    neighbors: Vec<NeighborData>,
+    layers: Vec<Layer>,
+}
+
+// a named per-cell value maintained alongside the terrain, such as
+// accumulated moisture, for use by shaders and exports
+#[derive(Clone)]
+struct Layer {
+    name: &'static str,
+    values: Vec<f64>,
 }
 
-pub struct Cell {
-    location: Point,
-    height: f64,
-    depth: f64,
-    neighbor_data: Vec<NeighborData>,
+// a view of one cell of a terrain
+#[derive(Clone, Copy)]
+pub struct Cell<'a> {
+    terrain: &'a Terrain,
+    index: usize,
 }
 
 pub struct TerrainDelta {
@@ -19,29 +47,158 @@ pub struct TerrainDelta {
     pub depth_delta: f64,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum Topology {
+    Delaunay,
+    SquareGrid,
+    HexGrid,
+}
+
+impl Topology {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Topology::Delaunay => "delaunay",
+            Topology::SquareGrid => "square",
+            Topology::HexGrid => "hex",
+        }
+    }
+}
+
+// an index of u32 beside a distance of Real, so single precision halves the
+// neighbor list too; laid out as in C for plugins
+#[derive(Clone)]
+#[repr(C)]
 pub struct NeighborData {
-    index: usize,
-    distance: f64,
+    index: u32,
+    distance: Real,
 }
 
 impl Terrain {
     pub fn generate(points: impl Iterator<Item=Point>, height_at: impl Fn(&Point) -> f64, depth_at: impl Fn(&Point) -> f64) -> Terrain {
-        let mut cells: Vec<Cell> = points
-            .map(|point| -> Cell {
-                let height = height_at(&point);
-                let depth = depth_at(&point);
-                Cell::new(point, height, depth)
-            })
-            .collect();
+        let locations: Vec<Point> = points.collect();
+        let neighbors = Terrain::calculate_neighbors(&locations);
+        Terrain::create(locations, height_at, depth_at, neighbors)
+    }
+
+    // builds a terrain over a precomputed topology, where each neighbor pair
+    // (a, b, distance) is listed once and linked in both directions
+    pub fn generate_with_neighbors(
+        points: impl Iterator<Item=Point>,
+        neighbors: &[(usize, usize, f64)],
+        height_at: impl Fn(&Point) -> f64,
+        depth_at: impl Fn(&Point) -> f64,
+    ) -> Terrain {
+        let locations: Vec<Point> = points.collect();
+        let mut cell_neighbors: Vec<Vec<NeighborData>> = (0..locations.len()).map(|_| Vec::new()).collect();
+        for &(a, b, distance) in neighbors {
+            add_neighbor(&mut cell_neighbors[a], b, distance);
+            add_neighbor(&mut cell_neighbors[b], a, distance);
+        }
+        Terrain::create(locations, height_at, depth_at, cell_neighbors)
+    }
+
+    // rebuilds a terrain from saved cells, each a location, height, depth and
+    // neighbors that keep their order, and named layers
+    pub fn from_parts(cells: Vec<(Point, f64, f64, Vec<NeighborData>)>, layers: Vec<(&'static str, Vec<f64>)>) -> Terrain {
+        assert!(layers.iter().all(|(_, values)| values.len() == cells.len()));
+        let mut terrain = Terrain::empty();
+        for (location, height, depth, neighbors) in cells {
+            terrain.push_cell(location, height, depth, neighbors);
+        }
+        terrain.layers = layers.into_iter().map(|(name, values)| Layer { name, values }).collect();
+        terrain
+    }
+
+    // a square lattice filling width x height, with cells stored row by row
+    // and each linked to its eight surrounding cells
+    pub fn square_grid(
+        width: f64,
+        height: f64,
+        spacing: f64,
+        height_at: impl Fn(&Point) -> f64,
+        depth_at: impl Fn(&Point) -> f64,
+    ) -> Terrain {
+        let columns = (width / spacing).floor() as usize;
+        let rows = (height / spacing).floor() as usize;
+        assert!(columns > 1 && rows > 1);
+
+        let points = (0..rows).flat_map(|row| (0..columns).map(move |column| Point {
+            x: (column as f64 + 0.5) * spacing,
+            y: (row as f64 + 0.5) * spacing,
+        }));
+
+        let diagonal = spacing * 2f64.sqrt();
+        let mut neighbors = Vec::new();
+        for row in 0..rows {
+            for column in 0..columns {
+                let index = row * columns + column;
+                if column + 1 < columns {
+                    neighbors.push((index, index + 1, spacing));
+                }
+                if row + 1 < rows {
+                    neighbors.push((index, index + columns, spacing));
+                    if column + 1 < columns {
+                        neighbors.push((index, index + columns + 1, diagonal));
+                    }
+                    if column > 0 {
+                        neighbors.push((index, index + columns - 1, diagonal));
+                    }
+                }
+            }
+        }
+
+        Terrain::generate_with_neighbors(points, &neighbors, height_at, depth_at)
+    }
+
+    // a hexagonal lattice filling width x height, with cells stored row by row,
+    // odd rows shifted right by half a cell, and each linked to its six
+    // surrounding cells
+    pub fn hex_grid(
+        width: f64,
+        height: f64,
+        spacing: f64,
+        height_at: impl Fn(&Point) -> f64,
+        depth_at: impl Fn(&Point) -> f64,
+    ) -> Terrain {
+        let row_spacing = spacing * 3f64.sqrt() / 2.0;
+        let columns = (width / spacing - 0.5).floor() as usize;
+        let rows = (height / row_spacing).floor() as usize;
+        assert!(columns > 1 && rows > 1);
 
-        Terrain::calculate_neighbors(&mut cells);
+        let points = (0..rows).flat_map(|row| (0..columns).map(move |column| Point {
+            x: (column as f64 + 0.5 + (row % 2) as f64 * 0.5) * spacing,
+            y: (row as f64 + 0.5) * row_spacing,
+        }));
+
+        let mut neighbors = Vec::new();
+        for row in 0..rows {
+            for column in 0..columns {
+                let index = row * columns + column;
+                if column + 1 < columns {
+                    neighbors.push((index, index + 1, spacing));
+                }
+                if row + 1 < rows {
+                    // the two cells above sit at columns (c - 1, c) from an
+                    // even row and (c, c + 1) from an odd row
+                    let (left, right) = if row % 2 == 0 {
+                        (column.checked_sub(1), Some(column))
+                    } else {
+                        (Some(column), Some(column + 1).filter(|&c| c < columns))
+                    };
+                    for above in left.into_iter().chain(right) {
+                        neighbors.push((index, (row + 1) * columns + above, spacing));
+                    }
+                }
+            }
+        }
 
-        Terrain { cells }
+        Terrain::generate_with_neighbors(points, &neighbors, height_at, depth_at)
     }
 
     pub fn apply_delta(&mut self, delta: &TerrainDelta) {
-        let cell = &mut self.cells[delta.cell_index];
-        cell.apply_delta(delta.height_delta, delta.depth_delta);
+        let index = delta.cell_index;
+        self.heights[index] = (widen(self.heights[index]) + delta.height_delta) as Real;
+        self.depths[index] = (widen(self.depths[index]) + delta.depth_delta) as Real;
     }
 
     pub fn apply_deltas(&mut self, deltas: &[TerrainDelta]) {
@@ -50,24 +207,204 @@ impl Terrain {
         }
     }
 
+    // overwrites a cell's height and water depth
+    pub fn set_cell(&mut self, index: usize, height: f64, depth: f64) {
+        self.heights[index] = height as Real;
+        self.depths[index] = depth as Real;
+    }
+
+    // every broken invariant: values that aren't finite, depths against the
+    // policy and neighbors that don't list each other back
+    pub fn validate(&self, depth_policy: DepthPolicy) -> Vec<Violation> {
+        let mut violations = invariants::check_finite(self);
+        violations.extend(invariants::check_depth(self, depth_policy));
+        violations.extend(invariants::check_neighbor_symmetry(self));
+        violations
+    }
+
     pub fn cells_len(&self) -> usize {
-        self.cells.len()
+        self.heights.len()
+    }
+
+    pub fn get_cell(&self, index: usize) -> Cell<'_> {
+        assert!(index < self.cells_len());
+        Cell { terrain: self, index }
+    }
+
+    pub fn cells_iter(&self) -> impl Iterator<Item=Cell<'_>> {
+        (0..self.cells_len()).map(move |index| Cell { terrain: self, index })
+    }
+
+    pub fn locations(&self) -> &[Point] {
+        &self.locations
+    }
+
+    pub fn heights(&self) -> &[Real] {
+        &self.heights
     }
 
-    pub fn get_cell(&self, index: usize) -> &Cell {
-        &self.cells[index]
+    pub fn depths(&self) -> &[Real] {
+        &self.depths
     }
 
-    pub fn cells_iter(&self) -> impl Iterator<Item=&Cell> {
-        self.cells.iter()
+    // the neighbors of every cell in one list, cell i's running from
+    // neighbor_offsets()[i] up to neighbor_offsets()[i + 1]
+    pub fn neighbor_offsets(&self) -> &[usize] {
+        &self.neighbor_offsets
     }
 
-    fn calculate_neighbors(cells: &mut [Cell]) {
-        let del_points: Vec<DelPoint> = cells.iter()
+    pub fn neighbors(&self) -> &[NeighborData] {
+        &self.neighbors
+    }
+
+    pub fn nearest_cell_index(&self, x: f64, y: f64) -> Option<usize> {
+        self.locations.iter()
+            .enumerate()
+            .map(|(index, location)| {
+                let x_dist = location.x - x;
+                let y_dist = location.y - y;
+                (index, x_dist * x_dist + y_dist * y_dist)
+            })
+            .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+            .map(|(index, _)| index)
+    }
+
+    // every triangle of mutually neighboring cells, as sorted index triples
+    pub fn triangles(&self) -> Vec<[usize; 3]> {
+        let mut triangles = Vec::new();
+        for i in 0..self.cells_len() {
+            let neighbors = self.neighbors_of(i);
+            for a in neighbors.iter().filter(|nd| nd.index() > i) {
+                for b in neighbors.iter().filter(|nd| nd.index() > a.index()) {
+                    if self.neighbors_of(a.index()).iter().any(|nd| nd.index() == b.index()) {
+                        triangles.push([i, a.index(), b.index()]);
+                    }
+                }
+            }
+        }
+        triangles
+    }
+
+    // the triangles of the mesh itself, leaving out those of the above that
+    // enclose a cell neighboring all three corners, as its own triangles
+    // cover them, and of the four triangles of a convex quad of mutual
+    // neighbors (as in a square grid) keeping only the two on one diagonal
+    pub fn faces(&self) -> Vec<[usize; 3]> {
+        let mut faces = self.triangles();
+        faces.retain(|&[a, b, c]| {
+            !self.neighbors_of(a).iter()
+                .map(|nd| nd.index())
+                .filter(|&d| d != b && d != c)
+                .filter(|&d| self.neighbors_of(d).iter().any(|nd| nd.index() == b))
+                .filter(|&d| self.neighbors_of(d).iter().any(|nd| nd.index() == c))
+                .any(|d| self.encloses([a, b, c], d) || self.split_across([a, b, c], d))
+        });
+        faces
+    }
+
+    // writes the heights above the datum, resampled to unit pixels over the
+    // world, as a 16-bit grayscale png with their range in a json sidecar of
+    // the same name
+    pub fn export_heightmap(&self, path: &str, world_size: (usize, usize), vertical: VerticalScale) {
+        let (width, height) = world_size;
+        let elevations: Vec<f64> = self.heights.iter().map(|&height| vertical.elevation(widen(height))).collect();
+        let grid = raster::sample_grid(self, &elevations, width, height);
+        raster::write_png16(path, width, height, &grid, &[("datum", vertical.datum)]);
+    }
+
+    pub fn layer(&self, name: &str) -> Option<&[f64]> {
+        self.layers.iter()
+            .find(|layer| layer.name == name)
+            .map(|layer| layer.values.as_slice())
+    }
+
+    pub fn layers_iter(&self) -> impl Iterator<Item=(&'static str, &[f64])> {
+        self.layers.iter().map(|layer| (layer.name, layer.values.as_slice()))
+    }
+
+    // the named layer's values, created zeroed if the layer doesn't exist yet
+    pub fn layer_mut(&mut self, name: &'static str) -> &mut [f64] {
+        let position = match self.layers.iter().position(|layer| layer.name == name) {
+            Some(position) => position,
+            None => {
+                self.layers.push(Layer { name, values: vec![0.0; self.cells_len()] });
+                self.layers.len() - 1
+            }
+        };
+        &mut self.layers[position].values
+    }
+
+    fn empty() -> Terrain {
+        Terrain {
+            locations: Vec::new(),
+            heights: Vec::new(),
+            depths: Vec::new(),
+            neighbor_offsets: vec![0],
+            neighbors: Vec::new(),
+            layers: Vec::new(),
+        }
+    }
+
+    fn create(
+        locations: Vec<Point>,
+        height_at: impl Fn(&Point) -> f64,
+        depth_at: impl Fn(&Point) -> f64,
+        cell_neighbors: Vec<Vec<NeighborData>>,
+    ) -> Terrain {
+        let mut terrain = Terrain::empty();
+        for (location, neighbors) in locations.into_iter().zip(cell_neighbors) {
+            let (height, depth) = (height_at(&location), depth_at(&location));
+            terrain.push_cell(location, height, depth, neighbors);
+        }
+        terrain
+    }
+
+    fn push_cell(&mut self, location: Point, height: f64, depth: f64, neighbors: Vec<NeighborData>) {
+        self.locations.push(location);
+        self.heights.push(height as Real);
+        self.depths.push(depth as Real);
+        self.neighbors.extend(neighbors);
+        self.neighbor_offsets.push(self.neighbors.len());
+    }
+
+    // whether a point lies inside a triangle of cells, going round it either
+    // way
+    fn encloses(&self, triangle: [usize; 3], index: usize) -> bool {
+        let turns = (0..3).map(|corner| self.turn(triangle[corner], triangle[(corner + 1) % 3], index));
+        let (min, max) = turns.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), turn| (min.min(turn), max.max(turn)));
+        min > 0.0 || max < 0.0
+    }
+
+    // whether the triangle and the cell make a convex quad whose other
+    // diagonal, from the cell to the corner across from it, is the one kept:
+    // that of the lower pair of indices
+    fn split_across(&self, triangle: [usize; 3], index: usize) -> bool {
+        (0..3).any(|corner| {
+            let (z, x, y) = (triangle[corner], triangle[(corner + 1) % 3], triangle[(corner + 2) % 3]);
+            self.turn(x, y, z) * self.turn(x, y, index) < 0.0
+                && self.turn(z, index, x) * self.turn(z, index, y) < 0.0
+                && (z.min(index), z.max(index)) < (x.min(y), x.max(y))
+        })
+    }
+
+    // positive where the cell is to the left of the line from one cell to
+    // another, negative to the right
+    fn turn(&self, from: usize, to: usize, index: usize) -> f64 {
+        let (from, to, point) = (&self.locations[from], &self.locations[to], &self.locations[index]);
+        (to.x - from.x) * (point.y - from.y) - (to.y - from.y) * (point.x - from.x)
+    }
+
+    fn neighbors_of(&self, index: usize) -> &[NeighborData] {
+        &self.neighbors[self.neighbor_offsets[index]..self.neighbor_offsets[index + 1]]
+    }
+
+    fn calculate_neighbors(locations: &[Point]) -> Vec<Vec<NeighborData>> {
+        let del_points: Vec<DelPoint> = locations.iter()
             .map(|point| -> DelPoint {
-                DelPoint { x: point.x(), y: point.y() }
+                DelPoint { x: point.x, y: point.y }
             })
             .collect();
+        let mut cell_neighbors: Vec<Vec<NeighborData>> = (0..locations.len()).map(|_| Vec::new()).collect();
         let triangulation = triangulate(&del_points).unwrap();
         for i in (0..triangulation.triangles.len()).step_by(3) {
             for cell_vertex in 0..3 {
@@ -75,61 +412,59 @@ impl Terrain {
                     if cell_vertex != neighbor_vertex {
                         let cell_index = triangulation.triangles[cell_vertex + i];
                         let neighbor_index = triangulation.triangles[neighbor_vertex + i];
-                        let neighbor = &cells[neighbor_index];
-                        let neighbor_location = Point {
-                            x: neighbor.x(),
-                            y: neighbor.y(),
-                        };
-                        cells[cell_index].add_neighbor(neighbor_index, &neighbor_location);
+                        let (cell, neighbor) = (&locations[cell_index], &locations[neighbor_index]);
+                        let x_dist = cell.x - neighbor.x;
+                        let y_dist = cell.y - neighbor.y;
+                        let distance = (x_dist * x_dist + y_dist * y_dist).sqrt();
+                        add_neighbor(&mut cell_neighbors[cell_index], neighbor_index, distance);
                     }
                 }
             }
         }
+        cell_neighbors
     }
 }
 
-impl Cell {
-    pub fn new(location: Point, height: f64, depth: f64) -> Cell {
-        Cell {
-            location,
-            height,
-            depth,
-            neighbor_data: Vec::new(),
-        }
+impl<'a> Cell<'a> {
+    pub fn index(&self) -> usize {
+        self.index
     }
 
     pub fn x(&self) -> f64 {
-        self.location.x
+        self.terrain.locations[self.index].x
     }
 
     pub fn y(&self) -> f64 {
-        self.location.y
+        self.terrain.locations[self.index].y
     }
 
     pub fn height(&self) -> f64 {
-        self.height
+        widen(self.terrain.heights[self.index])
     }
 
     pub fn depth(&self) -> f64 {
-        self.depth
+        widen(self.terrain.depths[self.index])
     }
 
-    pub fn neighbor_data_iter(&self) -> impl Iterator<Item=&NeighborData> {
-        self.neighbor_data.iter()
+    pub fn neighbor_data_iter(&self) -> impl Iterator<Item=&'a NeighborData> {
+        self.terrain.neighbors_of(self.index).iter()
     }
+}
 
-    fn apply_delta(&mut self, height_delta: f64, level_delta: f64) {
-        self.height += height_delta;
-        self.depth += level_delta;
-    }
+#[cfg(not(feature = "f32"))]
+fn widen(value: Real) -> f64 {
+    value
+}
 
-    fn add_neighbor(&mut self, index: usize, neighbor_location: &Point) {
-        if self.neighbor_data.iter().find(|&nd| -> bool { nd.index == index }).is_none() {
-            let x_dist = self.x() - neighbor_location.x;
-            let y_dist = self.y() - neighbor_location.y;
-            let distance = (x_dist * x_dist + y_dist * y_dist).sqrt();
-            self.neighbor_data.push(NeighborData { index, distance });
-        }
+#[cfg(feature = "f32")]
+fn widen(value: Real) -> f64 {
+    value as f64
+}
+
+// links a neighbor to a cell being built, once
+fn add_neighbor(neighbors: &mut Vec<NeighborData>, index: usize, distance: f64) {
+    if !neighbors.iter().any(|nd| nd.index() == index) {
+        neighbors.push(NeighborData::new(index, distance));
     }
 }
 
@@ -144,11 +479,16 @@ impl TerrainDelta {
 }
 
 impl NeighborData {
+    pub fn new(index: usize, distance: f64) -> NeighborData {
+        assert!(index <= u32::MAX as usize);
+        NeighborData { index: index as u32, distance: distance as Real }
+    }
+
     pub fn index(&self) -> usize {
-        self.index
+        self.index as usize
     }
 
     pub fn distance(&self) -> f64 {
-        self.distance
+        widen(self.distance)
     }
 }
\ No newline at end of file
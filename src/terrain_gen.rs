@@ -0,0 +1,134 @@
+use std::f64::consts::FRAC_1_SQRT_2;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::height_source::HeightSource;
+use crate::point::Point;
+
+const PERMUTATION_SIZE: usize = 256;
+
+// the skew and unskew factors between the plane and simplex (triangle) space
+const F2: f64 = 0.366_025_403_784_438_6; // (sqrt(3) - 1) / 2
+const G2: f64 = 0.211_324_865_405_187_1; // (3 - sqrt(3)) / 6
+
+const GRADIENTS: [(f64, f64); 8] = [
+    (1.0, 0.0), (-1.0, 0.0), (0.0, 1.0), (0.0, -1.0),
+    (FRAC_1_SQRT_2, FRAC_1_SQRT_2), (-FRAC_1_SQRT_2, FRAC_1_SQRT_2),
+    (FRAC_1_SQRT_2, -FRAC_1_SQRT_2), (-FRAC_1_SQRT_2, -FRAC_1_SQRT_2),
+];
+
+// How fractal noise is built up: `octaves` layers of simplex noise, the
+// first with features about `scale` world units across, each after it
+// `lacunarity` times finer and weighted `persistence` times as much.
+#[derive(Clone, Copy, Debug)]
+pub struct NoiseParams {
+    pub seed: u64,
+    pub octaves: u32,
+    pub lacunarity: f64,
+    pub persistence: f64,
+    pub scale: f64,
+}
+
+pub struct SimplexNoise {
+    permutation: Vec<usize>,
+}
+
+// fractal Brownian motion over simplex noise, from zero up to max_z
+pub struct NoiseTerrain {
+    noise: SimplexNoise,
+    params: NoiseParams,
+    max_z: f64,
+}
+
+impl NoiseParams {
+    pub fn new(seed: u64) -> NoiseParams {
+        NoiseParams { seed, octaves: 6, lacunarity: 2.0, persistence: 0.5, scale: 200.0 }
+    }
+
+    pub fn validate(&self) {
+        assert!(self.octaves > 0 && self.octaves <= 16, "octaves must be from 1 to 16");
+        assert!(self.lacunarity > 1.0 && self.lacunarity.is_finite(), "lacunarity must be over 1");
+        assert!(self.persistence > 0.0 && self.persistence < 1.0, "persistence must be between 0 and 1");
+        assert!(self.scale > 0.0 && self.scale.is_finite(), "scale must be positive");
+    }
+}
+
+impl SimplexNoise {
+    pub fn new(seed: u64) -> SimplexNoise {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut permutation: Vec<usize> = (0..PERMUTATION_SIZE).collect();
+        for i in (1..PERMUTATION_SIZE).rev() {
+            permutation.swap(i, rng.gen_range(0..=i));
+        }
+        SimplexNoise { permutation }
+    }
+
+    // noise in about [-1, 1], varying over about a unit
+    pub fn sample(&self, x: f64, y: f64) -> f64 {
+        // the corner of the skewed cell holding the point, and the point
+        // relative to it back in the plane
+        let skew = (x + y) * F2;
+        let (i, j) = ((x + skew).floor(), (y + skew).floor());
+        let unskew = (i + j) * G2;
+        let (x0, y0) = (x - (i - unskew), y - (j - unskew));
+
+        // the middle corner of the triangle the point is in
+        let (i1, j1) = if x0 > y0 { (1, 0) } else { (0, 1) };
+        let corners = [
+            (0, 0, x0, y0),
+            (i1, j1, x0 - i1 as f64 + G2, y0 - j1 as f64 + G2),
+            (1, 1, x0 - 1.0 + 2.0 * G2, y0 - 1.0 + 2.0 * G2),
+        ];
+
+        let (i, j) = (i as i64, j as i64);
+        let sum: f64 = corners.iter()
+            .map(|&(di, dj, dx, dy)| {
+                let t = 0.5 - dx * dx - dy * dy;
+                if t <= 0.0 {
+                    return 0.0;
+                }
+                let (gx, gy) = GRADIENTS[self.hash(i + di, j + dj) % GRADIENTS.len()];
+                t.powi(4) * (gx * dx + gy * dy)
+            })
+            .sum();
+        // brings the extremes of the sum close to -1 and 1
+        70.0 * sum
+    }
+
+    // sums octaves of noise, normalized to about [-1, 1]
+    pub fn fbm(&self, x: f64, y: f64, params: &NoiseParams) -> f64 {
+        let mut sum = 0.0;
+        let mut amplitude = 1.0;
+        let mut total_amplitude = 0.0;
+        let mut frequency = params.scale.recip();
+        for octave in 0..params.octaves {
+            // offsets each octave so their lattices don't line up at the origin
+            let offset = octave as f64 * 17.31;
+            sum += amplitude * self.sample(x * frequency + offset, y * frequency - offset);
+            total_amplitude += amplitude;
+            amplitude *= params.persistence;
+            frequency *= params.lacunarity;
+        }
+        sum / total_amplitude
+    }
+
+    fn hash(&self, i: i64, j: i64) -> usize {
+        let mask = PERMUTATION_SIZE as i64 - 1;
+        self.permutation[((i & mask) as usize + self.permutation[(j & mask) as usize]) % PERMUTATION_SIZE]
+    }
+}
+
+impl NoiseTerrain {
+    pub fn new(params: NoiseParams, max_z: f64) -> NoiseTerrain {
+        params.validate();
+        NoiseTerrain { noise: SimplexNoise::new(params.seed), params, max_z }
+    }
+}
+
+impl HeightSource for NoiseTerrain {
+    fn height_at(&self, p: &Point) -> f64 {
+        let n = self.noise.fbm(p.x, p.y, &self.params);
+        self.max_z * (0.5 + 0.5 * n).clamp(0.0, 1.0)
+    }
+}
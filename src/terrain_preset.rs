@@ -0,0 +1,190 @@
+use std::f64::consts::TAU;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::height_source::DemScale;
+use crate::point::Point;
+use crate::terrain_gen::NoiseParams;
+
+const NOISE_TABLE_SIZE: usize = 256;
+
+#[derive(Clone, Copy)]
+pub enum InitialTerrain<'a> {
+    Dome,
+    Random(u64),
+    // a grayscale png, black at zero and white at max_z
+    Heightmap(&'a str),
+    // fractal simplex noise from zero to max_z
+    Noise(NoiseParams),
+    // a GeoTIFF elevation model
+    GeoTiff(&'a str, DemScale),
+}
+
+pub struct TerrainPreset {
+    name: &'static str,
+    kind: PresetKind,
+    width: f64,
+    height: f64,
+    max_z: f64,
+    noise: ValueNoise,
+    noise_scale: f64,
+    octaves: u32,
+}
+
+enum PresetKind {
+    Island { falloff: f64 },
+    Ridges { angle: f64, frequency: f64 },
+    Volcanoes { cones: Vec<Cone> },
+    Mesas { terraces: f64 },
+    Basin { rim: f64 },
+}
+
+struct Cone {
+    x: f64,
+    y: f64,
+    radius: f64,
+    height: f64,
+}
+
+struct ValueNoise {
+    permutation: Vec<usize>,
+    values: Vec<f64>,
+}
+
+impl TerrainPreset {
+    pub fn random(seed: u64, width: f64, height: f64, max_z: f64) -> TerrainPreset {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let noise = ValueNoise::new(&mut rng);
+        let noise_scale = width.min(height) / rng.gen_range(2.0..6.0);
+        let octaves = rng.gen_range(3..7);
+
+        let (name, kind) = match rng.gen_range(0..5) {
+            0 => ("island", PresetKind::Island { falloff: rng.gen_range(1.5..4.0) }),
+            1 => ("ridges", PresetKind::Ridges {
+                angle: rng.gen_range(0.0..TAU),
+                frequency: rng.gen_range(1.0..4.0),
+            }),
+            2 => {
+                let cones = (0..rng.gen_range(1..5))
+                    .map(|_| Cone {
+                        x: rng.gen_range(0.2..0.8) * width,
+                        y: rng.gen_range(0.2..0.8) * height,
+                        radius: rng.gen_range(0.15..0.4) * width.min(height),
+                        height: rng.gen_range(0.5..1.0),
+                    })
+                    .collect();
+                ("volcanoes", PresetKind::Volcanoes { cones })
+            }
+            3 => ("mesas", PresetKind::Mesas { terraces: rng.gen_range(3.0..8.0) }),
+            _ => ("basin", PresetKind::Basin { rim: rng.gen_range(0.5..1.0) }),
+        };
+
+        TerrainPreset { name, kind, width, height, max_z, noise, noise_scale, octaves }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn height_at(&self, p: &Point) -> f64 {
+        let u = p.x / self.width;
+        let v = p.y / self.height;
+        let n = self.noise.fbm(p.x / self.noise_scale, p.y / self.noise_scale, self.octaves);
+        let z = match &self.kind {
+            PresetKind::Island { falloff } => {
+                let dx = 2.0 * u - 1.0;
+                let dy = 2.0 * v - 1.0;
+                let d = (dx * dx + dy * dy).sqrt();
+                (1.0 - d.powf(*falloff)).max(0.0) * (0.3 + 0.7 * n)
+            }
+            PresetKind::Ridges { angle, frequency } => {
+                let along = (p.x * angle.cos() + p.y * angle.sin()) / self.width.min(self.height);
+                let wave = (along * frequency * TAU + 4.0 * n).sin();
+                let ridged = 1.0 - wave.abs();
+                edge_falloff(u, v) * (0.6 * ridged * ridged + 0.4 * n)
+            }
+            PresetKind::Volcanoes { cones } => {
+                let cone_z = cones.iter()
+                    .map(|cone| {
+                        let r = ((p.x - cone.x).powi(2) + (p.y - cone.y).powi(2)).sqrt() / cone.radius;
+                        let profile = (1.0 - r).max(0.0).powf(1.5);
+                        let crater = if r < 0.12 { 0.3 * (0.12 - r) / 0.12 } else { 0.0 };
+                        cone.height * (profile - crater).max(0.0)
+                    })
+                    .fold(0.0, f64::max);
+                edge_falloff(u, v) * (0.15 * n + cone_z).min(1.0)
+            }
+            PresetKind::Mesas { terraces } => {
+                let stepped = (n * terraces).floor() / terraces;
+                let blend = (n * terraces).fract();
+                let smooth = stepped + blend.powi(6) / terraces;
+                edge_falloff(u, v) * smooth
+            }
+            PresetKind::Basin { rim } => {
+                let dx = 2.0 * u - 1.0;
+                let dy = 2.0 * v - 1.0;
+                let d = (dx * dx + dy * dy).sqrt().min(1.0);
+                rim * d * d * (0.5 + 0.5 * n) + 0.1 * n
+            }
+        };
+        self.max_z * z
+    }
+}
+
+impl ValueNoise {
+    fn new(rng: &mut StdRng) -> ValueNoise {
+        let mut permutation: Vec<usize> = (0..NOISE_TABLE_SIZE).collect();
+        for i in (1..NOISE_TABLE_SIZE).rev() {
+            permutation.swap(i, rng.gen_range(0..=i));
+        }
+        let values = (0..NOISE_TABLE_SIZE).map(|_| rng.gen::<f64>()).collect();
+        ValueNoise { permutation, values }
+    }
+
+    // sums octaves of lattice noise, normalized to roughly [0, 1]
+    fn fbm(&self, x: f64, y: f64, octaves: u32) -> f64 {
+        let mut sum = 0.0;
+        let mut amplitude = 1.0;
+        let mut total_amplitude = 0.0;
+        let mut frequency = 1.0;
+        for _ in 0..octaves {
+            sum += amplitude * self.sample(x * frequency, y * frequency);
+            total_amplitude += amplitude;
+            amplitude *= 0.5;
+            frequency *= 2.0;
+        }
+        sum / total_amplitude
+    }
+
+    fn sample(&self, x: f64, y: f64) -> f64 {
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let fx = smoothstep(x - x0);
+        let fy = smoothstep(y - y0);
+        let (xi, yi) = (x0 as i64, y0 as i64);
+        let v00 = self.lattice(xi, yi);
+        let v10 = self.lattice(xi + 1, yi);
+        let v01 = self.lattice(xi, yi + 1);
+        let v11 = self.lattice(xi + 1, yi + 1);
+        let top = v00 + (v10 - v00) * fx;
+        let bottom = v01 + (v11 - v01) * fx;
+        top + (bottom - top) * fy
+    }
+
+    fn lattice(&self, x: i64, y: i64) -> f64 {
+        let mask = NOISE_TABLE_SIZE as i64 - 1;
+        let hashed = self.permutation[((x & mask) as usize + self.permutation[(y & mask) as usize]) % NOISE_TABLE_SIZE];
+        self.values[hashed]
+    }
+}
+
+fn smoothstep(t: f64) -> f64 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+// lowers the margins so random terrains drain towards the edges of the map
+fn edge_falloff(u: f64, v: f64) -> f64 {
+    let edge = u.min(1.0 - u).min(v).min(1.0 - v).clamp(0.0, 0.1) / 0.1;
+    smoothstep(edge)
+}
@@ -0,0 +1,75 @@
+use crate::flow::{within_step, Flow};
+use crate::terrain::{Terrain, TerrainDelta};
+
+// Talus slides: wherever ground stands steeper than the angle of repose
+// above a neighbor, `rate` of the excess height per unit time slumps down to
+// it, shared among the over-steep neighbors in proportion to their excess,
+// wet or dry. Unlike diffusion it leaves slopes below the angle alone, so
+// cliffs crumble back to scree slopes without smoothing everything else.
+// Steps never slump more than would level the steepest pair.
+pub struct ThermalFlow {
+    // the tangent of the angle of repose, the steepest stable slope
+    max_slope: f64,
+    rate: f64,
+}
+
+impl ThermalFlow {
+    // repose angle in degrees
+    pub fn new(repose_angle: f64, rate: f64) -> ThermalFlow {
+        assert!(repose_angle > 0.0 && repose_angle < 90.0);
+        assert!(rate >= 0.0 && rate.is_finite());
+        ThermalFlow { max_slope: repose_angle.to_radians().tan(), rate }
+    }
+}
+
+impl Flow for ThermalFlow {
+    fn flow(&mut self, terrain: &Terrain, dt: f64) -> Vec<TerrainDelta> {
+        let mut deltas = Vec::new();
+        for cell in terrain.cells_iter() {
+            let excesses: Vec<(usize, f64)> = cell.neighbor_data_iter()
+                .map(|nd| {
+                    let diff = cell.height() - terrain.get_cell(nd.index()).height();
+                    (nd.index(), diff - self.max_slope * nd.distance())
+                })
+                .filter(|&(_, excess)| excess > 0.0)
+                .collect();
+            let total: f64 = excesses.iter().map(|(_, excess)| excess).sum();
+            if total <= 0.0 {
+                continue;
+            }
+
+            // the most over-steep neighbor sets how much moves, as half its
+            // excess would level the pair
+            let largest = excesses.iter().map(|&(_, excess)| excess).fold(0.0, f64::max);
+            let moved = within_step(self.rate, dt) * largest / 2.0;
+            for &(neighbor_index, excess) in excesses.iter() {
+                let mut neighbor_delta = TerrainDelta::new(neighbor_index);
+                neighbor_delta.height_delta = moved * excess / total;
+                deltas.push(neighbor_delta);
+            }
+            let mut self_delta = TerrainDelta::new(cell.index());
+            self_delta.height_delta = -moved;
+            deltas.push(self_delta);
+        }
+        deltas
+    }
+
+    fn accepts_param(&self, name: &str, value: f64) -> bool {
+        match name {
+            "repose_angle" => value > 0.0 && value < 90.0,
+            "thermal_rate" => value >= 0.0 && value.is_finite(),
+            _ => false,
+        }
+    }
+
+    fn set_param(&mut self, name: &str, value: f64) -> bool {
+        if !self.accepts_param(name, value) {
+            return false;
+        }
+        match name {
+            "repose_angle" => self.max_slope = value.to_radians().tan(),
+            _ => self.rate = value,
+        }
+        true
+    }
+}
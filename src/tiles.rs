@@ -0,0 +1,64 @@
+use std::fs;
+
+use kdtree::{distance, KdTree};
+
+use crate::render::{FrameTime, Image, RGB, Shade};
+use crate::terrain::Terrain;
+
+const TILE_SIZE: usize = 256;
+
+// Writes a slippy map pyramid of {z}/{x}/{y}.png tiles for viewers like
+// Leaflet or OpenLayers. Zoom level z splits a square the size of the longer
+// world side into 2^z by 2^z tiles with y counting down from the top edge,
+// so the pyramid suits a flat (CRS.Simple style) map rather than web
+// mercator. Each tile pixel takes the color of the nearest cell, which keeps
+// the deepest zoom levels sharp where pixels are finer than the cells.
+pub struct TileExporter {
+    max_zoom: u32,
+}
+
+impl TileExporter {
+    pub fn new(max_zoom: u32) -> TileExporter {
+        assert!(max_zoom <= 10);
+        TileExporter { max_zoom }
+    }
+
+    pub fn export<S: Shade + ?Sized>(&self, shader: &S, terrain: &Terrain, time: FrameTime, world_size: (usize, usize), path: &str) {
+        let colors: Vec<RGB> = terrain.cells_iter().map(|cell| shader.shade_cell(&cell, terrain, time)).collect();
+        let mut kd_tree = KdTree::new(2);
+        for cell in terrain.cells_iter() {
+            kd_tree.add([cell.x(), cell.y()], cell.index()).unwrap();
+        }
+
+        let (world_width, world_height) = (world_size.0 as f64, world_size.1 as f64);
+        let side = world_width.max(world_height);
+        for zoom in 0..=self.max_zoom {
+            let tile_count = 1usize << zoom;
+            let pixel_size = side / (tile_count * TILE_SIZE) as f64;
+            for tile_x in 0..tile_count {
+                let dir = format!("{}/{}/{}", path, zoom, tile_x);
+                fs::create_dir_all(&dir).unwrap();
+                for tile_y in 0..tile_count {
+                    let mut image = Image::new(TILE_SIZE, TILE_SIZE, RGB { r: 0.0, g: 0.0, b: 0.0 });
+                    for row in 0..TILE_SIZE {
+                        let y = world_height - ((tile_y * TILE_SIZE + row) as f64 + 0.5) * pixel_size;
+                        if y < 0.0 {
+                            break;
+                        }
+                        for column in 0..TILE_SIZE {
+                            let x = ((tile_x * TILE_SIZE + column) as f64 + 0.5) * pixel_size;
+                            if x >= world_width {
+                                break;
+                            }
+                            let nearest = kd_tree.nearest(&[x, y], 1, &distance::squared_euclidean).unwrap();
+                            if let Some((_, &index)) = nearest.first() {
+                                image.pixels[row * TILE_SIZE + column] = colors[index].clone();
+                            }
+                        }
+                    }
+                    image.save(&format!("{}/{}.png", dir, tile_y));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::terrain::Terrain;
+
+pub const TRACER_LAYER: &str = "tracers";
+
+#[derive(Clone)]
+pub struct TracerRegion {
+    pub x: f64,
+    pub y: f64,
+    pub radius: f64,
+    pub count: usize,
+}
+
+// Tracer particles ride along with eroded material. After each step, a
+// tracer in a cell that lost height is picked up with probability
+// loss / mixing_depth and dropped in a neighbor that gained height, chosen in
+// proportion to the gain, or otherwise the steepest downhill neighbor. The
+// tracer count per cell is kept in the tracer layer, and positions are
+// written as "frame,tracer,region,x,y" lines.
+pub struct Tracers {
+    cells: Vec<usize>,
+    regions: Vec<usize>,
+    mixing_depth: f64,
+    previous_heights: Vec<f64>,
+    writer: BufWriter<File>,
+    rng: StdRng,
+}
+
+impl Tracers {
+    pub fn seed(regions: &[TracerRegion], mixing_depth: f64, terrain: &mut Terrain, path: &str, mut rng: StdRng) -> Tracers {
+        let mut cells = Vec::new();
+        let mut region_indices = Vec::new();
+        for (region_index, region) in regions.iter().enumerate() {
+            let inside: Vec<usize> = terrain.cells_iter()
+                .filter(|cell| (cell.x() - region.x).hypot(cell.y() - region.y) <= region.radius)
+                .map(|cell| cell.index())
+                .collect();
+            assert!(!inside.is_empty(), "tracer region {} contains no cells", region_index);
+            for _ in 0..region.count {
+                cells.push(*inside.choose(&mut rng).unwrap());
+                region_indices.push(region_index);
+            }
+        }
+
+        let previous_heights = terrain.cells_iter().map(|cell| cell.height()).collect();
+        let mut writer = BufWriter::new(File::create(path).unwrap());
+        writeln!(writer, "frame,tracer,region,x,y").unwrap();
+        let tracers = Tracers { cells, regions: region_indices, mixing_depth, previous_heights, writer, rng };
+        tracers.update_layer(terrain);
+        tracers
+    }
+
+    pub fn update(&mut self, terrain: &mut Terrain) {
+        let rng = &mut self.rng;
+        let previous_heights = &self.previous_heights;
+        for cell_index in self.cells.iter_mut() {
+            let cell = terrain.get_cell(*cell_index);
+            let loss = previous_heights[*cell_index] - cell.height();
+            if loss <= 0.0 || rng.gen::<f64>() >= loss / self.mixing_depth {
+                continue;
+            }
+
+            let gains: Vec<(usize, f64)> = cell.neighbor_data_iter()
+                .map(|nd| (nd.index(), terrain.get_cell(nd.index()).height() - previous_heights[nd.index()]))
+                .filter(|&(_, gain)| gain > 0.0)
+                .collect();
+            if let Ok(&(index, _)) = gains.choose_weighted(rng, |&(_, gain)| gain) {
+                *cell_index = index;
+            } else {
+                let downhill = cell.neighbor_data_iter()
+                    .map(|nd| (nd.index(), (cell.height() - terrain.get_cell(nd.index()).height()) / nd.distance()))
+                    .filter(|&(_, slope)| slope > 0.0)
+                    .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap());
+                if let Some((index, _)) = downhill {
+                    *cell_index = index;
+                }
+            }
+        }
+
+        for (previous, cell) in self.previous_heights.iter_mut().zip(terrain.cells_iter()) {
+            *previous = cell.height();
+        }
+        self.update_layer(terrain);
+    }
+
+    pub fn write_positions(&mut self, frame_num: u32, terrain: &Terrain) {
+        for (tracer, (&cell_index, &region)) in self.cells.iter().zip(self.regions.iter()).enumerate() {
+            let cell = terrain.get_cell(cell_index);
+            writeln!(self.writer, "{},{},{},{},{}", frame_num, tracer, region, cell.x(), cell.y()).unwrap();
+        }
+    }
+
+    fn update_layer(&self, terrain: &mut Terrain) {
+        let layer = terrain.layer_mut(TRACER_LAYER);
+        layer.iter_mut().for_each(|count| *count = 0.0);
+        for &cell_index in self.cells.iter() {
+            layer[cell_index] += 1.0;
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use std::io::{self, Stdout};
+use std::time::Duration;
+
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Color, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Paragraph};
+use ratatui::Terminal;
+
+use crate::render::{FrameTime, Renderer, Shade, RGB};
+use crate::stats::TerrainStats;
+use crate::terrain::Terrain;
+
+const STATS_WIDTH: u16 = 32;
+const PAUSED_POLL_MILLIS: u64 = 250;
+
+pub struct TuiViewer {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+    paused: bool,
+}
+
+pub enum TuiAction {
+    Continue,
+    Quit,
+}
+
+struct View {
+    cols: usize,
+    rows: usize,
+    pixels: Vec<RGB>,
+}
+
+impl TuiViewer {
+    pub fn new() -> TuiViewer {
+        enable_raw_mode().unwrap();
+        execute!(io::stdout(), EnterAlternateScreen).unwrap();
+        let terminal = Terminal::new(CrosstermBackend::new(io::stdout())).unwrap();
+        TuiViewer { terminal, paused: false }
+    }
+
+    // draws the current frame and handles input; blocks while paused until
+    // the user resumes, single-steps or quits
+    pub fn update<S: Shade>(
+        &mut self,
+        renderer: &Renderer<S>,
+        terrain: &Terrain,
+        time: FrameTime,
+        frame_count: u32,
+    ) -> TuiAction {
+        let stats = TerrainStats::of(terrain);
+        let mut view: Option<View> = None;
+
+        loop {
+            let paused = self.paused;
+            self.terminal.draw(|frame| {
+                let area = frame.area();
+                let columns = Layout::default()
+                    .direction(Direction::Horizontal)
+                    .constraints([Constraint::Min(1), Constraint::Length(STATS_WIDTH)])
+                    .split(area);
+                let rows = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([Constraint::Min(1), Constraint::Length(1)])
+                    .split(columns[1]);
+
+                let terrain_block = Block::default().borders(Borders::ALL).title("terrain");
+                let inner = terrain_block.inner(columns[0]);
+                let cols = inner.width as usize;
+                let rows_needed = inner.height as usize;
+                if !view.as_ref().is_some_and(|v| v.cols == cols && v.rows == rows_needed) {
+                    view = Some(View {
+                        cols,
+                        rows: rows_needed,
+                        pixels: renderer.shade_pixels(terrain, time, cols.max(1), (rows_needed * 2).max(1)),
+                    });
+                }
+                let view = view.as_ref().unwrap();
+                frame.render_widget(Paragraph::new(view.lines()).block(terrain_block), columns[0]);
+
+                let stats_block = Block::default().borders(Borders::ALL).title("stats");
+                frame.render_widget(
+                    Paragraph::new(stats_lines(&stats, time.frame, frame_count, paused)).block(stats_block),
+                    rows[0],
+                );
+                frame.render_widget(Paragraph::new("space: pause  s: step  q: quit"), rows[1]);
+            }).unwrap();
+
+            let timeout = if self.paused { Duration::from_millis(PAUSED_POLL_MILLIS) } else { Duration::ZERO };
+            if event::poll(timeout).unwrap() {
+                if let Event::Key(key) = event::read().unwrap() {
+                    if key.kind == KeyEventKind::Press {
+                        match key.code {
+                            KeyCode::Char('q') | KeyCode::Esc => return TuiAction::Quit,
+                            KeyCode::Char(' ') | KeyCode::Char('p') => self.paused = !self.paused,
+                            KeyCode::Char('s') if self.paused => return TuiAction::Continue,
+                            _ => {}
+                        }
+                    }
+                }
+            } else if !self.paused {
+                return TuiAction::Continue;
+            }
+        }
+    }
+}
+
+impl Drop for TuiViewer {
+    fn drop(&mut self) {
+        disable_raw_mode().unwrap();
+        execute!(io::stdout(), LeaveAlternateScreen).unwrap();
+    }
+}
+
+impl View {
+    // each character cell shows two vertically stacked pixels using an upper
+    // half block: the foreground is the top pixel, the background the bottom
+    fn lines(&self) -> Vec<Line<'static>> {
+        (0..self.rows)
+            .map(|row| {
+                let spans: Vec<Span> = (0..self.cols)
+                    .map(|col| {
+                        let top = &self.pixels[(row * 2) * self.cols + col];
+                        let bottom = &self.pixels[(row * 2 + 1) * self.cols + col];
+                        Span::styled("\u{2580}", Style::default().fg(to_color(top)).bg(to_color(bottom)))
+                    })
+                    .collect();
+                Line::from(spans)
+            })
+            .collect()
+    }
+}
+
+fn to_color(rgb: &RGB) -> Color {
+    let [r, g, b] = rgb.to_data();
+    Color::Rgb(r, g, b)
+}
+
+fn stats_lines(stats: &TerrainStats, frame_num: u32, frame_count: u32, paused: bool) -> Vec<Line<'static>> {
+    vec![
+        Line::from(format!("frame    {} of {}", frame_num + 1, frame_count)),
+        Line::from(if paused { "state    paused" } else { "state    running" }),
+        Line::from(""),
+        Line::from(format!("cells    {}", stats.cell_count)),
+        Line::from(format!("wet      {}", stats.wet_cells)),
+        Line::from(format!("water    {:.2}", stats.water_volume)),
+        Line::from(""),
+        Line::from(format!("min z    {:.3}", stats.min_height)),
+        Line::from(format!("mean z   {:.3}", stats.mean_height)),
+        Line::from(format!("max z    {:.3}", stats.max_height)),
+        Line::from(format!("relief   {:.3}", stats.relief())),
+    ]
+}
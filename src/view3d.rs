@@ -0,0 +1,190 @@
+use crate::render::{FrameTime, Image, RGB, Shade, VerticalScale};
+use crate::terrain::Terrain;
+
+const SKY: RGB = RGB { r: 0.55, g: 0.7, b: 0.9 };
+const NEAR: f64 = 0.1;
+
+// A camera circling (center_x, center_y) at ground level, `distance` away
+// and `elevation` degrees above the horizon, starting from `azimuth` degrees
+// and turning `spin` degrees every frame for flyovers.
+#[derive(Clone, Copy)]
+pub struct OrbitCamera {
+    pub center_x: f64,
+    pub center_y: f64,
+    pub distance: f64,
+    pub elevation: f64,
+    pub azimuth: f64,
+    pub spin: f64,
+    pub fov: f64,
+}
+
+#[derive(Clone, Copy)]
+pub enum Stereo {
+    Mono,
+    // left eye image beside right eye image
+    SideBySide { eye_separation: f64 },
+    // red channel from the left eye, green and blue from the right
+    Anaglyph { eye_separation: f64 },
+}
+
+// Perspective views of the terrain surface, drawn as z-buffered triangles
+// between neighboring cells with colors from the shader blended across
+// each triangle.
+pub struct View3d {
+    width: usize,
+    height: usize,
+    camera: OrbitCamera,
+    stereo: Stereo,
+    vertical: VerticalScale,
+    triangles: Vec<[usize; 3]>,
+}
+
+struct Eye {
+    position: [f64; 3],
+    right: [f64; 3],
+    up: [f64; 3],
+    forward: [f64; 3],
+    focal_length: f64,
+}
+
+impl View3d {
+    pub fn new(width: usize, height: usize, camera: OrbitCamera, stereo: Stereo, vertical: VerticalScale) -> View3d {
+        assert!(width > 0 && height > 0);
+        assert!(camera.distance > 0.0);
+        assert!(camera.fov > 0.0 && camera.fov < 180.0);
+        View3d { width, height, camera, stereo, vertical, triangles: Vec::new() }
+    }
+
+    pub fn render<S: Shade + ?Sized>(&mut self, shader: &S, terrain: &Terrain, time: FrameTime) -> Image {
+        if self.triangles.is_empty() {
+            self.triangles = terrain.triangles();
+        }
+        let colors: Vec<RGB> = terrain.cells_iter().map(|cell| shader.shade_cell(&cell, terrain, time)).collect();
+        let azimuth = self.camera.azimuth + self.camera.spin * time.frame as f64;
+
+        match self.stereo {
+            Stereo::Mono => self.render_eye(terrain, &colors, azimuth, 0.0),
+            Stereo::SideBySide { eye_separation } => {
+                let left = self.render_eye(terrain, &colors, azimuth, -eye_separation / 2.0);
+                let right = self.render_eye(terrain, &colors, azimuth, eye_separation / 2.0);
+                let mut image = Image::new(self.width * 2, self.height, SKY);
+                for row in 0..self.height {
+                    let out = row * self.width * 2;
+                    let src = row * self.width;
+                    image.pixels[out..out + self.width].clone_from_slice(&left.pixels[src..src + self.width]);
+                    image.pixels[out + self.width..out + self.width * 2].clone_from_slice(&right.pixels[src..src + self.width]);
+                }
+                image
+            }
+            Stereo::Anaglyph { eye_separation } => {
+                let left = self.render_eye(terrain, &colors, azimuth, -eye_separation / 2.0);
+                let mut image = self.render_eye(terrain, &colors, azimuth, eye_separation / 2.0);
+                for (pixel, left_pixel) in image.pixels.iter_mut().zip(left.pixels.iter()) {
+                    pixel.r = 0.3 * left_pixel.r + 0.59 * left_pixel.g + 0.11 * left_pixel.b;
+                }
+                image
+            }
+        }
+    }
+
+    fn render_eye(&self, terrain: &Terrain, colors: &[RGB], azimuth: f64, offset: f64) -> Image {
+        let eye = self.eye(terrain, azimuth, offset);
+        let projected: Vec<Option<[f64; 3]>> = terrain.cells_iter()
+            .map(|cell| {
+                let z = self.vertical.relief(cell.height() + cell.depth());
+                eye.project([cell.x(), cell.y(), z], self.width, self.height)
+            })
+            .collect();
+
+        let mut image = Image::new(self.width, self.height, SKY);
+        let mut depths = vec![f64::INFINITY; self.width * self.height];
+        for triangle in self.triangles.iter() {
+            let (a, b, c) = match (projected[triangle[0]], projected[triangle[1]], projected[triangle[2]]) {
+                (Some(a), Some(b), Some(c)) => (a, b, c),
+                _ => continue,
+            };
+            let area = edge(&a, &b, &c);
+            if area.abs() < f64::EPSILON {
+                continue;
+            }
+            let min_x = a[0].min(b[0]).min(c[0]).floor().max(0.0) as usize;
+            let max_x = a[0].max(b[0]).max(c[0]).ceil().min(self.width as f64 - 1.0);
+            let min_y = a[1].min(b[1]).min(c[1]).floor().max(0.0) as usize;
+            let max_y = a[1].max(b[1]).max(c[1]).ceil().min(self.height as f64 - 1.0);
+            if max_x < 0.0 || max_y < 0.0 {
+                continue;
+            }
+            for py in min_y..=max_y as usize {
+                for px in min_x..=max_x as usize {
+                    let p = [px as f64 + 0.5, py as f64 + 0.5, 0.0];
+                    let wa = edge(&b, &c, &p) / area;
+                    let wb = edge(&c, &a, &p) / area;
+                    let wc = 1.0 - wa - wb;
+                    if wa < 0.0 || wb < 0.0 || wc < 0.0 {
+                        continue;
+                    }
+                    let depth = wa * a[2] + wb * b[2] + wc * c[2];
+                    let index = py * self.width + px;
+                    if depth < depths[index] {
+                        depths[index] = depth;
+                        let (ca, cb, cc) = (&colors[triangle[0]], &colors[triangle[1]], &colors[triangle[2]]);
+                        image.pixels[index] = RGB {
+                            r: wa * ca.r + wb * cb.r + wc * cc.r,
+                            g: wa * ca.g + wb * cb.g + wc * cc.g,
+                            b: wa * ca.b + wb * cb.b + wc * cc.b,
+                        };
+                    }
+                }
+            }
+        }
+        image
+    }
+
+    // the camera for one eye, shifted sideways by offset
+    fn eye(&self, terrain: &Terrain, azimuth: f64, offset: f64) -> Eye {
+        let mean_z = terrain.cells_iter().map(|cell| cell.height()).sum::<f64>() / terrain.cells_len().max(1) as f64;
+        let target = [self.camera.center_x, self.camera.center_y, self.vertical.relief(mean_z)];
+        let (elevation, azimuth) = (self.camera.elevation.to_radians(), azimuth.to_radians());
+        let mut position = [
+            target[0] + self.camera.distance * elevation.cos() * azimuth.cos(),
+            target[1] + self.camera.distance * elevation.cos() * azimuth.sin(),
+            target[2] + self.camera.distance * elevation.sin(),
+        ];
+
+        let mut forward = [0.0; 3];
+        vec3::sub(&mut forward, &target, &position);
+        vec3::norm_mut(&mut forward);
+        let mut right = [0.0; 3];
+        vec3::cross(&mut right, &forward, &[0.0, 0.0, 1.0]);
+        vec3::norm_mut(&mut right);
+        let mut up = [0.0; 3];
+        vec3::cross(&mut up, &right, &forward);
+
+        for (p, r) in position.iter_mut().zip(right.iter()) {
+            *p += r * offset;
+        }
+        let focal_length = (self.height as f64 / 2.0) / (self.camera.fov.to_radians() / 2.0).tan();
+        Eye { position, right, up, forward, focal_length }
+    }
+}
+
+impl Eye {
+    // screen x and y, with y growing downwards, and distance from the eye
+    fn project(&self, point: [f64; 3], width: usize, height: usize) -> Option<[f64; 3]> {
+        let mut v = [0.0; 3];
+        vec3::sub(&mut v, &point, &self.position);
+        let z = vec3::dot(&v, &self.forward);
+        if z < NEAR {
+            return None;
+        }
+        Some([
+            width as f64 / 2.0 + self.focal_length * vec3::dot(&v, &self.right) / z,
+            height as f64 / 2.0 - self.focal_length * vec3::dot(&v, &self.up) / z,
+            z,
+        ])
+    }
+}
+
+fn edge(a: &[f64; 3], b: &[f64; 3], p: &[f64; 3]) -> f64 {
+    (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0])
+}
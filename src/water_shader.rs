@@ -0,0 +1,45 @@
+use crate::render::{FrameTime, RGB, Shade};
+use crate::terrain::{Cell, Terrain};
+
+// absorption per unit depth, red going first as in clear water, and the
+// deep blue the water scatters back
+pub const CLEAR_WATER_ABSORPTION: RGB = RGB { r: 6.0, g: 2.5, b: 1.2 };
+pub const CLEAR_WATER_SCATTERED: RGB = RGB { r: 0.03, g: 0.12, b: 0.3 };
+
+// Draws water over another shader's ground as light passing down through
+// it and back: each channel of the ground shows through by exp(-absorption
+// * depth), Beer-Lambert style, and the rest of the light is what the water
+// itself scatters back, so shallows fade smoothly into the bank and deep
+// water settles on the scattered color.
+pub struct WaterShader<S: Shade> {
+    ground: S,
+    absorption: RGB,
+    scattered: RGB,
+}
+
+impl<S: Shade> WaterShader<S> {
+    pub fn new(ground: S) -> WaterShader<S> {
+        WaterShader::with_optics(ground, CLEAR_WATER_ABSORPTION, CLEAR_WATER_SCATTERED)
+    }
+
+    pub fn with_optics(ground: S, absorption: RGB, scattered: RGB) -> WaterShader<S> {
+        assert!([absorption.r, absorption.g, absorption.b].iter().all(|&a| a >= 0.0 && a.is_finite()));
+        WaterShader { ground, absorption, scattered }
+    }
+}
+
+impl<S: Shade> Shade for WaterShader<S> {
+    fn shade_cell(&self, cell: &Cell, terrain: &Terrain, time: FrameTime) -> RGB {
+        let ground = self.ground.shade_cell(cell, terrain, time);
+        let depth = cell.depth().max(0.0);
+        let through = |absorption: f64, ground: f64, scattered: f64| {
+            let transmitted = (-absorption * depth).exp();
+            ground * transmitted + scattered * (1.0 - transmitted)
+        };
+        RGB {
+            r: through(self.absorption.r, ground.r, self.scattered.r),
+            g: through(self.absorption.g, ground.g, self.scattered.g),
+            b: through(self.absorption.b, ground.b, self.scattered.b),
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use pixels::{Pixels, SurfaceTexture};
+use winit::application::ApplicationHandler;
+use winit::dpi::LogicalSize;
+use winit::event::{ElementState, KeyEvent, MouseButton, WindowEvent};
+use winit::event_loop::{ActiveEventLoop, EventLoop};
+use winit::keyboard::{Key, NamedKey};
+use winit::platform::pump_events::{EventLoopExtPumpEvents, PumpStatus};
+use winit::window::{Window, WindowId};
+
+use crate::brush::{Brush, BrushOp, Falloff};
+use crate::render::{shade_pixels, FrameTime, Projection, Shade};
+use crate::terrain::Terrain;
+
+const PAUSED_POLL_MILLIS: u64 = 50;
+const BRUSH_RADIUS: f64 = 10.0;
+const BRUSH_TOOLS: [(BrushOp, f64); 4] = [
+    (BrushOp::Raise, 1.0),
+    (BrushOp::Lower, 1.0),
+    (BrushOp::Smooth, 0.5),
+    (BrushOp::AddWater, 1.0),
+];
+
+pub struct WindowViewer {
+    event_loop: EventLoop<()>,
+    state: WindowState,
+    shaders: Vec<(&'static str, Box<dyn Shade>)>,
+    shader_index: usize,
+    // index into BRUSH_TOOLS, or None to inspect cells on click
+    tool: Option<usize>,
+}
+
+pub enum WindowAction {
+    Continue,
+    Quit,
+}
+
+struct WindowState {
+    width: usize,
+    height: usize,
+    window: Option<Arc<Window>>,
+    pixels: Option<Pixels<'static>>,
+    cursor: Option<(f64, f64)>,
+    input: Input,
+}
+
+#[derive(Default)]
+struct Input {
+    paused: bool,
+    step: bool,
+    quit: bool,
+    next_shader: bool,
+    next_tool: bool,
+    redraw: bool,
+    click: Option<(usize, usize)>,
+}
+
+impl WindowViewer {
+    pub fn new(width: usize, height: usize, shaders: Vec<(&'static str, Box<dyn Shade>)>) -> WindowViewer {
+        assert!(!shaders.is_empty());
+        let event_loop = EventLoop::new().unwrap();
+        let state = WindowState {
+            width,
+            height,
+            window: None,
+            pixels: None,
+            cursor: None,
+            input: Input::default(),
+        };
+        WindowViewer { event_loop, state, shaders, shader_index: 0, tool: None }
+    }
+
+    // shows the current frame and handles input; blocks while paused until
+    // the user resumes, single-steps or quits. Clicking inspects the cell
+    // under the cursor, or sculpts with the brush tool selected by b
+    pub fn update(&mut self, terrain: &mut Terrain, time: FrameTime, frame_count: u32) -> WindowAction {
+        self.draw(terrain, time);
+        self.set_title(time.frame, frame_count, None);
+
+        loop {
+            let timeout = if self.state.input.paused { Duration::from_millis(PAUSED_POLL_MILLIS) } else { Duration::ZERO };
+            if let PumpStatus::Exit(_) = self.event_loop.pump_app_events(Some(timeout), &mut self.state) {
+                return WindowAction::Quit;
+            }
+
+            let input = &mut self.state.input;
+            if input.quit {
+                return WindowAction::Quit;
+            }
+            if input.next_shader {
+                input.next_shader = false;
+                self.shader_index = (self.shader_index + 1) % self.shaders.len();
+                self.draw(terrain, time);
+                self.set_title(time.frame, frame_count, None);
+            } else if input.next_tool {
+                input.next_tool = false;
+                self.tool = match self.tool {
+                    None => Some(0),
+                    Some(tool) if tool + 1 < BRUSH_TOOLS.len() => Some(tool + 1),
+                    Some(_) => None,
+                };
+                self.set_title(time.frame, frame_count, None);
+            } else if input.redraw {
+                self.draw(terrain, time);
+            }
+            if let Some(pixel) = self.state.input.click.take() {
+                let info = match self.tool {
+                    Some(tool) => {
+                        let info = self.sculpt(terrain, pixel, tool);
+                        self.draw(terrain, time);
+                        info
+                    }
+                    None => self.inspect(terrain, pixel),
+                };
+                println!("{}", info);
+                self.set_title(time.frame, frame_count, Some(&info));
+            }
+
+            let input = &mut self.state.input;
+            if input.step {
+                input.step = false;
+                return WindowAction::Continue;
+            }
+            if !input.paused {
+                return WindowAction::Continue;
+            }
+        }
+    }
+
+    fn draw(&mut self, terrain: &Terrain, time: FrameTime) {
+        self.state.input.redraw = false;
+        let (width, height) = (self.state.width, self.state.height);
+        let shader = &self.shaders[self.shader_index].1;
+        if let Some(pixels) = self.state.pixels.as_mut() {
+            let colors = shade_pixels(shader.as_ref(), terrain, time, Projection::Equirectangular, (width, height), (width, height));
+            for (rgba, color) in pixels.frame_mut().chunks_exact_mut(4).zip(colors.iter()) {
+                let [r, g, b] = color.to_data();
+                rgba.copy_from_slice(&[r, g, b, 0xff]);
+            }
+            pixels.render().unwrap();
+        }
+    }
+
+    fn set_title(&self, frame_num: u32, frame_count: u32, info: Option<&str>) {
+        if let Some(window) = self.state.window.as_ref() {
+            let paused = if self.state.input.paused { " (paused)" } else { "" };
+            let tool = self.tool.map_or("inspect", |tool| BRUSH_TOOLS[tool].0.name());
+            let mut title = format!(
+                "terrain flow - frame {} of {}{} - {} - {}",
+                frame_num + 1,
+                frame_count,
+                paused,
+                self.shaders[self.shader_index].0,
+                tool,
+            );
+            if let Some(info) = info {
+                title = format!("{} - {}", title, info);
+            }
+            window.set_title(&title);
+        }
+    }
+
+    fn inspect(&self, terrain: &Terrain, pixel: (usize, usize)) -> String {
+        let (x, y) = self.world_location(pixel);
+        match terrain.nearest_cell_index(x, y) {
+            Some(index) => {
+                let cell = terrain.get_cell(index);
+                format!(
+                    "cell {} at ({:.2}, {:.2}): height {:.4}, depth {:.4}, {} neighbors",
+                    index,
+                    cell.x(),
+                    cell.y(),
+                    cell.height(),
+                    cell.depth(),
+                    cell.neighbor_data_iter().count(),
+                )
+            }
+            None => String::from("no cell"),
+        }
+    }
+
+    fn sculpt(&self, terrain: &mut Terrain, pixel: (usize, usize), tool: usize) -> String {
+        let (x, y) = self.world_location(pixel);
+        let (op, strength) = BRUSH_TOOLS[tool];
+        let cells = Brush::new(op, x, y, BRUSH_RADIUS, strength, Falloff::Smooth).apply(terrain);
+        format!("{} at ({:.2}, {:.2}): {} cells", op.name(), x, y, cells)
+    }
+
+    fn world_location(&self, (px, py): (usize, usize)) -> (f64, f64) {
+        (px as f64 + 0.5, (self.state.height - py) as f64 - 0.5)
+    }
+}
+
+impl ApplicationHandler for WindowState {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.window.is_none() {
+            let attributes = Window::default_attributes()
+                .with_title("terrain flow")
+                .with_inner_size(LogicalSize::new(self.width as f64, self.height as f64));
+            let window = Arc::new(event_loop.create_window(attributes).unwrap());
+            let size = window.inner_size();
+            let surface = SurfaceTexture::new(size.width, size.height, window.clone());
+            self.pixels = Some(Pixels::new(self.width as u32, self.height as u32, surface).unwrap());
+            self.window = Some(window);
+            self.input.redraw = true;
+        }
+    }
+
+    fn window_event(&mut self, _event_loop: &ActiveEventLoop, _window_id: WindowId, event: WindowEvent) {
+        match event {
+            WindowEvent::CloseRequested => self.input.quit = true,
+            WindowEvent::Resized(size) => {
+                if let Some(pixels) = self.pixels.as_mut() {
+                    pixels.resize_surface(size.width.max(1), size.height.max(1)).unwrap();
+                }
+                self.input.redraw = true;
+            }
+            WindowEvent::RedrawRequested => {
+                if let Some(pixels) = self.pixels.as_ref() {
+                    pixels.render().unwrap();
+                }
+            }
+            WindowEvent::CursorMoved { position, .. } => self.cursor = Some((position.x, position.y)),
+            WindowEvent::MouseInput { state: ElementState::Pressed, button: MouseButton::Left, .. } => {
+                if let (Some(cursor), Some(pixels)) = (self.cursor, self.pixels.as_ref()) {
+                    if let Ok(pixel) = pixels.window_pos_to_pixel((cursor.0 as f32, cursor.1 as f32)) {
+                        self.input.click = Some(pixel);
+                    }
+                }
+            }
+            WindowEvent::KeyboardInput { event: KeyEvent { logical_key, state: ElementState::Pressed, .. }, .. } => {
+                match logical_key.as_ref() {
+                    Key::Named(NamedKey::Escape) | Key::Character("q") => self.input.quit = true,
+                    Key::Named(NamedKey::Space) => self.input.paused = !self.input.paused,
+                    Key::Named(NamedKey::Tab) => self.input.next_shader = true,
+                    Key::Character("b") => self.input.next_tool = true,
+                    Key::Character("s") if self.input.paused => self.input.step = true,
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+}